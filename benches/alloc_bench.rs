@@ -0,0 +1,193 @@
+#![allow(clippy::field_reassign_with_default)]
+use bitkv_rs::{
+  db::Engine,
+  option::Options,
+  util::rand_kv::{get_test_key, get_test_value},
+};
+use bytes::Bytes;
+use criterion::{
+  criterion_group, criterion_main,
+  measurement::{Measurement, ValueFormatter},
+  Criterion, Throughput,
+};
+use std::{
+  alloc::{GlobalAlloc, Layout, System},
+  path::PathBuf,
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+// counts allocated bytes, to compare allocation of copying and zero-copy reads. It's the
+// global allocator of this bench target only, timings in kv_bench aren't skewed by it
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// criterion measurement of the bytes allocated by an iteration instead of its time
+struct Allocated;
+
+impl Measurement for Allocated {
+  type Intermediate = usize;
+  type Value = usize;
+
+  fn start(&self) -> usize {
+    ALLOCATED.load(Ordering::SeqCst)
+  }
+
+  fn end(&self, start: usize) -> usize {
+    ALLOCATED.load(Ordering::SeqCst) - start
+  }
+
+  fn add(&self, v1: &usize, v2: &usize) -> usize {
+    v1 + v2
+  }
+
+  fn zero(&self) -> usize {
+    0
+  }
+
+  fn to_f64(&self, value: &usize) -> f64 {
+    *value as f64
+  }
+
+  fn formatter(&self) -> &dyn ValueFormatter {
+    &BytesFormatter
+  }
+}
+
+struct BytesFormatter;
+
+impl ValueFormatter for BytesFormatter {
+  fn scale_values(&self, typical_value: f64, values: &mut [f64]) -> &'static str {
+    let (denominator, unit) = match typical_value {
+      v if v < 1024.0 => (1.0, "B"),
+      v if v < 1024.0 * 1024.0 => (1024.0, "KiB"),
+      _ => (1024.0 * 1024.0, "MiB"),
+    };
+    for value in values {
+      *value /= denominator;
+    }
+    unit
+  }
+
+  fn scale_throughputs(
+    &self,
+    _typical_value: f64,
+    throughput: &Throughput,
+    values: &mut [f64],
+  ) -> &'static str {
+    // allocated bytes per element, a scan reports them per record
+    match *throughput {
+      Throughput::Elements(elems) => {
+        for value in values {
+          *value /= elems as f64;
+        }
+        "B/elem"
+      }
+      Throughput::Bytes(bytes) | Throughput::BytesDecimal(bytes) => {
+        for value in values {
+          *value /= bytes as f64;
+        }
+        "B/B"
+      }
+    }
+  }
+
+  fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+    "B"
+  }
+}
+
+fn bench_scan_alloc(c: &mut Criterion<Allocated>) {
+  let mut option = Options::default();
+  option.dir_path = PathBuf::from("/tmp/bitkv-rs-bench/scan-alloc-bench");
+  if !option.dir_path.is_dir() {
+    std::fs::create_dir_all(&option.dir_path).unwrap();
+  }
+  let engine = Engine::open(option.clone()).unwrap();
+
+  for i in 0..100000 {
+    let res = engine.put(get_test_key(i), get_test_value(i));
+    assert!(res.is_ok());
+  }
+  std::mem::drop(engine);
+
+  // decode buffers are reused, so a record costs only its key and value copies
+  option.scan_buffer_size = 0;
+  let engine = Engine::open(option).unwrap();
+  let mut group = c.benchmark_group("bitkv-scan-alloc-bench");
+  group.throughput(Throughput::Elements(100000));
+  group.bench_function("scan", |b| {
+    b.iter(|| {
+      let mut scan = engine.scan().unwrap();
+      while let Some(item) = scan.next().unwrap() {
+        assert!(!item.0.is_empty());
+      }
+    })
+  });
+  group.finish();
+  std::mem::drop(engine);
+
+  std::fs::remove_dir_all("/tmp/bitkv-rs-bench/scan-alloc-bench").unwrap();
+}
+
+fn bench_get_mmap_alloc(c: &mut Criterion<Allocated>) {
+  let mut option = Options::default();
+  option.dir_path = PathBuf::from("/tmp/bitkv-rs-bench/get-mmap-alloc-bench");
+  option.data_file_size = 16 * 1024 * 1024; // 16MB
+  option.mmap_old_files = true;
+  if !option.dir_path.is_dir() {
+    std::fs::create_dir_all(&option.dir_path).unwrap();
+  }
+  let engine = Engine::open(option).unwrap();
+
+  // large values, most of them in old data files
+  let value = Bytes::from(vec![b'v'; 256 * 1024]);
+  for i in 0..1000 {
+    let res = engine.put(get_test_key(i), value.clone());
+    assert!(res.is_ok());
+  }
+
+  // keys are built up front, only the read allocates in the measured loop
+  let keys: Vec<Bytes> = (0..1000).map(get_test_key).collect();
+  let mut group = c.benchmark_group("bitkv-get-large-value-alloc-bench");
+  group.bench_function("get", |b| {
+    let mut i = 0;
+    b.iter(|| {
+      let res = engine.get(keys[i % keys.len()].clone());
+      assert!(res.is_ok());
+      i += 1;
+    })
+  });
+  group.bench_function("get-mmap", |b| {
+    let mut i = 0;
+    b.iter(|| {
+      let res = engine.get_mmap(keys[i % keys.len()].clone());
+      assert!(res.is_ok());
+      i += 1;
+    })
+  });
+  group.finish();
+
+  std::fs::remove_dir_all("/tmp/bitkv-rs-bench/get-mmap-alloc-bench").unwrap();
+}
+
+criterion_group! {
+  name = benches;
+  config = Criterion::default().with_measurement(Allocated);
+  targets = bench_scan_alloc, bench_get_mmap_alloc
+}
+criterion_main!(benches);
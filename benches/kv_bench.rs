@@ -1,8 +1,10 @@
+#![allow(clippy::field_reassign_with_default)]
 use bitkv_rs::{
   db::Engine,
-  option::Options,
+  option::{Consistency, FlushPolicy, IndexType, IteratorOptions, Options},
   util::rand_kv::{get_test_key, get_test_value},
 };
+use bytes::Bytes;
 use criterion::{criterion_group, criterion_main, Criterion};
 use rand::Rng;
 use std::path::PathBuf;
@@ -19,7 +21,7 @@ fn bench_put(c: &mut Criterion) {
 
   c.bench_function("bitkv-put-bench", |b| {
     b.iter(|| {
-      let i = rnd.gen_range(0..std::u32::MAX) as usize;
+      let i = rnd.gen_range(0..u32::MAX) as usize;
       let res = engine.put(get_test_key(i), get_test_value(i));
       assert!(res.is_ok());
     })
@@ -45,7 +47,7 @@ fn bench_get(c: &mut Criterion) {
 
   c.bench_function("bitkv-get-bench", |b| {
     b.iter(|| {
-      let i = rnd.gen_range(0..std::u32::MAX) as usize;
+      let i = rnd.gen_range(0..u32::MAX) as usize;
 
       if (0..100000).contains(&i) {
         let res = engine.get(get_test_key(i));
@@ -77,7 +79,7 @@ fn bench_delete(c: &mut Criterion) {
 
   c.bench_function("bitkv-delete-bench", |b| {
     b.iter(|| {
-      let i = rnd.gen_range(0..std::u32::MAX) as usize;
+      let i = rnd.gen_range(0..u32::MAX) as usize;
       engine.delete(get_test_key(i)).unwrap();
     })
   });
@@ -131,12 +133,424 @@ fn bench_stat(c: &mut Criterion) {
   std::fs::remove_dir_all("/tmp/bitkv-rs-bench/stat-bench").unwrap();
 }
 
+fn bench_scan(c: &mut Criterion) {
+  let mut option = Options::default();
+  option.dir_path = PathBuf::from("/tmp/bitkv-rs-bench/scan-bench");
+  if !option.dir_path.is_dir() {
+    std::fs::create_dir_all(&option.dir_path).unwrap();
+  }
+  let engine = Engine::open(option.clone()).unwrap();
+
+  for i in 0..100000 {
+    let res = engine.put(get_test_key(i), get_test_value(i));
+    assert!(res.is_ok());
+  }
+  std::mem::drop(engine);
+
+  // full scan with one positioned read per record
+  option.scan_buffer_size = 0;
+  let engine = Engine::open(option.clone()).unwrap();
+
+  c.bench_function("bitkv-scan-bench", |b| {
+    b.iter(|| {
+      let mut scan = engine.scan().unwrap();
+      while let Some(item) = scan.next().unwrap() {
+        assert!(!item.0.is_empty());
+      }
+    })
+  });
+  std::mem::drop(engine);
+
+  // full scan with read-ahead
+  option.scan_buffer_size = 4 * 1024 * 1024;
+  let engine = Engine::open(option.clone()).unwrap();
+  c.bench_function("bitkv-scan-read-ahead-bench", |b| {
+    b.iter(|| {
+      let mut scan = engine.scan().unwrap();
+      while let Some(item) = scan.next().unwrap() {
+        assert!(!item.0.is_empty());
+      }
+    })
+  });
+  std::mem::drop(engine);
+
+  std::fs::remove_dir_all("/tmp/bitkv-rs-bench/scan-bench").unwrap();
+}
+
+fn bench_get_mmap(c: &mut Criterion) {
+  let mut option = Options::default();
+  option.dir_path = PathBuf::from("/tmp/bitkv-rs-bench/get-mmap-bench");
+  option.data_file_size = 16 * 1024 * 1024; // 16MB
+  option.mmap_old_files = true;
+  if !option.dir_path.is_dir() {
+    std::fs::create_dir_all(&option.dir_path).unwrap();
+  }
+  let engine = Engine::open(option).unwrap();
+
+  // large values, most of them in old data files
+  let value = Bytes::from(vec![b'v'; 256 * 1024]);
+  for i in 0..1000 {
+    let res = engine.put(get_test_key(i), value.clone());
+    assert!(res.is_ok());
+  }
+
+  let mut rnd = rand::thread_rng();
+
+  c.bench_function("bitkv-get-large-value-bench", |b| {
+    b.iter(|| {
+      let i = rnd.gen_range(0..1000);
+      let res = engine.get(get_test_key(i));
+      assert!(res.is_ok());
+    })
+  });
+
+  c.bench_function("bitkv-get-mmap-large-value-bench", |b| {
+    b.iter(|| {
+      let i = rnd.gen_range(0..1000);
+      let res = engine.get_mmap(get_test_key(i));
+      assert!(res.is_ok());
+    })
+  });
+
+  std::fs::remove_dir_all("/tmp/bitkv-rs-bench/get-mmap-bench").unwrap();
+}
+
+fn bench_get_verify_checksum(c: &mut Criterion) {
+  let mut option = Options::default();
+  option.dir_path = PathBuf::from("/tmp/bitkv-rs-bench/get-verify-checksum-bench");
+  if !option.dir_path.is_dir() {
+    std::fs::create_dir_all(&option.dir_path).unwrap();
+  }
+  let engine = Engine::open(option.clone()).unwrap();
+
+  let value = Bytes::from(vec![b'v'; 4 * 1024]);
+  for i in 0..100000 {
+    let res = engine.put(get_test_key(i), value.clone());
+    assert!(res.is_ok());
+  }
+  std::mem::drop(engine);
+
+  let mut rnd = rand::thread_rng();
+
+  // read with and without crc verification
+  for verify in [true, false] {
+    option.verify_checksum_on_read = verify;
+    let engine = Engine::open(option.clone()).unwrap();
+    let name = format!("bitkv-get-verify-checksum-{}-bench", verify);
+    c.bench_function(&name, |b| {
+      b.iter(|| {
+        let i = rnd.gen_range(0..100000);
+        let res = engine.get(get_test_key(i));
+        assert!(res.is_ok());
+      })
+    });
+  }
+
+  std::fs::remove_dir_all("/tmp/bitkv-rs-bench/get-verify-checksum-bench").unwrap();
+}
+
+fn bench_bptree_put(c: &mut Criterion) {
+  // bulk puts, committing the index per write or once every 1000 writes
+  for (name, policy) in [
+    ("always", FlushPolicy::Always),
+    ("every-1000", FlushPolicy::EveryOps(1000)),
+  ] {
+    let mut option = Options::default();
+    option.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-bench/bptree-put-{}-bench", name));
+    option.index_type = IndexType::BPlusTree;
+    option.bptree_flush_policy = policy;
+    if !option.dir_path.is_dir() {
+      std::fs::create_dir_all(&option.dir_path).unwrap();
+    }
+    let engine = Engine::open(option.clone()).unwrap();
+
+    let mut rnd = rand::thread_rng();
+
+    let bench_name = format!("bitkv-bptree-put-{}-bench", name);
+    c.bench_function(&bench_name, |b| {
+      b.iter(|| {
+        let i = rnd.gen_range(0..u32::MAX) as usize;
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+      })
+    });
+    std::mem::drop(engine);
+
+    std::fs::remove_dir_all(option.dir_path).unwrap();
+  }
+}
+
+fn bench_put_append_only(c: &mut Criterion) {
+  // puts of sequential keys, through the general path and the append only one
+  for (name, append_only) in [("general", false), ("append-only", true)] {
+    let mut option = Options::default();
+    option.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-bench/put-{}-bench", name));
+    option.append_only = append_only;
+    if !option.dir_path.is_dir() {
+      std::fs::create_dir_all(&option.dir_path).unwrap();
+    }
+    let engine = Engine::open(option.clone()).unwrap();
+
+    let mut i = 0;
+    let bench_name = format!("bitkv-put-sequential-{}-bench", name);
+    c.bench_function(&bench_name, |b| {
+      b.iter(|| {
+        i += 1;
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+      })
+    });
+    std::mem::drop(engine);
+
+    std::fs::remove_dir_all(option.dir_path).unwrap();
+  }
+}
+
+fn bench_merge_parallel(c: &mut Criterion) {
+  // merge writes all live records every time, it only takes effect on reopen
+  let mut group = c.benchmark_group("bitkv-merge-bench");
+  group.sample_size(10);
+  for parallelism in [1, 4] {
+    let mut option = Options::default();
+    option.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-bench/merge-{}-bench", parallelism));
+    option.data_file_size = 8 * 1024 * 1024; // 8MB
+    option.max_value_size = 1024 * 1024; // 1MB
+    option.file_merge_threshold = 0 as f32;
+    option.merge_parallelism = parallelism;
+    if !option.dir_path.is_dir() {
+      std::fs::create_dir_all(&option.dir_path).unwrap();
+    }
+    let engine = Engine::open(option.clone()).unwrap();
+
+    let value = Bytes::from(vec![b'v'; 1024]);
+    for i in 0..50000 {
+      engine.put(get_test_key(i), value.clone()).unwrap();
+    }
+
+    group.bench_function(format!("parallelism-{}", parallelism), |b| {
+      b.iter(|| {
+        let report = engine.merge().unwrap();
+        assert_eq!(50000, report.records_kept);
+      })
+    });
+    std::mem::drop(engine);
+
+    std::fs::remove_dir_all(option.dir_path).unwrap();
+  }
+  group.finish();
+}
+
+fn bench_skiplist_iter_create(c: &mut Criterion) {
+  let mut option = Options::default();
+  option.dir_path = PathBuf::from("/tmp/bitkv-rs-bench/skiplist-iter-create-bench");
+  option.index_type = IndexType::SkipList;
+  if !option.dir_path.is_dir() {
+    std::fs::create_dir_all(&option.dir_path).unwrap();
+  }
+  let engine = Engine::open(option.clone()).unwrap();
+  for i in 0..100000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+
+  // a snapshot copies every entry up front, a live iterator only finds the first one
+  let mut group = c.benchmark_group("bitkv-skiplist-iter-create-bench");
+  for (name, consistency) in [
+    ("snapshot", Consistency::Snapshot),
+    ("live", Consistency::Live),
+  ] {
+    group.bench_function(name, |b| {
+      b.iter(|| {
+        let iter = engine
+          .iter(IteratorOptions {
+            consistency,
+            ..Default::default()
+          })
+          .unwrap();
+        assert!(iter.next().is_some());
+      })
+    });
+  }
+  group.finish();
+  std::mem::drop(engine);
+
+  std::fs::remove_dir_all(option.dir_path).unwrap();
+}
+
+fn bench_iter_prefetch(c: &mut Criterion) {
+  let mut option = Options::default();
+  option.dir_path = PathBuf::from("/tmp/bitkv-rs-bench/iter-prefetch-bench");
+  if !option.dir_path.is_dir() {
+    std::fs::create_dir_all(&option.dir_path).unwrap();
+  }
+  let engine = Engine::open(option).unwrap();
+
+  for i in 0..10000 {
+    let res = engine.put(get_test_key(i), Bytes::from(vec![i as u8; 4096]));
+    assert!(res.is_ok());
+  }
+
+  // value processing comparable to a read, overlapped with reading ahead when prefetching
+  let process = |value: &Bytes| {
+    let mut hash = 0u64;
+    for _ in 0..8 {
+      for b in value.iter() {
+        hash = hash.wrapping_mul(31).wrapping_add(*b as u64);
+      }
+    }
+    hash
+  };
+
+  for prefetch in [0, 64] {
+    c.bench_function(&format!("bitkv-iter-prefetch-{}-bench", prefetch), |b| {
+      b.iter(|| {
+        let iter = engine
+          .iter(IteratorOptions {
+            prefetch,
+            ..Default::default()
+          })
+          .unwrap();
+        let mut hash = 0u64;
+        while let Some((_, value)) = iter.next() {
+          hash ^= process(&value);
+        }
+        criterion::black_box(hash);
+      })
+    });
+  }
+
+  std::fs::remove_dir_all("/tmp/bitkv-rs-bench/iter-prefetch-bench").unwrap();
+}
+
+fn bench_get_many_ordered(c: &mut Criterion) {
+  let mut option = Options::default();
+  option.dir_path = PathBuf::from("/tmp/bitkv-rs-bench/get-many-ordered-bench");
+  if !option.dir_path.is_dir() {
+    std::fs::create_dir_all(&option.dir_path).unwrap();
+  }
+  let engine = Engine::open(option.clone()).unwrap();
+  for i in 0..1000000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+
+  // sorted inputs, a dense one of every third key from the start of the index and a sparse one
+  // of every thousandth key across all of it, a sixth of them past the last key and absent
+  let key_sets = [
+    (
+      "dense",
+      (0..40000)
+        .map(|i| get_test_key(i * 3))
+        .collect::<Vec<Bytes>>(),
+    ),
+    (
+      "sparse",
+      (0..1200).map(|i| get_test_key(i * 1000)).collect(),
+    ),
+  ];
+  let mut group = c.benchmark_group("bitkv-get-many-ordered-bench");
+  group.sample_size(10);
+  for (name, keys) in key_sets.iter() {
+    group.bench_function(format!("merge-join-{}", name), |b| {
+      b.iter(|| {
+        let values = engine.get_many_ordered(keys).unwrap();
+        assert_eq!(keys.len(), values.len());
+      })
+    });
+    group.bench_function(format!("per-key-get-{}", name), |b| {
+      b.iter(|| {
+        let values: Vec<Option<Bytes>> = keys
+          .iter()
+          .map(|key| engine.get(key.clone()).ok())
+          .collect();
+        assert_eq!(keys.len(), values.len());
+      })
+    });
+  }
+  group.finish();
+  std::mem::drop(engine);
+
+  std::fs::remove_dir_all(option.dir_path).unwrap();
+}
+
+fn bench_put_sync_concurrent(c: &mut Criterion) {
+  // durable puts from one writer and from eight, concurrent writers share group syncs
+  let mut group = c.benchmark_group("bitkv-put-sync-concurrent-bench");
+  group.sample_size(10);
+  for threads in [1, 8] {
+    let mut option = Options::default();
+    option.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-bench/put-sync-{}-bench", threads));
+    option.sync_writes = true;
+    if !option.dir_path.is_dir() {
+      std::fs::create_dir_all(&option.dir_path).unwrap();
+    }
+    let engine = Engine::open(option.clone()).unwrap();
+
+    // 64 puts per iteration, split between the writers
+    group.bench_function(format!("threads-{}", threads), |b| {
+      b.iter(|| {
+        std::thread::scope(|s| {
+          for t in 0..threads {
+            let engine = &engine;
+            s.spawn(move || {
+              for i in 0..64 / threads {
+                let res = engine.put(get_test_key(t * 64 + i), get_test_value(i));
+                assert!(res.is_ok());
+              }
+            });
+          }
+        });
+      })
+    });
+    std::mem::drop(engine);
+
+    std::fs::remove_dir_all(option.dir_path).unwrap();
+  }
+  group.finish();
+}
+
+fn bench_get_large_value(c: &mut Criterion) {
+  let mut option = Options::default();
+  option.dir_path = PathBuf::from("/tmp/bitkv-rs-bench/get-large-value-bench");
+  if !option.dir_path.is_dir() {
+    std::fs::create_dir_all(&option.dir_path).unwrap();
+  }
+  let engine = Engine::open(option).unwrap();
+
+  // 1MB values, read into a single buffer without copying out of the record
+  let value = Bytes::from(vec![b'v'; 1024 * 1024]);
+  for i in 0..64 {
+    engine.put(get_test_key(i), value.clone()).unwrap();
+  }
+
+  let mut rnd = rand::thread_rng();
+
+  c.bench_function("bitkv-get-large-value-bench", |b| {
+    b.iter(|| {
+      let i = rnd.gen_range(0..64);
+      let res = engine.get(get_test_key(i)).unwrap();
+      assert_eq!(value.len(), res.len());
+    })
+  });
+
+  std::fs::remove_dir_all("/tmp/bitkv-rs-bench/get-large-value-bench").unwrap();
+}
+
 criterion_group!(
   benches,
   bench_get,
+  bench_get_large_value,
   bench_put,
   bench_delete,
   bench_listkeys,
-  bench_stat
+  bench_stat,
+  bench_scan,
+  bench_iter_prefetch,
+  bench_get_mmap,
+  bench_get_verify_checksum,
+  bench_bptree_put,
+  bench_put_append_only,
+  bench_merge_parallel,
+  bench_skiplist_iter_create,
+  bench_get_many_ordered,
+  bench_put_sync_concurrent
 );
 criterion_main!(benches);
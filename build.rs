@@ -0,0 +1,8 @@
+fn main() {
+  // generate gRPC service code, protoc is vendored so no system install is needed
+  #[cfg(feature = "grpc-server")]
+  {
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+    tonic_build::compile_protos("proto/bitkv.proto").expect("failed to compile protos");
+  }
+}
@@ -2,43 +2,95 @@
 mod test;
 
 use actix_web::{
-  delete, get, post, rt::signal, web, App, HttpResponse, HttpServer, Responder, Scope,
+  body::{BodySize, MessageBody},
+  delete, get, post,
+  rt::signal,
+  web, App, HttpResponse, HttpServer, Responder, Scope,
 };
-use bitkv_rs::{db::Engine, errors::Errors, option::Options};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use bitkv_rs::{
+  db::Engine,
+  errors::Errors,
+  option::{IteratorOptions, Options},
+};
+use serde::Deserialize;
 use serde_json::json;
 use std::{
   collections::HashMap,
+  convert::Infallible,
   path::PathBuf,
+  pin::Pin,
   sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
   },
+  task::{Context, Poll},
 };
 use surf::post as surf_post; // 为避免与 actix_web 的 post 宏冲突
 use tokio::{
   io::{self, AsyncBufReadExt, BufReader},
-  sync::broadcast,
+  sync::{broadcast, mpsc},
 };
 
+// how keys in paths and keys and values in put bodies are written, `?encoding=base64` takes
+// them as standard base64 so binary data round-trips like the keys listed by `listkeys`
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+  #[default]
+  Utf8,
+  Base64,
+}
+
+#[derive(Deserialize)]
+pub struct EncodingQuery {
+  #[serde(default)]
+  encoding: Encoding,
+}
+
+fn decode_param(param: &str, encoding: Encoding) -> Result<web::Bytes, HttpResponse> {
+  match encoding {
+    Encoding::Utf8 => Ok(web::Bytes::from(param.to_string())),
+    Encoding::Base64 => BASE64
+      .decode(param)
+      .map(web::Bytes::from)
+      .map_err(|_| HttpResponse::BadRequest().body("invalid base64")),
+  }
+}
+
 #[post("/put")]
 pub async fn put_handler(
   eng: web::Data<Arc<Engine>>,
   data: web::Json<HashMap<String, String>>,
+  query: web::Query<EncodingQuery>,
 ) -> impl Responder {
   for (key, val) in data.iter() {
-    if eng
-      .put(web::Bytes::from(key.clone()), web::Bytes::from(val.clone()))
-      .is_err()
-    {
+    let (key, val) = match (
+      decode_param(key, query.encoding),
+      decode_param(val, query.encoding),
+    ) {
+      (Ok(key), Ok(val)) => (key, val),
+      (Err(resp), _) | (_, Err(resp)) => return resp,
+    };
+    if eng.put(key, val).is_err() {
       return HttpResponse::InternalServerError().body("failed to put value into engine");
     }
   }
   HttpResponse::Ok().body("成功")
 }
 
-#[get("/get/{key}")]
-pub async fn get_handler(eng: web::Data<Arc<Engine>>, key: web::Path<String>) -> impl Responder {
-  match eng.get(web::Bytes::from(key.to_string())) {
+// the key takes the rest of the path, base64 keys may contain `/`
+#[get("/get/{key:.*}")]
+pub async fn get_handler(
+  eng: web::Data<Arc<Engine>>,
+  key: web::Path<String>,
+  query: web::Query<EncodingQuery>,
+) -> impl Responder {
+  let key = match decode_param(&key, query.encoding) {
+    Ok(key) => key,
+    Err(resp) => return resp,
+  };
+  match eng.get(key) {
     Ok(val) => HttpResponse::Ok().body(val),
     Err(e) => match e {
       Errors::KeyNotFound => HttpResponse::Ok().body("key not found"),
@@ -47,9 +99,17 @@ pub async fn get_handler(eng: web::Data<Arc<Engine>>, key: web::Path<String>) ->
   }
 }
 
-#[delete("/delete/{key}")]
-pub async fn delete_handler(eng: web::Data<Arc<Engine>>, key: web::Path<String>) -> impl Responder {
-  if let Err(e) = eng.delete(web::Bytes::from(key.to_string())) {
+#[delete("/delete/{key:.*}")]
+pub async fn delete_handler(
+  eng: web::Data<Arc<Engine>>,
+  key: web::Path<String>,
+  query: web::Query<EncodingQuery>,
+) -> impl Responder {
+  let key = match decode_param(&key, query.encoding) {
+    Ok(key) => key,
+    Err(resp) => return resp,
+  };
+  if let Err(e) = eng.delete(key) {
     match e {
       Errors::KeyNotFound => return HttpResponse::Ok().body("key not found"),
       _ => return HttpResponse::InternalServerError().body("failed to delete value in engine"),
@@ -58,21 +118,140 @@ pub async fn delete_handler(eng: web::Data<Arc<Engine>>, key: web::Path<String>)
   HttpResponse::Ok().body("OK")
 }
 
+const LISTKEYS_CHUNK_SIZE: usize = 256;
+// chunks walked ahead of the response before the walk waits for them to be sent
+const LISTKEYS_CHUNKS_QUEUED: usize = 4;
+
+// NDJSON body of all keys, one `{"key": "<base64>"}` line per key, so binary keys survive.
+// keys are sent a chunk at a time instead of collected up front
+struct ListKeysBody {
+  chunks: mpsc::Receiver<web::Bytes>,
+}
+
+impl ListKeysBody {
+  // walk the keys with a single iterator on a blocking thread, the index and data files
+  // aren't read on the executor. The walk stops once the response is dropped
+  fn new(eng: Arc<Engine>) -> Self {
+    let (sender, chunks) = mpsc::channel(LISTKEYS_CHUNKS_QUEUED);
+    tokio::task::spawn_blocking(move || {
+      // the body just ends if the keys can't be walked
+      let Ok(iter) = eng.iter(IteratorOptions::default()) else {
+        return;
+      };
+      let mut chunk = Vec::new();
+      let mut count = 0;
+      while let Some(key) = iter.next_key() {
+        chunk.extend_from_slice(json!({ "key": BASE64.encode(&key) }).to_string().as_bytes());
+        chunk.push(b'\n');
+        count += 1;
+        if count == LISTKEYS_CHUNK_SIZE {
+          let chunk = web::Bytes::from(std::mem::take(&mut chunk));
+          if sender.blocking_send(chunk).is_err() {
+            return;
+          }
+          count = 0;
+        }
+      }
+      if !chunk.is_empty() {
+        let _ = sender.blocking_send(web::Bytes::from(chunk));
+      }
+    });
+    Self { chunks }
+  }
+}
+
+impl MessageBody for ListKeysBody {
+  type Error = Infallible;
+
+  fn size(&self) -> BodySize {
+    BodySize::Stream
+  }
+
+  fn poll_next(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<web::Bytes, Self::Error>>> {
+    self
+      .get_mut()
+      .chunks
+      .poll_recv(cx)
+      .map(|chunk| chunk.map(Ok))
+  }
+}
+
 #[get("/listkeys")]
 pub async fn listkeys_handler(eng: web::Data<Arc<Engine>>) -> impl Responder {
-  let keys = match eng.list_keys() {
-    Ok(keys) => keys,
-    Err(_) => return HttpResponse::InternalServerError().body("failed to list keys"),
+  HttpResponse::Ok()
+    .content_type("application/x-ndjson")
+    .body(ListKeysBody::new(eng.get_ref().clone()))
+}
+
+const DEFAULT_SCAN_LIMIT: usize = 100;
+const MAX_SCAN_LIMIT: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct ScanQuery {
+  prefix: Option<String>,
+  start: Option<String>,
+  limit: Option<usize>,
+  #[serde(default)]
+  encoding: Encoding,
+}
+
+// returns one page of key/value pairs in key order, keys and values in standard base64 so
+// binary data round-trips. `next_start` is the base64 key to pass as `start` to fetch the
+// following page, null once the scan is exhausted. The prefix is taken as `encoding` says
+#[get("/scan")]
+pub async fn scan_handler(
+  eng: web::Data<Arc<Engine>>,
+  query: web::Query<ScanQuery>,
+) -> impl Responder {
+  let limit = query
+    .limit
+    .unwrap_or(DEFAULT_SCAN_LIMIT)
+    .clamp(1, MAX_SCAN_LIMIT);
+  let prefix = match decode_param(query.prefix.as_deref().unwrap_or_default(), query.encoding) {
+    Ok(prefix) => prefix,
+    Err(resp) => return resp,
+  };
+  let start_key = match &query.start {
+    Some(start) => match decode_param(start, Encoding::Base64) {
+      Ok(start) => Some(start.to_vec()),
+      Err(resp) => return resp,
+    },
+    None => None,
   };
 
-  let keys = keys
-    .into_iter()
-    .map(|key| String::from_utf8(key.to_vec()).unwrap())
-    .collect::<Vec<String>>();
-  let res = serde_json::to_string(&keys).unwrap();
-  HttpResponse::Ok()
-    .content_type("application/json")
-    .body(res)
+  // the page is read on a blocking thread, from the index entries at or after start only
+  let eng = eng.get_ref().clone();
+  let page = web::block(move || {
+    let iter = eng.iter(IteratorOptions {
+      prefix: prefix.to_vec(),
+      start_key,
+      ..Default::default()
+    })?;
+    let mut items = Vec::with_capacity(limit);
+    let mut next_start = None;
+    while let Some((key, value)) = iter.next() {
+      if items.len() == limit {
+        next_start = Some(BASE64.encode(&key));
+        break;
+      }
+      items.push(json!({
+        "key": BASE64.encode(&key),
+        "value": BASE64.encode(&value),
+      }));
+    }
+    Ok::<_, Errors>(json!({ "items": items, "next_start": next_start }))
+  })
+  .await;
+
+  match page {
+    Ok(Ok(page)) => HttpResponse::Ok()
+      .content_type("application/json")
+      .body(page.to_string()),
+    _ => HttpResponse::InternalServerError().body("failed to scan engine"),
+  }
 }
 
 #[get("/stat")]
@@ -93,6 +272,29 @@ pub async fn stat_handler(eng: web::Data<Arc<Engine>>) -> impl Responder {
     .body(serde_json::to_string(&res).unwrap())
 }
 
+#[post("/merge")]
+pub async fn merge_handler(eng: web::Data<Arc<Engine>>) -> impl Responder {
+  let report = match eng.merge() {
+    Ok(report) => report,
+    Err(e) => match e {
+      Errors::MergeInProgress | Errors::MergeThresholdUnreached => {
+        return HttpResponse::Ok().body(e.to_string())
+      }
+      _ => return HttpResponse::InternalServerError().body("failed to merge engine"),
+    },
+  };
+
+  let mut res = HashMap::new();
+  res.insert("files_merged", report.files_merged);
+  res.insert("records_kept", report.records_kept);
+  res.insert("records_dropped", report.records_dropped);
+  res.insert("bytes_reclaimed", report.bytes_reclaimed as usize);
+
+  HttpResponse::Ok()
+    .content_type("application/json")
+    .body(serde_json::to_string(&res).unwrap())
+}
+
 async fn send_request() -> surf::Result<()> {
   let uri = "http://127.0.0.1:8080/bitkv/put";
   let data = json!({ "key1": "value1", "key2": "value2" });
@@ -108,10 +310,12 @@ async fn send_request() -> surf::Result<()> {
   println!("Status: {}", res.status());
   let body = res.body_string().await?;
   println!("Response: {}", body);
-  let keys: Vec<String> = serde_json::from_str(&body)?;
 
-  for key in keys {
-    let url = format!("http://127.0.0.1:8080/bitkv/get/{}", key);
+  // keys are listed in base64, one json object per line
+  for line in body.lines() {
+    let item: serde_json::Value = serde_json::from_str(line)?;
+    let key = item["key"].as_str().unwrap_or_default();
+    let url = format!("http://127.0.0.1:8080/bitkv/get/{}?encoding=base64", key);
     let mut res = surf::get(url).await?;
     println!("Status: {}", res.status());
     let body = res.body_string().await?;
@@ -129,7 +333,9 @@ async fn run_server(engine: Arc<Engine>) -> std::io::Result<()> {
         .service(get_handler)
         .service(delete_handler)
         .service(listkeys_handler)
-        .service(stat_handler),
+        .service(scan_handler)
+        .service(stat_handler)
+        .service(merge_handler),
     )
   })
   .bind("127.0.0.1:8080")
@@ -190,7 +396,7 @@ async fn main() -> std::io::Result<()> {
     eprintln!("failed to close engine: {}", e);
   }
 
-  let _ = shutdown_handle.abort();
+  shutdown_handle.abort();
   println!("engine is closed");
 
   Ok(())
@@ -3,11 +3,13 @@ use actix_web::{http::StatusCode, test};
 
 #[actix_web::test]
 async fn test_put_handler() {
-  let mut opts = Options::default();
-  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-http");
+  let opts = Options {
+    dir_path: PathBuf::from("/tmp/bitkv-rs-http"),
+    ..Default::default()
+  };
   let engine = Arc::new(Engine::open(opts).unwrap());
 
-  let mut app = test::init_service(
+  let app = test::init_service(
     App::new()
       .app_data(web::Data::new(engine.clone()))
       .service(Scope::new("/bitkv").service(put_handler)),
@@ -16,20 +18,22 @@ async fn test_put_handler() {
 
   let req = test::TestRequest::with_uri("/bitkv/put")
     .method(actix_web::http::Method::POST)
-    .set_json(&json!({"key": "test", "value": "test value"}))
+    .set_json(json!({"key": "test", "value": "test value"}))
     .to_request();
 
-  let resp = test::call_service(&mut app, req).await;
+  let resp = test::call_service(&app, req).await;
   assert_eq!(resp.status(), StatusCode::OK);
 }
 
 #[actix_web::test]
 async fn test_get_handler() {
-  let mut opts = Options::default();
-  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-http");
+  let opts = Options {
+    dir_path: PathBuf::from("/tmp/bitkv-rs-http"),
+    ..Default::default()
+  };
   let engine = Arc::new(Engine::open(opts).unwrap());
 
-  let mut app = test::init_service(
+  let app = test::init_service(
     App::new()
       .app_data(web::Data::new(engine.clone()))
       .service(Scope::new("/bitkv").service(get_handler)),
@@ -39,21 +43,77 @@ async fn test_get_handler() {
   // Insert a key-value pair
   let _ = test::TestRequest::with_uri("/bitkv/put")
     .method(actix_web::http::Method::POST)
-    .set_json(&json!({"key": "test", "value": "test value"}))
+    .set_json(json!({"key": "test", "value": "test value"}))
     .to_request();
 
   let req = test::TestRequest::with_uri("/bitkv/get/test").to_request();
-  let resp = test::call_service(&mut app, req).await;
+  let resp = test::call_service(&app, req).await;
   assert_eq!(resp.status(), StatusCode::OK);
 }
 
+#[actix_web::test]
+async fn test_binary_key_handlers() {
+  let opts = Options {
+    dir_path: PathBuf::from("/tmp/bitkv-rs-http-binary-key"),
+    ..Default::default()
+  };
+  let engine = Arc::new(Engine::open(opts.clone()).unwrap());
+
+  let app = test::init_service(
+    App::new().app_data(web::Data::new(engine.clone())).service(
+      Scope::new("/bitkv")
+        .service(put_handler)
+        .service(get_handler)
+        .service(delete_handler),
+    ),
+  )
+  .await;
+
+  // a key with a null byte, whose base64 form also has a `/`
+  let key = b"key\0\xff\xfe".to_vec();
+  let value = b"value\0".to_vec();
+  let encoded = BASE64.encode(&key);
+  assert!(encoded.contains('/'));
+
+  let req = test::TestRequest::with_uri("/bitkv/put?encoding=base64")
+    .method(actix_web::http::Method::POST)
+    .set_json(json!({ encoded.clone(): BASE64.encode(&value) }))
+    .to_request();
+  let resp = test::call_service(&app, req).await;
+  assert_eq!(resp.status(), StatusCode::OK);
+  assert_eq!(value, engine.get(web::Bytes::from(key.clone())).unwrap());
+
+  let uri = format!("/bitkv/get/{}?encoding=base64", encoded);
+  let req = test::TestRequest::with_uri(&uri).to_request();
+  let body = test::call_and_read_body(&app, req).await;
+  assert_eq!(value, body);
+
+  let uri = format!("/bitkv/delete/{}?encoding=base64", encoded);
+  let req = test::TestRequest::with_uri(&uri)
+    .method(actix_web::http::Method::DELETE)
+    .to_request();
+  let resp = test::call_service(&app, req).await;
+  assert_eq!(resp.status(), StatusCode::OK);
+  assert!(engine.get(web::Bytes::from(key)).is_err());
+
+  let req = test::TestRequest::with_uri("/bitkv/get/not-base64!?encoding=base64").to_request();
+  let resp = test::call_service(&app, req).await;
+  assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+  std::mem::drop(app);
+  std::mem::drop(engine);
+  std::fs::remove_dir_all(opts.dir_path).unwrap();
+}
+
 #[actix_web::test]
 async fn test_listkeys_handler() {
-  let mut opts = Options::default();
-  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-http");
+  let opts = Options {
+    dir_path: PathBuf::from("/tmp/bitkv-rs-http"),
+    ..Default::default()
+  };
   let engine = Arc::new(Engine::open(opts).unwrap());
 
-  let mut app = test::init_service(
+  let app = test::init_service(
     App::new()
       .app_data(web::Data::new(engine.clone()))
       .service(Scope::new("/bitkv").service(listkeys_handler)),
@@ -61,17 +121,64 @@ async fn test_listkeys_handler() {
   .await;
 
   let req = test::TestRequest::with_uri("/bitkv/listkeys").to_request();
-  let resp = test::call_service(&mut app, req).await;
+  let resp = test::call_service(&app, req).await;
   assert_eq!(resp.status(), StatusCode::OK);
 }
 
+#[actix_web::test]
+async fn test_listkeys_handler_binary_keys() {
+  let opts = Options {
+    dir_path: PathBuf::from("/tmp/bitkv-rs-http-listkeys"),
+    ..Default::default()
+  };
+  let engine = Arc::new(Engine::open(opts.clone()).unwrap());
+  // more keys than one chunk, plus a key that isn't valid utf-8
+  let mut keys = (0..300)
+    .map(|i| format!("key-{:03}", i).into_bytes())
+    .collect::<Vec<_>>();
+  keys.push(vec![0xff, 0xfe, 0x00, 0x80]);
+  for key in keys.iter() {
+    engine
+      .put(web::Bytes::from(key.clone()), web::Bytes::from("value"))
+      .unwrap();
+  }
+
+  let app = test::init_service(
+    App::new()
+      .app_data(web::Data::new(engine.clone()))
+      .service(Scope::new("/bitkv").service(listkeys_handler)),
+  )
+  .await;
+
+  let req = test::TestRequest::with_uri("/bitkv/listkeys").to_request();
+  let resp = test::call_service(&app, req).await;
+  assert_eq!(resp.status(), StatusCode::OK);
+  let body = test::read_body(resp).await;
+  let listed = String::from_utf8(body.to_vec())
+    .unwrap()
+    .lines()
+    .map(|line| {
+      let item: serde_json::Value = serde_json::from_str(line).unwrap();
+      BASE64.decode(item["key"].as_str().unwrap()).unwrap()
+    })
+    .collect::<Vec<_>>();
+  keys.sort();
+  assert_eq!(keys, listed);
+
+  std::mem::drop(app);
+  std::mem::drop(engine);
+  std::fs::remove_dir_all(opts.dir_path).unwrap();
+}
+
 #[actix_web::test]
 async fn test_stat_handler() {
-  let mut opts = Options::default();
-  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-http");
+  let opts = Options {
+    dir_path: PathBuf::from("/tmp/bitkv-rs-http"),
+    ..Default::default()
+  };
   let engine = Arc::new(Engine::open(opts).unwrap());
 
-  let mut app = test::init_service(
+  let app = test::init_service(
     App::new()
       .app_data(web::Data::new(engine.clone()))
       .service(Scope::new("/bitkv").service(stat_handler)),
@@ -79,6 +186,124 @@ async fn test_stat_handler() {
   .await;
 
   let req = test::TestRequest::with_uri("/bitkv/stat").to_request();
-  let resp = test::call_service(&mut app, req).await;
+  let resp = test::call_service(&app, req).await;
   assert_eq!(resp.status(), StatusCode::OK);
 }
+
+#[actix_web::test]
+async fn test_merge_handler() {
+  let opts = Options {
+    dir_path: PathBuf::from("/tmp/bitkv-rs-http-merge"),
+    file_merge_threshold: 0.0,
+    ..Default::default()
+  };
+  let engine = Arc::new(Engine::open(opts.clone()).unwrap());
+  for i in 0..100 {
+    engine
+      .put(
+        web::Bytes::from("key"),
+        web::Bytes::from(format!("value-{}", i)),
+      )
+      .unwrap();
+  }
+
+  let app = test::init_service(
+    App::new()
+      .app_data(web::Data::new(engine.clone()))
+      .service(Scope::new("/bitkv").service(merge_handler)),
+  )
+  .await;
+
+  let req = test::TestRequest::with_uri("/bitkv/merge")
+    .method(actix_web::http::Method::POST)
+    .to_request();
+  let resp: HashMap<String, usize> = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(resp["records_kept"], 1);
+  assert_eq!(resp["records_dropped"], 99);
+  assert!(resp["bytes_reclaimed"] > 0);
+
+  std::mem::drop(app);
+  std::mem::drop(engine);
+  std::fs::remove_dir_all(opts.dir_path).unwrap();
+}
+
+#[actix_web::test]
+async fn test_scan_handler() {
+  let opts = Options {
+    dir_path: PathBuf::from("/tmp/bitkv-rs-http-scan"),
+    ..Default::default()
+  };
+  let engine = Arc::new(Engine::open(opts.clone()).unwrap());
+  for i in 0..10 {
+    engine
+      .put(
+        web::Bytes::from(format!("user-{:02}", i)),
+        web::Bytes::from(format!("value-{}", i)),
+      )
+      .unwrap();
+  }
+  engine
+    .put(web::Bytes::from("other"), web::Bytes::from("value"))
+    .unwrap();
+
+  let app = test::init_service(
+    App::new()
+      .app_data(web::Data::new(engine.clone()))
+      .service(Scope::new("/bitkv").service(scan_handler)),
+  )
+  .await;
+
+  let req = test::TestRequest::with_uri("/bitkv/scan?prefix=user-&limit=6").to_request();
+  let page1: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  let next_start = page1["next_start"].as_str().unwrap().to_string();
+  assert_eq!(BASE64.decode(&next_start).unwrap(), b"user-06");
+
+  // the cursor is base64, escaped in the query string
+  let next_start = next_start
+    .replace('+', "%2B")
+    .replace('/', "%2F")
+    .replace('=', "%3D");
+  let uri = format!("/bitkv/scan?prefix=user-&start={}&limit=6", next_start);
+  let req = test::TestRequest::with_uri(&uri).to_request();
+  let page2: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert!(page2["next_start"].is_null());
+
+  let decode = |item: &serde_json::Value, field: &str| {
+    String::from_utf8(BASE64.decode(item[field].as_str().unwrap()).unwrap()).unwrap()
+  };
+  let keys = page1["items"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .chain(page2["items"].as_array().unwrap().iter())
+    .map(|item| decode(item, "key"))
+    .collect::<Vec<String>>();
+  let expected = (0..10)
+    .map(|i| format!("user-{:02}", i))
+    .collect::<Vec<String>>();
+  assert_eq!(keys, expected);
+  assert_eq!(decode(&page1["items"][0], "value"), "value-0");
+
+  // keys and values that aren't utf-8 come back unchanged
+  engine
+    .put(
+      web::Bytes::from(vec![b'b', 0xff, 0x00]),
+      web::Bytes::from(vec![0xfe, 0x80]),
+    )
+    .unwrap();
+  let req = test::TestRequest::with_uri("/bitkv/scan?prefix=b").to_request();
+  let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  let item = &page["items"][0];
+  assert_eq!(
+    BASE64.decode(item["key"].as_str().unwrap()).unwrap(),
+    vec![b'b', 0xff, 0x00]
+  );
+  assert_eq!(
+    BASE64.decode(item["value"].as_str().unwrap()).unwrap(),
+    vec![0xfe, 0x80]
+  );
+
+  std::mem::drop(app);
+  std::mem::drop(engine);
+  std::fs::remove_dir_all(opts.dir_path).unwrap();
+}
@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tokio::task;
+
+use crate::{
+  db::Engine,
+  errors::{Errors, Result},
+  merge::MergeReport,
+};
+
+/// Async wrapper around a shared engine
+///
+/// Engine calls do blocking disk io, each method runs them on tokio's blocking thread pool so
+/// the async executor is never blocked.
+#[derive(Clone)]
+pub struct AsyncEngine {
+  engine: Arc<Engine>,
+}
+
+impl AsyncEngine {
+  pub fn new(engine: Arc<Engine>) -> Self {
+    Self { engine }
+  }
+
+  /// the underlying engine, for calls without an async variant
+  pub fn engine(&self) -> &Arc<Engine> {
+    &self.engine
+  }
+
+  /// store a key/value pair, see `Engine::put`
+  pub async fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
+    self.spawn(move |engine| engine.put(key, value)).await
+  }
+
+  /// retrieve the value of a key, see `Engine::get`
+  pub async fn get(&self, key: Bytes) -> Result<Bytes> {
+    self.spawn(move |engine| engine.get(key)).await
+  }
+
+  /// delete a key, see `Engine::delete`
+  pub async fn delete(&self, key: Bytes) -> Result<()> {
+    self.spawn(move |engine| engine.delete(key)).await
+  }
+
+  /// merge data files, see `Engine::merge`
+  pub async fn merge(&self) -> Result<MergeReport> {
+    self.spawn(|engine| engine.merge()).await
+  }
+
+  // run a blocking engine call on the blocking thread pool
+  async fn spawn<T, F>(&self, f: F) -> Result<T>
+  where
+    T: Send + 'static,
+    F: FnOnce(&Engine) -> Result<T> + Send + 'static,
+  {
+    let engine = self.engine.clone();
+    task::spawn_blocking(move || f(&engine))
+      .await
+      .map_err(|_| Errors::AsyncTaskFailed)?
+  }
+}
+
+impl From<Engine> for AsyncEngine {
+  fn from(engine: Engine) -> Self {
+    Self::new(Arc::new(engine))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+  };
+
+  use crate::{
+    option::Options,
+    util::rand_kv::{get_test_key, get_test_value},
+  };
+
+  use super::*;
+
+  #[tokio::test]
+  async fn test_async_engine_put_get() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-async-engine");
+    opts.file_merge_threshold = 0 as f32;
+    let engine = AsyncEngine::from(Engine::open(opts.clone()).expect("failed to open engine"));
+
+    // a task that only makes progress while the executor is free
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let done = Arc::new(AtomicBool::new(false));
+    let ticker = {
+      let ticks = ticks.clone();
+      let done = done.clone();
+      tokio::spawn(async move {
+        while !done.load(Ordering::SeqCst) {
+          ticks.fetch_add(1, Ordering::SeqCst);
+          task::yield_now().await;
+        }
+      })
+    };
+
+    let mut handles = Vec::new();
+    for i in 0..1000 {
+      let engine = engine.clone();
+      handles.push(tokio::spawn(async move {
+        engine.put(get_test_key(i), get_test_value(i)).await?;
+        engine.get(get_test_key(i)).await
+      }));
+    }
+    for (i, handle) in handles.into_iter().enumerate() {
+      assert_eq!(get_test_value(i), handle.await.unwrap().unwrap());
+    }
+    done.store(true, Ordering::SeqCst);
+    ticker.await.unwrap();
+    assert!(ticks.load(Ordering::SeqCst) > 0);
+
+    assert!(engine.delete(get_test_key(0)).await.is_ok());
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.get(get_test_key(0)).await.unwrap_err()
+    );
+    let report = engine.merge().await.unwrap();
+    assert_eq!(999, report.records_kept);
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+  }
+}
@@ -1,11 +1,16 @@
 use std::{
   collections::HashMap,
+  ops::Deref,
   sync::{atomic::Ordering, Arc},
+  time::{Duration, Instant},
 };
 
 use bytes::{BufMut, Bytes, BytesMut};
 use parking_lot::Mutex;
-use prost::{decode_length_delimiter, encode_length_delimiter};
+use prost::{
+  decode_length_delimiter, encode_length_delimiter,
+  encoding::{decode_varint, encode_varint},
+};
 
 use crate::{
   data::log_record::{LogRecord, LogRecordType},
@@ -15,42 +20,117 @@ use crate::{
 };
 
 const TXN_FIN_KEY: &[u8] = "txn-fin".as_bytes();
-pub(crate) const NON_TXN_SEQ_NO: usize = 0;
+/// seq_no of log records written outside a write batch
+pub const NON_TXN_SEQ_NO: usize = 0;
+// leads the key of a log record stamped with a version, followed by the varint seq_no and
+// version. A varint is never encoded with a trailing zero byte, so keys written before
+// versions don't start with it
+const VERSIONED_KEY_MARKER: [u8; 2] = [0x80, 0x00];
+/// max length of what precedes the key in a log record key, the versioned key marker along
+/// with the varint seq_no and version
+pub(crate) const MAX_LOG_RECORD_KEY_PREFIX_LEN: usize = VERSIONED_KEY_MARKER.len() + 10 + 10;
 
 /// A batch of write operations. Ensuring Atomicity and Consistency.
+///
+/// Puts and deletes may be staged from several threads at once, a commit takes all writes
+/// staged before it and later ones go to the next commit.
 pub struct WriteBatch<'a> {
   pending_writes: Arc<Mutex<HashMap<Vec<u8>, LogRecord>>>, // temporarily store the write data
-  engine: &'a Engine,
+  engine: BatchEngine<'a>,
   options: WriteBatchOptions,
 }
 
+// engine a write batch commits to, borrowed or shared by `new_shared_write_batch`
+enum BatchEngine<'a> {
+  Borrowed(&'a Engine),
+  Shared(Arc<Engine>),
+}
+
+impl Deref for BatchEngine<'_> {
+  type Target = Engine;
+
+  fn deref(&self) -> &Engine {
+    match self {
+      BatchEngine::Borrowed(engine) => engine,
+      BatchEngine::Shared(engine) => engine,
+    }
+  }
+}
+
 impl Engine {
   /// Create a new write batch.
-  pub fn new_write_batch(&self, options: WriteBatchOptions) -> Result<WriteBatch> {
-    if self.options.index_type == IndexType::BPlusTree && !self.seq_file_exists && !self.is_initial
-    {
-      return Err(Errors::UnableToUseWriteBatch);
-    }
+  pub fn new_write_batch(&self, options: WriteBatchOptions) -> Result<WriteBatch<'_>> {
+    self.check_write_batch()?;
+    Ok(WriteBatch {
+      pending_writes: Arc::new(Mutex::new(HashMap::new())),
+      engine: BatchEngine::Borrowed(self),
+      options,
+    })
+  }
 
+  /// Create a new write batch holding its own handle to the engine, so it isn't tied to
+  /// a borrow and can be moved or shared, e.g. in an `Arc`, between threads staging writes.
+  pub fn new_shared_write_batch(
+    self: &Arc<Self>,
+    options: WriteBatchOptions,
+  ) -> Result<WriteBatch<'static>> {
+    self.check_write_batch()?;
     Ok(WriteBatch {
       pending_writes: Arc::new(Mutex::new(HashMap::new())),
-      engine: self,
+      engine: BatchEngine::Shared(self.clone()),
       options,
     })
   }
+
+  /// store a key/value pair only if the current version of key, see `version`, equals
+  /// expected_version, use 0 for a key that doesn't exist yet. Checking and writing are
+  /// serialized with every other write, the new version is returned. `Errors::VersionMismatch`
+  /// if another write got there first.
+  pub fn put_with_version(&self, key: Bytes, value: Bytes, expected_version: u64) -> Result<u64> {
+    if self.is_read_only() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+    self.check_key_value(&key, &value)?;
+
+    // hold off every other write between the check and the write
+    let write_lock = self.lock_writes(None)?;
+    if self.version(key.clone())? != expected_version {
+      return Err(Errors::VersionMismatch);
+    }
+    let (version, _) = self.write_non_txn(
+      &write_lock,
+      &key,
+      self.encode_value(&value),
+      LogRecordType::Normal,
+      None,
+    )?;
+    drop(write_lock);
+    self.sync_appended()?;
+    Ok(version)
+  }
+
+  fn check_write_batch(&self) -> Result<()> {
+    if self.is_read_only() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+
+    if self.options.index_type == IndexType::BPlusTree && !self.seq_file_exists && !self.is_initial
+    {
+      return Err(Errors::UnableToUseWriteBatch);
+    }
+    Ok(())
+  }
 }
 
 impl WriteBatch<'_> {
   /// batch put data
   pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
-    if key.is_empty() {
-      return Err(Errors::KeyIsEmpty);
-    }
+    self.engine.check_key_value(&key, &value)?;
 
     // pending write
     let record = LogRecord {
       key: key.to_vec(),
-      value: value.to_vec(),
+      value: self.engine.encode_value(&value),
       rec_type: LogRecordType::Normal,
     };
 
@@ -66,7 +146,7 @@ impl WriteBatch<'_> {
 
     let mut pending_writes = self.pending_writes.lock();
     // if data not exist, just return
-    let index_pos = self.engine.index.get(key.to_vec());
+    let index_pos = self.engine.index.read().get(key.to_vec());
     if index_pos.is_none() {
       if pending_writes.contains_key(&key.to_vec()) {
         pending_writes.remove(&key.to_vec());
@@ -86,8 +166,18 @@ impl WriteBatch<'_> {
 
   /// commit the batch write to data file, and update index
   pub fn commit(&self) -> Result<()> {
+    self.commit_until(None)
+  }
+
+  /// commit like `commit`, but return `Errors::LockTimeout` if the commit lock or the
+  /// active data file can't be acquired within timeout
+  pub fn try_commit(&self, timeout: Duration) -> Result<()> {
+    self.commit_until(Some(Instant::now() + timeout))
+  }
+
+  fn commit_until(&self, deadline: Option<Instant>) -> Result<()> {
     let mut pending_writes = self.pending_writes.lock();
-    if pending_writes.len() == 0 {
+    if pending_writes.is_empty() {
       return Ok(());
     }
     if pending_writes.len() > self.options.max_batch_num {
@@ -95,42 +185,80 @@ impl WriteBatch<'_> {
     }
 
     // mutex lock the engine to ensure serial write
-    let _lock = self.engine.batch_commit_lock.lock();
+    let commit_lock = match deadline {
+      Some(deadline) => self
+        .engine
+        .batch_commit_lock
+        .try_lock_until(deadline)
+        .ok_or(Errors::LockTimeout)?,
+      None => self.engine.batch_commit_lock.lock(),
+    };
 
-    // obtain txn id
+    // append only batches must put keys after the greatest one written, held until the index
+    // is updated so puts can't write a greater key in between
+    let mut last_key = match self.engine.options.append_only {
+      true => Some(self.engine.last_key.lock()),
+      false => None,
+    };
+    let mut batch_last = None;
+    if let Some(last_key) = &last_key {
+      let batch_keys = pending_writes
+        .values()
+        .filter(|record| record.rec_type == LogRecordType::Normal)
+        .map(|record| &record.key);
+      if let (Some(last), Some(first)) = (last_key.as_deref(), batch_keys.clone().min()) {
+        if first.as_slice() <= last {
+          return Err(Errors::KeyNotAscending);
+        }
+      }
+      batch_last = batch_keys.max().cloned();
+    }
+
+    // other writes wait until the index is updated, so the version of the batch is ordered
+    // like the writes take effect
+    let write_lock = self.engine.lock_writes(deadline)?;
+
+    // obtain txn id, and the version shared by the records of the batch
     let seq_no = self.engine.seq_no.fetch_add(1, Ordering::SeqCst);
+    let version = self.engine.next_version.fetch_add(1, Ordering::SeqCst);
 
     let mut positions = HashMap::new();
     // start write to data file
     for (_, item) in pending_writes.iter() {
       let mut record = LogRecord {
-        key: log_record_key_with_seq(item.key.clone(), seq_no),
+        key: log_record_key_with_version(item.key.clone(), seq_no, version)?,
         value: item.value.clone(),
         rec_type: item.rec_type,
       };
 
-      let pos = self.engine.append_log_record(&mut record)?;
+      // records are synced together after the finish record
+      let pos = self
+        .engine
+        .append_log_record_unsynced_until(&mut record, deadline)?;
       positions.insert(item.key.clone(), pos);
     }
 
     // last write txn finished record
     let mut finish_record = LogRecord {
-      key: log_record_key_with_seq(TXN_FIN_KEY.to_vec(), seq_no),
+      key: log_record_key_with_seq(TXN_FIN_KEY.to_vec(), seq_no)?,
       value: Default::default(),
       rec_type: LogRecordType::TxnFinished,
     };
 
-    // if sync writes configs, sync data file
-    self.engine.append_log_record(&mut finish_record)?;
-    if self.options.sync_writes {
-      self.engine.sync()?;
-    }
+    self
+      .engine
+      .append_log_record_unsynced_until(&mut finish_record, deadline)?;
 
     // after write, update index
     for (_, item) in pending_writes.iter() {
       let record_pos = positions.get(&item.key).unwrap();
       if item.rec_type == LogRecordType::Normal {
-        if let Some(old_pos) = self.engine.index.put(item.key.clone(), *record_pos) {
+        if let Some(old_pos) = self
+          .engine
+          .index
+          .read()
+          .put(item.key.clone(), *record_pos)?
+        {
           self
             .engine
             .reclaim_size
@@ -138,7 +266,7 @@ impl WriteBatch<'_> {
         }
       }
       if item.rec_type == LogRecordType::Deleted {
-        if let Some(old_pos) = self.engine.index.delete(item.key.clone()) {
+        if let Some(old_pos) = self.engine.index.read().delete(item.key.clone())? {
           self
             .engine
             .reclaim_size
@@ -147,34 +275,79 @@ impl WriteBatch<'_> {
       }
     }
 
+    if let (Some(last_key), Some(batch_last)) = (last_key.as_mut(), batch_last) {
+      **last_key = Some(batch_last);
+    }
+
     // clear pending writes for next commit
     pending_writes.clear();
-
-    Ok(())
+    drop(write_lock);
+    drop(last_key);
+    drop(commit_lock);
+
+    // sync the whole batch once, if the batch or the engine configs ask for it. Like a put,
+    // it's synced outside the write lock so concurrent writers share the sync
+    match self.options.sync_writes {
+      true => self.engine.sync_appended_now(),
+      false => self.engine.sync_appended(),
+    }
   }
 }
 
 // encode log record key with sequence number
-pub(crate) fn log_record_key_with_seq(key: Vec<u8>, seq_no: usize) -> Vec<u8> {
+pub(crate) fn log_record_key_with_seq(key: Vec<u8>, seq_no: usize) -> Result<Vec<u8>> {
   let mut enc_key = BytesMut::new();
-  encode_length_delimiter(seq_no, &mut enc_key).unwrap();
+  encode_length_delimiter(seq_no, &mut enc_key).map_err(|_| Errors::FailedToWriteToDataFile)?;
   enc_key.extend_from_slice(&key.to_vec());
-  enc_key.to_vec()
+  Ok(enc_key.to_vec())
+}
+
+// encode log record key with sequence number and the version of the write, see
+// `Engine::version`
+pub(crate) fn log_record_key_with_version(
+  key: Vec<u8>,
+  seq_no: usize,
+  version: u64,
+) -> Result<Vec<u8>> {
+  let mut enc_key = BytesMut::new();
+  enc_key.extend_from_slice(&VERSIONED_KEY_MARKER);
+  encode_length_delimiter(seq_no, &mut enc_key).map_err(|_| Errors::FailedToWriteToDataFile)?;
+  encode_varint(version, &mut enc_key);
+  enc_key.extend_from_slice(&key);
+  Ok(enc_key.to_vec())
 }
 
-// decode log record key and return key and sequence number
-pub(crate) fn parse_log_record_key(key: Vec<u8>) -> (Vec<u8>, usize) {
+/// decode log record key and return key and sequence number, `NON_TXN_SEQ_NO` for writes
+/// outside a write batch. A malformed sequence number means the data file is corrupted
+pub fn parse_log_record_key(key: Vec<u8>) -> Result<(Vec<u8>, usize)> {
+  let (key, seq_no, _) = parse_log_record_key_version(key)?;
+  Ok((key, seq_no))
+}
+
+/// decode log record key like `parse_log_record_key`, along with the version of the write.
+/// Records written before versions were stamped have version 0
+pub fn parse_log_record_key_version(key: Vec<u8>) -> Result<(Vec<u8>, usize, u64)> {
   let mut buf = BytesMut::new();
-  buf.put_slice(&key);
-  let seq_no = decode_length_delimiter(&mut buf).unwrap();
-  (buf.to_vec(), seq_no)
+  let versioned = key.starts_with(&VERSIONED_KEY_MARKER);
+  match versioned {
+    true => buf.put_slice(&key[VERSIONED_KEY_MARKER.len()..]),
+    false => buf.put_slice(&key),
+  }
+  let seq_no = decode_length_delimiter(&mut buf).map_err(|_| Errors::DatabaseDirectoryCorrupted)?;
+  let version = match versioned {
+    true => decode_varint(&mut buf).map_err(|_| Errors::DatabaseDirectoryCorrupted)?,
+    false => 0,
+  };
+  Ok((buf.to_vec(), seq_no, version))
 }
 
 #[cfg(test)]
 mod tests {
-  use std::path::PathBuf;
+  use std::{path::PathBuf, sync::atomic::AtomicUsize};
 
   use crate::{
+    data::data_file::get_data_file_name,
+    fio::{file_io::FileIO, hooked::HookedIO},
     option::Options,
     util::rand_kv::{get_test_key, get_test_value},
   };
@@ -276,4 +449,283 @@ mod tests {
     // delete tested files
     std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
   }
+
+  #[test]
+  fn test_shared_write_batch() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-batch-shared");
+    opt.data_file_size = 64 * 1024 * 1024; // 64MB
+    let engine = Arc::new(Engine::open(opt.clone()).expect("fail to open engine"));
+
+    let wb = Arc::new(
+      engine
+        .new_shared_write_batch(WriteBatchOptions::default())
+        .expect("fail to create write batch"),
+    );
+    // several threads stage into the same batch
+    let handles = (0..8)
+      .map(|t| {
+        let wb = wb.clone();
+        std::thread::spawn(move || {
+          for i in t * 100..(t + 1) * 100 {
+            wb.put(get_test_key(i), get_test_value(i)).unwrap();
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+    for handle in handles {
+      handle.join().unwrap();
+    }
+    // nothing is visible before the commit
+    assert_eq!(0, engine.list_keys().unwrap().len());
+
+    let seq_no = engine.seq_no.load(Ordering::SeqCst);
+    wb.commit().unwrap();
+    assert_eq!(seq_no + 1, engine.seq_no.load(Ordering::SeqCst));
+    assert_eq!(800, engine.list_keys().unwrap().len());
+    for i in 0..800 {
+      assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+    }
+
+    // delete tested files
+    std::mem::drop(wb);
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+  }
+
+  #[test]
+  fn test_write_batch_single_sync() {
+    for (engine_sync, batch_sync) in [(true, true), (true, false), (false, true), (false, false)] {
+      let mut opt = Options::default();
+      opt.dir_path = PathBuf::from(format!(
+        "/tmp/bitkv-rs-batch-single-sync-{}-{}",
+        engine_sync, batch_sync
+      ));
+      opt.sync_writes = engine_sync;
+      let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+      let syncs = Arc::new(AtomicUsize::new(0));
+      let file_name = get_data_file_name(&opt.dir_path, 0, &opt.file_suffix);
+      engine.active_data_file.write().replace_io_manager(Box::new(
+        HookedIO::new(Box::new(FileIO::new(&file_name).unwrap())).on_synced({
+          let syncs = syncs.clone();
+          move |_| {
+            syncs.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+          }
+        }),
+      ));
+
+      let wb = engine
+        .new_write_batch(WriteBatchOptions {
+          sync_writes: batch_sync,
+          ..Default::default()
+        })
+        .expect("fail to create write batch");
+      for i in 0..10 {
+        wb.put(get_test_key(i), get_test_value(i)).unwrap();
+      }
+      wb.commit().unwrap();
+
+      // one sync for the whole batch, not one per record
+      let expected = match engine_sync || batch_sync {
+        true => 1,
+        false => 0,
+      };
+      assert_eq!(expected, syncs.load(Ordering::SeqCst));
+      assert_eq!(10, engine.list_keys().unwrap().len());
+
+      std::mem::drop(wb);
+      std::mem::drop(engine);
+      std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove path");
+    }
+  }
+
+  #[test]
+  fn test_write_batch_sync_unlocked() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-batch-sync-unlocked");
+    let engine = Arc::new(Engine::open(opt.clone()).expect("fail to open engine"));
+
+    // other writers can go on while the batch is synced
+    let write_unlocked = Arc::new(Mutex::new(Vec::new()));
+    let file_name = get_data_file_name(&opt.dir_path, 0, &opt.file_suffix);
+    engine.active_data_file.write().replace_io_manager(Box::new(
+      HookedIO::new(Box::new(FileIO::new(&file_name).unwrap())).on_synced({
+        let engine = Arc::downgrade(&engine);
+        let write_unlocked = write_unlocked.clone();
+        move |_| {
+          if let Some(engine) = engine.upgrade() {
+            let unlocked = engine.lock_writes(Some(Instant::now())).is_ok();
+            write_unlocked.lock().push(unlocked);
+          }
+          Ok(())
+        }
+      }),
+    ));
+
+    let wb = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .expect("fail to create write batch");
+    for i in 0..10 {
+      wb.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    wb.commit().unwrap();
+    assert_eq!(vec![true], *write_unlocked.lock());
+
+    std::mem::drop(wb);
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_put_with_version() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-put-with-version");
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+    // first write expects version 0
+    assert_eq!(0, engine.version(get_test_key(1)).unwrap());
+    let v1 = engine
+      .put_with_version(get_test_key(1), get_test_value(1), 0)
+      .unwrap();
+    assert!(v1 > 0);
+    assert_eq!(v1, engine.version(get_test_key(1)).unwrap());
+    assert_eq!(
+      Errors::VersionMismatch,
+      engine
+        .put_with_version(get_test_key(1), get_test_value(2), 0)
+        .unwrap_err()
+    );
+
+    // matching version writes and bumps the version
+    let v2 = engine
+      .put_with_version(get_test_key(1), get_test_value(2), v1)
+      .unwrap();
+    assert!(v2 > v1);
+    assert_eq!(get_test_value(2), engine.get(get_test_key(1)).unwrap());
+
+    // stale version is rejected and the value is kept
+    assert_eq!(
+      Errors::VersionMismatch,
+      engine
+        .put_with_version(get_test_key(1), get_test_value(3), v1)
+        .unwrap_err()
+    );
+    assert_eq!(get_test_value(2), engine.get(get_test_key(1)).unwrap());
+
+    // every write is stamped with a greater version, a batch commit too
+    let wb = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .expect("fail to create write batch");
+    wb.put(get_test_key(2), get_test_value(2)).unwrap();
+    wb.commit().unwrap();
+    let v3 = engine.version(get_test_key(2)).unwrap();
+    assert!(v3 > v2);
+    assert_eq!(v2, engine.version(get_test_key(1)).unwrap());
+    engine.put(get_test_key(1), get_test_value(3)).unwrap();
+    let v4 = engine.version(get_test_key(1)).unwrap();
+    assert!(v4 > v3);
+    assert_eq!(
+      Errors::VersionMismatch,
+      engine
+        .put_with_version(get_test_key(1), get_test_value(4), v2)
+        .unwrap_err()
+    );
+
+    // deleted key is absent again
+    engine.delete(get_test_key(2)).unwrap();
+    assert_eq!(0, engine.version(get_test_key(2)).unwrap());
+    let v5 = engine
+      .put_with_version(get_test_key(2), get_test_value(5), 0)
+      .unwrap();
+    assert!(v5 > v4);
+
+    // versions survive a restart and a merge
+    std::mem::drop(wb);
+    std::mem::drop(engine);
+    let mut merge_opt = opt.clone();
+    merge_opt.file_merge_threshold = 0.0;
+    let engine2 = Engine::open(merge_opt.clone()).expect("fail to open engine");
+    assert_eq!(v4, engine2.version(get_test_key(1)).unwrap());
+    engine2.merge().unwrap();
+    std::mem::drop(engine2);
+    let engine3 = Engine::open(merge_opt).expect("fail to open engine");
+    assert_eq!(v4, engine3.version(get_test_key(1)).unwrap());
+    assert_eq!(v5, engine3.version(get_test_key(2)).unwrap());
+    let v6 = engine3
+      .put_with_version(get_test_key(1), get_test_value(6), v4)
+      .unwrap();
+    assert!(v6 > v5);
+    std::mem::drop(engine3);
+
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_put_with_version_concurrent() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-put-with-version-concurrent");
+    let engine = Arc::new(Engine::open(opt.clone()).expect("fail to open engine"));
+    let v1 = engine
+      .put_with_version(get_test_key(1), get_test_value(1), 0)
+      .unwrap();
+
+    // plain puts race the versioned writes expecting v1, at most one of them wins and only
+    // before any plain put took effect
+    let handles = (0..8)
+      .map(|t| {
+        let engine = engine.clone();
+        std::thread::spawn(move || match t % 2 {
+          0 => {
+            engine.put(get_test_key(1), get_test_value(t)).unwrap();
+            false
+          }
+          _ => engine
+            .put_with_version(get_test_key(1), get_test_value(t), v1)
+            .is_ok(),
+        })
+      })
+      .collect::<Vec<_>>();
+    let wins = handles
+      .into_iter()
+      .map(|handle| handle.join().unwrap())
+      .filter(|won| *won)
+      .count();
+    assert!(wins <= 1);
+    assert!(engine.version(get_test_key(1)).unwrap() > v1);
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_parse_log_record_key() {
+    let enc_key = log_record_key_with_seq(get_test_key(1).to_vec(), 300).unwrap();
+    let (key, seq_no) = parse_log_record_key(enc_key).unwrap();
+    assert_eq!(get_test_key(1).to_vec(), key);
+    assert_eq!(300, seq_no);
+
+    // truncated varint seq_no, continuation bit set but no more bytes
+    let res1 = parse_log_record_key(vec![0x80]);
+    assert_eq!(Errors::DatabaseDirectoryCorrupted, res1.err().unwrap());
+
+    // empty key
+    let res2 = parse_log_record_key(Vec::new());
+    assert_eq!(Errors::DatabaseDirectoryCorrupted, res2.err().unwrap());
+
+    // versioned key, keys written before versions have version 0
+    let enc_key = log_record_key_with_version(get_test_key(1).to_vec(), 300, 1 << 40).unwrap();
+    let (key, seq_no, version) = parse_log_record_key_version(enc_key.clone()).unwrap();
+    assert_eq!(get_test_key(1).to_vec(), key);
+    assert_eq!((300, 1 << 40), (seq_no, version));
+    assert_eq!((key, 300), parse_log_record_key(enc_key).unwrap());
+    let enc_key = log_record_key_with_seq(get_test_key(1).to_vec(), 128).unwrap();
+    let (_, seq_no, version) = parse_log_record_key_version(enc_key).unwrap();
+    assert_eq!((128, 0), (seq_no, version));
+
+    // truncated version
+    let res3 = parse_log_record_key_version(vec![0x80, 0x00, 0x00, 0x80]);
+    assert_eq!(Errors::DatabaseDirectoryCorrupted, res3.err().unwrap());
+  }
 }
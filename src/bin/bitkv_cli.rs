@@ -0,0 +1,158 @@
+use std::{
+  env,
+  error::Error,
+  io::{self, Write},
+  path::PathBuf,
+  process::ExitCode,
+};
+
+use bitkv_rs::{
+  db::Engine,
+  option::{IndexType, IteratorOptions, Options},
+};
+use bytes::Bytes;
+
+const USAGE: &str =
+  "usage: bitkv-cli --dir <path> [--index-type <type>] [--file-suffix <suffix>] <command>
+
+options, as the database was created with:
+  --index-type <type>    btree, skiplist or bptree, btree by default
+  --file-suffix <suffix> data file name suffix, .data by default
+
+commands:
+  get <key>              print the value of key
+  put <key> <value>      store value under key
+  delete <key>           remove key
+  scan [--prefix <p>]    print `key<TAB>value` for every key, in key order. Tabs, newlines,
+                         carriage returns and backslashes are escaped as \\t, \\n, \\r and \\\\
+  merge                  reclaim space, takes effect on the next open
+  stat                   print `name<TAB>value` engine statistics";
+
+fn main() -> ExitCode {
+  match run(env::args().skip(1).collect()) {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(e) => {
+      eprintln!("{}", e);
+      ExitCode::FAILURE
+    }
+  }
+}
+
+fn run(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+  let (opts, args) = parse_options(args)?;
+  let args: Vec<&str> = args.iter().map(String::as_str).collect();
+  let mut stdout = io::stdout().lock();
+
+  match args.as_slice() {
+    ["get", key] => {
+      let engine = open_read_only(opts)?;
+      let value = engine.get(Bytes::copy_from_slice(key.as_bytes()))?;
+      stdout.write_all(&value)?;
+      stdout.write_all(b"\n")?;
+    }
+    ["put", key, value] => {
+      let engine = open(opts)?;
+      engine.put(
+        Bytes::copy_from_slice(key.as_bytes()),
+        Bytes::copy_from_slice(value.as_bytes()),
+      )?;
+      engine.close()?;
+    }
+    ["delete", key] => {
+      let engine = open(opts)?;
+      engine.delete(Bytes::copy_from_slice(key.as_bytes()))?;
+      engine.close()?;
+    }
+    ["scan", rest @ ..] => {
+      let prefix = match rest {
+        [] => Vec::new(),
+        ["--prefix", prefix] => prefix.as_bytes().to_vec(),
+        _ => return Err(USAGE.into()),
+      };
+      let engine = open_read_only(opts)?;
+      let iter = engine.iter(IteratorOptions {
+        prefix,
+        ..Default::default()
+      })?;
+      while let Some((key, value)) = iter.next() {
+        write_escaped(&mut stdout, &key)?;
+        stdout.write_all(b"\t")?;
+        write_escaped(&mut stdout, &value)?;
+        stdout.write_all(b"\n")?;
+      }
+    }
+    ["merge"] => {
+      let engine = open(opts)?;
+      let report = engine.merge()?;
+      writeln!(stdout, "files_merged\t{}", report.files_merged)?;
+      writeln!(stdout, "records_kept\t{}", report.records_kept)?;
+      writeln!(stdout, "records_dropped\t{}", report.records_dropped)?;
+      writeln!(stdout, "bytes_reclaimed\t{}", report.bytes_reclaimed)?;
+      engine.close()?;
+    }
+    ["stat"] => {
+      let engine = open_read_only(opts)?;
+      let stat = engine.get_engine_stat()?;
+      writeln!(stdout, "key_num\t{}", stat.key_num)?;
+      writeln!(stdout, "data_file_num\t{}", stat.data_file_num)?;
+      writeln!(stdout, "reclaim_size\t{}", stat.reclaim_size)?;
+      writeln!(stdout, "disk_size\t{}", stat.disk_size)?;
+    }
+    _ => return Err(USAGE.into()),
+  }
+  Ok(())
+}
+
+// take `--dir <path>` and the other options from anywhere in the arguments, the rest is the
+// command
+fn parse_options(args: Vec<String>) -> Result<(Options, Vec<String>), Box<dyn Error>> {
+  let mut opts = Options::default();
+  let mut dir_path = None;
+  let mut rest = Vec::new();
+  let mut args = args.into_iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--dir" => dir_path = Some(PathBuf::from(args.next().ok_or(USAGE)?)),
+      "--index-type" => {
+        opts.index_type = match args.next().ok_or(USAGE)?.as_str() {
+          "btree" => IndexType::BTree,
+          "skiplist" => IndexType::SkipList,
+          "bptree" => IndexType::BPlusTree,
+          _ => return Err(USAGE.into()),
+        }
+      }
+      "--file-suffix" => opts.file_suffix = args.next().ok_or(USAGE)?,
+      _ => rest.push(arg),
+    }
+  }
+  opts.dir_path = dir_path.ok_or(USAGE)?;
+  Ok((opts, rest))
+}
+
+// write bytes keeping a scanned record on one line, the bytes splitting the fields and lines
+// are escaped the way C string literals are
+fn write_escaped(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+  let mut start = 0;
+  for (i, b) in bytes.iter().enumerate() {
+    let escaped: &[u8] = match b {
+      b'\t' => b"\\t",
+      b'\n' => b"\\n",
+      b'\r' => b"\\r",
+      b'\\' => b"\\\\",
+      _ => continue,
+    };
+    out.write_all(&bytes[start..i])?;
+    out.write_all(escaped)?;
+    start = i + 1;
+  }
+  out.write_all(&bytes[start..])
+}
+
+fn open(opts: Options) -> Result<Engine, Box<dyn Error>> {
+  Ok(Engine::open(opts)?)
+}
+
+// commands only reading never write to the database, nor create it
+fn open_read_only(opts: Options) -> Result<Engine, Box<dyn Error>> {
+  Ok(Engine::open_read_only(opts)?)
+}
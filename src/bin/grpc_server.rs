@@ -0,0 +1,28 @@
+use std::{path::PathBuf, sync::Arc};
+
+use bitkv_rs::{db::Engine, grpc::BitkvService, option::Options};
+use tonic::transport::Server;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let engine = Arc::new(Engine::open(Options {
+    dir_path: PathBuf::from("/tmp/bitkv-rs-grpc"),
+    ..Default::default()
+  })?);
+
+  let addr = "127.0.0.1:50051".parse()?;
+  println!("gRPC server listening on {}", addr);
+
+  Server::builder()
+    .add_service(BitkvService::new(engine.clone()).into_server())
+    .serve_with_shutdown(addr, async {
+      let _ = tokio::signal::ctrl_c().await;
+      println!("Receive the Ctrl+C shutdown signal, the server starts to close ...");
+    })
+    .await?;
+
+  engine.close()?;
+  println!("engine is closed");
+
+  Ok(())
+}
@@ -0,0 +1,108 @@
+//! Value codecs, transforms applied to values on write and reversed on read
+
+use crate::errors::{Errors, Result};
+
+/// Transform applied to every value before it's written to a data file, and reversed when it's
+/// read back. Set with `Options::value_codec`, the same codec must be used every time the
+/// database is opened.
+pub trait ValueCodec: Send + Sync {
+  /// encode a value before it's written
+  fn encode(&self, value: &[u8]) -> Vec<u8>;
+
+  /// decode a value read from a data file
+  fn decode(&self, value: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Stores values as is
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IdentityCodec;
+
+impl ValueCodec for IdentityCodec {
+  fn encode(&self, value: &[u8]) -> Vec<u8> {
+    value.to_vec()
+  }
+
+  fn decode(&self, value: &[u8]) -> Result<Vec<u8>> {
+    Ok(value.to_vec())
+  }
+}
+
+/// Compresses values with lz4, the uncompressed size is prepended
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lz4Codec;
+
+impl ValueCodec for Lz4Codec {
+  fn encode(&self, value: &[u8]) -> Vec<u8> {
+    lz4_flex::compress_prepend_size(value)
+  }
+
+  fn decode(&self, value: &[u8]) -> Result<Vec<u8>> {
+    lz4_flex::decompress_size_prepended(value).map_err(|_| Errors::ValueCodecFailed)
+  }
+}
+
+/// XORs values with a repeating key. Only a demo of the extension point, it's not encryption.
+#[derive(Debug, Clone)]
+pub struct XorCodec {
+  key: Vec<u8>,
+}
+
+impl XorCodec {
+  /// create a codec XORing with key, an empty key leaves values as is
+  pub fn new(key: impl Into<Vec<u8>>) -> Self {
+    Self { key: key.into() }
+  }
+
+  fn apply(&self, value: &[u8]) -> Vec<u8> {
+    if self.key.is_empty() {
+      return value.to_vec();
+    }
+    value
+      .iter()
+      .zip(self.key.iter().cycle())
+      .map(|(b, k)| b ^ k)
+      .collect()
+  }
+}
+
+impl ValueCodec for XorCodec {
+  fn encode(&self, value: &[u8]) -> Vec<u8> {
+    self.apply(value)
+  }
+
+  fn decode(&self, value: &[u8]) -> Result<Vec<u8>> {
+    Ok(self.apply(value))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_builtin_codecs_round_trip() {
+    let value = b"bitkv-rs value bitkv-rs value bitkv-rs value".to_vec();
+    let codecs: Vec<Box<dyn ValueCodec>> = vec![
+      Box::new(IdentityCodec),
+      Box::new(Lz4Codec),
+      Box::new(XorCodec::new("key")),
+    ];
+    for codec in codecs {
+      let encoded = codec.encode(&value);
+      assert_eq!(value, codec.decode(&encoded).unwrap());
+      assert_eq!(Vec::<u8>::new(), codec.decode(&codec.encode(&[])).unwrap());
+    }
+
+    assert_ne!(value, XorCodec::new("key").encode(&value));
+    assert_eq!(value, XorCodec::new("").encode(&value));
+  }
+
+  #[test]
+  fn test_lz4_codec_corrupted() {
+    let encoded = Lz4Codec.encode(b"value");
+    assert_eq!(
+      Err(Errors::ValueCodecFailed),
+      Lz4Codec.decode(&encoded[..encoded.len() - 2])
+    );
+  }
+}
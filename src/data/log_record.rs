@@ -1,10 +1,26 @@
+use alloc::vec::Vec;
 use bytes::{BufMut, BytesMut};
+#[cfg(any(feature = "std", test))]
+use prost::encoding::decode_varint;
 use prost::{
-  encode_length_delimiter,
-  encoding::{decode_varint, encode_varint},
-  length_delimiter_len,
+  decode_length_delimiter, encode_length_delimiter, encoding::encode_varint, length_delimiter_len,
 };
 
+use crate::{
+  errors::{Errors, Result},
+  option::CrcScope,
+};
+
+// max encoded log record length, its size is kept as u32 in LogRecordPos
+#[cfg(feature = "std")]
+pub const MAX_LOG_RECORD_SIZE: usize = u32::MAX as usize;
+
+// set in the type byte of a record whose crc covers the value only
+const CRC_VALUE_ONLY_FLAG: u8 = 0x80;
+
+// type byte and two u32 varints, equals `max_log_record_header_size()`
+pub(crate) const LOG_RECORD_HEADER_BUF_SIZE: usize = 1 + 5 * 2;
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum LogRecordType {
   // normal putting data
@@ -15,6 +31,10 @@ pub enum LogRecordType {
 
   // transaction finished
   TxnFinished = 3,
+
+  // live data whose value is in another record, the value is the encoded position of it.
+  // Written by merge for values stored once, see `Options::dedup_values`
+  ValueRef = 4,
 }
 // LogRecord write to data file record
 // for it is called log, data writes by appending to datafile, WAL format
@@ -34,6 +54,7 @@ pub struct LogRecordPos {
 }
 
 // read log_record info from data file, contains its size
+#[cfg(feature = "std")]
 #[derive(Debug)]
 pub struct ReadLogRecord {
   pub(crate) record: LogRecord,
@@ -41,12 +62,50 @@ pub struct ReadLogRecord {
 }
 
 // temp record for transaction
+#[cfg(feature = "std")]
 pub struct TransactionRecord {
   pub(crate) record: LogRecord,
   pub(crate) pos: LogRecordPos,
 }
 
 impl LogRecord {
+  pub fn new(key: Vec<u8>, value: Vec<u8>, rec_type: LogRecordType) -> Self {
+    Self {
+      key,
+      value,
+      rec_type,
+    }
+  }
+
+  /// decode the record at the start of buf and verify its crc, returns the record and its
+  /// encoded size. `Errors::ReadDataFileEOF` if buf ends before the record
+  pub fn decode(buf: &[u8]) -> Result<(LogRecord, usize)> {
+    // bytes past the end of buf read as zero, like past the end of a data file
+    let mut header_buf = [0u8; LOG_RECORD_HEADER_BUF_SIZE];
+    let n = buf.len().min(LOG_RECORD_HEADER_BUF_SIZE);
+    header_buf[..n].copy_from_slice(&buf[..n]);
+    let (type_byte, key_size, value_size, header_size) = decode_log_record_header(&header_buf)?;
+
+    let kv_end = header_size + key_size + value_size;
+    if buf.len() < kv_end + 4 {
+      return Err(Errors::ReadDataFileEOF);
+    }
+    let key = &buf[header_size..header_size + key_size];
+    let value = &buf[header_size + key_size..kv_end];
+    let mut crc_buf = [0u8; 4];
+    crc_buf.copy_from_slice(&buf[kv_end..kv_end + 4]);
+    if u32::from_be_bytes(crc_buf) != log_record_crc(&buf[..header_size], key, value) {
+      return Err(Errors::InvalidLogRecordCrc);
+    }
+
+    let record = LogRecord {
+      key: key.to_vec(),
+      value: value.to_vec(),
+      rec_type: rec_type_of(type_byte)?,
+    };
+    Ok((record, kv_end + 4))
+  }
+
   // Encode for log record, return bytes and its size
   // +----------+----------------+------------------+---------+-----------+---------+
   // |   Type   |   Key Length   |   Value Length   |   Key   |   Value   |   Crc   |
@@ -54,22 +113,51 @@ impl LogRecord {
   //  1bytes       n(n<=5) bytes     m(m<=5) bytes       x          y        4bytes
   //
   pub fn encode(&self) -> Vec<u8> {
-    let (encode_buf, _) = self.encode_and_get_crc();
+    self.encode_with_crc_scope(CrcScope::Full)
+  }
+
+  /// encode with the crc covering the given scope, which is marked in the type byte
+  pub fn encode_with_crc_scope(&self, crc_scope: CrcScope) -> Vec<u8> {
+    let (encode_buf, _) = self.encode_and_get_crc(crc_scope);
     encode_buf
   }
 
   pub fn get_crc(&self) -> u32 {
-    let (_, crc_val) = self.encode_and_get_crc();
+    let (_, crc_val) = self.encode_and_get_crc(CrcScope::Full);
     crc_val
   }
 
-  fn encode_and_get_crc(&self) -> (Vec<u8>, u32) {
+  /// key as stored, prefixed with the varint seq_no, see `batch::parse_log_record_key`
+  pub fn key(&self) -> &[u8] {
+    &self.key
+  }
+
+  /// value as stored, empty for tombstones and transaction finished records
+  pub fn value(&self) -> &[u8] {
+    &self.value
+  }
+
+  pub fn rec_type(&self) -> LogRecordType {
+    self.rec_type
+  }
+
+  // check the record can be encoded and read back, must be called before writing it
+  #[cfg(feature = "std")]
+  pub(crate) fn check_size(&self) -> Result<()> {
+    check_log_record_size(self.key.len(), self.value.len())
+  }
+
+  fn encode_and_get_crc(&self, crc_scope: CrcScope) -> (Vec<u8>, u32) {
     // init bytes array, store encoded log record
     let mut buf = BytesMut::new();
     buf.reserve(self.encoded_length());
 
     // write log record type into buffer
-    buf.put_u8(self.rec_type as u8);
+    let type_byte = match crc_scope {
+      CrcScope::Full => self.rec_type as u8,
+      CrcScope::ValueOnly => self.rec_type as u8 | CRC_VALUE_ONLY_FLAG,
+    };
+    buf.put_u8(type_byte);
 
     // write key length and value length into buffer
     encode_length_delimiter(self.key.len(), &mut buf).unwrap();
@@ -81,9 +169,10 @@ impl LogRecord {
     buf.extend_from_slice(&self.value);
 
     // write crc32 checksum into buffer
-    let mut hasher = crc32fast::Hasher::new();
-    hasher.update(&buf);
-    let crc = hasher.finalize();
+    let crc = match crc_scope {
+      CrcScope::Full => crc32fast::hash(&buf),
+      CrcScope::ValueOnly => crc32fast::hash(&self.value),
+    };
     buf.put_u32(crc);
 
     (buf.to_vec(), crc)
@@ -91,7 +180,7 @@ impl LogRecord {
 
   // get encoded log record length
   fn encoded_length(&self) -> usize {
-    std::mem::size_of::<u8>()
+    core::mem::size_of::<u8>()
       + length_delimiter_len(self.key.len())
       + length_delimiter_len(self.value.len())
       + self.key.len()
@@ -101,6 +190,26 @@ impl LogRecord {
 }
 
 impl LogRecordPos {
+  pub fn new(file_id: u32, offset: u64, size: u32) -> Self {
+    Self {
+      file_id,
+      offset,
+      size,
+    }
+  }
+
+  pub fn file_id(&self) -> u32 {
+    self.file_id
+  }
+
+  pub fn offset(&self) -> u64 {
+    self.offset
+  }
+
+  pub fn size(&self) -> u32 {
+    self.size
+  }
+
   pub fn encode(&self) -> Vec<u8> {
     let mut buf = BytesMut::new();
     encode_varint(self.file_id as u64, &mut buf);
@@ -111,22 +220,90 @@ impl LogRecordPos {
 }
 
 impl LogRecordType {
-  pub fn from_u8(value: u8) -> Self {
+  // a type byte of a corrupt or newer format record is an error
+  pub fn from_u8(value: u8) -> Result<Self> {
     match value {
-      1 => LogRecordType::Normal,
-      2 => LogRecordType::Deleted,
-      3 => LogRecordType::TxnFinished,
-      _ => panic!("unsupported log record type"),
+      1 => Ok(LogRecordType::Normal),
+      2 => Ok(LogRecordType::Deleted),
+      3 => Ok(LogRecordType::TxnFinished),
+      4 => Ok(LogRecordType::ValueRef),
+      found => Err(Errors::InvalidLogRecordType { found }),
     }
   }
 }
 
+// crc scope marked in a stored type byte
+pub(crate) fn crc_scope_of(type_byte: u8) -> CrcScope {
+  match type_byte & CRC_VALUE_ONLY_FLAG {
+    0 => CrcScope::Full,
+    _ => CrcScope::ValueOnly,
+  }
+}
+
+// record type of a stored type byte, whatever its crc scope
+pub(crate) fn rec_type_of(type_byte: u8) -> Result<LogRecordType> {
+  LogRecordType::from_u8(type_byte & !CRC_VALUE_ONLY_FLAG)
+}
+
+/// decode the header of a log record, returns its type byte, key size, value size and header
+/// size. A zeroed header, as past the last record of a data file, is `Errors::ReadDataFileEOF`
+pub(crate) fn decode_log_record_header(
+  header_buf: &[u8; LOG_RECORD_HEADER_BUF_SIZE],
+) -> Result<(u8, usize, usize, usize)> {
+  let mut header = &header_buf[1..];
+  let key_size = decode_length_delimiter(&mut header).map_err(|_| Errors::InvalidLogRecordCrc)?;
+  let value_size = decode_length_delimiter(&mut header).map_err(|_| Errors::InvalidLogRecordCrc)?;
+  if key_size == 0 && value_size == 0 {
+    return Err(Errors::ReadDataFileEOF);
+  }
+  let header_size = length_delimiter_len(key_size) + length_delimiter_len(value_size) + 1;
+  Ok((header_buf[0], key_size, value_size, header_size))
+}
+
+// crc of a record from its encoded header, key and value, covering what its type byte marks
+pub(crate) fn log_record_crc(header: &[u8], key: &[u8], value: &[u8]) -> u32 {
+  let mut hasher = crc32fast::Hasher::new();
+  match crc_scope_of(header[0]) {
+    CrcScope::Full => {
+      hasher.update(header);
+      hasher.update(key);
+      hasher.update(value);
+    }
+    CrcScope::ValueOnly => hasher.update(value),
+  }
+  hasher.finalize()
+}
+
+// key and value lengths must fit in the u32 varints assumed by `max_log_record_header_size`,
+// and the whole record in `MAX_LOG_RECORD_SIZE`
+#[cfg(feature = "std")]
+pub(crate) fn check_log_record_size(key_len: usize, value_len: usize) -> Result<()> {
+  if key_len > u32::MAX as usize {
+    return Err(Errors::KeyTooLarge);
+  }
+  if value_len > u32::MAX as usize {
+    return Err(Errors::ValueTooLarge);
+  }
+  let size = core::mem::size_of::<u8>()
+    + length_delimiter_len(key_len)
+    + length_delimiter_len(value_len)
+    + key_len
+    + value_len
+    + 4;
+  if size > MAX_LOG_RECORD_SIZE {
+    return Err(Errors::LogRecordTooLarge);
+  }
+  Ok(())
+}
+
 // get max log record header length
+#[cfg(feature = "std")]
 pub fn max_log_record_header_size() -> usize {
-  std::mem::size_of::<u8>() + length_delimiter_len(std::u32::MAX as usize) * 2
+  core::mem::size_of::<u8>() + length_delimiter_len(u32::MAX as usize) * 2
 }
 
-// decode LogRecordPos
+// decode LogRecordPos, the `no_std` tests check it against `LogRecordPos::encode`
+#[cfg(any(feature = "std", test))]
 pub fn decode_log_record_pos(pos: Vec<u8>) -> LogRecordPos {
   let mut buf = BytesMut::new();
   buf.put_slice(&pos);
@@ -153,6 +330,8 @@ pub fn decode_log_record_pos(pos: Vec<u8>) -> LogRecordPos {
 #[cfg(test)]
 mod tests {
   use super::*;
+  #[cfg(feature = "std")]
+  use crate::option::{KEY_SIZE_HARD_LIMIT, VALUE_SIZE_HARD_LIMIT};
 
   #[test]
   fn test_log_record_encode_and_get_crc() {
@@ -186,4 +365,97 @@ mod tests {
     assert!(enc3.len() > 5);
     assert_eq!(2488525827, rec3.get_crc());
   }
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn test_check_log_record_size() {
+    // lengths only, nothing this large is allocated
+    assert!(check_log_record_size(64, 1024).is_ok());
+    assert_eq!(
+      Errors::KeyTooLarge,
+      check_log_record_size(u32::MAX as usize + 1, 0).unwrap_err()
+    );
+    assert_eq!(
+      Errors::ValueTooLarge,
+      check_log_record_size(1, u32::MAX as usize + 1).unwrap_err()
+    );
+    assert_eq!(
+      Errors::LogRecordTooLarge,
+      check_log_record_size(KEY_SIZE_HARD_LIMIT, VALUE_SIZE_HARD_LIMIT).unwrap_err()
+    );
+    assert_eq!(
+      Errors::LogRecordTooLarge,
+      check_log_record_size(1, u32::MAX as usize - 10).unwrap_err()
+    );
+
+    // largest record that fits
+    let max_value_len = MAX_LOG_RECORD_SIZE - 1 - 1 - 5 - 1 - 4;
+    assert!(check_log_record_size(1, max_value_len).is_ok());
+    assert!(check_log_record_size(1, max_value_len + 1).is_err());
+
+    let rec = LogRecord {
+      key: "key-a".as_bytes().to_vec(),
+      value: "value-a".as_bytes().to_vec(),
+      rec_type: LogRecordType::Normal,
+    };
+    assert!(rec.check_size().is_ok());
+  }
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn test_max_log_record_header_size() {
+    // key prefix, a 2 byte marker with the varint seq_no and version, and max allowed key
+    // length fit into the header's key length varint
+    let max_key_len = KEY_SIZE_HARD_LIMIT + 2 + length_delimiter_len(usize::MAX) * 2;
+    assert!(max_key_len <= u32::MAX as usize);
+    assert!(length_delimiter_len(max_key_len) <= length_delimiter_len(u32::MAX as usize));
+
+    // max allowed value length fits into the header's value length varint
+    assert!(length_delimiter_len(VALUE_SIZE_HARD_LIMIT) <= length_delimiter_len(u32::MAX as usize));
+
+    // type byte plus two varints
+    assert_eq!(
+      max_log_record_header_size(),
+      1 + length_delimiter_len(max_key_len) + length_delimiter_len(VALUE_SIZE_HARD_LIMIT)
+    );
+  }
+
+  #[test]
+  fn test_log_record_type_from_u8() {
+    assert_eq!(LogRecordType::Normal, LogRecordType::from_u8(1).unwrap());
+    assert_eq!(LogRecordType::Deleted, LogRecordType::from_u8(2).unwrap());
+    assert_eq!(
+      LogRecordType::TxnFinished,
+      LogRecordType::from_u8(3).unwrap()
+    );
+    assert_eq!(
+      Errors::InvalidLogRecordType { found: 99 },
+      LogRecordType::from_u8(99).unwrap_err()
+    );
+  }
+
+  #[test]
+  fn test_log_record_encode_crc_scope() {
+    let rec = LogRecord {
+      key: "key-a".as_bytes().to_vec(),
+      value: "value-a".as_bytes().to_vec(),
+      rec_type: LogRecordType::Deleted,
+    };
+    let full = rec.encode_with_crc_scope(CrcScope::Full);
+    assert_eq!(full, rec.encode());
+    assert_eq!(CrcScope::Full, crc_scope_of(full[0]));
+    assert_eq!(LogRecordType::Deleted, rec_type_of(full[0]).unwrap());
+
+    // same layout, the type byte is marked and the crc is the value's
+    let value_only = rec.encode_with_crc_scope(CrcScope::ValueOnly);
+    assert_eq!(full.len(), value_only.len());
+    assert_eq!(CrcScope::ValueOnly, crc_scope_of(value_only[0]));
+    assert_eq!(LogRecordType::Deleted, rec_type_of(value_only[0]).unwrap());
+    let crc_off = value_only.len() - 4;
+    assert_eq!(full[1..crc_off], value_only[1..crc_off]);
+    assert_eq!(
+      crc32fast::hash(b"value-a").to_be_bytes(),
+      value_only[crc_off..]
+    );
+  }
 }
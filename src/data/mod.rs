@@ -1,2 +1,3 @@
+#[cfg(feature = "std")]
 pub mod data_file;
 pub mod log_record;
@@ -1,34 +1,160 @@
 #![allow(clippy::redundant_closure)]
 use crate::{
-  batch::{log_record_key_with_seq, parse_log_record_key, NON_TXN_SEQ_NO},
+  batch::{
+    log_record_key_with_version, parse_log_record_key, parse_log_record_key_version, NON_TXN_SEQ_NO,
+  },
+  codec::ValueCodec,
   data::{
-    data_file::{DataFile, DATA_FILE_NAME_SUFFIX, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME},
-    log_record::{LogRecord, LogRecordPos, LogRecordType, TransactionRecord},
+    data_file::{
+      get_data_file_name, parse_data_file_name, DataFile, StoredValue, FILE_TIMES_FILE_NAME,
+      FILE_TIMES_TMP_FILE_NAME, HINT_FILE_NAME, MERGE_FINISHED_FILE_NAME, META_FILE_NAME,
+      META_TMP_FILE_NAME, SEQ_NO_FILE_NAME, SEQ_NO_TMP_FILE_NAME,
+    },
+    log_record::{
+      decode_log_record_pos, LogRecord, LogRecordPos, LogRecordType, TransactionRecord,
+    },
   },
   errors::{Errors, Result},
-  index,
-  merge::load_merge_files,
-  option::{IOManagerType, IndexType, Options},
+  fio::cached::FileCache,
+  index::{
+    self,
+    bptree::{BPlusTree, BPTREE_BUCKET_NAME, BPTREE_INDEX_FILE_NAME, BPTREE_SHRINK_FILE_NAME},
+    Indexer,
+  },
+  merge::{load_merge_files, load_merge_history, parse_merge_fin_value, MergeReport},
+  option::{
+    FlushPolicy, IOManagerType, IndexType, IteratorOptions, OpenProgress, Options, RecoveryMode,
+    GET_MANY_MAX_CHUNK_LEN, GET_MANY_MIN_CHUNK_LEN,
+  },
   util,
 };
 use bytes::Bytes;
 use fs2::FileExt;
 use log::{error, warn};
-use parking_lot::{Mutex, RwLock};
+use parking_lot::{Condvar, Mutex, MutexGuard, RwLock};
 use std::{
-  collections::HashMap,
-  fs::{self, File},
-  path::Path,
+  collections::{HashMap, HashSet, VecDeque},
+  fs::{self, File, OpenOptions},
+  path::{Path, PathBuf},
   sync::{
-    atomic::{AtomicUsize, Ordering},
-    Arc,
+    atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    Arc, OnceLock,
   },
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 const INITIAL_FILE_ID: u32 = 0;
 const SEQ_NO_KEY: &str = "seq.no";
+// seq_no file format version, value is
+// `version:seq_no:next_version:active_file_id:active_file_size`
+const SEQ_NO_FILE_VERSION: &str = "2";
+const META_KEY: &str = "meta";
+// meta file format version, value is `version:created_at:last_merge_at:total_bytes_written`,
+// times in unix millis and last_merge_at 0 if never merged. Version 1 has no byte counter
+const META_FILE_VERSION: &str = "2";
+// value of the file times record is `file_id:created_at,...` in unix millis
+const FILE_TIMES_KEY: &str = "file.times";
 pub(crate) const FILE_LOCK_NAME: &str = "flock";
 
+// database directories opened in this process, the file lock alone doesn't reliably exclude
+// a second open from the same process on every platform
+static OPEN_DIRS: OnceLock<Mutex<HashSet<PathBuf>>> = OnceLock::new();
+
+// registration of an open database directory, released on close or drop
+struct OpenDir {
+  path: PathBuf,
+  released: AtomicBool,
+}
+
+impl OpenDir {
+  // register dir_path, fail if it is already open in this process
+  fn register(dir_path: &Path) -> Result<Self> {
+    let path = fs::canonicalize(dir_path).map_err(|_| Errors::FailedToReadDatabaseDir)?;
+    let open_dirs = OPEN_DIRS.get_or_init(|| Mutex::new(HashSet::new()));
+    if !open_dirs.lock().insert(path.clone()) {
+      return Err(Errors::DatabaseIsUsing);
+    }
+    Ok(Self {
+      path,
+      released: AtomicBool::new(false),
+    })
+  }
+
+  fn release(&self) {
+    if !self.released.swap(true, Ordering::SeqCst) {
+      if let Some(open_dirs) = OPEN_DIRS.get() {
+        open_dirs.lock().remove(&self.path);
+      }
+    }
+  }
+}
+
+impl Drop for OpenDir {
+  fn drop(&mut self) {
+    self.release();
+  }
+}
+
+// shares active file syncs between concurrent `sync_writes` writers, a writer waits until the
+// bytes it appended are synced, by a sync it runs itself or one another writer ran meanwhile
+struct GroupCommit {
+  appended: AtomicU64, // bytes appended to the data files, advanced with the active file locked
+  state: Mutex<GroupCommitState>,
+  synced_cond: Condvar, // notified when a group sync finishes
+}
+
+struct GroupCommitState {
+  synced: u64,   // appended bytes known to be synced
+  syncing: bool, // a writer is syncing for the group
+}
+
+impl GroupCommit {
+  fn new() -> Self {
+    Self {
+      appended: AtomicU64::new(0),
+      state: Mutex::new(GroupCommitState {
+        synced: 0,
+        syncing: false,
+      }),
+      synced_cond: Condvar::new(),
+    }
+  }
+
+  // mark len bytes written to the active file, `sync_appended` waits for them
+  fn append(&self, len: u64) {
+    self.appended.fetch_add(len, Ordering::SeqCst);
+  }
+
+  // wait until bytes up to mark are synced, running sync for the group if no writer is
+  fn wait_synced<F>(&self, mark: u64, sync: F) -> Result<()>
+  where
+    F: FnOnce() -> Result<()>,
+  {
+    let mut state = self.state.lock();
+    loop {
+      if state.synced >= mark {
+        return Ok(());
+      }
+      if !state.syncing {
+        state.syncing = true;
+        // every byte counted so far is in the active file or a synced rotated one
+        let target = self.appended.load(Ordering::SeqCst);
+        let res = MutexGuard::unlocked(&mut state, sync);
+        state.syncing = false;
+        if res.is_ok() {
+          state.synced = state.synced.max(target);
+        }
+        // on error the waiters retry with a sync of their own
+        self.synced_cond.notify_all();
+        return res;
+      }
+      self.synced_cond.wait(&mut state);
+    }
+  }
+}
+// exists while the B+Tree index may lag behind data files, it is rebuilt on open if found
+pub(crate) const BPTREE_DIRTY_FILE_NAME: &str = "bptree-dirty";
+
 pub enum SeqNoExist {
   Yes(usize),
   None,
@@ -38,17 +164,35 @@ pub enum SeqNoExist {
 pub struct Engine {
   pub(crate) options: Arc<Options>,
   pub(crate) active_data_file: Arc<RwLock<DataFile>>, // current active data file
-  pub(crate) old_data_files: Arc<RwLock<HashMap<u32, DataFile>>>, // old data files
-  pub(crate) index: Box<dyn index::Indexer>,          // data cache index
+  pub(crate) next_file_id: Arc<AtomicU32>, // next active data file id, shared by rotations
+  pub(crate) old_data_files: Arc<RwLock<HashMap<u32, Arc<DataFile>>>>, // old data files, shared with readers
+  pub(crate) index: RwLock<Box<dyn index::Indexer>>, // data cache index, swapped by `rebuild_index`
+  index_type: RwLock<IndexType>, // type of the current index, starts as `options.index_type`
   file_ids: Vec<u32>, // database setup file id list, only used for setup, not allowed to be modified or updated somewhere else
   pub(crate) batch_commit_lock: Mutex<()>, // txn commit lock ensure serializable
   pub(crate) seq_no: Arc<AtomicUsize>, // transaction sequence number
+  pub(crate) next_version: AtomicU64, // version stamped on the next write, see `version`
+  write_lock: Mutex<()>, // held from stamping a write's version until its index update
   pub(crate) merging_lock: Mutex<()>, // prevent multiple threads from merging data files at the same time
+  pub(crate) merge_cancelled: AtomicBool, // set by `cancel_merge`, checked by the running merge
   pub(crate) seq_file_exists: bool,   // whether the seq_no file exists
   pub(crate) is_initial: bool,        // whether the engine is initialized
   lock_file: File, // file lock, ensure only one engine instance can open the database directory
+  index_lock_file: Option<File>, // file lock of `options.index_dir` if it is another directory
+  open_dir: OpenDir, // registration of the database directory in this process
   bytes_write: Arc<AtomicUsize>, // the add up number of bytes written
   pub(crate) reclaim_size: Arc<AtomicUsize>, // the add up number of bytes to be merged
+  data_size: AtomicU64, // bytes in the data files, appended ones included
+  total_bytes_written: AtomicU64, // bytes ever appended by writes and merges, kept in the meta file
+  max_seq_no: Option<usize>, // opened at this seq_no by `open_at_seq`, engine is read only
+  read_only: bool, // opened by `open_read_only` or `open_at_seq`, writes are rejected
+  closed: AtomicBool, // set by the first close, later ones are no-op
+  pub(crate) meta: RwLock<DbInfo>, // persisted database metadata
+  file_cache: Option<Arc<FileCache>>, // open old data file handles, None if all stay open
+  pub(crate) last_key: Mutex<Option<Vec<u8>>>, // greatest key written, only kept when append only
+  pub(crate) merge_history: Mutex<VecDeque<MergeReport>>, // reports of the latest merges, oldest first
+  pub(crate) file_times: Mutex<HashMap<u32, SystemTime>>, // creation time of each data file
+  group_commit: GroupCommit, // shares syncs of concurrent `sync_writes` writers
 }
 
 // engine statistics info
@@ -65,48 +209,157 @@ pub struct Stat {
 
   // total directory size on disk
   pub disk_size: u64,
+
+  // bytes in the data files, counted from their sizes at open and growing with appends
+  pub data_size: u64,
+
+  // bytes of the data files not reclaimable by a merge
+  pub live_size: u64,
+
+  // ratio of data file bytes to live bytes, 0 if there is no live data
+  pub space_amplification: f64,
+
+  // bytes appended to data files by writes and merges over the lifetime of the database
+  pub total_bytes_written: u64,
+
+  // ratio of total bytes written to live bytes, see `Engine::overwrite_ratio`
+  pub overwrite_ratio: f64,
+}
+
+/// Distribution of live value sizes, from `Engine::value_size_histogram`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ValueSizeHistogram {
+  /// number of values smaller than 64B
+  pub under_64b: usize,
+
+  /// number of values from 64B up to 1KB
+  pub under_1kb: usize,
+
+  /// number of values from 1KB up to 64KB
+  pub under_64kb: usize,
+
+  /// number of values of 64KB or larger
+  pub larger: usize,
+
+  /// average value size in bytes, 0 if there are no values
+  pub average_value_size: f64,
+}
+
+/// Breakdown of one data file, from `Engine::data_files_info`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DataFileInfo {
+  /// id of the data file
+  pub file_id: u32,
+
+  /// size of the data file in bytes
+  pub size: u64,
+
+  /// number of log records, tombstones and transaction records included
+  pub record_count: usize,
+
+  /// number of log records the index points to
+  pub live_record_count: usize,
+
+  /// whether this is the active data file
+  pub is_active: bool,
+}
+
+/// Database metadata, persisted in the meta file of the database directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DbInfo {
+  /// time the database was created
+  pub created_at: SystemTime,
+
+  /// time of the last finished merge, None if never merged
+  pub last_merge_at: Option<SystemTime>,
 }
 
 impl Engine {
-  /// open bitkv storage engine instance
+  /// open bitkv storage engine instance, creating an empty database if the directory
+  /// doesn't hold one
   pub fn open(opts: Options) -> Result<Self> {
-    // check user options
-    if let Some(e) = check_options(&opts) {
-      return Err(e);
-    };
-    let mut is_initial = false;
-    let options = Arc::new(opts);
+    Self::open_engine(opts, None, false)
+  }
 
-    // determine if dir is valid, dir does not exist, create a new one
-    let dir_path = &options.dir_path;
-    if !dir_path.is_dir() {
-      is_initial = true;
-      if let Err(e) = fs::create_dir(dir_path.as_path()) {
-        warn!("failed to create database directory error: {}", e);
-        return Err(Errors::FailedToCreateDatabaseDir);
-      };
+  /// open bitkv storage engine instance like `open`, but only an existing database.
+  /// `Errors::DatabaseNotFound` if the directory is missing or holds no database, so a
+  /// mistyped path fails instead of opening a new empty database
+  pub fn open_existing(opts: Options) -> Result<Self> {
+    if !is_database_dir(&opts.dir_path, &opts.file_suffix) {
+      return Err(Errors::DatabaseNotFound);
     }
+    Self::open_engine(opts, None, false)
+  }
 
-    // determine if dir is empty, if empty, set is_initial to true
-    let lock_file = fs::OpenOptions::new()
-      .read(true)
-      .create(true)
-      .append(true)
-      .open(dir_path.join(FILE_LOCK_NAME))
-      .unwrap();
-    if lock_file.try_lock_exclusive().is_err() {
-      return Err(Errors::DatabaseIsUsing);
+  /// open an existing database without writing to it: writes are rejected, a finished merge
+  /// isn't applied and a B+Tree index is rebuilt in memory instead of opening its file.
+  /// `Errors::DatabaseNotFound` if the directory holds no database
+  pub fn open_read_only(opts: Options) -> Result<Self> {
+    if !is_database_dir(&opts.dir_path, &opts.file_suffix) {
+      return Err(Errors::DatabaseNotFound);
+    }
+    Self::open_engine(opts, None, true)
+  }
+
+  /// open a read only bitkv storage engine instance with the state right after the
+  /// write batch with `seq_no` committed, ignoring later writes.
+  ///
+  /// Writes made without a write batch are ordered by their position in the data files.
+  /// History before the last merge is not kept, merged data is always visible.
+  pub fn open_at_seq(opts: Options, seq_no: usize) -> Result<Self> {
+    // B+ tree index is persisted, it can't be rebuilt at an older seq_no
+    if opts.index_type == IndexType::BPlusTree {
+      return Err(Errors::OpenAtSeqUnsupported);
     }
+    Self::open_engine(opts, Some(seq_no), true)
+  }
+
+  fn open_engine(opts: Options, max_seq_no: Option<usize>, read_only: bool) -> Result<Self> {
+    let _span = span!(INFO, "open", dir = opts.dir_path.display());
+
+    // check user options
+    opts.validate()?;
+    let options = Arc::new(opts);
+
+    // determine if dir is valid, dir does not exist, create a new one along with its parents
+    let dir_path = &options.dir_path;
+    create_db_dir(dir_path, options.create_dirs)?;
+
+    // only one engine instance per database directory in this process
+    let open_dir = OpenDir::register(dir_path)?;
+    let lock_file = lock_db_dir(dir_path)?;
+
+    // a separate index directory is locked too, so no other engine shares the index files
+    let index_lock_file = match &options.index_dir {
+      Some(_) if read_only => None,
+      Some(index_dir) => {
+        create_db_dir(index_dir, options.create_dirs)?;
+        match is_same_dir(dir_path, index_dir) {
+          true => None,
+          false => Some(lock_db_dir(index_dir)?),
+        }
+      }
+      None => None,
+    };
 
-    let entry = fs::read_dir(dir_path).unwrap();
-    if entry.count() == 0 {
-      is_initial = true;
+    // load merge files, a read only engine reads the data files the merge would replace
+    if !read_only {
+      load_merge_files(dir_path, &options.file_suffix)?;
     }
-    // load merge files
-    load_merge_files(dir_path)?;
 
-    // load data files
-    let mut data_files = load_data_files(dir_path, options.mmap_at_startup)?;
+    // load data files, a directory without any of them is initial, whatever else it holds
+    let file_cache = match options.max_open_files {
+      0 if options.lazy_open_files => Some(Arc::new(FileCache::new(usize::MAX))),
+      0 => None,
+      max_open_files => Some(Arc::new(FileCache::new(max_open_files))),
+    };
+    let mut data_files = load_data_files(
+      dir_path,
+      &options.file_suffix,
+      options.mmap_at_startup,
+      file_cache.as_ref(),
+    )?;
+    let is_initial = data_files.is_empty();
 
     // set file id info
     let mut file_ids = Vec::new();
@@ -121,36 +374,83 @@ impl Engine {
     if data_files.len() > 1 {
       for _ in 0..=data_files.len() - 2 {
         let file = data_files.pop().unwrap();
-        older_files.insert(file.get_file_id(), file);
+        older_files.insert(file.get_file_id(), Arc::new(file));
       }
     }
 
     // Retrieve the active data file, which is the last one in the data_files
     let active_file = match data_files.pop() {
       Some(v) => v,
-      None => DataFile::new(dir_path, INITIAL_FILE_ID, IOManagerType::StandardFileIO)?,
+      None => DataFile::new(
+        dir_path,
+        INITIAL_FILE_ID,
+        &options.file_suffix,
+        IOManagerType::StandardFileIO,
+      )?,
+    };
+
+    let next_file_id = active_file.get_file_id() + 1;
+
+    // B+Tree index with buffered changes wasn't closed cleanly, rebuild it from data files.
+    // A read only engine leaves the index files alone and loads a B+Tree index into memory
+    let index_dir = options.index_dir_path();
+    let dirty_file = index_dir.join(BPTREE_DIRTY_FILE_NAME);
+    let index_type = match options.index_type {
+      IndexType::BPlusTree if read_only => IndexType::BTree,
+      ref index_type => index_type.clone(),
     };
+    let rebuild_index = index_type == IndexType::BPlusTree && dirty_file.is_file();
+    if rebuild_index {
+      warn!("b+ tree index may be stale, rebuilding it from data files");
+      if let Err(e) = fs::remove_file(index_dir.join(BPTREE_INDEX_FILE_NAME)) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+          error!("failed to remove b+ tree index error: {}", e);
+          return Err(Errors::DatabaseDirectoryCorrupted);
+        }
+      }
+    }
 
     // create a new engine instance
     let mut engine = Self {
       options: options.clone(),
       active_data_file: Arc::new(RwLock::new(active_file)),
+      next_file_id: Arc::new(AtomicU32::new(next_file_id)),
       old_data_files: Arc::new(RwLock::new(older_files)),
-      index: index::new_indexer(&options.index_type, &options.dir_path),
+      index: RwLock::new(index::new_indexer(&index_type, index_dir, &options)),
+      index_type: RwLock::new(index_type.clone()),
       file_ids,
       batch_commit_lock: Mutex::new(()),
       seq_no: Arc::new(AtomicUsize::new(1)),
+      next_version: AtomicU64::new(1),
+      write_lock: Mutex::new(()),
       merging_lock: Mutex::new(()),
+      merge_cancelled: AtomicBool::new(false),
       seq_file_exists: false,
       is_initial,
       lock_file,
+      index_lock_file,
+      open_dir,
       bytes_write: Arc::new(AtomicUsize::new(0)),
       reclaim_size: Arc::new(AtomicUsize::new(0)),
+      data_size: AtomicU64::new(0),
+      total_bytes_written: AtomicU64::new(0),
+      max_seq_no,
+      read_only,
+      closed: AtomicBool::new(false),
+      meta: RwLock::new(DbInfo {
+        created_at: meta_time_now(),
+        last_merge_at: None,
+      }),
+      file_cache,
+      last_key: Mutex::new(None),
+      merge_history: Mutex::new(load_merge_history(dir_path)),
+      file_times: Mutex::new(read_file_times(dir_path)),
+      group_commit: GroupCommit::new(),
     };
 
     // if not B+Tree index type, load index from hint file and data files
-    match engine.options.index_type {
-      IndexType::BPlusTree => {
+    match index_type {
+      IndexType::BPlusTree if !rebuild_index => {
         // load seq_no from current transaction
         let (is_exists, seq_no) = engine.load_seq_no();
         if is_exists {
@@ -158,16 +458,41 @@ impl Engine {
           engine.seq_file_exists = is_exists;
         }
 
-        // update offset of active data file
+        // update offset of active data file, the data files aren't scanned for the last record
         let active_file = engine.active_data_file.write();
-        active_file.set_write_off(active_file.file_size());
+        active_file.set_write_off(active_file.physical_size()?);
       }
       _ => {
+        // progress of index loading, hint file counts as one file
+        let mut progress = OpenProgress {
+          total_files: engine.file_ids.len(),
+          ..Default::default()
+        };
+        if engine.options.dir_path.join(HINT_FILE_NAME).is_file() {
+          progress.total_files += 1;
+        }
+
+        let index = engine.index.read();
+
         // load index from hint file
-        engine.load_index_from_hint_file()?;
+        engine.load_index_from_hint_file(&**index, &engine.file_ids, &mut progress)?;
 
         // load index from data files
-        let curr_seq_no = engine.load_index_from_data_files()?;
+        let (curr_seq_no, write_off) =
+          engine.load_index_from_data_files(&**index, &engine.file_ids, &mut progress)?;
+        std::mem::drop(index);
+        event!(
+          DEBUG,
+          "index loaded",
+          files = progress.files_processed,
+          records = progress.records_loaded
+        );
+
+        // set active file offset, `write_off` has its own lock inside the data file so a read
+        // guard would do, the write guard makes the mutation explicit
+        if let Some(write_off) = write_off {
+          engine.active_data_file.write().set_write_off(write_off);
+        }
 
         // update seq_no
         if curr_seq_no > 0 {
@@ -175,61 +500,476 @@ impl Engine {
             .seq_no
             .store(curr_seq_no + 1, std::sync::atomic::Ordering::Relaxed);
         }
+      }
+    }
 
-        // reset io_manager type
-        if engine.options.mmap_at_startup {
-          engine.reset_io_type();
+    // append only writes go on after the greatest key in the index
+    if engine.options.append_only {
+      let mut index_iter = engine.index.read().iterator(IteratorOptions {
+        reverse: true,
+        ..Default::default()
+      })?;
+      *engine.last_key.get_mut() = index_iter.next().map(|(key, _)| key.clone());
+    }
+
+    // count the data already in the files
+    let old_size = engine
+      .old_data_files
+      .read()
+      .values()
+      .map(|data_file| data_file.physical_size())
+      .sum::<Result<u64>>()?;
+    let active_size = engine.active_data_file.read().logical_size();
+    engine
+      .data_size
+      .store(old_size + active_size, Ordering::SeqCst);
+
+    // mark the B+Tree index dirty while changes are buffered, until a clean close
+    if index_type == IndexType::BPlusTree {
+      if engine.options.bptree_flush_policy != FlushPolicy::Always {
+        let file = File::create(&dirty_file).map_err(|_| Errors::FailedToWriteToDataFile)?;
+        file
+          .sync_all()
+          .map_err(|_| Errors::FailedToSyncToDataFile)?;
+      } else if rebuild_index {
+        fs::remove_file(&dirty_file).map_err(|_| Errors::FailedToWriteToDataFile)?;
+      }
+    }
+
+    // data files without a saved creation time, written by a merge or before the times were
+    // kept, count as created now. Times of removed files are dropped
+    let mut file_times = engine.file_times.lock();
+    let saved_file_times = file_times.clone();
+    let mut file_ids: Vec<u32> = engine.old_data_files.read().keys().copied().collect();
+    file_ids.push(engine.active_data_file.read().get_file_id());
+    file_times.retain(|file_id, _| file_ids.contains(file_id));
+    let now = meta_time_now();
+    for file_id in file_ids {
+      file_times.entry(file_id).or_insert(now);
+    }
+    if !engine.is_read_only() && *file_times != saved_file_times {
+      save_file_times(&engine.options.dir_path, &file_times)?;
+    }
+    std::mem::drop(file_times);
+
+    // load database metadata, it is created along with the database. Writes after the last
+    // close are missing from the saved byte counter if the engine crashed, the data files
+    // hold at least those
+    match engine.read_meta_file() {
+      Some((info, total_bytes_written)) => {
+        *engine.meta.write() = info;
+        engine
+          .total_bytes_written
+          .store(total_bytes_written, Ordering::SeqCst);
+      }
+      None if !engine.is_read_only() => {
+        if engine.options.dir_path.join(META_FILE_NAME).is_file() {
+          warn!("meta file is corrupt, recreating it");
         }
+        let info = engine.meta.read();
+        engine.save_meta(&info)?;
       }
+      None => {}
     }
+    engine
+      .total_bytes_written
+      .fetch_max(engine.data_size.load(Ordering::SeqCst), Ordering::SeqCst);
 
+    // reset io_manager type
+    if engine.options.mmap_at_startup || engine.options.mmap_old_files {
+      engine.reset_io_type();
+    }
+
+    // old data files are opened again by the reads that need them
+    if engine.options.lazy_open_files {
+      if let Some(file_cache) = &engine.file_cache {
+        file_cache.close_all();
+      }
+    }
+
+    event!(
+      INFO,
+      "opened",
+      data_files = engine.file_ids.len(),
+      seq_no = engine.seq_no.load(Ordering::SeqCst)
+    );
     Ok(engine)
   }
 
-  /// close engine, release resources
+  /// close the engine and open it again from disk with the same options, e.g. after another
+  /// tool changed the directory. Like a drop then open, a finished merge is applied. An engine
+  /// opened by `open_at_seq` is reopened at the same seq_no, one opened read only stays so
+  pub fn reopen(self) -> Result<Engine> {
+    self.close()?;
+    let opts = (*self.options).clone();
+    let max_seq_no = self.max_seq_no;
+    let read_only = self.read_only;
+    // release the index and data files before they are opened again
+    std::mem::drop(self);
+    Self::open_engine(opts, max_seq_no, read_only)
+  }
+
+  /// close engine, release resources. Only the first call does the work, either explicit or
+  /// on drop.
   pub fn close(&self) -> Result<()> {
+    if self.closed.swap(true, Ordering::SeqCst) {
+      return Ok(());
+    }
+
     // if dir_path doesn't exist, return
     if !self.options.dir_path.is_dir() {
+      self.open_dir.release();
+      return Ok(());
+    }
+    let read_guard = self.active_data_file.read();
+    read_guard.sync()?;
+    self.index.read().sync()?;
+
+    // read only engine must not overwrite the latest seq_no
+    if self.is_read_only() {
+      self.unlock_dirs();
+      self.open_dir.release();
       return Ok(());
     }
-    // load seq_no from current transaction
-    let seq_no_file = DataFile::new_seq_no_file(&self.options.dir_path)?;
+
+    // save seq_no from current transaction and the next version, along with the active file
+    // size they are valid for
     let seq_no = self.seq_no.load(Ordering::SeqCst);
     let record = LogRecord {
       key: SEQ_NO_KEY.as_bytes().to_vec(),
-      value: seq_no.to_string().into(),
+      value: format!(
+        "{}:{}:{}:{}:{}",
+        SEQ_NO_FILE_VERSION,
+        seq_no,
+        self.next_version.load(Ordering::SeqCst),
+        read_guard.get_file_id(),
+        read_guard.logical_size()
+      )
+      .into(),
       rec_type: LogRecordType::Normal,
     };
+
+    // write into a temp file and rename it over the previous one,
+    // so the seq_no file is never missing or partially written
+    let tmp_file_name = self.options.dir_path.join(SEQ_NO_TMP_FILE_NAME);
+    if tmp_file_name.is_file() {
+      if let Err(e) = fs::remove_file(&tmp_file_name) {
+        error!("failed to remove seq_no tmp file error: {}", e);
+        return Err(Errors::FailedToWriteToDataFile);
+      }
+    }
+    let seq_no_file = DataFile::new_seq_no_tmp_file(&self.options.dir_path)?;
     seq_no_file.write(&record.encode())?;
     seq_no_file.sync()?;
+    if let Err(e) = fs::rename(tmp_file_name, self.options.dir_path.join(SEQ_NO_FILE_NAME)) {
+      error!("failed to rename seq_no file error: {}", e);
+      return Err(Errors::FailedToWriteToDataFile);
+    }
 
-    let read_guard = self.active_data_file.read();
-    read_guard.sync()?;
+    // keep the bytes written counter
+    self.save_meta(&self.meta.read())?;
+
+    // index is flushed, nothing to rebuild on next open
+    let dirty_file = self.options.index_dir_path().join(BPTREE_DIRTY_FILE_NAME);
+    if *self.index_type.read() == IndexType::BPlusTree && dirty_file.is_file() {
+      fs::remove_file(dirty_file).map_err(|_| Errors::FailedToWriteToDataFile)?;
+    }
 
     // release file lock
-    self.lock_file.unlock().unwrap();
+    self.unlock_dirs();
+    self.open_dir.release();
 
     Ok(())
   }
 
-  /// sync current active data file to disk
+  // release the file locks of the database and index directories. Also reached from drop, a
+  // failure is only logged, the lock goes away with the file handle anyway
+  fn unlock_dirs(&self) {
+    if let Err(e) = self.lock_file.unlock() {
+      error!("failed to unlock database dir error: {}", e);
+    }
+    if let Some(index_lock_file) = &self.index_lock_file {
+      if let Err(e) = index_lock_file.unlock() {
+        error!("failed to unlock index dir error: {}", e);
+      }
+    }
+  }
+
+  /// sync current active data file to disk, along with buffered index changes
   pub fn sync(&self) -> Result<()> {
     let read_guard = self.active_data_file.read();
-    read_guard.sync()
+    read_guard.sync()?;
+    self.index.read().sync()
+  }
+
+  /// sync the active data file and every old data file to disk, along with buffered index
+  /// changes. `sync` only covers the active file and is cheaper when old files are known
+  /// to be persisted, e.g. synced on rotation.
+  pub fn sync_all(&self) -> Result<()> {
+    for old_file in self.old_data_files.read().values() {
+      old_file.sync()?;
+    }
+    self.sync()
+  }
+
+  /// sync the active file and return the highest write batch seq_no durable on disk, every
+  /// batch up to it and every write made before the call survive a crash. 0 if no batch
+  /// has committed yet
+  pub fn durable_seq_no(&self) -> Result<usize> {
+    // a batch holds the commit lock until its finish record is written, so every seq_no
+    // handed out so far belongs to a complete batch
+    let _lock = self.batch_commit_lock.lock();
+    let seq_no = self.seq_no.load(Ordering::SeqCst);
+    self.active_data_file.read().sync()?;
+    Ok(seq_no - 1)
+  }
+
+  /// type of the index in use, `options.index_type` unless changed by `rebuild_index`
+  pub fn index_type(&self) -> IndexType {
+    self.index_type.read().clone()
+  }
+
+  /// build a fresh index of new_type from the hint file and data files, and swap it in for
+  /// the current one. Writes, batch commits and merges wait until it's done. No-op if the
+  /// index is already of new_type. Progress is reported to `Options::open_progress` as on open.
+  ///
+  /// Switching to B+Tree replaces its index file in the database directory. Switching away
+  /// from it leaves the file marked stale, so it's rebuilt if the database is opened with
+  /// B+Tree index again.
+  pub fn rebuild_index(&self, new_type: IndexType) -> Result<()> {
+    if self.is_read_only() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+
+    // writes wait for the swap, the active file is scanned up to its end and nothing written
+    // after that can miss the new index
+    let _merge_lock = self.merging_lock.lock();
+    let _commit_lock = self.batch_commit_lock.lock();
+    let _write_lock = self.lock_writes(None)?;
+    let mut index = self.index.write();
+    let mut index_type = self.index_type.write();
+    if *index_type == new_type {
+      return Ok(());
+    }
+
+    // the B+Tree index file is stale from now on, until the new one is complete. Switching
+    // between in-memory indexes leaves it as it is
+    let dir_path = &self.options.dir_path;
+    let index_dir = self.options.index_dir_path();
+    let dirty_file = index_dir.join(BPTREE_DIRTY_FILE_NAME);
+    if *index_type == IndexType::BPlusTree || new_type == IndexType::BPlusTree {
+      let file = File::create(&dirty_file).map_err(|_| Errors::FailedToWriteToDataFile)?;
+      file
+        .sync_all()
+        .map_err(|_| Errors::FailedToSyncToDataFile)?;
+    }
+    if new_type == IndexType::BPlusTree {
+      if let Err(e) = fs::remove_file(index_dir.join(BPTREE_INDEX_FILE_NAME)) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+          error!("failed to remove b+ tree index error: {}", e);
+          return Err(Errors::FailedToWriteToDataFile);
+        }
+      }
+    }
+
+    // current data files, including the ones rotated since open
+    let mut file_ids: Vec<u32> = self.old_data_files.read().keys().copied().collect();
+    file_ids.push(self.active_data_file.read().get_file_id());
+    file_ids.sort();
+
+    let mut progress = OpenProgress {
+      total_files: file_ids.len(),
+      ..Default::default()
+    };
+    if dir_path.join(HINT_FILE_NAME).is_file() {
+      progress.total_files += 1;
+    }
+
+    // reclaimable size is recounted while loading
+    let new_index = index::new_indexer(&new_type, index_dir, &self.options);
+    let reclaim_size = self.reclaim_size.swap(0, Ordering::SeqCst);
+    let loaded = self
+      .load_index_from_hint_file(&*new_index, &file_ids, &mut progress)
+      .and_then(|_| self.load_index_from_data_files(&*new_index, &file_ids, &mut progress))
+      .and_then(|_| new_index.sync());
+    if let Err(e) = loaded {
+      self.reclaim_size.store(reclaim_size, Ordering::SeqCst);
+      return Err(e);
+    }
+
+    // B+Tree index stays marked dirty while changes are buffered, as on open
+    if new_type == IndexType::BPlusTree && self.options.bptree_flush_policy == FlushPolicy::Always {
+      fs::remove_file(&dirty_file).map_err(|_| Errors::FailedToWriteToDataFile)?;
+    }
+
+    *index = new_index;
+    *index_type = new_type;
+    Ok(())
+  }
+
+  /// rewrite the B+Tree index file with only the live entries and swap it in for the current
+  /// one, giving back the space left by deleted keys. The new file replaces the old one with a
+  /// rename, a crash before that leaves the old file in use. Writes and reads wait until it's
+  /// done. No-op for in-memory indexes.
+  pub fn shrink_index(&self) -> Result<()> {
+    if self.is_read_only() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+
+    let _commit_lock = self.batch_commit_lock.lock();
+    let mut index = self.index.write();
+    if *self.index_type.read() != IndexType::BPlusTree {
+      return Ok(());
+    }
+
+    // a previous shrink may have been interrupted
+    let dir_path = self.options.index_dir_path();
+    let shrink_file = dir_path.join(BPTREE_SHRINK_FILE_NAME);
+    if let Err(e) = fs::remove_file(&shrink_file) {
+      if e.kind() != std::io::ErrorKind::NotFound {
+        error!("failed to remove b+ tree shrink file error: {}", e);
+        return Err(Errors::FailedToWriteToDataFile);
+      }
+    }
+
+    // copy the live entries in a single commit
+    index.sync()?;
+    let shrunk = BPlusTree::new(dir_path, BPTREE_SHRINK_FILE_NAME, BPTREE_BUCKET_NAME)
+      .with_flush_policy(FlushPolicy::Manual);
+    let mut index_iter = index.iterator(IteratorOptions::default())?;
+    while let Some((key, pos)) = index_iter.next() {
+      shrunk.put(key.clone(), *pos)?;
+    }
+    shrunk.sync()?;
+    std::mem::drop(shrunk);
+
+    fs::rename(&shrink_file, dir_path.join(BPTREE_INDEX_FILE_NAME))
+      .map_err(|_| Errors::FailedToWriteToDataFile)?;
+    *index = index::new_indexer(&IndexType::BPlusTree, dir_path, &self.options);
+    Ok(())
+  }
+
+  /// get database metadata, creation and last merge time
+  pub fn info(&self) -> DbInfo {
+    *self.meta.read()
   }
 
   pub fn get_engine_stat(&self) -> Result<Stat> {
     let keys = self.list_keys()?;
     let old_files = self.old_data_files.read();
 
+    let reclaim_size = self.reclaim_size.load(Ordering::SeqCst);
+    let data_size = self.data_size.load(Ordering::SeqCst);
+    let live_size = data_size.saturating_sub(reclaim_size as u64);
+    let space_amplification = match live_size {
+      0 => 0.0,
+      _ => data_size as f64 / live_size as f64,
+    };
+    let total_bytes_written = self.total_bytes_written.load(Ordering::SeqCst);
+
     Ok(Stat {
       key_num: keys.len(),
       data_file_num: old_files.len() + 1,
-      reclaim_size: self.reclaim_size.load(Ordering::SeqCst),
+      reclaim_size,
       disk_size: util::file::dir_disk_size(&self.options.dir_path),
+      data_size,
+      live_size,
+      space_amplification,
+      total_bytes_written,
+      overwrite_ratio: self.overwrite_ratio(),
     })
   }
 
+  /// write amplification, the ratio of bytes ever appended to the data files to the live bytes
+  /// in them. Overwrites, deletes and the records rewritten by merges all raise it, 0 if there
+  /// is no live data
+  pub fn overwrite_ratio(&self) -> f64 {
+    let data_size = self.data_size.load(Ordering::SeqCst);
+    let live_size = data_size.saturating_sub(self.reclaim_size.load(Ordering::SeqCst) as u64);
+    match live_size {
+      0 => 0.0,
+      _ => self.total_bytes_written.load(Ordering::SeqCst) as f64 / live_size as f64,
+    }
+  }
+
+  /// record a new active data file as created now. The time only decides when the file is
+  /// cold enough to merge, if it can't be saved the file counts as created at the next open
+  pub(crate) fn record_file_created(&self, file_id: u32) {
+    let mut file_times = self.file_times.lock();
+    file_times.insert(file_id, meta_time_now());
+    if let Err(e) = save_file_times(&self.options.dir_path, &file_times) {
+      warn!("failed to save data file creation times: {}", e);
+    }
+  }
+
+  /// count bytes written to data files outside the active file, e.g. by a merge
+  pub(crate) fn add_bytes_written(&self, size: u64) {
+    self.total_bytes_written.fetch_add(size, Ordering::SeqCst);
+  }
+
+  /// report every data file in file id order, reading all their log records once and
+  /// checking each against the index
+  pub fn data_files_info(&self) -> Result<Vec<DataFileInfo>> {
+    let mut file_ids: Vec<u32> = self.old_data_files.read().keys().copied().collect();
+    file_ids.sort();
+
+    let mut infos = Vec::with_capacity(file_ids.len() + 1);
+    for file_id in file_ids {
+      let data_file = self.open_old_data_file(file_id, IOManagerType::StandardFileIO)?;
+      infos.push(self.data_file_info(&data_file, false)?);
+    }
+
+    // hold the active file so no record is appended while it's read
+    let active_file = self.active_data_file.read();
+    infos.push(self.data_file_info(&active_file, true)?);
+    Ok(infos)
+  }
+
+  fn data_file_info(&self, data_file: &DataFile, is_active: bool) -> Result<DataFileInfo> {
+    let mut info = DataFileInfo {
+      file_id: data_file.get_file_id(),
+      size: data_file.physical_size()?,
+      is_active,
+      ..Default::default()
+    };
+    for record in data_file.iter_records() {
+      let (pos, log_record, _) = record?;
+      info.record_count += 1;
+      if log_record.rec_type == LogRecordType::TxnFinished {
+        continue;
+      }
+      let (real_key, _) = parse_log_record_key(log_record.key)?;
+      if let Some(index_pos) = self.index.read().get(real_key) {
+        if index_pos.file_id == pos.file_id && index_pos.offset == pos.offset {
+          info.live_record_count += 1;
+        }
+      }
+    }
+    Ok(info)
+  }
+
+  /// compute the distribution of live value sizes, reading every live value once
+  pub fn value_size_histogram(&self) -> Result<ValueSizeHistogram> {
+    let mut histogram = ValueSizeHistogram::default();
+    let mut total_size = 0;
+    let mut scan = self.scan()?;
+    while let Some((_, value)) = scan.next()? {
+      match value.len() {
+        len if len < 64 => histogram.under_64b += 1,
+        len if len < 1024 => histogram.under_1kb += 1,
+        len if len < 64 * 1024 => histogram.under_64kb += 1,
+        _ => histogram.larger += 1,
+      }
+      total_size += value.len();
+    }
+
+    let count = histogram.under_64b + histogram.under_1kb + histogram.under_64kb + histogram.larger;
+    if count > 0 {
+      histogram.average_value_size = total_size as f64 / count as f64;
+    }
+    Ok(histogram)
+  }
+
   /// backup data directory
   pub fn backup<P>(&self, dir_path: P) -> Result<()>
   where
@@ -244,79 +984,375 @@ impl Engine {
       log::error!("failed to copy data directory error: {}", e);
       return Err(Errors::FailedToCopyDirectory);
     }
+    // the backup keeps the B+Tree index files next to the data files
+    if self.options.index_dir.is_some() {
+      let index_dir = self.options.index_dir_path();
+      for file_name in [BPTREE_INDEX_FILE_NAME, BPTREE_DIRTY_FILE_NAME] {
+        let src = index_dir.join(file_name);
+        if src.is_file() && fs::copy(&src, dir_path.as_ref().join(file_name)).is_err() {
+          log::error!("failed to copy index file {:?}", src);
+          return Err(Errors::FailedToCopyDirectory);
+        }
+      }
+    }
     Ok(())
   }
 
   /// store a key/value pair, ensuring key isn't null.
   pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
-    // if the key is valid
-    if key.is_empty() {
-      return Err(Errors::KeyIsEmpty);
-    }
+    let _span = span!(TRACE, "put", key_len = key.len(), value_len = value.len());
+    self.put_until(key, value, None)
+  }
 
-    // construct LogRecord
-    let mut record = LogRecord {
-      key: log_record_key_with_seq(key.to_vec(), NON_TXN_SEQ_NO),
-      value: value.to_vec(),
-      rec_type: LogRecordType::Normal,
-    };
+  /// store a key/value pair only if the key doesn't exist, return whether it was inserted.
+  /// Checking and writing are serialized with every other write.
+  pub fn put_if_absent(&self, key: Bytes, value: Bytes) -> Result<bool> {
+    if self.is_read_only() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+    self.check_key_value(&key, &value)?;
 
-    // appending write to active file
-    let log_record_pos = self.append_log_record(&mut record)?;
+    // append only puts never overwrite, the key written meanwhile fails it as not ascending
+    if self.options.append_only {
+      if self.index.read().get(key.to_vec()).is_some() {
+        return Ok(false);
+      }
+      self.put_until(key, value, None)?;
+      return Ok(true);
+    }
 
-    // update index
-    if let Some(old_pos) = self.index.put(key.to_vec(), log_record_pos) {
-      self
-        .reclaim_size
-        .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+    // hold off every other write between the check and the write
+    let write_lock = self.lock_writes(None)?;
+    if self.index.read().get(key.to_vec()).is_some() {
+      return Ok(false);
     }
-    Ok(())
+    self.write_non_txn(
+      &write_lock,
+      &key,
+      self.encode_value(&value),
+      LogRecordType::Normal,
+      None,
+    )?;
+    drop(write_lock);
+    self.sync_appended()?;
+    Ok(true)
   }
 
-  // delete the data associated with the specified key.
-  pub fn delete(&self, key: Bytes) -> Result<()> {
-    // if the key is valid
+  /// append `suffix` to the value of key, a missing key is appended to as an empty value.
+  /// Bitcask can't append in place, the whole combined value is written as a new record.
+  /// Reading and writing are serialized with every other write.
+  pub fn append(&self, key: Bytes, suffix: Bytes) -> Result<()> {
+    if self.is_read_only() {
+      return Err(Errors::ReadOnlyEngine);
+    }
     if key.is_empty() {
       return Err(Errors::KeyIsEmpty);
     }
 
-    // retrieve specified data from index if it not exists then return
-    let pos = self.index.get(key.to_vec());
-    if pos.is_none() {
-      return Ok(());
+    // append only puts never overwrite, only a missing key can be appended to
+    if self.options.append_only {
+      if self.index.read().get(key.to_vec()).is_some() {
+        return Err(Errors::KeyNotAscending);
+      }
+      return self.put_until(key, suffix, None);
     }
 
-    // construct LogRecord
-    let mut record = LogRecord {
-      key: log_record_key_with_seq(key.to_vec(), NON_TXN_SEQ_NO),
-      value: Default::default(),
-      rec_type: LogRecordType::Deleted,
+    // hold off every other write between reading the value and writing it back
+    let write_lock = self.lock_writes(None)?;
+    let mut value = match self.get(key.clone()) {
+      Ok(value) => value.to_vec(),
+      Err(Errors::KeyNotFound) => Vec::new(),
+      Err(e) => return Err(e),
     };
-
-    // appending write to active file
-    let pos = self.append_log_record(&mut record)?;
-    self
-      .reclaim_size
-      .fetch_add(pos.size as usize, Ordering::SeqCst);
-
-    // delete key in index
-    if let Some(old_pos) = self.index.delete(key.to_vec()) {
-      self
-        .reclaim_size
-        .fetch_add(old_pos.size as usize, Ordering::SeqCst);
-    }
-    Ok(())
+    value.extend_from_slice(&suffix);
+    let value = Bytes::from(value);
+    self.check_key_value(&key, &value)?;
+    self.write_non_txn(
+      &write_lock,
+      &key,
+      self.encode_value(&value),
+      LogRecordType::Normal,
+      None,
+    )?;
+    drop(write_lock);
+    self.sync_appended()
   }
 
-  /// Retrieves the data associated with the specified key.
-  pub fn get(&self, key: Bytes) -> Result<Bytes> {
-    // if the key is empty then return
+  /// current version of key. Every write is stamped with a version greater than all before it,
+  /// the records of a write batch share one. Versions are persisted with the records and kept
+  /// by merge, so they keep increasing across restarts. 0 if the key doesn't exist, or was
+  /// last written before versions were stamped.
+  pub fn version(&self, key: Bytes) -> Result<u64> {
     if key.is_empty() {
       return Err(Errors::KeyIsEmpty);
     }
+    let Some(pos) = self.index.read().get(key.to_vec()) else {
+      return Ok(0);
+    };
+    let log_record = match read_live_record(
+      &self.active_data_file,
+      &self.old_data_files,
+      self.options.verify_checksum_on_read,
+      &pos,
+    ) {
+      Ok(log_record) => log_record,
+      Err(Errors::KeyNotFound) => return Ok(0),
+      Err(e) => return Err(e),
+    };
+    let (_, _, version) = parse_log_record_key_version(log_record.key)?;
+    Ok(version)
+  }
 
-    // Retrieves data for the specified key from the in-memory index.
-    let pos = self.index.get(key.to_vec());
+  /// store a key/value pair like `put`, but return `Errors::LockTimeout` if the write locks
+  /// can't be acquired within timeout, e.g. while a batch commit or merge is stuck
+  pub fn try_put(&self, key: Bytes, value: Bytes, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+
+    // wait for in-flight batch commits so a timed write can't be blocked behind them forever
+    let _lock = self
+      .batch_commit_lock
+      .try_lock_until(deadline)
+      .ok_or(Errors::LockTimeout)?;
+    self.put_until(key, value, Some(deadline))
+  }
+
+  fn put_until(&self, key: Bytes, value: Bytes, deadline: Option<Instant>) -> Result<()> {
+    if self.is_read_only() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+
+    // append only puts skip the overwrite path, the key can't be in the index
+    if self.options.append_only {
+      return self.put_sorted_until(&[(key, value)], deadline);
+    }
+
+    // if the key and value are valid
+    self.check_key_value(&key, &value)?;
+
+    // appending write to active file and update index
+    let write_lock = self.lock_writes(deadline)?;
+    self.write_non_txn(
+      &write_lock,
+      &key,
+      self.encode_value(&value),
+      LogRecordType::Normal,
+      deadline,
+    )?;
+    drop(write_lock);
+    self.sync_appended()
+  }
+
+  /// append entries sorted by strictly ascending key straight to the active file, syncing once
+  /// after the last one. In append only mode the first key must also be greater than every key
+  /// written before. Fails with `Errors::KeyNotAscending` before writing anything if the keys
+  /// are out of order. Unlike a write batch the entries aren't atomic, a crash may keep only
+  /// the first ones.
+  pub fn put_batch_sorted_into_file(&self, entries: &[(Bytes, Bytes)]) -> Result<()> {
+    if self.is_read_only() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+    self.put_sorted_until(entries, None)
+  }
+
+  fn put_sorted_until(&self, entries: &[(Bytes, Bytes)], deadline: Option<Instant>) -> Result<()> {
+    // held across the appends so no other put slips a smaller key in between
+    let mut last_key = self.last_key.lock();
+    let mut prev = match self.options.append_only {
+      true => last_key.as_deref(),
+      false => None,
+    };
+    for (key, value) in entries {
+      self.check_key_value(key, value)?;
+      if prev.is_some_and(|prev| key.as_ref() <= prev) {
+        return Err(Errors::KeyNotAscending);
+      }
+      prev = Some(key.as_ref());
+    }
+
+    for (key, value) in entries {
+      let write_lock = self.lock_writes(deadline)?;
+      self.write_non_txn(
+        &write_lock,
+        key,
+        self.encode_value(value),
+        LogRecordType::Normal,
+        deadline,
+      )?;
+      drop(write_lock);
+      if self.options.append_only {
+        *last_key = Some(key.to_vec());
+      }
+    }
+    self.sync_appended()
+  }
+
+  // delete the data associated with the specified key.
+  pub fn delete(&self, key: Bytes) -> Result<()> {
+    self.delete_checked(key).map(|_| ())
+  }
+
+  /// delete the data associated with key like `delete`, return whether a live value was
+  /// removed. No tombstone is written if the key doesn't exist.
+  pub fn delete_checked(&self, key: Bytes) -> Result<bool> {
+    if self.is_read_only() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+
+    // if the key is valid
+    if key.is_empty() {
+      return Err(Errors::KeyIsEmpty);
+    }
+
+    // retrieve specified data from index if it not exists then return
+    let pos = self.index.read().get(key.to_vec());
+    if pos.is_none() {
+      return Ok(false);
+    }
+
+    // appending tombstone to active file and delete key in index, a concurrent delete may
+    // have removed it first
+    let write_lock = self.lock_writes(None)?;
+    let (_, old_pos) = self.write_non_txn(
+      &write_lock,
+      &key,
+      Default::default(),
+      LogRecordType::Deleted,
+      None,
+    )?;
+    drop(write_lock);
+    self.sync_appended()?;
+    Ok(old_pos.is_some())
+  }
+
+  /// rewrite the live value of key to the active file, so all its older versions
+  /// become reclaimable by the next merge. No-op if the key doesn't exist.
+  pub fn compact_key(&self, key: Bytes) -> Result<()> {
+    if self.is_read_only() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+    if key.is_empty() {
+      return Err(Errors::KeyIsEmpty);
+    }
+
+    // other writes wait until the fresh copy is indexed, so it can't replace a newer value
+    let write_lock = self.lock_writes(None)?;
+    let pos = match self.index.read().get(key.to_vec()) {
+      Some(pos) => pos,
+      None => return Ok(()),
+    };
+    let value = match self.get_value_by_key_position(&key, &pos) {
+      Ok(value) => value,
+      Err(Errors::KeyNotFound) => return Ok(()),
+      Err(e) => return Err(e),
+    };
+
+    // appending write a fresh copy to active file, keeping its version
+    let version = self.version(key.clone())?;
+    let mut record = LogRecord {
+      key: log_record_key_with_version(key.to_vec(), NON_TXN_SEQ_NO, version)?,
+      value: self.encode_value(&value),
+      rec_type: LogRecordType::Normal,
+    };
+    let new_pos = self.append_log_record_unsynced_until(&mut record, None)?;
+
+    // update index
+    if let Some(old_pos) = self.index.read().put(key.to_vec(), new_pos)? {
+      self
+        .reclaim_size
+        .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+    }
+    drop(write_lock);
+    self.sync_appended()
+  }
+
+  /// lock out other writes until the guard is dropped, see `write_non_txn`. Write batches hold
+  /// it through their commit. `Errors::LockTimeout` if it can't be acquired before deadline
+  pub(crate) fn lock_writes(&self, deadline: Option<Instant>) -> Result<MutexGuard<'_, ()>> {
+    match deadline {
+      Some(deadline) => self
+        .write_lock
+        .try_lock_until(deadline)
+        .ok_or(Errors::LockTimeout),
+      None => Ok(self.write_lock.lock()),
+    }
+  }
+
+  /// append a record outside a write batch stamped with a new version, and update the index,
+  /// under the guard of `lock_writes` so versions follow the order writes take effect in.
+  /// The record isn't synced, callers release the guard and call `sync_appended` so
+  /// concurrent writers share one sync, readers may see the write before it is durable.
+  /// Returns the version along with the position the write replaced in the index, if any.
+  pub(crate) fn write_non_txn(
+    &self,
+    _write_lock: &MutexGuard<'_, ()>,
+    key: &[u8],
+    value: Vec<u8>,
+    rec_type: LogRecordType,
+    deadline: Option<Instant>,
+  ) -> Result<(u64, Option<LogRecordPos>)> {
+    let version = self.next_version.fetch_add(1, Ordering::SeqCst);
+    let mut record = LogRecord {
+      key: log_record_key_with_version(key.to_vec(), NON_TXN_SEQ_NO, version)?,
+      value,
+      rec_type,
+    };
+    let pos = self.append_log_record_unsynced_until(&mut record, deadline)?;
+
+    let old_pos = match rec_type {
+      LogRecordType::Deleted => {
+        // tombstone itself is reclaimable
+        self
+          .reclaim_size
+          .fetch_add(pos.size as usize, Ordering::SeqCst);
+        self.index.read().delete(key.to_vec())?
+      }
+      _ => self.index.read().put(key.to_vec(), pos)?,
+    };
+    if let Some(old_pos) = old_pos {
+      self
+        .reclaim_size
+        .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+    }
+    Ok((version, old_pos))
+  }
+
+  /// whether the engine is opened by `open_read_only` or `open_at_seq` and rejects writes
+  pub fn is_read_only(&self) -> bool {
+    self.read_only
+  }
+
+  /// check key is not empty and key/value sizes are within limits
+  pub(crate) fn check_key_value(&self, key: &Bytes, value: &Bytes) -> Result<()> {
+    if key.is_empty() {
+      return Err(Errors::KeyIsEmpty);
+    }
+    if key.len() > self.options.effective_max_key_size() {
+      return Err(Errors::KeyTooLarge);
+    }
+    if value.len() > self.options.effective_max_value_size(key.len()) {
+      return Err(Errors::ValueTooLarge);
+    }
+    Ok(())
+  }
+
+  /// Retrieves the data associated with the specified key.
+  ///
+  /// `Errors::KeyNotFound` only means the key has no live value, it was never written or is
+  /// deleted. Failing to read it is a distinct error:
+  /// - `Errors::FailedToReadFromDataFile` on an IO error
+  /// - `Errors::InvalidLogRecordCrc` or `Errors::InvalidLogRecordType` if the record is corrupt
+  /// - `Errors::TruncatedLogRecord` if the record was cut off the end of the data file
+  /// - `Errors::DataFileNotFound` if the index points at a data file that doesn't exist
+  /// - `Errors::ValueCodecFailed` if `Options::value_codec` can't decode the value
+  pub fn get(&self, key: Bytes) -> Result<Bytes> {
+    let _span = span!(TRACE, "get", key_len = key.len());
+    // if the key is empty then return
+    if key.is_empty() {
+      return Err(Errors::KeyIsEmpty);
+    }
+
+    // Retrieves data for the specified key from the in-memory index.
+    let pos = self.index.read().get(key.to_vec());
 
     // if key not found then return
     if pos.is_none() {
@@ -324,49 +1360,323 @@ impl Engine {
     }
 
     // Retrieves LogRecord from the specified file data.
-    self.get_value_by_position(&pos.unwrap())
+    self.get_value_by_key_position(&key, &pos.unwrap())
   }
 
-  /// Retrieves the data by position.
-  pub(crate) fn get_value_by_position(&self, log_record_pos: &LogRecordPos) -> Result<Bytes> {
-    // Retrieves LogRecord from the specified file data.
+  /// Values of sorted_keys in the same order, None for keys not found. Keys in ascending order
+  /// are joined with the index in a single forward pass, seeking past the entries between
+  /// sparse keys, instead of a lookup per key. Unsorted keys and case insensitive databases
+  /// fall back to a `get` per key
+  pub fn get_many_ordered(&self, sorted_keys: &[Bytes]) -> Result<Vec<Option<Bytes>>> {
+    let _span = span!(TRACE, "get_many_ordered", keys = sorted_keys.len());
+    if sorted_keys.iter().any(|key| key.is_empty()) {
+      return Err(Errors::KeyIsEmpty);
+    }
+
+    let sorted = sorted_keys.windows(2).all(|keys| keys[0] <= keys[1]);
+    if !sorted || self.options.key_case_insensitive {
+      return sorted_keys
+        .iter()
+        .map(|key| match self.get(key.clone()) {
+          Ok(value) => Ok(Some(value)),
+          Err(Errors::KeyNotFound) => Ok(None),
+          Err(e) => Err(e),
+        })
+        .collect();
+    }
+    if sorted_keys.is_empty() {
+      return Ok(Vec::new());
+    }
+
+    // merge join against the index a chunk at a time under its read lock. A key past the chunk
+    // seeks the next one from itself, so sparse keys skip the entries between them. Chunks
+    // grow while the keys use them up and shrink back once keys jump past them
+    let index = self.index.read();
+    let mut positions = Vec::with_capacity(sorted_keys.len());
+    let mut chunk: Vec<(Vec<u8>, LogRecordPos)> = Vec::new();
+    let mut chunk_len = GET_MANY_MIN_CHUNK_LEN;
+    let mut next = 0;
+    let mut index_end = false; // the chunk reaches the end of the index
+    let mut prev_key: Option<&Bytes> = None;
+    for key in sorted_keys {
+      while chunk
+        .get(next)
+        .is_some_and(|(index_key, _)| index_key.as_slice() < key.as_ref())
+      {
+        next += 1;
+      }
+      if next == chunk.len() && !index_end {
+        // the previous key reaching the end of the chunk used all of it
+        let dense = matches!(
+          (chunk.last(), prev_key),
+          (Some((last, _)), Some(prev_key)) if prev_key.as_ref() >= last.as_slice()
+        );
+        chunk_len = if dense {
+          (chunk_len * 2).min(GET_MANY_MAX_CHUNK_LEN)
+        } else {
+          GET_MANY_MIN_CHUNK_LEN
+        };
+        chunk = index.range_from(key, chunk_len)?;
+        next = 0;
+        index_end = chunk.len() < chunk_len;
+      }
+      positions.push(match chunk.get(next) {
+        Some((index_key, pos)) if index_key.as_slice() == key.as_ref() => Some(*pos),
+        _ => None,
+      });
+      prev_key = Some(key);
+    }
+    std::mem::drop(index);
+
+    sorted_keys
+      .iter()
+      .zip(positions)
+      .map(|(key, pos)| match pos {
+        Some(pos) => match self.get_value_by_key_position(key, &pos) {
+          Ok(value) => Ok(Some(value)),
+          Err(Errors::KeyNotFound) => Ok(None),
+          Err(e) => Err(e),
+        },
+        None => Ok(None),
+      })
+      .collect()
+  }
+
+  /// length of the value of key, None if the key doesn't exist. Only the log record header is
+  /// read, so the value isn't checked against its crc. With `Options::value_codec` the stored
+  /// value is read and decoded to get its length
+  pub fn value_len(&self, key: Bytes) -> Result<Option<usize>> {
+    if key.is_empty() {
+      return Err(Errors::KeyIsEmpty);
+    }
+    if self.options.value_codec.is_some() {
+      return match self.get(key) {
+        Ok(value) => Ok(Some(value.len())),
+        Err(Errors::KeyNotFound) => Ok(None),
+        Err(e) => Err(e),
+      };
+    }
+    let pos = match self.index.read().get(key.to_vec()) {
+      Some(pos) => pos,
+      None => return Ok(None),
+    };
+
     let active_file = self.active_data_file.read();
-    let oldre_files = self.old_data_files.read();
-    let log_record = match active_file.get_file_id() == log_record_pos.file_id {
-      true => active_file.read_log_record(log_record_pos.offset)?.record,
+    let read_res = match active_file.get_file_id() == pos.file_id {
+      true => active_file.read_value_len(pos.offset),
       false => {
-        let data_file = oldre_files.get(&log_record_pos.file_id);
-        if data_file.is_none() {
-          // Returns the error if the corresponding data file is not found.
-          return Err(Errors::DataFileNotFound);
-        }
-        data_file
-          .unwrap()
-          .read_log_record(log_record_pos.offset)?
-          .record
+        drop(active_file);
+        let data_file = self
+          .old_data_files
+          .read()
+          .get(&pos.file_id)
+          .cloned()
+          .ok_or(Errors::DataFileNotFound)?;
+        data_file.read_value_len(pos.offset)
       }
     };
+    match read_res {
+      Ok((LogRecordType::Deleted, _)) => Ok(None),
+      // the value is stored by another record, read it through the reference
+      Ok((LogRecordType::ValueRef, _)) => match self.get(key) {
+        Ok(value) => Ok(Some(value.len())),
+        Err(Errors::KeyNotFound) => Ok(None),
+        Err(e) => Err(e),
+      },
+      Ok((_, value_len)) => Ok(Some(value_len)),
+      // the index points past the end of the data file, the record was lost
+      Err(Errors::ReadDataFileEOF) => Err(Errors::TruncatedLogRecord),
+      Err(e) => Err(e),
+    }
+  }
 
-    // Determines the type of the log record.
-    if let LogRecordType::Deleted = log_record.rec_type {
-      return Err(Errors::KeyNotFound);
+  /// Retrieves the data associated with the specified key like `get`, but values in memory
+  /// mapped data files (see `Options::mmap_old_files`) are returned without copying.
+  /// The returned bytes keep the mapping alive, other files fall back to a copying read.
+  /// Values are always copied when decoded by `Options::value_codec`. Returns the same errors.
+  pub fn get_mmap(&self, key: Bytes) -> Result<Bytes> {
+    if key.is_empty() {
+      return Err(Errors::KeyIsEmpty);
+    }
+    let pos = match self.index.read().get(key.to_vec()) {
+      Some(pos) => pos,
+      None => return Err(Errors::KeyNotFound),
+    };
+
+    let verify_crc = self.options.verify_checksum_on_read;
+    let shared = {
+      let active_file = self.active_data_file.read();
+      match active_file.get_file_id() == pos.file_id {
+        true => active_file.read_value_shared(pos.offset, pos.size, verify_crc)?,
+        false => {
+          drop(active_file);
+          match self.old_data_files.read().get(&pos.file_id).cloned() {
+            Some(data_file) => data_file.read_value_shared(pos.offset, pos.size, verify_crc)?,
+            None => None,
+          }
+        }
+      }
     };
 
+    match shared {
+      Some(StoredValue::Inline(value)) if self.options.value_codec.is_some() => {
+        self.decode_value(value)
+      }
+      Some(StoredValue::Inline(value)) => Ok(value),
+      // referenced values are rare, they're read by a copying read
+      Some(StoredValue::Ref(_)) | None => self.get_value_by_key_position(&key, &pos),
+    }
+  }
+
+  /// Retrieves the data of key by position, if the data file was swapped out by
+  /// a concurrent merge, re-consults the index for the key's new position and retries once.
+  pub(crate) fn get_value_by_key_position(
+    &self,
+    key: &[u8],
+    log_record_pos: &LogRecordPos,
+  ) -> Result<Bytes> {
+    match self.get_value_by_position(log_record_pos) {
+      Err(Errors::DataFileNotFound) => {
+        // don't hold the index lock while reading the data file
+        let new_pos = self.index.read().get(key.to_vec());
+        match new_pos {
+          Some(new_pos) if new_pos != *log_record_pos => self.get_value_by_position(&new_pos),
+          Some(_) => Err(Errors::DataFileNotFound),
+          None => Err(Errors::KeyNotFound),
+        }
+      }
+      res => res,
+    }
+  }
+
+  /// Retrieves the data by position.
+  pub(crate) fn get_value_by_position(&self, log_record_pos: &LogRecordPos) -> Result<Bytes> {
+    let value = read_live_value(
+      &self.active_data_file,
+      &self.old_data_files,
+      self.options.verify_checksum_on_read,
+      log_record_pos,
+    )?;
+
     // return corresponding value
-    Ok(log_record.value.into())
+    self.decode_value(value)
+  }
+
+  /// key as written in the record at position, the index of a case insensitive database
+  /// holds lowercased keys. Other databases get key back as is
+  pub(crate) fn original_key(&self, key: Vec<u8>, log_record_pos: &LogRecordPos) -> Vec<u8> {
+    if !self.options.key_case_insensitive {
+      return key;
+    }
+    let log_record = read_live_record(
+      &self.active_data_file,
+      &self.old_data_files,
+      self.options.verify_checksum_on_read,
+      log_record_pos,
+    );
+    match log_record.and_then(|log_record| parse_log_record_key(log_record.key)) {
+      Ok((real_key, _)) => real_key,
+      // the record moved in a merge, the lowercased key is the best left
+      Err(_) => key,
+    }
+  }
+
+  /// a value reader not borrowing the engine, e.g. for a background thread
+  pub(crate) fn value_reader(&self) -> ValueReader {
+    ValueReader {
+      active_data_file: self.active_data_file.clone(),
+      old_data_files: self.old_data_files.clone(),
+      verify_crc: self.options.verify_checksum_on_read,
+      value_codec: self.options.value_codec.clone(),
+    }
+  }
+
+  /// encode value with the value codec, if any
+  pub(crate) fn encode_value(&self, value: &[u8]) -> Vec<u8> {
+    match &self.options.value_codec {
+      Some(codec) => codec.encode(value),
+      None => value.to_vec(),
+    }
+  }
+
+  /// decode value read from a data file with the value codec, if any
+  pub(crate) fn decode_value(&self, value: Bytes) -> Result<Bytes> {
+    match &self.options.value_codec {
+      Some(codec) => codec.decode(&value).map(Bytes::from),
+      None => Ok(value),
+    }
+  }
+
+  /// decoded value of a live log record of rec_type read from a data file, following a
+  /// reference to the record storing the value
+  pub(crate) fn decode_record_value(
+    &self,
+    rec_type: LogRecordType,
+    value: Vec<u8>,
+  ) -> Result<Bytes> {
+    match rec_type {
+      LogRecordType::ValueRef => self.get_value_by_position(&decode_log_record_pos(value)),
+      _ => self.decode_value(value.into()),
+    }
+  }
+
+  /// sync the active file once for the records appended so far, if `sync_writes` or
+  /// `bytes_per_sync` asks for it
+  pub(crate) fn sync_appended(&self) -> Result<()> {
+    if self.options.sync_writes {
+      return self.sync_appended_now();
+    }
+    if self.options.bytes_per_sync > 0
+      && self.bytes_write.load(Ordering::SeqCst) >= self.options.bytes_per_sync
+    {
+      self.active_data_file.read().sync()?;
+      self.bytes_write.store(0, Ordering::SeqCst);
+    }
+    Ok(())
   }
 
-  /// append write data to current active data file
-  pub(crate) fn append_log_record(&self, log_record: &mut LogRecord) -> Result<LogRecordPos> {
+  /// sync the active file once for the records appended so far whatever the options say,
+  /// through the same group commit as `sync_appended`
+  pub(crate) fn sync_appended_now(&self) -> Result<()> {
+    self.sync_group(self.group_commit.appended.load(Ordering::SeqCst))
+  }
+
+  /// sync the active file for the records appended up to mark, a sync another writer runs
+  /// meanwhile covers them too, so concurrent writers share one sync
+  fn sync_group(&self, mark: u64) -> Result<()> {
+    self.group_commit.wait_synced(mark, || {
+      self.active_data_file.read().sync()?;
+      self.bytes_write.store(0, Ordering::SeqCst);
+      Ok(())
+    })
+  }
+
+  /// append write data to current active data file without syncing, giving up with
+  /// `Errors::LockTimeout` if the active file can't be locked before deadline. Writers sync
+  /// once after their last record with `sync_appended`
+  pub(crate) fn append_log_record_unsynced_until(
+    &self,
+    log_record: &mut LogRecord,
+    deadline: Option<Instant>,
+  ) -> Result<LogRecordPos> {
     let dir_path = &self.options.dir_path;
 
+    // reject records that can't be read back before touching the data file
+    log_record.check_size()?;
+
     // encode input data
-    let enc_record = log_record.encode();
+    let enc_record = log_record.encode_with_crc_scope(self.options.crc_scope);
     let record_len = enc_record.len() as u64;
 
     // obtain current active file
-    let mut active_file = self.active_data_file.write();
-    if active_file.get_write_off() + record_len > self.options.data_file_size {
+    let mut active_file = match deadline {
+      Some(deadline) => self
+        .active_data_file
+        .try_write_until(deadline)
+        .ok_or(Errors::LockTimeout)?,
+      None => self.active_data_file.write(),
+    };
+    if active_file.logical_size() + record_len > self.options.data_file_size {
       // active file persistence
       active_file.sync()?;
 
@@ -374,174 +1684,424 @@ impl Engine {
 
       // insert old data file to hash map
       let mut old_files = self.old_data_files.write();
-      let old_file = DataFile::new(dir_path, current_fid, IOManagerType::StandardFileIO)?;
-      old_files.insert(current_fid, old_file);
+      let old_file = self.open_old_data_file(current_fid, self.old_file_io_type())?;
+      old_files.insert(current_fid, Arc::new(old_file));
 
       // open a new active data file
-      let new_file = DataFile::new(dir_path, current_fid + 1, IOManagerType::StandardFileIO)?;
+      let new_fid = self.next_file_id.fetch_add(1, Ordering::SeqCst);
+      let new_file = DataFile::new(
+        dir_path,
+        new_fid,
+        &self.options.file_suffix,
+        IOManagerType::StandardFileIO,
+      )?;
       *active_file = new_file;
+      self.record_file_created(new_fid);
     }
 
     // append write to active file
     let write_off = active_file.get_write_off();
     active_file.write(&enc_record)?;
+    self.group_commit.append(record_len);
+    self.data_size.fetch_add(record_len, Ordering::SeqCst);
+    self
+      .total_bytes_written
+      .fetch_add(record_len, Ordering::SeqCst);
 
-    let previous = self
+    self
       .bytes_write
       .fetch_add(enc_record.len(), Ordering::SeqCst);
 
-    // options to sync or not
-    let mut need_sync = self.options.sync_writes;
-    if !need_sync
-      && self.options.bytes_per_sync > 0
-      && previous + enc_record.len() >= self.options.bytes_per_sync
-    {
-      need_sync = true;
-      self.bytes_write.store(0, Ordering::SeqCst);
-    }
-
-    if need_sync {
-      active_file.sync()?;
-
-      self.bytes_write.store(0, Ordering::SeqCst);
-    }
-
     // construct log record return info
-    Ok(LogRecordPos {
+    let pos = LogRecordPos {
       file_id: active_file.get_file_id(),
       offset: write_off,
       size: enc_record.len() as u32,
-    })
+    };
+
+    Ok(pos)
   }
 
   /// load memory index from data files
-  /// traverse all data files, and process each log record
-
-  fn load_index_from_data_files(&self) -> Result<usize> {
+  /// traverse the data files of file_ids, and process each log record. Returns the latest
+  /// seq_no, along with the end offset of the last file if it was fully loaded
+  fn load_index_from_data_files(
+    &self,
+    index: &dyn index::Indexer,
+    file_ids: &[u32],
+    progress: &mut OpenProgress,
+  ) -> Result<(usize, Option<u64>)> {
     let mut current_seq_no = NON_TXN_SEQ_NO;
+    let mut write_off = None;
     // if data_files is empty then return
-    if self.file_ids.is_empty() {
-      return Ok(current_seq_no);
+    if file_ids.is_empty() {
+      return Ok((current_seq_no, write_off));
     }
 
-    // get latest unmerged file id
+    // get latest unmerged file id, merged files are only skipped if the hint file indexed them
     let mut has_merged = false;
     let mut non_merge_fid = 0;
     let merge_fin_file = self.options.dir_path.join(MERGE_FINISHED_FILE_NAME);
-    if merge_fin_file.is_file() {
+    let hint_file = self.options.dir_path.join(HINT_FILE_NAME);
+    if merge_fin_file.is_file() && hint_file.is_file() {
       let merge_file = DataFile::new_merge_fin_file(&self.options.dir_path)?;
       let merge_fin_record = merge_file.read_log_record(0)?;
-      let v = String::from_utf8(merge_fin_record.record.value).unwrap();
+      let (file_id, next_version) = parse_merge_fin_value(merge_fin_record.record.value)?;
 
-      non_merge_fid = v.parse::<u32>().unwrap();
+      // versions of the merged records indexed by the hint file
+      non_merge_fid = file_id;
+      self.next_version.fetch_max(next_version, Ordering::SeqCst);
       has_merged = true;
     }
 
-    // temporary store data related to txn
+    // temporary store data related to txn, and the number of records of write batches found
+    // without their finish record
     let mut transaction_records = HashMap::new();
+    let mut dropped_txn_records = 0;
+    let mut stopped = false;
 
-    let active_file = self.active_data_file.read();
-    let old_files = self.old_data_files.read();
-
-    // traverse each file_id, retrieve data file and load its data
-    for (i, file_id) in self.file_ids.iter().enumerate() {
+    // traverse each file_id, retrieve data file and load its data. Each file is scanned under
+    // its own read guard, no data file lock is held across the whole scan
+    for (i, file_id) in file_ids.iter().enumerate() {
       // if file_id is less than non_merge_fid, then skip
       if has_merged && *file_id < non_merge_fid {
+        progress.files_processed += 1;
+        self.report_open_progress(*progress);
         continue;
       }
 
-      let mut offset = 0;
-      loop {
-        // read data in loop
-        let log_record_res = match *file_id == active_file.get_file_id() {
-          true => active_file.read_log_record(offset),
-          _ => {
-            let data_file = old_files.get(file_id).unwrap();
-            data_file.read_log_record(offset)
-          }
-        };
+      let is_active = self.active_data_file.read().get_file_id() == *file_id;
+      let is_last = i == file_ids.len() - 1;
+      let records_loaded = progress.records_loaded;
+      let scanned = match is_active {
+        true => self.load_index_from_data_file(
+          index,
+          &self.active_data_file.read(),
+          is_last,
+          &mut transaction_records,
+          &mut dropped_txn_records,
+          &mut current_seq_no,
+          progress,
+        )?,
+        false => self.load_index_from_data_file(
+          index,
+          self.old_data_files.read().get(file_id).unwrap(),
+          is_last,
+          &mut transaction_records,
+          &mut dropped_txn_records,
+          &mut current_seq_no,
+          progress,
+        )?,
+      };
+      // reached a write batch after max_seq_no, the rest is not loaded
+      let Some(offset) = scanned else {
+        stopped = true;
+        break;
+      };
+      event!(
+        DEBUG,
+        "loaded data file",
+        file_id = file_id,
+        records = progress.records_loaded - records_loaded
+      );
+
+      progress.files_processed += 1;
+      self.report_open_progress(*progress);
+
+      if is_last {
+        write_off = Some(offset);
+      }
+    }
 
-        let (mut log_record, size) = match log_record_res {
-          Ok(result) => (result.record, result.size),
-          Err(e) => {
-            if e == Errors::ReadDataFileEOF {
-              break;
-            }
-            return Err(e);
-          }
-        };
+    // write batches still without a finish record never committed, e.g. crashed mid commit.
+    // Those after max_seq_no are just not loaded
+    if !stopped {
+      dropped_txn_records += transaction_records.values().map(Vec::len).sum::<usize>();
+    }
+    if dropped_txn_records > 0 {
+      warn!(
+        "discarded {} records of uncommitted write batches",
+        dropped_txn_records
+      );
+    }
+    Ok((current_seq_no, write_off))
+  }
 
-        // construct memory index
-        let log_record_pos = LogRecordPos {
-          file_id: *file_id,
-          offset,
-          size: size as u32,
-        };
+  // load index from the log records of one data file, txn records are buffered in
+  // transaction_records until their finish record, records of batches that never finished are
+  // counted in dropped_txn_records. Returns the end offset of the file, or None if a write
+  // batch after max_seq_no is reached
+  fn load_index_from_data_file(
+    &self,
+    index: &dyn index::Indexer,
+    data_file: &DataFile,
+    is_last: bool,
+    transaction_records: &mut HashMap<usize, Vec<TransactionRecord>>,
+    dropped_txn_records: &mut usize,
+    current_seq_no: &mut usize,
+    progress: &mut OpenProgress,
+  ) -> Result<Option<u64>> {
+    let file_id = data_file.get_file_id();
+    let mut offset = 0;
+    let mut corrupt = None;
+    for record in data_file.iter_records() {
+      let (log_record_pos, mut log_record, size) = match record {
+        Ok(record) => record,
+        // the file is cut back to the records before it below
+        Err(e @ (Errors::InvalidLogRecordCrc | Errors::InvalidLogRecordType { .. }))
+          if is_last && self.options.recovery_mode == RecoveryMode::Lenient =>
+        {
+          corrupt = Some(e);
+          break;
+        }
+        Err(e) => return Err(e),
+      };
 
-        // parse key, obtain actual key and seq_no
-        let (real_key, seq_no) = parse_log_record_key(log_record.key.clone());
-        // non txn log record, update index as usual
-        if seq_no == NON_TXN_SEQ_NO {
-          self.update_index(real_key, log_record.rec_type, log_record_pos)?;
+      // parse key, obtain actual key, seq_no and version
+      let (real_key, seq_no, version) = parse_log_record_key_version(log_record.key.clone())?;
+      self.next_version.fetch_max(version + 1, Ordering::SeqCst);
+      // non txn log record, update index as usual
+      if seq_no == NON_TXN_SEQ_NO {
+        self.update_index(index, real_key, log_record.rec_type, log_record_pos)?;
+      } else {
+        // txn log record commit, update index
+        if log_record.rec_type == LogRecordType::TxnFinished {
+          // commits are serialized, so everything after this is newer than max_seq_no
+          if self
+            .max_seq_no
+            .is_some_and(|max_seq_no| seq_no > max_seq_no)
+          {
+            return Ok(None);
+          }
+          let records = transaction_records.remove(&seq_no).unwrap_or_default();
+          for txn_record in records {
+            self.update_index(
+              index,
+              txn_record.record.key,
+              txn_record.record.rec_type,
+              txn_record.pos,
+            )?;
+          }
         } else {
-          // txn log record commit, update index
-          if log_record.rec_type == LogRecordType::TxnFinished {
-            let records: &Vec<TransactionRecord> = transaction_records.get(&seq_no).unwrap();
-            for txn_record in records.iter() {
-              self.update_index(
-                txn_record.record.key.clone(),
-                txn_record.record.rec_type,
-                txn_record.pos,
-              )?;
+          // commits are serialized, a record of a newer batch means the buffered ones never
+          // finished. Dropping them keeps at most one batch buffered however many crashed
+          transaction_records.retain(|buffered_seq_no, records| {
+            let keep = *buffered_seq_no == seq_no;
+            if !keep {
+              *dropped_txn_records += records.len();
             }
-            transaction_records.remove(&seq_no);
-          } else {
-            log_record.key = real_key;
-            transaction_records
-              .entry(seq_no)
-              .or_insert_with(|| Vec::new())
-              .push(TransactionRecord {
-                record: log_record,
-                pos: log_record_pos,
-              });
-          }
-        }
-
-        // seq_no update
-        if seq_no > current_seq_no {
-          current_seq_no = seq_no;
+            keep
+          });
+          log_record.key = real_key;
+          transaction_records
+            .entry(seq_no)
+            .or_insert_with(|| Vec::new())
+            .push(TransactionRecord {
+              record: log_record,
+              pos: log_record_pos,
+            });
         }
+      }
 
-        // offset move, read next log record
-        offset += size as u64;
+      // seq_no update
+      if seq_no > *current_seq_no {
+        *current_seq_no = seq_no;
       }
 
-      // set active file offset
-      if i == self.file_ids.len() - 1 {
-        active_file.set_write_off(offset);
+      // end of the records loaded so far
+      offset += size as u64;
+      progress.records_loaded += 1;
+    }
+
+    // zeros preallocated past the records, or left by a torn write, read as the end of the
+    // records, as does a corrupt record in lenient mode. New records are appended at the end
+    // of the file, so it's cut back to them
+    if is_last && (corrupt.is_some() || offset < data_file.physical_size()?) {
+      match corrupt {
+        Some(e) => warn!(
+          "corrupt log record in data file {} at offset {}: {}, truncating the file",
+          file_id, offset, e
+        ),
+        None => warn!(
+          "incomplete log record at the end of data file {} at offset {}, truncating the file",
+          file_id, offset
+        ),
       }
+      self.truncate_data_file(file_id, offset)?;
+    }
+    Ok(Some(offset))
+  }
+
+  // drop everything from offset on in a data file, the records after a torn write are lost
+  fn truncate_data_file(&self, file_id: u32, offset: u64) -> Result<()> {
+    // a read only engine never writes, the rest of the file is just not loaded
+    if self.is_read_only() {
+      return Ok(());
+    }
+    let file_name = get_data_file_name(&self.options.dir_path, file_id, &self.options.file_suffix);
+    let file = OpenOptions::new()
+      .write(true)
+      .open(file_name)
+      .map_err(|_| Errors::FailedToOpenDataFile)?;
+    file
+      .set_len(offset)
+      .map_err(|_| Errors::FailedToWriteToDataFile)?;
+    file.sync_all().map_err(|_| Errors::FailedToSyncToDataFile)
+  }
+
+  /// report index loading progress to the user callback, if any
+  pub(crate) fn report_open_progress(&self, progress: OpenProgress) {
+    if let Some(open_progress) = &self.options.open_progress {
+      open_progress(progress);
     }
-    Ok(current_seq_no)
   }
 
   /// load seq_no under B+Tree index type
+  ///
+  /// The seq_no file is kept until close durably replaces it. If it is corrupt, or stale
+  /// because data was written after it, seq_no is recomputed from data files instead.
   fn load_seq_no(&self) -> (bool, usize) {
     let file_name = self.options.dir_path.join(SEQ_NO_FILE_NAME);
     if !file_name.is_file() {
+      // no write batch is allowed, but versions of other writes must keep increasing
+      if !self.is_initial {
+        if let Err(e) = self.load_seq_no_from_data_files() {
+          warn!("failed to recompute versions from data files error: {}", e);
+        }
+      }
       return (false, 0);
     }
-    let seq_no_file = DataFile::new_seq_no_file(&self.options.dir_path).unwrap();
-    let record = match seq_no_file.read_log_record(0) {
-      Ok(res) => res.record,
-      Err(e) => panic!("failed to read seq_no: {}", e),
+    if let Some(seq_no) = self.read_seq_no_file() {
+      return (true, seq_no);
+    }
+
+    warn!("seq_no file is corrupt or stale, recomputing from data files");
+    match self.load_seq_no_from_data_files() {
+      Ok(seq_no) => (true, seq_no),
+      Err(e) => {
+        warn!("failed to recompute seq_no from data files error: {}", e);
+        (false, 0)
+      }
+    }
+  }
+
+  // read seq_no file, checking crc and that it matches the current active file. Restores the
+  // next version too, if the file has it
+  fn read_seq_no_file(&self) -> Option<usize> {
+    let seq_no_file = DataFile::new_seq_no_file(&self.options.dir_path).ok()?;
+    let record = seq_no_file.read_log_record(0).ok()?.record;
+    if record.key != SEQ_NO_KEY.as_bytes() {
+      return None;
+    }
+
+    let v = String::from_utf8(record.value).ok()?;
+    match v.split(':').collect::<Vec<_>>().as_slice() {
+      // unversioned seq_no file written by earlier releases
+      [seq_no] => seq_no.parse::<usize>().ok(),
+      // written before versions, data files hold no versions either
+      ["1", seq_no, file_id, file_size] => {
+        self.check_seq_no_file_active(file_id, file_size)?;
+        seq_no.parse::<usize>().ok()
+      }
+      [SEQ_NO_FILE_VERSION, seq_no, next_version, file_id, file_size] => {
+        self.check_seq_no_file_active(file_id, file_size)?;
+        let seq_no = seq_no.parse::<usize>().ok()?;
+        let next_version = next_version.parse::<u64>().ok()?;
+        self.next_version.fetch_max(next_version, Ordering::SeqCst);
+        Some(seq_no)
+      }
+      _ => None,
+    }
+  }
+
+  // whether the active file id and size saved in the seq_no file match the current active file,
+  // otherwise data was written after the file
+  fn check_seq_no_file_active(&self, file_id: &str, file_size: &str) -> Option<()> {
+    let active_file = self.active_data_file.read();
+    if file_id.parse::<u32>().ok()? != active_file.get_file_id()
+      || file_size.parse::<u64>().ok()? != active_file.logical_size()
+    {
+      return None;
+    }
+    Some(())
+  }
+
+  // read meta file, checking crc and version. Returns the total bytes written along, 0 if the
+  // meta file predates the counter
+  fn read_meta_file(&self) -> Option<(DbInfo, u64)> {
+    if !self.options.dir_path.join(META_FILE_NAME).is_file() {
+      return None;
+    }
+    let meta_file = DataFile::new_meta_file(&self.options.dir_path).ok()?;
+    let record = meta_file.read_log_record(0).ok()?.record;
+    if record.key != META_KEY.as_bytes() {
+      return None;
+    }
+
+    let v = String::from_utf8(record.value).ok()?;
+    match v.split(':').collect::<Vec<_>>().as_slice() {
+      ["1", created_at, last_merge_at] => Some((parse_meta(created_at, last_merge_at)?, 0)),
+      [META_FILE_VERSION, created_at, last_merge_at, total_bytes_written] => Some((
+        parse_meta(created_at, last_merge_at)?,
+        total_bytes_written.parse::<u64>().ok()?,
+      )),
+      _ => None,
+    }
+  }
+
+  // write meta file into a temp file and rename it over the previous one, along with the
+  // current total bytes written
+  pub(crate) fn save_meta(&self, info: &DbInfo) -> Result<()> {
+    let record = LogRecord {
+      key: META_KEY.as_bytes().to_vec(),
+      value: format!(
+        "{}:{}:{}:{}",
+        META_FILE_VERSION,
+        to_millis(info.created_at),
+        info.last_merge_at.map(to_millis).unwrap_or_default(),
+        self.total_bytes_written.load(Ordering::SeqCst)
+      )
+      .into(),
+      rec_type: LogRecordType::Normal,
     };
-    let v = String::from_utf8(record.value).unwrap();
-    let seq_no = v.parse::<usize>().unwrap();
 
-    // remove seq_no file, avoiding repeated writing
-    fs::remove_file(file_name).unwrap();
+    let tmp_file_name = self.options.dir_path.join(META_TMP_FILE_NAME);
+    if tmp_file_name.is_file() {
+      fs::remove_file(&tmp_file_name).map_err(|_| Errors::FailedToWriteToDataFile)?;
+    }
+    let meta_file = DataFile::new_meta_tmp_file(&self.options.dir_path)?;
+    meta_file.write(&record.encode())?;
+    meta_file.sync()?;
+    if let Err(e) = fs::rename(tmp_file_name, self.options.dir_path.join(META_FILE_NAME)) {
+      error!("failed to rename meta file error: {}", e);
+      return Err(Errors::FailedToWriteToDataFile);
+    }
+    Ok(())
+  }
+
+  // traverse all data files to find the next seq_no, the next version is restored along
+  fn load_seq_no_from_data_files(&self) -> Result<usize> {
+    let mut current_seq_no = NON_TXN_SEQ_NO;
+    let active_file = self.active_data_file.read();
+    let old_files = self.old_data_files.read();
+
+    for file_id in self.file_ids.iter() {
+      let data_file: &DataFile = match *file_id == active_file.get_file_id() {
+        true => &active_file,
+        _ => old_files.get(file_id).unwrap(),
+      };
 
-    (true, seq_no)
+      for record in data_file.iter_records() {
+        let (_, log_record, _) = record?;
+        let (_, seq_no, version) = parse_log_record_key_version(log_record.key)?;
+        self.next_version.fetch_max(version + 1, Ordering::SeqCst);
+        if seq_no > current_seq_no {
+          current_seq_no = seq_no;
+        }
+      }
+    }
+
+    Ok(current_seq_no + 1)
   }
 
   /// Updates in-memory index upon loading
@@ -551,9 +2111,15 @@ impl Engine {
   /// it increments a counter for reclaimed space size with the old position's size.
   /// For a deleted record, it removes the key from the index and updates the reclaimed space size counter accordingly.
   ///
-  fn update_index(&self, key: Vec<u8>, rec_type: LogRecordType, pos: LogRecordPos) -> Result<()> {
-    if rec_type == LogRecordType::Normal {
-      if let Some(old_pos) = self.index.put(key.clone(), pos) {
+  fn update_index(
+    &self,
+    index: &dyn index::Indexer,
+    key: Vec<u8>,
+    rec_type: LogRecordType,
+    pos: LogRecordPos,
+  ) -> Result<()> {
+    if rec_type == LogRecordType::Normal || rec_type == LogRecordType::ValueRef {
+      if let Some(old_pos) = index.put(key.clone(), pos)? {
         // Increments the reclaimed space size counter by the size of the old position.
         self
           .reclaim_size
@@ -565,7 +2131,7 @@ impl Engine {
       // Starts with the current record's size for the reclaimed space.
       let mut size = pos.size;
       // Attempts to remove the key from the index. If the key exists, returns the old position.
-      if let Some(old_pos) = self.index.delete(key) {
+      if let Some(old_pos) = index.delete(key)? {
         // Adds the size of the old position to the reclaimed space size.
         size += old_pos.size;
       }
@@ -578,14 +2144,242 @@ impl Engine {
   /// reset io_manager type for all data files
   fn reset_io_type(&self) {
     let mut active_file = self.active_data_file.write();
-    active_file.set_io_manager(&self.options.dir_path, IOManagerType::StandardFileIO);
+    let dir_path = &self.options.dir_path;
+    let file_suffix = &self.options.file_suffix;
+    active_file.set_io_manager(dir_path, file_suffix, IOManagerType::StandardFileIO);
     let mut old_files = self.old_data_files.write();
     for (_, file) in old_files.iter_mut() {
-      file.set_io_manager(&self.options.dir_path, IOManagerType::StandardFileIO);
+      // only called on open, before any reader could clone a file out
+      let file = Arc::get_mut(file).expect("old data file is shared while opening");
+      match &self.file_cache {
+        Some(file_cache) => {
+          file.set_cached_io_manager(dir_path, file_suffix, self.old_file_io_type(), file_cache)
+        }
+        None => file.set_io_manager(dir_path, file_suffix, self.old_file_io_type()),
+      }
+    }
+  }
+
+  /// io type of old data files, which are never written again
+  /// open an old data file, through the file cache if `max_open_files` limits open handles
+  pub(crate) fn open_old_data_file(
+    &self,
+    file_id: u32,
+    io_type: IOManagerType,
+  ) -> Result<DataFile> {
+    let dir_path = &self.options.dir_path;
+    let file_suffix = &self.options.file_suffix;
+    match &self.file_cache {
+      Some(file_cache) => DataFile::new_cached(dir_path, file_id, file_suffix, io_type, file_cache),
+      None => DataFile::new(dir_path, file_id, file_suffix, io_type),
+    }
+  }
+
+  pub(crate) fn old_file_io_type(&self) -> IOManagerType {
+    match self.options.mmap_old_files {
+      true => IOManagerType::MemoryMap,
+      false => IOManagerType::StandardFileIO,
+    }
+  }
+}
+
+/// Reads values by position like `Engine::get_value_by_position`, holding the data files
+/// instead of the engine
+pub(crate) struct ValueReader {
+  active_data_file: Arc<RwLock<DataFile>>,
+  old_data_files: Arc<RwLock<HashMap<u32, Arc<DataFile>>>>,
+  verify_crc: bool,
+  value_codec: Option<Arc<dyn ValueCodec>>,
+}
+
+impl ValueReader {
+  pub(crate) fn read(&self, log_record_pos: &LogRecordPos) -> Result<Bytes> {
+    let value = read_live_value(
+      &self.active_data_file,
+      &self.old_data_files,
+      self.verify_crc,
+      log_record_pos,
+    )?;
+    match &self.value_codec {
+      Some(codec) => codec.decode(&value).map(Bytes::from),
+      None => Ok(value),
     }
   }
 }
 
+// read the log record at position, `Errors::KeyNotFound` for a tombstone
+fn read_live_record(
+  active_data_file: &RwLock<DataFile>,
+  old_data_files: &RwLock<HashMap<u32, Arc<DataFile>>>,
+  verify_crc: bool,
+  log_record_pos: &LogRecordPos,
+) -> Result<LogRecord> {
+  // Retrieves LogRecord from the specified file data.
+  let active_file = active_data_file.read();
+  let read_res = match active_file.get_file_id() == log_record_pos.file_id {
+    true => active_file.read_log_record_checked(log_record_pos.offset, verify_crc),
+    false => {
+      // a rotation moves the active file into the old files before releasing its lock
+      drop(active_file);
+      // hold the file rather than the map, the map may be swapped while reading
+      let data_file = old_data_files
+        .read()
+        .get(&log_record_pos.file_id)
+        .cloned()
+        .ok_or(Errors::DataFileNotFound)?;
+      data_file.read_log_record_checked(log_record_pos.offset, verify_crc)
+    }
+  };
+  let log_record = match read_res {
+    Ok(read_log_record) => read_log_record.record,
+    // the index points past the end of the data file, the record was lost
+    Err(Errors::ReadDataFileEOF) => return Err(Errors::TruncatedLogRecord),
+    Err(e) => return Err(e),
+  };
+
+  // Determines the type of the log record.
+  if let LogRecordType::Deleted = log_record.rec_type {
+    return Err(Errors::KeyNotFound);
+  };
+  Ok(log_record)
+}
+
+// read the value at position straight into the returned bytes, `Errors::KeyNotFound` for a
+// tombstone. A value stored by another record is read from there
+pub(crate) fn read_live_value(
+  active_data_file: &RwLock<DataFile>,
+  old_data_files: &RwLock<HashMap<u32, Arc<DataFile>>>,
+  verify_crc: bool,
+  log_record_pos: &LogRecordPos,
+) -> Result<Bytes> {
+  match read_stored_value(active_data_file, old_data_files, verify_crc, log_record_pos)? {
+    StoredValue::Inline(value) => Ok(value),
+    // merge only references records holding the value, there's never a second hop
+    StoredValue::Ref(value_pos) => {
+      match read_stored_value(active_data_file, old_data_files, verify_crc, &value_pos)? {
+        StoredValue::Inline(value) => Ok(value),
+        StoredValue::Ref(_) => Err(Errors::InvalidLogRecordType {
+          found: LogRecordType::ValueRef as u8,
+        }),
+      }
+    }
+  }
+}
+
+fn read_stored_value(
+  active_data_file: &RwLock<DataFile>,
+  old_data_files: &RwLock<HashMap<u32, Arc<DataFile>>>,
+  verify_crc: bool,
+  log_record_pos: &LogRecordPos,
+) -> Result<StoredValue> {
+  let LogRecordPos {
+    file_id,
+    offset,
+    size,
+  } = *log_record_pos;
+  let active_file = active_data_file.read();
+  if active_file.get_file_id() == file_id {
+    return active_file.read_value(offset, size, verify_crc);
+  }
+  // same as `read_live_record`, the old file is held rather than the map
+  drop(active_file);
+  let data_file = old_data_files
+    .read()
+    .get(&file_id)
+    .cloned()
+    .ok_or(Errors::DataFileNotFound)?;
+  data_file.read_value(offset, size, verify_crc)
+}
+
+// current time truncated to the millis precision of the meta file
+pub(crate) fn meta_time_now() -> SystemTime {
+  from_millis(to_millis(SystemTime::now()))
+}
+
+pub(crate) fn to_millis(t: SystemTime) -> u64 {
+  t.duration_since(UNIX_EPOCH)
+    .map(|d| d.as_millis() as u64)
+    .unwrap_or_default()
+}
+
+pub(crate) fn from_millis(millis: u64) -> SystemTime {
+  UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+// database metadata from the saved creation and last merge millis, 0 if never merged
+fn parse_meta(created_at: &str, last_merge_at: &str) -> Option<DbInfo> {
+  let created_at = created_at.parse::<u64>().ok()?;
+  let last_merge_at = last_merge_at.parse::<u64>().ok()?;
+  Some(DbInfo {
+    created_at: from_millis(created_at),
+    last_merge_at: match last_merge_at {
+      0 => None,
+      millis => Some(from_millis(millis)),
+    },
+  })
+}
+
+// creation times of the data files saved in the file times file, empty if it's missing or
+// corrupt
+pub(crate) fn read_file_times(dir_path: &Path) -> HashMap<u32, SystemTime> {
+  if !dir_path.join(FILE_TIMES_FILE_NAME).is_file() {
+    return HashMap::new();
+  }
+  let file_times = DataFile::new_file_times_file(dir_path)
+    .and_then(|times_file| times_file.read_log_record(0))
+    .ok()
+    .filter(|times_record| times_record.record.key == FILE_TIMES_KEY.as_bytes())
+    .and_then(|times_record| String::from_utf8(times_record.record.value).ok())
+    .and_then(|file_times| {
+      file_times
+        .split(',')
+        .filter(|file_time| !file_time.is_empty())
+        .map(|file_time| {
+          let (file_id, created_at) = file_time.split_once(':')?;
+          Some((
+            file_id.parse::<u32>().ok()?,
+            from_millis(created_at.parse::<u64>().ok()?),
+          ))
+        })
+        .collect::<Option<HashMap<_, _>>>()
+    });
+  file_times.unwrap_or_else(|| {
+    warn!("failed to read data file creation times, counting the files as created now");
+    HashMap::new()
+  })
+}
+
+// write file times into a temp file and rename it over the previous one
+pub(crate) fn save_file_times(
+  dir_path: &Path,
+  file_times: &HashMap<u32, SystemTime>,
+) -> Result<()> {
+  let mut file_ids: Vec<u32> = file_times.keys().copied().collect();
+  file_ids.sort();
+  let value: Vec<String> = file_ids
+    .iter()
+    .map(|file_id| format!("{}:{}", file_id, to_millis(file_times[file_id])))
+    .collect();
+  let record = LogRecord {
+    key: FILE_TIMES_KEY.as_bytes().to_vec(),
+    value: value.join(",").into_bytes(),
+    rec_type: LogRecordType::Normal,
+  };
+
+  let tmp_file_name = dir_path.join(FILE_TIMES_TMP_FILE_NAME);
+  if tmp_file_name.is_file() {
+    fs::remove_file(&tmp_file_name).map_err(|_| Errors::FailedToWriteToDataFile)?;
+  }
+  let times_file = DataFile::new_file_times_tmp_file(dir_path)?;
+  times_file.write(&record.encode())?;
+  times_file.sync()?;
+  if let Err(e) = fs::rename(tmp_file_name, dir_path.join(FILE_TIMES_FILE_NAME)) {
+    error!("failed to rename file times file error: {}", e);
+    return Err(Errors::FailedToWriteToDataFile);
+  }
+  Ok(())
+}
+
 impl Drop for Engine {
   fn drop(&mut self) {
     if let Err(e) = self.close() {
@@ -594,8 +2388,118 @@ impl Drop for Engine {
   }
 }
 
-// load data files from database directory
-fn load_data_files<P>(dir_path: P, use_mmap: bool) -> Result<Vec<DataFile>>
+/// Cheaply cloneable handle to a shared engine, derefs to `Engine` for all its methods.
+/// The engine is closed when the last handle is closed or dropped.
+#[derive(Clone)]
+pub struct Db {
+  engine: Arc<Engine>,
+}
+
+impl Db {
+  /// open a bitkv storage engine instance behind a shared handle
+  pub fn open(opts: Options) -> Result<Self> {
+    Ok(Self::from(Engine::open(opts)?))
+  }
+
+  /// open a shared engine like `Engine::open_existing`
+  pub fn open_existing(opts: Options) -> Result<Self> {
+    Ok(Self::from(Engine::open_existing(opts)?))
+  }
+
+  /// the shared engine, e.g. for APIs taking `Arc<Engine>`
+  pub fn engine(&self) -> &Arc<Engine> {
+    &self.engine
+  }
+
+  /// release this handle, closing the engine if it is the last one
+  pub fn close(self) -> Result<()> {
+    match Arc::try_unwrap(self.engine) {
+      Ok(engine) => engine.close(),
+      // other handles are alive, the last of them closes the engine
+      Err(_) => Ok(()),
+    }
+  }
+}
+
+impl From<Engine> for Db {
+  fn from(engine: Engine) -> Self {
+    Self {
+      engine: Arc::new(engine),
+    }
+  }
+}
+
+impl std::ops::Deref for Db {
+  type Target = Engine;
+
+  fn deref(&self) -> &Engine {
+    &self.engine
+  }
+}
+
+// create a missing database directory along with its parents, if allowed
+fn create_db_dir(dir_path: &Path, create_dirs: bool) -> Result<()> {
+  if dir_path.is_dir() {
+    return Ok(());
+  }
+  if !create_dirs {
+    warn!("database directory {:?} does not exist", dir_path);
+    return Err(Errors::FailedToCreateDatabaseDir);
+  }
+  if let Err(e) = fs::create_dir_all(dir_path) {
+    warn!("failed to create database directory error: {}", e);
+    return Err(Errors::FailedToCreateDatabaseDir);
+  }
+  Ok(())
+}
+
+// take the exclusive file lock of a database directory
+fn lock_db_dir(dir_path: &Path) -> Result<File> {
+  let lock_file = fs::OpenOptions::new()
+    .read(true)
+    .create(true)
+    .append(true)
+    .open(dir_path.join(FILE_LOCK_NAME))
+    .unwrap();
+  if lock_file.try_lock_exclusive().is_err() {
+    return Err(Errors::DatabaseIsUsing);
+  }
+  Ok(lock_file)
+}
+
+// whether both paths name the same existing directory
+fn is_same_dir(a: &Path, b: &Path) -> bool {
+  match (fs::canonicalize(a), fs::canonicalize(b)) {
+    (Ok(a), Ok(b)) => a == b,
+    _ => false,
+  }
+}
+
+// whether dir_path holds a database, its meta file or any data file
+fn is_database_dir(dir_path: &Path, file_suffix: &str) -> bool {
+  if dir_path.join(META_FILE_NAME).is_file() {
+    return true;
+  }
+  match fs::read_dir(dir_path) {
+    Ok(dir) => dir.flatten().any(|file| {
+      file
+        .file_name()
+        .to_str()
+        .and_then(|file_name| parse_data_file_name(file_name, file_suffix))
+        .is_some()
+    }),
+    Err(_) => false,
+  }
+}
+
+// load data files from database directory, all but the last one, which becomes the active
+// file, go through file_cache if given
+fn load_data_files<P>(
+  dir_path: P,
+  file_suffix: &str,
+  use_mmap: bool,
+  file_cache: Option<&Arc<FileCache>>,
+) -> Result<Vec<DataFile>>
 where
   P: AsRef<Path>,
 {
@@ -613,17 +2517,9 @@ where
     let file_os_str = file.file_name();
     let file_name = file_os_str.to_str().unwrap();
 
-    // determine if file name ends up with .data
-    if file_name.ends_with(DATA_FILE_NAME_SUFFIX) {
-      let splited_names: Vec<&str> = file_name.split('.').collect();
-      let file_id = match splited_names[0].parse::<u32>() {
-        Ok(fid) => fid,
-        Err(_) => {
-          return Err(Errors::DatabaseDirectoryCorrupted);
-        }
-      };
-
-      file_ids.push(file_id);
+    // determine if file name ends up with the data file suffix
+    if let Some(file_id) = parse_data_file_name(file_name, file_suffix) {
+      file_ids.push(file_id?);
     }
   }
 
@@ -636,30 +2532,19 @@ where
   file_ids.sort();
 
   // traverse file_ids, sequentially loading data files
+  let active_file_id = file_ids[file_ids.len() - 1];
   for file_id in file_ids.iter() {
     let mut io_type = IOManagerType::StandardFileIO;
     if use_mmap {
       io_type = IOManagerType::MemoryMap;
     }
-    let data_file = DataFile::new(&dir_path, *file_id, io_type)?;
+    let data_file = match file_cache {
+      Some(file_cache) if *file_id != active_file_id => {
+        DataFile::new_cached(&dir_path, *file_id, file_suffix, io_type, file_cache)?
+      }
+      _ => DataFile::new(&dir_path, *file_id, file_suffix, io_type)?,
+    };
     data_files.push(data_file);
   }
   Ok(data_files)
 }
-
-fn check_options(opts: &Options) -> Option<Errors> {
-  let dir_path = opts.dir_path.to_str();
-  if dir_path.is_none() || dir_path.unwrap().is_empty() {
-    return Some(Errors::DirPathIsEmpty);
-  }
-
-  if opts.data_file_size == 0 {
-    return Some(Errors::DataFileSizeTooSmall);
-  }
-
-  if opts.file_merge_threshold < 0f32 || opts.file_merge_threshold > 1f32 {
-    return Some(Errors::InvalidMergeThreshold);
-  }
-
-  None
-}
@@ -1,11 +1,31 @@
-use std::{fs, path::PathBuf};
+use std::{
+  fs,
+  path::PathBuf,
+  sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex,
+  },
+  thread,
+  time::Duration,
+};
 
 use bytes::Bytes;
 
 use crate::{
-  db::Engine,
-  errors::Errors,
-  option::{self, Options},
+  batch::log_record_key_with_seq,
+  codec::ValueCodec,
+  data::{
+    data_file::{get_data_file_name, DataFile},
+    log_record::{LogRecord, LogRecordPos, LogRecordType},
+  },
+  db::{Db, Engine, ValueSizeHistogram, BPTREE_DIRTY_FILE_NAME},
+  errors::{Errors, Result},
+  fio::{file_io::FileIO, hooked::HookedIO},
+  index::{bptree::BPTREE_INDEX_FILE_NAME, btree::BTree, IndexIterator, Indexer},
+  option::{
+    self, ExportFormat, IOManagerType, OpenProgress, Options, EXPORT_BUFFER_SIZE,
+    EXPORT_INDEX_CHUNK_LEN, GET_MANY_MAX_CHUNK_LEN, GET_MANY_MIN_CHUNK_LEN,
+  },
   util::rand_kv::{get_test_key, get_test_value},
 };
 
@@ -21,7 +41,7 @@ fn test_engine_put() {
   assert!(res1.is_ok());
   let res2 = engine.get(get_test_key(11));
   assert!(res2.is_ok());
-  assert!(res2.unwrap().len() > 0);
+  assert!(!res2.unwrap().is_empty());
 
   // put another item repeatedly
   let res3 = engine.put(get_test_key(22), get_test_value(11));
@@ -74,7 +94,7 @@ fn test_engine_get() {
   assert!(res1.is_ok());
   let res2 = engine.get(get_test_key(11));
   assert!(res2.is_ok());
-  assert!(res2.unwrap().len() > 0);
+  assert!(!res2.unwrap().is_empty());
 
   // read after putting another items
   let res3 = engine.put(get_test_key(22), Bytes::from("22"));
@@ -122,6 +142,207 @@ fn test_engine_get() {
   std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
 }
 
+#[test]
+fn test_engine_get_errors() {
+  let mut opt = Options::default();
+  opt.dir_path = PathBuf::from("/tmp/bitkv-rs-get-errors");
+  let engine = Engine::open(opt.clone()).expect("fail to open engine");
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+  engine.put(get_test_key(2), get_test_value(2)).unwrap();
+  engine.delete(get_test_key(2)).unwrap();
+  let file_id = engine.active_data_file.read().get_file_id();
+  let file_name = get_data_file_name(&opt.dir_path, file_id, &opt.file_suffix);
+
+  // reads failing is an error of its own, not found keys aren't read at all
+  engine.active_data_file.write().replace_io_manager(Box::new(
+    HookedIO::new(Box::new(FileIO::new(&file_name).unwrap()))
+      .on_read(|_| Err(Errors::FailedToReadFromDataFile)),
+  ));
+  assert_eq!(
+    Errors::FailedToReadFromDataFile,
+    engine.get(get_test_key(1)).unwrap_err()
+  );
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.get(get_test_key(2)).unwrap_err()
+  );
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.get(get_test_key(3)).unwrap_err()
+  );
+  engine
+    .active_data_file
+    .write()
+    .replace_io_manager(Box::new(FileIO::new(&file_name).unwrap()));
+  assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+
+  // a record cut off the data file, wholly or partly, is truncated, not missing nor corrupt
+  engine.put(get_test_key(3), get_test_value(3)).unwrap();
+  let pos = engine.index.read().get(get_test_key(3).to_vec()).unwrap();
+  for len in [pos.offset + pos.size as u64 - 1, pos.offset] {
+    fs::OpenOptions::new()
+      .write(true)
+      .open(&file_name)
+      .unwrap()
+      .set_len(len)
+      .unwrap();
+    assert_eq!(
+      Errors::TruncatedLogRecord,
+      engine.get(get_test_key(3)).unwrap_err()
+    );
+  }
+  assert_eq!(
+    Errors::TruncatedLogRecord,
+    engine.value_len(get_test_key(3)).unwrap_err()
+  );
+  assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+  std::mem::drop(engine);
+
+  std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+}
+
+#[test]
+fn test_engine_rotate_at_logical_size() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-rotate-logical-size");
+  opts.data_file_size = 4096;
+  opts.max_key_size = 64;
+  opts.max_value_size = 64;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..10 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  let file_id = engine.active_data_file.read().get_file_id();
+  let logical_size = engine.active_data_file.read().logical_size();
+  std::mem::drop(engine);
+
+  // a crash leaves zeros up to the data file size past the records, as preallocation would
+  let file_name = get_data_file_name(&opts.dir_path, file_id, &opts.file_suffix);
+  fs::OpenOptions::new()
+    .write(true)
+    .open(&file_name)
+    .unwrap()
+    .set_len(opts.data_file_size)
+    .unwrap();
+
+  // recovery ends the file at the last record, the next record still fits in it
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  let active_file = engine.active_data_file.read();
+  assert_eq!(logical_size, active_file.logical_size());
+  assert_eq!(logical_size, active_file.physical_size().unwrap());
+  std::mem::drop(active_file);
+  engine.put(get_test_key(10), get_test_value(10)).unwrap();
+  let pos = engine.index.read().get(get_test_key(10).to_vec()).unwrap();
+  assert_eq!(file_id, pos.file_id);
+  assert_eq!(logical_size, pos.offset);
+  for i in 0..=10 {
+    assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+  }
+  std::mem::drop(engine);
+
+  std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove dir");
+}
+
+#[test]
+fn test_engine_get_value_sizes() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-get-value-sizes");
+  opts.data_file_size = 1024 * 1024; // 1MB
+  opts.max_key_size = 1024;
+  opts.max_value_size = 256 * 1024;
+  opts.verify_checksum_on_read = true;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  let value_of = |i: usize, size: usize| -> Bytes { (0..size).map(|j| (i + j) as u8).collect() };
+  let sizes = [0, 1, 127, 128, 16 * 1024, 200 * 1024];
+  for (i, size) in sizes.into_iter().enumerate() {
+    engine.put(get_test_key(i), value_of(i, size)).unwrap();
+  }
+  // rotate the first values into old data files
+  for i in 100..10000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  assert!(engine.get_engine_stat().unwrap().data_file_num > 1);
+  engine.delete(get_test_key(2)).unwrap();
+  // the last value stays in the active data file
+  engine
+    .put(get_test_key(50), value_of(50, 100 * 1024))
+    .unwrap();
+
+  let check = |engine: &Engine| {
+    for (i, size) in sizes.into_iter().enumerate() {
+      match engine.get(get_test_key(i)) {
+        Ok(value) => assert_eq!(value_of(i, size), value),
+        Err(e) => {
+          assert_eq!(2, i);
+          assert_eq!(Errors::KeyNotFound, e);
+        }
+      }
+    }
+    assert_eq!(
+      value_of(50, 100 * 1024),
+      engine.get(get_test_key(50)).unwrap()
+    );
+    assert_eq!(
+      get_test_value(9999),
+      engine.get(get_test_key(9999)).unwrap()
+    );
+  };
+  check(&engine);
+  engine.close().unwrap();
+  std::mem::drop(engine);
+
+  // old data files read through mmap
+  opts.mmap_old_files = true;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  check(&engine);
+  std::mem::drop(engine);
+
+  std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove dir");
+}
+
+#[test]
+fn test_engine_value_len() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-value-len");
+  opts.data_file_size = 1024 * 1024; // 1MB
+  opts.max_key_size = 1024;
+  opts.max_value_size = 256 * 1024;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  let sizes = [0, 1, 127, 128, 16 * 1024, 200 * 1024];
+  for (i, size) in sizes.into_iter().enumerate() {
+    engine
+      .put(get_test_key(i), Bytes::from(vec![b'v'; size]))
+      .unwrap();
+  }
+  // rotate the first values into old data files
+  for i in 100..10000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  assert!(engine.get_engine_stat().unwrap().data_file_num > 1);
+  engine.delete(get_test_key(2)).unwrap();
+
+  for i in (0..sizes.len()).chain([100, 9999]) {
+    let expected = match engine.get(get_test_key(i)) {
+      Ok(value) => Some(value.len()),
+      Err(Errors::KeyNotFound) => None,
+      Err(e) => panic!("unexpected error {}", e),
+    };
+    assert_eq!(expected, engine.value_len(get_test_key(i)).unwrap());
+  }
+  assert_eq!(Some(200 * 1024), engine.value_len(get_test_key(5)).unwrap());
+  assert_eq!(None, engine.value_len(get_test_key(2)).unwrap());
+  assert_eq!(None, engine.value_len(Bytes::from("missing")).unwrap());
+  assert_eq!(
+    Errors::KeyIsEmpty,
+    engine.value_len(Bytes::new()).unwrap_err()
+  );
+  std::mem::drop(engine);
+
+  std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove dir");
+}
+
 #[test]
 fn test_engine_delete() {
   let mut opt = Options::default();
@@ -167,6 +388,78 @@ fn test_engine_delete() {
   std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
 }
 
+#[test]
+fn test_engine_delete_checked() {
+  let mut opt = Options::default();
+  opt.dir_path = PathBuf::from("/tmp/bitkv-rs-delete-checked");
+  opt.data_file_size = 64 * 1024 * 1024; // 64MB
+  let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+  // present key is removed
+  engine.put(get_test_key(11), get_test_value(11)).unwrap();
+  assert!(engine.delete_checked(get_test_key(11)).unwrap());
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.get(get_test_key(11)).err().unwrap()
+  );
+
+  // absent keys write no tombstone
+  let write_off = engine.active_data_file.read().get_write_off();
+  assert!(!engine.delete_checked(get_test_key(11)).unwrap());
+  assert!(!engine.delete_checked(get_test_key(12)).unwrap());
+  assert_eq!(write_off, engine.active_data_file.read().get_write_off());
+
+  assert_eq!(
+    Errors::KeyIsEmpty,
+    engine.delete_checked(Bytes::new()).err().unwrap()
+  );
+
+  // delete tested files
+  std::mem::drop(engine);
+  std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+}
+
+#[test]
+fn test_engine_value_size_histogram() {
+  let mut opt = Options::default();
+  opt.dir_path = PathBuf::from("/tmp/bitkv-rs-value-histogram");
+  opt.data_file_size = 64 * 1024 * 1024; // 64MB
+  let engine = Engine::open(opt.clone()).expect("fail to open engine");
+  assert_eq!(
+    ValueSizeHistogram::default(),
+    engine.value_size_histogram().unwrap()
+  );
+
+  for (i, size) in [0, 63, 64, 1023, 1024, 64 * 1024 - 1, 64 * 1024, 100 * 1024]
+    .into_iter()
+    .enumerate()
+  {
+    engine
+      .put(get_test_key(i), Bytes::from(vec![b'a'; size]))
+      .unwrap();
+  }
+  // overwritten and deleted values are not counted
+  engine
+    .put(get_test_key(100), Bytes::from(vec![b'a'; 2048]))
+    .unwrap();
+  engine.delete(get_test_key(100)).unwrap();
+  engine
+    .put(get_test_key(7), Bytes::from(vec![b'a'; 128 * 1024]))
+    .unwrap();
+
+  let histogram = engine.value_size_histogram().unwrap();
+  assert_eq!(2, histogram.under_64b);
+  assert_eq!(2, histogram.under_1kb);
+  assert_eq!(2, histogram.under_64kb);
+  assert_eq!(2, histogram.larger);
+  let total = 63 + 64 + 1023 + 1024 + (64 * 1024 - 1) + 64 * 1024 + 128 * 1024;
+  assert_eq!(total as f64 / 8.0, histogram.average_value_size);
+
+  // delete tested files
+  std::mem::drop(engine);
+  std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+}
+
 #[test]
 fn test_engine_sync() {
   let mut opt = Options::default();
@@ -201,6 +494,162 @@ fn test_engine_close() {
   std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
 }
 
+#[test]
+fn test_engine_reopen() {
+  for (i, index_type) in [option::IndexType::BTree, option::IndexType::BPlusTree]
+    .into_iter()
+    .enumerate()
+  {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-reopen-{}", i));
+    opts.index_type = index_type;
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+    for i in 0..100 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    engine.delete(get_test_key(0)).unwrap();
+    let wb = engine
+      .new_write_batch(option::WriteBatchOptions::default())
+      .expect("fail to create write batch");
+    for i in 100..200 {
+      wb.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    wb.commit().unwrap();
+    std::mem::drop(wb);
+    let seq_no = engine.seq_no.load(Ordering::SeqCst);
+    assert_eq!(2, seq_no);
+
+    let engine = engine.reopen().expect("fail to reopen engine");
+    assert_eq!(seq_no, engine.seq_no.load(Ordering::SeqCst));
+    assert_eq!(199, engine.list_keys().unwrap().len());
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.get(get_test_key(0)).unwrap_err()
+    );
+    for i in 1..200 {
+      assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+    }
+
+    // the reopened engine takes writes, and can be reopened again
+    engine.put(get_test_key(0), get_test_value(0)).unwrap();
+    let engine = engine.reopen().expect("fail to reopen engine");
+    assert_eq!(get_test_value(0), engine.get(get_test_key(0)).unwrap());
+    std::mem::drop(engine);
+
+    fs::remove_dir_all(opts.clone().dir_path).unwrap();
+  }
+}
+
+#[test]
+fn test_options_validate() {
+  let base = Options {
+    dir_path: PathBuf::from("/tmp/bitkv-rs-options-validate"),
+    ..Default::default()
+  };
+  assert!(base.validate().is_ok());
+
+  let cases: [(fn(&mut Options), Errors); 10] = [
+    (
+      |opts| opts.dir_path = PathBuf::new(),
+      Errors::DirPathIsEmpty,
+    ),
+    (
+      |opts| opts.max_value_size = 0,
+      Errors::InvalidMaxKeyValueSize,
+    ),
+    (|opts| opts.data_file_size = 0, Errors::DataFileSizeTooSmall),
+    // header, key prefix and crc alone fill the data file, no room for a key
+    (
+      |opts| opts.data_file_size = 11 + 22 + 4,
+      Errors::DataFileSizeTooSmall,
+    ),
+    (
+      |opts| opts.bytes_per_sync = opts.data_file_size as usize + 1,
+      Errors::InvalidBytesPerSync,
+    ),
+    (
+      |opts| opts.file_merge_threshold = 1.5,
+      Errors::InvalidMergeThreshold,
+    ),
+    (
+      |opts| opts.file_merge_threshold = -0.1,
+      Errors::InvalidMergeThreshold,
+    ),
+    (
+      |opts| opts.merge_parallelism = 0,
+      Errors::InvalidMergeParallelism,
+    ),
+    (
+      |opts| opts.bptree_flush_policy = option::FlushPolicy::EveryOps(0),
+      Errors::InvalidFlushPolicy,
+    ),
+    (
+      |opts| {
+        opts.dedup_values = true;
+        opts.merge_parallelism = 2;
+      },
+      Errors::InvalidDedupValues,
+    ),
+    (
+      |opts| opts.file_suffix = String::new(),
+      Errors::InvalidFileSuffix,
+    ),
+  ];
+  for (set, err) in cases {
+    let mut opts = base.clone();
+    set(&mut opts);
+    assert_eq!(err, opts.validate().unwrap_err());
+    assert_eq!(err, Engine::open(opts).err().unwrap());
+  }
+  assert!(!base.dir_path.exists());
+
+  // bounds themselves are fine
+  let mut opts = base.clone();
+  opts.max_key_size = 16;
+  opts.max_value_size = 32;
+  opts.data_file_size = 11 + 16 + 22 + 32 + 4;
+  opts.bytes_per_sync = opts.data_file_size as usize;
+  opts.file_merge_threshold = 1.0;
+  assert!(opts.validate().is_ok());
+
+  // default key and value limits are the hard limits, capped to what fits in a data file
+  assert_eq!(option::KEY_SIZE_HARD_LIMIT, base.max_key_size);
+  assert_eq!(option::VALUE_SIZE_HARD_LIMIT, base.max_value_size);
+  let mut opts = base.clone();
+  opts.data_file_size = 32 * 1024;
+  assert!(opts.validate().is_ok());
+  assert_eq!(32 * 1024 - 37, opts.effective_max_key_size());
+  assert_eq!(32 * 1024 - 37 - 16, opts.effective_max_value_size(16));
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  let key = Bytes::from(vec![b'k'; 16]);
+  let res1 = engine.put(key.clone(), Bytes::from(vec![b'v'; 32 * 1024 - 37 - 16]));
+  assert!(res1.is_ok());
+  let res2 = engine.put(key, Bytes::from(vec![b'v'; 32 * 1024 - 37 - 15]));
+  assert_eq!(Errors::ValueTooLarge, res2.err().unwrap());
+  std::mem::drop(engine);
+  fs::remove_dir_all(&base.dir_path).unwrap();
+
+  // B+Tree index needs to write into the directory
+  fs::create_dir_all(&base.dir_path).unwrap();
+  let mut permissions = fs::metadata(&base.dir_path).unwrap().permissions();
+  permissions.set_readonly(true);
+  fs::set_permissions(&base.dir_path, permissions.clone()).unwrap();
+  let mut opts = base.clone();
+  opts.index_type = option::IndexType::BPlusTree;
+  assert_eq!(Errors::DatabaseDirNotWritable, opts.validate().unwrap_err());
+  assert_eq!(
+    Errors::DatabaseDirNotWritable,
+    Engine::open(opts).err().unwrap()
+  );
+  assert!(base.validate().is_ok());
+  #[allow(clippy::permissions_set_readonly_false)]
+  permissions.set_readonly(false);
+  fs::set_permissions(&base.dir_path, permissions).unwrap();
+
+  fs::remove_dir_all(base.dir_path).unwrap();
+}
+
 #[test]
 fn test_engine_filelock() {
   // let mut opt = Options::default();
@@ -234,6 +683,63 @@ fn test_engine_filelock() {
   std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
 }
 
+#[test]
+fn test_engine_open_twice_in_process() {
+  let mut opt = Options::default();
+  opt.dir_path = PathBuf::from("/tmp/bitkv-rs-open-twice");
+  let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+  // the same directory, spelled differently
+  let res1 = Engine::open(opt.clone());
+  assert_eq!(Errors::DatabaseIsUsing, res1.err().unwrap());
+  let mut opt2 = opt.clone();
+  opt2.dir_path = PathBuf::from("/tmp/../tmp/bitkv-rs-open-twice/");
+  let res2 = Engine::open(opt2.clone());
+  assert_eq!(Errors::DatabaseIsUsing, res2.err().unwrap());
+
+  // released on drop
+  std::mem::drop(engine);
+  let engine = Engine::open(opt2.clone()).expect("fail to open engine");
+  std::mem::drop(engine);
+
+  // delete tested files
+  std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+}
+
+#[test]
+fn test_engine_open_initial_without_data_files() {
+  let mut opt = Options::default();
+  opt.dir_path = PathBuf::from("/tmp/bitkv-rs-open-initial");
+  opt.index_type = option::IndexType::BPlusTree;
+  let engine = Engine::open(opt.clone()).expect("fail to open engine");
+  assert!(engine.is_initial);
+  engine.close().expect("fail to close engine");
+  std::mem::drop(engine);
+
+  // keep only the b+ tree index file
+  for entry in fs::read_dir(&opt.dir_path).unwrap().flatten() {
+    if entry.file_name() != "bptree-index" {
+      fs::remove_file(entry.path()).unwrap();
+    }
+  }
+  assert_eq!(1, fs::read_dir(&opt.dir_path).unwrap().count());
+
+  let engine = Engine::open(opt.clone()).expect("fail to open engine");
+  assert!(engine.is_initial);
+  // write batches are allowed on an initial b+ tree database
+  let wb = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .expect("fail to create write batch");
+  wb.put(get_test_key(1), get_test_value(1)).unwrap();
+  wb.commit().unwrap();
+  assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+  std::mem::drop(wb);
+  std::mem::drop(engine);
+
+  // delete tested files
+  std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+}
+
 #[test]
 fn test_engine_stat() {
   let mut opts = option::Options::default();
@@ -283,3 +789,2038 @@ fn test_engine_backup() {
   fs::remove_dir_all(opts.clone().dir_path).unwrap();
   fs::remove_dir_all(backup_dir.clone()).unwrap();
 }
+
+#[test]
+fn test_engine_open_progress() {
+  let mut opts = option::Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-open-progress");
+  opts.data_file_size = 1024 * 1024; // 1MB
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  for i in 0..=50000 {
+    let res = engine.put(get_test_key(i), get_test_value(i));
+    assert!(res.is_ok());
+  }
+  let data_file_num = engine.get_engine_stat().unwrap().data_file_num;
+  assert!(data_file_num > 1);
+  std::mem::drop(engine);
+
+  // reopen with a progress callback
+  let calls = Arc::new(AtomicUsize::new(0));
+  let last = Arc::new(Mutex::new(OpenProgress::default()));
+  let (calls_cb, last_cb) = (calls.clone(), last.clone());
+  opts.open_progress = Some(Arc::new(move |progress| {
+    calls_cb.fetch_add(1, Ordering::SeqCst);
+    *last_cb.lock().unwrap() = progress;
+  }));
+  let engine2 = Engine::open(opts.clone()).expect("fail to open engine");
+
+  assert_eq!(data_file_num, calls.load(Ordering::SeqCst));
+  let last = *last.lock().unwrap();
+  assert_eq!(data_file_num, last.files_processed);
+  assert_eq!(data_file_num, last.total_files);
+  assert_eq!(50001, last.records_loaded);
+  std::mem::drop(engine2);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_key_value_size_limit() {
+  let mut opts = option::Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-size-limit");
+  opts.max_key_size = 16;
+  opts.max_value_size = 32;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  // key at the boundary and just over it
+  let res1 = engine.put(Bytes::from(vec![b'k'; 16]), get_test_value(1));
+  assert!(res1.is_ok());
+  let res2 = engine.put(Bytes::from(vec![b'k'; 17]), get_test_value(1));
+  assert_eq!(Errors::KeyTooLarge, res2.err().unwrap());
+
+  // value at the boundary and just over it
+  let res3 = engine.put(get_test_key(1), Bytes::from(vec![b'v'; 32]));
+  assert!(res3.is_ok());
+  let res4 = engine.put(get_test_key(1), Bytes::from(vec![b'v'; 33]));
+  assert_eq!(Errors::ValueTooLarge, res4.err().unwrap());
+  assert_eq!(
+    Bytes::from(vec![b'v'; 32]),
+    engine.get(get_test_key(1)).unwrap()
+  );
+
+  // batch put is validated too
+  let wb = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .expect("fail to create write batch");
+  let res5 = wb.put(Bytes::from(vec![b'k'; 17]), get_test_value(1));
+  assert_eq!(Errors::KeyTooLarge, res5.err().unwrap());
+  let res6 = wb.put(get_test_key(2), Bytes::from(vec![b'v'; 33]));
+  assert_eq!(Errors::ValueTooLarge, res6.err().unwrap());
+
+  // invalid limits
+  std::mem::drop(wb);
+  std::mem::drop(engine);
+  opts.max_key_size = option::KEY_SIZE_HARD_LIMIT + 1;
+  let res7 = Engine::open(opts.clone());
+  assert_eq!(Errors::InvalidMaxKeyValueSize, res7.err().unwrap());
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_try_put_lock_timeout() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-try-put");
+  let engine = Arc::new(Engine::open(opts.clone()).expect("fail to open engine"));
+
+  // lock is free, timed put succeeds
+  let res1 = engine.try_put(
+    get_test_key(1),
+    get_test_value(1),
+    Duration::from_millis(100),
+  );
+  assert!(res1.is_ok());
+
+  // hold the commit lock while another thread tries to put
+  let guard = engine.batch_commit_lock.lock();
+  let eng = engine.clone();
+  let handle = thread::spawn(move || {
+    let put_res = eng.try_put(
+      get_test_key(2),
+      get_test_value(2),
+      Duration::from_millis(100),
+    );
+    assert_eq!(Errors::LockTimeout, put_res.err().unwrap());
+
+    let wb = eng
+      .new_write_batch(option::WriteBatchOptions::default())
+      .expect("fail to create write batch");
+    wb.put(get_test_key(3), get_test_value(3)).unwrap();
+    let commit_res = wb.try_commit(Duration::from_millis(100));
+    assert_eq!(Errors::LockTimeout, commit_res.err().unwrap());
+  });
+  handle.join().unwrap();
+  std::mem::drop(guard);
+
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.get(get_test_key(2)).err().unwrap()
+  );
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.get(get_test_key(3)).err().unwrap()
+  );
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_corrupt_seq_no_file() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-corrupt-seq-no");
+  opts.index_type = option::IndexType::BPlusTree;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  let wb = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .expect("fail to create write batch");
+  wb.put(get_test_key(1), get_test_value(1)).unwrap();
+  wb.commit().unwrap();
+  wb.put(get_test_key(2), get_test_value(2)).unwrap();
+  wb.commit().unwrap();
+  std::mem::drop(wb);
+  std::mem::drop(engine);
+
+  // seq_no file is kept after open
+  let seq_no_path = opts.dir_path.join("seq-no");
+  let engine2 = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(3, engine2.seq_no.load(Ordering::SeqCst));
+  assert!(seq_no_path.is_file());
+  std::mem::drop(engine2);
+
+  // flip the last byte of crc
+  let mut content = fs::read(&seq_no_path).unwrap();
+  let last = content.len() - 1;
+  content[last] ^= 0xff;
+  fs::write(&seq_no_path, content).unwrap();
+
+  // seq_no is recomputed from data files instead of panicking
+  let engine3 = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(3, engine3.seq_no.load(Ordering::SeqCst));
+  assert!(engine3
+    .new_write_batch(option::WriteBatchOptions::default())
+    .is_ok());
+  std::mem::drop(engine3);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_corrupt_txn_key() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-corrupt-txn-key");
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+  std::mem::drop(engine);
+
+  // append a record with a valid crc but a truncated seq_no in its key
+  let data_file = DataFile::new(
+    &opts.dir_path,
+    0,
+    &opts.file_suffix,
+    IOManagerType::StandardFileIO,
+  )
+  .unwrap();
+  let record = LogRecord {
+    key: vec![0x80],
+    value: get_test_value(2).to_vec(),
+    rec_type: LogRecordType::Normal,
+  };
+  data_file.write(&record.encode()).unwrap();
+  data_file.sync().unwrap();
+  std::mem::drop(data_file);
+
+  // loading reports corruption instead of panicking
+  let res = Engine::open(opts.clone());
+  assert_eq!(Errors::DatabaseDirectoryCorrupted, res.err().unwrap());
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_recovery_mode() {
+  for recovery_mode in [option::RecoveryMode::Strict, option::RecoveryMode::Lenient] {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-recovery-mode-{:?}", recovery_mode));
+    opts.recovery_mode = recovery_mode;
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    for i in 0..100 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    let pos = engine.index.read().get(get_test_key(99).to_vec()).unwrap();
+    std::mem::drop(engine);
+
+    // corrupt the crc of the tail record, as a torn write would
+    let data_file_path = opts.dir_path.join("000000000.data");
+    let mut content = fs::read(&data_file_path).unwrap();
+    *content.last_mut().unwrap() ^= 0xff;
+    fs::write(&data_file_path, content).unwrap();
+
+    let res = Engine::open(opts.clone());
+    match recovery_mode {
+      option::RecoveryMode::Strict => {
+        // open fails and the data file is left untouched
+        assert_eq!(Errors::InvalidLogRecordCrc, res.err().unwrap());
+        assert_eq!(
+          pos.offset + pos.size as u64,
+          fs::metadata(&data_file_path).unwrap().len()
+        );
+      }
+      option::RecoveryMode::Lenient => {
+        // the tail record is dropped, the rest is loaded
+        let engine = res.expect("fail to open engine");
+        assert_eq!(pos.offset, fs::metadata(&data_file_path).unwrap().len());
+        assert_eq!(99, engine.list_keys().unwrap().len());
+        assert_eq!(
+          Errors::KeyNotFound,
+          engine.get(get_test_key(99)).err().unwrap()
+        );
+
+        // new writes go after the truncated tail and survive a strict reopen
+        engine.put(get_test_key(99), get_test_value(99)).unwrap();
+        std::mem::drop(engine);
+        opts.recovery_mode = option::RecoveryMode::Strict;
+        let engine2 = Engine::open(opts.clone()).expect("fail to open engine");
+        assert_eq!(100, engine2.list_keys().unwrap().len());
+        assert_eq!(get_test_value(99), engine2.get(get_test_key(99)).unwrap());
+        std::mem::drop(engine2);
+      }
+    }
+
+    fs::remove_dir_all(opts.clone().dir_path).unwrap();
+  }
+}
+
+#[test]
+fn test_engine_open_empty_newest_data_file() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-open-empty-data-file");
+  opts.data_file_size = 32 * 1024;
+  opts.mmap_at_startup = true;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..1000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  let data_file_num = engine.get_engine_stat().unwrap().data_file_num;
+  std::mem::drop(engine);
+
+  // newest data file created, then crashed before any write
+  fs::File::create(opts.dir_path.join(format!("{:09}.data", data_file_num))).unwrap();
+
+  let engine2 = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(
+    data_file_num + 1,
+    engine2.get_engine_stat().unwrap().data_file_num
+  );
+  assert_eq!(1000, engine2.list_keys().unwrap().len());
+  engine2
+    .put(get_test_key(1000), get_test_value(1000))
+    .unwrap();
+  std::mem::drop(engine2);
+
+  let engine3 = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(1001, engine3.list_keys().unwrap().len());
+  assert_eq!(
+    get_test_value(1000),
+    engine3.get(get_test_key(1000)).unwrap()
+  );
+  std::mem::drop(engine3);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_open_at_seq() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-open-at-seq");
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  // seq 1 writes the key, seq 2 overwrites it
+  let wb = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .expect("fail to create write batch");
+  wb.put(get_test_key(1), Bytes::from("value-1")).unwrap();
+  wb.commit().unwrap();
+  wb.put(get_test_key(1), Bytes::from("value-2")).unwrap();
+  wb.put(get_test_key(1), Bytes::from("value-3")).unwrap();
+  wb.put(get_test_key(2), get_test_value(2)).unwrap();
+  wb.commit().unwrap();
+  std::mem::drop(wb);
+
+  // a write without batch after seq 2
+  engine.put(get_test_key(3), get_test_value(3)).unwrap();
+  std::mem::drop(engine);
+
+  let engine1 = Engine::open_at_seq(opts.clone(), 1).expect("fail to open engine");
+  assert_eq!(
+    Bytes::from("value-1"),
+    engine1.get(get_test_key(1)).unwrap()
+  );
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine1.get(get_test_key(2)).err().unwrap()
+  );
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine1.get(get_test_key(3)).err().unwrap()
+  );
+
+  // snapshot is read only
+  assert!(engine1.is_read_only());
+  let put_res = engine1.put(get_test_key(4), get_test_value(4));
+  assert_eq!(Errors::ReadOnlyEngine, put_res.err().unwrap());
+  std::mem::drop(engine1);
+
+  let engine2 = Engine::open_at_seq(opts.clone(), 2).expect("fail to open engine");
+  assert_eq!(
+    Bytes::from("value-3"),
+    engine2.get(get_test_key(1)).unwrap()
+  );
+  assert!(engine2.get(get_test_key(2)).is_ok());
+  assert!(engine2.get(get_test_key(3)).is_ok());
+  std::mem::drop(engine2);
+
+  // latest state is untouched
+  let engine3 = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(
+    Bytes::from("value-3"),
+    engine3.get(get_test_key(1)).unwrap()
+  );
+  assert_eq!(3, engine3.list_keys().unwrap().len());
+  std::mem::drop(engine3);
+
+  // B+ tree index can't be opened at a seq_no
+  opts.index_type = option::IndexType::BPlusTree;
+  let res = Engine::open_at_seq(opts.clone(), 1);
+  assert_eq!(Errors::OpenAtSeqUnsupported, res.err().unwrap());
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_get_mmap() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-get-mmap");
+  opts.data_file_size = 1024 * 1024; // 1MB
+  opts.mmap_old_files = true;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  // large values rotate through several old data files
+  for i in 0..100 {
+    let value = Bytes::from(vec![(i % 256) as u8; 64 * 1024]);
+    engine.put(get_test_key(i), value).unwrap();
+  }
+  engine.delete(get_test_key(0)).unwrap();
+  assert!(engine.get_engine_stat().unwrap().data_file_num > 1);
+
+  // old files are served from the mapping, active file by copying
+  for i in 1..100 {
+    let value = engine.get_mmap(get_test_key(i)).unwrap();
+    assert_eq!(engine.get(get_test_key(i)).unwrap(), value);
+  }
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.get_mmap(get_test_key(0)).err().unwrap()
+  );
+
+  // returned bytes outlive the engine and its mapping
+  let value = engine.get_mmap(get_test_key(1)).unwrap();
+  std::mem::drop(engine);
+  assert_eq!(Bytes::from(vec![1u8; 64 * 1024]), value);
+
+  // after reopen
+  let engine2 = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 1..100 {
+    let value = engine2.get_mmap(get_test_key(i)).unwrap();
+    assert_eq!(engine2.get(get_test_key(i)).unwrap(), value);
+  }
+  std::mem::drop(engine2);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_create_dirs() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-create-dirs/nested/db");
+
+  // nested path is not created without the flag
+  opts.create_dirs = false;
+  let res1 = Engine::open(opts.clone());
+  assert_eq!(Errors::FailedToCreateDatabaseDir, res1.err().unwrap());
+  assert!(!opts.dir_path.exists());
+
+  // nested path is created with its parents
+  opts.create_dirs = true;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+  std::mem::drop(engine);
+
+  // existing path opens with the flag off
+  opts.create_dirs = false;
+  let engine2 = Engine::open(opts.clone()).expect("fail to open engine");
+  assert!(engine2.get(get_test_key(1)).is_ok());
+  std::mem::drop(engine2);
+
+  fs::remove_dir_all("/tmp/bitkv-rs-create-dirs").unwrap();
+}
+
+#[test]
+fn test_engine_open_existing() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-open-existing");
+
+  // missing directory and a directory without a database both fail, nothing is created
+  let res1 = Engine::open_existing(opts.clone());
+  assert_eq!(Errors::DatabaseNotFound, res1.err().unwrap());
+  assert!(!opts.dir_path.exists());
+  fs::create_dir_all(&opts.dir_path).unwrap();
+  let res2 = Engine::open_existing(opts.clone());
+  assert_eq!(Errors::DatabaseNotFound, res2.err().unwrap());
+
+  // `open` creates the database, which opens as existing from then on
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+  std::mem::drop(engine);
+  let engine2 = Engine::open_existing(opts.clone()).expect("fail to open engine");
+  assert_eq!(get_test_value(1), engine2.get(get_test_key(1)).unwrap());
+  std::mem::drop(engine2);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_open_read_only() {
+  for index_type in [option::IndexType::BTree, option::IndexType::BPlusTree] {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-open-read-only-{:?}", index_type));
+    opts.index_type = index_type;
+    opts.file_merge_threshold = 0 as f32;
+
+    // nothing is created for a directory without a database
+    let res1 = Engine::open_read_only(opts.clone());
+    assert_eq!(Errors::DatabaseNotFound, res1.err().unwrap());
+    assert!(!opts.dir_path.exists());
+
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    for i in 0..100 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    engine.delete(get_test_key(0)).unwrap();
+    engine.merge().unwrap();
+    std::mem::drop(engine);
+    let list_dir = || {
+      let mut files: Vec<(PathBuf, u64)> = fs::read_dir(&opts.dir_path)
+        .unwrap()
+        .flatten()
+        .map(|entry| (entry.path(), entry.metadata().unwrap().len()))
+        .collect();
+      files.sort();
+      files
+    };
+    let files = list_dir();
+
+    // the latest state is read, even from the data files a pending merge replaces, and the
+    // directory is left as it was
+    let engine2 = Engine::open_read_only(opts.clone()).expect("fail to open engine");
+    assert!(engine2.is_read_only());
+    assert_eq!(99, engine2.list_keys().unwrap().len());
+    assert_eq!(get_test_value(1), engine2.get(get_test_key(1)).unwrap());
+    assert_eq!(
+      Errors::ReadOnlyEngine,
+      engine2
+        .put(get_test_key(1), get_test_value(2))
+        .err()
+        .unwrap()
+    );
+    let engine2 = engine2.reopen().expect("fail to reopen engine");
+    assert!(engine2.is_read_only());
+    std::mem::drop(engine2);
+    assert_eq!(files, list_dir());
+
+    fs::remove_dir_all(opts.clone().dir_path).unwrap();
+  }
+}
+
+#[test]
+fn test_engine_index_dir() {
+  let base_dir = PathBuf::from("/tmp/bitkv-rs-index-dir");
+  let mut opts = Options::default();
+  opts.dir_path = base_dir.join("data");
+  opts.index_dir = Some(base_dir.join("index"));
+  opts.index_type = option::IndexType::BPlusTree;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..100 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  engine.sync().unwrap();
+
+  // index file lands in the index dir, data files stay in the data dir
+  let index_dir = opts.index_dir.clone().unwrap();
+  assert!(index_dir.join(BPTREE_INDEX_FILE_NAME).is_file());
+  assert!(!opts.dir_path.join(BPTREE_INDEX_FILE_NAME).exists());
+  assert!(get_data_file_name(&opts.dir_path, 0, &opts.file_suffix).is_file());
+  assert!(!get_data_file_name(&index_dir, 0, &opts.file_suffix).exists());
+
+  // the index dir is locked along with the data dir
+  let mut opts2 = opts.clone();
+  opts2.dir_path = base_dir.join("data2");
+  let res1 = Engine::open(opts2.clone());
+  assert_eq!(Errors::DatabaseIsUsing, res1.err().unwrap());
+
+  engine.close().unwrap();
+  std::mem::drop(engine);
+  let engine2 = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..100 {
+    assert_eq!(get_test_value(i), engine2.get(get_test_key(i)).unwrap());
+  }
+  std::mem::drop(engine2);
+
+  fs::remove_dir_all(base_dir).unwrap();
+}
+
+// writer keeping what's written, along with the largest single write and the flushes
+#[derive(Default)]
+struct CountingWriter {
+  written: Vec<u8>,
+  max_write: usize,
+  flushes: usize,
+}
+
+impl std::io::Write for CountingWriter {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.max_write = self.max_write.max(buf.len());
+    self.written.extend_from_slice(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    self.flushes += 1;
+    Ok(())
+  }
+}
+
+// index refusing to copy itself into an iterator, keeping the most entries handed out at once
+struct ChunkCountingIndex {
+  inner: Box<dyn Indexer>,
+  max_chunk: Arc<AtomicUsize>,
+}
+
+impl Indexer for ChunkCountingIndex {
+  fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
+    self.inner.put(key, pos)
+  }
+
+  fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    self.inner.get(key)
+  }
+
+  fn delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
+    self.inner.delete(key)
+  }
+
+  fn list_keys(&self) -> Result<Vec<Bytes>> {
+    self.inner.list_keys()
+  }
+
+  fn contains_prefix(&self, prefix: &[u8]) -> bool {
+    self.inner.contains_prefix(prefix)
+  }
+
+  fn range_from(&self, start: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, LogRecordPos)>> {
+    let items = self.inner.range_from(start, limit)?;
+    self.max_chunk.fetch_max(items.len(), Ordering::SeqCst);
+    Ok(items)
+  }
+
+  fn iterator(&self, _options: option::IteratorOptions) -> Result<Box<dyn IndexIterator>> {
+    panic!("the whole index is copied");
+  }
+  fn sync(&self) -> Result<()> {
+    self.inner.sync()
+  }
+}
+
+#[test]
+fn test_engine_export_to() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-export-to");
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  let count = 10000;
+  for i in 0..count {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  engine.delete(get_test_key(0)).unwrap();
+
+  // the index is only walked a chunk at a time
+  let max_chunk = Arc::new(AtomicUsize::new(0));
+  let inner = std::mem::replace(&mut *engine.index.write(), Box::new(BTree::new()));
+  *engine.index.write() = Box::new(ChunkCountingIndex {
+    inner,
+    max_chunk: max_chunk.clone(),
+  });
+
+  // the database is many times the buffer, the writer never gets more than a buffer at once
+  let mut writer = CountingWriter::default();
+  let res1 = engine.export_to(&mut writer, ExportFormat::LengthDelimited);
+  assert_eq!(count - 1, res1.unwrap());
+  assert!(writer.written.len() > 4 * EXPORT_BUFFER_SIZE);
+  assert!(writer.max_write <= EXPORT_BUFFER_SIZE);
+  assert_eq!(1, writer.flushes);
+
+  // every live pair comes back in key order
+  let mut exported = Vec::new();
+  let mut buf = writer.written.as_slice();
+  let read_field = |buf: &mut &[u8]| {
+    let len = u32::from_be_bytes(buf[..4].try_into().unwrap()) as usize;
+    let field = Bytes::copy_from_slice(&buf[4..4 + len]);
+    *buf = &buf[4 + len..];
+    field
+  };
+  while !buf.is_empty() {
+    let key = read_field(&mut buf);
+    let value = read_field(&mut buf);
+    exported.push((key, value));
+  }
+  let mut expected = (1..count)
+    .map(|i| (get_test_key(i), get_test_value(i)))
+    .collect::<Vec<_>>();
+  expected.sort();
+  assert_eq!(expected, exported);
+
+  // one json object per pair
+  let mut writer = CountingWriter::default();
+  let res2 = engine.export_to(&mut writer, ExportFormat::Ndjson);
+  assert_eq!(count - 1, res2.unwrap());
+  assert!(writer.max_write <= EXPORT_BUFFER_SIZE);
+  let output = String::from_utf8(writer.written).unwrap();
+  let lines = output.lines().collect::<Vec<_>>();
+  assert_eq!(count - 1, lines.len());
+  assert!(lines
+    .iter()
+    .all(|line| line.starts_with("{\"key\":\"") && line.ends_with("\"}")));
+  assert_eq!(EXPORT_INDEX_CHUNK_LEN, max_chunk.load(Ordering::SeqCst));
+
+  std::mem::drop(engine);
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_key_case_insensitive() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-key-case-insensitive");
+  opts.key_case_insensitive = true;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  engine.put(Bytes::from("User"), Bytes::from("v1")).unwrap();
+  engine.put(Bytes::from("admin"), Bytes::from("v2")).unwrap();
+  assert_eq!(Bytes::from("v1"), engine.get(Bytes::from("user")).unwrap());
+  assert_eq!(Bytes::from("v1"), engine.get(Bytes::from("USER")).unwrap());
+
+  // keys come back as written
+  let keys = engine.list_keys().unwrap();
+  assert_eq!(vec![Bytes::from("admin"), Bytes::from("User")], keys);
+  {
+    let iter = engine
+      .iter(option::IteratorOptions {
+        prefix: b"US".to_vec(),
+        ..Default::default()
+      })
+      .unwrap();
+    assert_eq!(Some((Bytes::from("User"), Bytes::from("v1"))), iter.next());
+    assert!(iter.next().is_none());
+  }
+
+  // writes with other casings replace the key, and survive reopening
+  engine.put(Bytes::from("USER"), Bytes::from("v3")).unwrap();
+  engine.delete(Bytes::from("ADMIN")).unwrap();
+  std::mem::drop(engine);
+  let engine2 = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(Bytes::from("v3"), engine2.get(Bytes::from("user")).unwrap());
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine2.get(Bytes::from("admin")).err().unwrap()
+  );
+  assert_eq!(vec![Bytes::from("USER")], engine2.list_keys().unwrap());
+  std::mem::drop(engine2);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_compact_key() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-compact-key");
+  opts.data_file_size = 64 * 1024; // 64KB
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  // stale versions of one key spread over several files
+  for i in 0..1000 {
+    engine.put(get_test_key(1), get_test_value(i)).unwrap();
+    engine.put(get_test_key(i + 2), get_test_value(i)).unwrap();
+  }
+  let value = engine.get(get_test_key(1)).unwrap();
+  let old_pos = engine.index.read().get(get_test_key(1).to_vec()).unwrap();
+  let reclaim_size = engine.reclaim_size.load(Ordering::SeqCst);
+
+  let res1 = engine.compact_key(get_test_key(1));
+  assert!(res1.is_ok());
+
+  // single live copy at the newest position of the active file
+  let new_pos = engine.index.read().get(get_test_key(1).to_vec()).unwrap();
+  let active_file = engine.active_data_file.read();
+  assert_eq!(active_file.get_file_id(), new_pos.file_id);
+  assert_eq!(
+    active_file.get_write_off(),
+    new_pos.offset + new_pos.size as u64
+  );
+  std::mem::drop(active_file);
+  assert_ne!(old_pos, new_pos);
+  assert_eq!(value, engine.get(get_test_key(1)).unwrap());
+  assert_eq!(
+    reclaim_size + old_pos.size as usize,
+    engine.reclaim_size.load(Ordering::SeqCst)
+  );
+
+  // absent key is a no-op
+  let res2 = engine.compact_key(get_test_key(5000));
+  assert!(res2.is_ok());
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.get(get_test_key(5000)).err().unwrap()
+  );
+
+  // compacted value and its version survive restart
+  let version = engine.version(get_test_key(1)).unwrap();
+  std::mem::drop(engine);
+  let engine2 = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(value, engine2.get(get_test_key(1)).unwrap());
+  assert_eq!(version, engine2.version(get_test_key(1)).unwrap());
+  std::mem::drop(engine2);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_compact_key_concurrent_put() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-compact-key-concurrent");
+  let engine = Arc::new(Engine::open(opts.clone()).expect("fail to open engine"));
+  engine.put(get_test_key(1), get_test_value(0)).unwrap();
+
+  // a compaction racing the puts must never bring back an older value
+  let stop = Arc::new(AtomicBool::new(false));
+  let compactor = {
+    let engine = engine.clone();
+    let stop = stop.clone();
+    thread::spawn(move || {
+      while !stop.load(Ordering::SeqCst) {
+        engine.compact_key(get_test_key(1)).unwrap();
+      }
+    })
+  };
+  for i in 1..=2000 {
+    engine.put(get_test_key(1), get_test_value(i)).unwrap();
+    assert_eq!(get_test_value(i), engine.get(get_test_key(1)).unwrap());
+  }
+  stop.store(true, Ordering::SeqCst);
+  compactor.join().unwrap();
+  assert_eq!(get_test_value(2000), engine.get(get_test_key(1)).unwrap());
+
+  std::mem::drop(engine);
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_verify_checksum_on_read() {
+  for verify in [true, false] {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-verify-checksum-{}", verify));
+    opts.verify_checksum_on_read = verify;
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    engine.put(get_test_key(1), Bytes::from("value-1")).unwrap();
+
+    // flip the last value byte on disk, just before crc
+    let pos = engine.index.read().get(get_test_key(1).to_vec()).unwrap();
+    let data_file_path = opts.dir_path.join("000000000.data");
+    let mut content = fs::read(&data_file_path).unwrap();
+    let value_end = (pos.offset + pos.size as u64) as usize - 4;
+    content[value_end - 1] ^= 0xff;
+    fs::write(&data_file_path, content).unwrap();
+
+    let res = engine.get(get_test_key(1));
+    if verify {
+      // corrupted record is still rejected
+      assert_eq!(Errors::InvalidLogRecordCrc, res.err().unwrap());
+    } else {
+      // corrupted value is returned as is
+      let value = res.unwrap();
+      assert_eq!(7, value.len());
+      assert_ne!(Bytes::from("value-1"), value);
+    }
+
+    std::mem::drop(engine);
+    fs::remove_dir_all(opts.clone().dir_path).unwrap();
+  }
+}
+
+#[test]
+fn test_engine_bptree_flush_policy_recover() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-bptree-flush");
+  opts.index_type = option::IndexType::BPlusTree;
+  opts.bptree_flush_policy = option::FlushPolicy::Manual;
+  opts.sync_writes = true;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  for i in 0..1000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in 0..100 {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+  assert_eq!(900, engine.list_keys().unwrap().len());
+
+  // a copy taken now is what a crash leaves behind, data files are synced but the index is not
+  let crash_dir = PathBuf::from("/tmp/bitkv-rs-bptree-flush-crash");
+  crate::util::file::copy_dir(&opts.dir_path, &crash_dir, &["flock"]).unwrap();
+  assert!(crash_dir.join("bptree-dirty").is_file());
+
+  let mut crash_opts = opts.clone();
+  crash_opts.dir_path = crash_dir.clone();
+  let engine2 = Engine::open(crash_opts.clone()).expect("fail to open engine");
+  assert_eq!(900, engine2.list_keys().unwrap().len());
+  for i in 0..100 {
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine2.get(get_test_key(i)).unwrap_err()
+    );
+  }
+  for i in 100..1000 {
+    assert_eq!(get_test_value(i), engine2.get(get_test_key(i)).unwrap());
+  }
+  std::mem::drop(engine2);
+
+  // clean close flushes the index, reopen doesn't need a rebuild
+  std::mem::drop(engine);
+  assert!(!opts.dir_path.join("bptree-dirty").exists());
+  opts.bptree_flush_policy = option::FlushPolicy::Always;
+  let engine3 = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(900, engine3.list_keys().unwrap().len());
+  assert_eq!(get_test_value(500), engine3.get(get_test_key(500)).unwrap());
+  std::mem::drop(engine3);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+  fs::remove_dir_all(crash_dir).unwrap();
+}
+
+#[test]
+fn test_engine_file_suffix() {
+  for file_suffix in [".bitkv", ".data"] {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-file-suffix{}", file_suffix));
+    opts.data_file_size = 32 * 1024;
+    opts.file_suffix = file_suffix.to_string();
+    // files of other applications may share the directory
+    fs::create_dir_all(&opts.dir_path).unwrap();
+    if file_suffix != ".data" {
+      fs::write(opts.dir_path.join("report.data"), b"not a bitkv file").unwrap();
+    }
+
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    for i in 0..1000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    assert!(engine.get_engine_stat().unwrap().data_file_num > 1);
+    std::mem::drop(engine);
+    assert!(opts
+      .dir_path
+      .join(format!("000000000{}", file_suffix))
+      .is_file());
+
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    assert_eq!(1000, engine.list_keys().unwrap().len());
+    for i in 0..1000 {
+      assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+    }
+    std::mem::drop(engine);
+
+    fs::remove_dir_all(opts.clone().dir_path).unwrap();
+  }
+
+  // suffixes that are empty or match other database files are rejected
+  for file_suffix in ["", ".tmp", "-index", "-shrink", "/x"] {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-file-suffix-invalid");
+    opts.file_suffix = file_suffix.to_string();
+    assert_eq!(Errors::InvalidFileSuffix, Engine::open(opts).err().unwrap());
+  }
+}
+
+// route the syncs of every data file through a hook, which records the file id and the size
+// each sync made durable
+fn hook_syncs(engine: &Engine, opts: &Options) -> Arc<Mutex<Vec<(u32, u64)>>> {
+  let synced = Arc::new(Mutex::new(Vec::new()));
+  let hooked_io = |file_id: u32| {
+    let file_name = get_data_file_name(&opts.dir_path, file_id, &opts.file_suffix);
+    let synced = synced.clone();
+    Box::new(
+      HookedIO::new(Box::new(FileIO::new(&file_name).unwrap())).on_synced(move |size| {
+        synced.lock().unwrap().push((file_id, size));
+        Ok(())
+      }),
+    )
+  };
+
+  let mut old_files = engine.old_data_files.write();
+  let file_ids: Vec<u32> = old_files.keys().copied().collect();
+  for file_id in file_ids {
+    let mut old_file = DataFile::new(
+      &opts.dir_path,
+      file_id,
+      &opts.file_suffix,
+      IOManagerType::StandardFileIO,
+    )
+    .unwrap();
+    old_file.replace_io_manager(hooked_io(file_id));
+    old_files.insert(file_id, Arc::new(old_file));
+  }
+  std::mem::drop(old_files);
+  let mut active_file = engine.active_data_file.write();
+  let file_id = active_file.get_file_id();
+  active_file.replace_io_manager(hooked_io(file_id));
+  synced
+}
+
+#[test]
+fn test_engine_sync_all() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-sync-all");
+  opts.data_file_size = 32 * 1024;
+  opts.sync_writes = false;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  for i in 0..1000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  assert!(engine.get_engine_stat().unwrap().data_file_num > 1);
+
+  // every data file is synced once, up to its end
+  let synced = hook_syncs(&engine, &opts);
+  engine.sync_all().unwrap();
+  let mut synced = synced.lock().unwrap().clone();
+  synced.sort();
+  let mut expected: Vec<(u32, u64)> = engine
+    .data_files_info()
+    .unwrap()
+    .iter()
+    .map(|info| (info.file_id, info.size))
+    .collect();
+  expected.sort();
+  assert_eq!(expected, synced);
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_durable_seq_no() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-durable-seq-no");
+  opts.data_file_size = 32 * 1024;
+  opts.sync_writes = false;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(0, engine.durable_seq_no().unwrap());
+
+  let batch_opts = || option::WriteBatchOptions {
+    sync_writes: false,
+    ..Default::default()
+  };
+  for i in 0..10 {
+    let wb = engine.new_write_batch(batch_opts()).unwrap();
+    for j in 0..100 {
+      wb.put(get_test_key(i * 100 + j), get_test_value(j))
+        .unwrap();
+    }
+    wb.commit().unwrap();
+  }
+  engine
+    .put(get_test_key(1000), get_test_value(1000))
+    .unwrap();
+  assert!(engine.get_engine_stat().unwrap().data_file_num > 1);
+
+  // counts syncs of the active file, failing them while fail is set
+  let file_id = engine.active_data_file.read().get_file_id();
+  let file_name = get_data_file_name(&opts.dir_path, file_id, &opts.file_suffix);
+  let syncs = Arc::new(AtomicUsize::new(0));
+  let fail = Arc::new(AtomicBool::new(false));
+  engine.active_data_file.write().replace_io_manager(Box::new(
+    HookedIO::new(Box::new(FileIO::new(&file_name).unwrap())).on_synced({
+      let syncs = syncs.clone();
+      let fail = fail.clone();
+      move |_| {
+        if fail.load(Ordering::SeqCst) {
+          return Err(Errors::FailedToSyncToDataFile);
+        }
+        syncs.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+      }
+    }),
+  ));
+  assert_eq!(10, engine.durable_seq_no().unwrap());
+  assert_eq!(1, syncs.load(Ordering::SeqCst));
+
+  // committing without sync_writes doesn't sync, the batch isn't reported durable until a
+  // sync succeeds
+  let wb = engine.new_write_batch(batch_opts()).unwrap();
+  wb.put(get_test_key(2000), get_test_value(2000)).unwrap();
+  wb.commit().unwrap();
+  assert_eq!(file_id, engine.active_data_file.read().get_file_id());
+  assert_eq!(1, syncs.load(Ordering::SeqCst));
+  fail.store(true, Ordering::SeqCst);
+  assert_eq!(
+    Errors::FailedToSyncToDataFile,
+    engine.durable_seq_no().unwrap_err()
+  );
+  fail.store(false, Ordering::SeqCst);
+  assert_eq!(11, engine.durable_seq_no().unwrap());
+  assert_eq!(2, syncs.load(Ordering::SeqCst));
+  std::mem::drop(wb);
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_data_files_info() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-data-files-info");
+  opts.data_file_size = 32 * 1024;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  for i in 0..1000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in 0..500 {
+    engine.put(get_test_key(i), get_test_value(i + 1)).unwrap();
+  }
+  for i in 900..1000 {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+  let wb = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  wb.put(get_test_key(2000), get_test_value(2000)).unwrap();
+  wb.commit().unwrap();
+
+  let infos = engine.data_files_info().unwrap();
+  assert_eq!(engine.get_engine_stat().unwrap().data_file_num, infos.len());
+  assert!(infos.len() > 1);
+  for (i, info) in infos.iter().enumerate() {
+    assert_eq!(i as u32, info.file_id);
+    assert_eq!(i == infos.len() - 1, info.is_active);
+    assert!(info.size > 0);
+    assert!(info.live_record_count <= info.record_count);
+  }
+
+  // 1000 puts, 500 overwrites, 100 tombstones and a batch of one put and its finish record
+  let record_count: usize = infos.iter().map(|info| info.record_count).sum();
+  assert_eq!(1000 + 500 + 100 + 2, record_count);
+  let live_record_count: usize = infos.iter().map(|info| info.live_record_count).sum();
+  assert_eq!(engine.list_keys().unwrap().len(), live_record_count);
+  assert_eq!(901, live_record_count);
+
+  // overwritten keys lived in the first file
+  assert!(infos[0].live_record_count < infos[0].record_count);
+
+  std::mem::drop(wb);
+  std::mem::drop(engine);
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+// data file handles of the test directory open in this process
+fn open_data_files(dir_path: &PathBuf) -> usize {
+  let dir_path = fs::canonicalize(dir_path).unwrap();
+  fs::read_dir("/proc/self/fd")
+    .unwrap()
+    .flatten()
+    .filter_map(|entry| fs::read_link(entry.path()).ok())
+    .filter(|target| target.starts_with(&dir_path))
+    .filter(|target| target.extension().is_some_and(|ext| ext == "data"))
+    .count()
+}
+
+#[test]
+fn test_engine_max_open_files() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-max-open-files");
+  opts.data_file_size = 4 * 1024;
+  opts.max_open_files = 3;
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..2000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  assert!(engine.get_engine_stat().unwrap().data_file_num > 10);
+  // the active file is always open
+  assert!(open_data_files(&opts.dir_path) <= opts.max_open_files + 1);
+  std::mem::drop(engine);
+
+  // reopened, old files are read back and forth on demand
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert!(open_data_files(&opts.dir_path) <= opts.max_open_files + 1);
+  for i in (0..2000).rev().chain(0..2000).step_by(3) {
+    assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+    assert!(open_data_files(&opts.dir_path) <= opts.max_open_files + 1);
+  }
+  let iter = engine.iter(option::IteratorOptions::default()).unwrap();
+  let mut count = 0;
+  while iter.next().is_some() {
+    count += 1;
+  }
+  assert_eq!(2000, count);
+  std::mem::drop(iter);
+
+  // readers reopening files while others evict them leave no handle open past the limit
+  let engine = Arc::new(engine);
+  let handles: Vec<_> = (0..8)
+    .map(|t| {
+      let engine = engine.clone();
+      thread::spawn(move || {
+        for i in (t..2000).step_by(7) {
+          assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+        }
+      })
+    })
+    .collect();
+  for handle in handles {
+    handle.join().unwrap();
+  }
+  assert!(open_data_files(&opts.dir_path) <= opts.max_open_files + 1);
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_lazy_open_files() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-lazy-open-files");
+  opts.data_file_size = 4 * 1024;
+  opts.max_key_size = 256;
+  opts.max_value_size = 1024;
+  opts.lazy_open_files = true;
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..2000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  assert!(engine.get_engine_stat().unwrap().data_file_num > 10);
+  std::mem::drop(engine);
+
+  // only the active file is open until a read touches an old file
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(1, open_data_files(&opts.dir_path));
+  assert_eq!(get_test_value(0), engine.get(get_test_key(0)).unwrap());
+  assert_eq!(2, open_data_files(&opts.dir_path));
+  assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+  assert_eq!(2, open_data_files(&opts.dir_path));
+  assert_eq!(
+    get_test_value(1000),
+    engine.get(get_test_key(1000)).unwrap()
+  );
+  assert_eq!(3, open_data_files(&opts.dir_path));
+
+  // a closed file removed meanwhile fails the read, it isn't created again empty
+  let file_of = |i: usize| {
+    let pos = engine.index.read().get(get_test_key(i).to_vec());
+    pos.unwrap().file_id
+  };
+  let active_id = engine.active_data_file.read().get_file_id();
+  let key = (0..2000)
+    .find(|i| ![file_of(0), file_of(1000), active_id].contains(&file_of(*i)))
+    .unwrap();
+  let file_id = file_of(key);
+  let file_name = get_data_file_name(&opts.dir_path, file_id, &opts.file_suffix);
+  fs::remove_file(&file_name).unwrap();
+  assert_eq!(
+    Errors::DataFileNotFound,
+    engine.get(get_test_key(key)).unwrap_err()
+  );
+  assert!(!file_name.exists());
+  // nor is its size taken for 0
+  let data_file = engine.old_data_files.read().get(&file_id).cloned().unwrap();
+  assert_eq!(
+    Errors::FailedToReadFromDataFile,
+    data_file.physical_size().unwrap_err()
+  );
+  std::mem::drop(data_file);
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_get_many_ordered() {
+  for index_type in [
+    option::IndexType::BTree,
+    option::IndexType::SkipList,
+    option::IndexType::BPlusTree,
+  ] {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-get-many-ordered-{:?}", index_type));
+    opts.index_type = index_type;
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+    // even keys only, then delete every tenth
+    for i in (0..1000).step_by(2) {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in (0..1000).step_by(10) {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+    let expected = |i: usize| match i % 2 == 0 && i % 10 != 0 {
+      true => Some(get_test_value(i)),
+      false => None,
+    };
+
+    // sorted keys with absent ones, duplicates and keys past the last one
+    let ids: Vec<usize> = vec![1, 2, 2, 3, 10, 12, 500, 998, 999, 1200];
+    let keys: Vec<Bytes> = ids.iter().map(|i| get_test_key(*i)).collect();
+    let values = engine.get_many_ordered(&keys).unwrap();
+    assert_eq!(ids.len(), values.len());
+    for (i, value) in ids.iter().zip(values) {
+      assert_eq!(expected(*i), value);
+    }
+
+    // every key in range matches a get
+    let keys: Vec<Bytes> = (0..1000).map(get_test_key).collect();
+    let values = engine.get_many_ordered(&keys).unwrap();
+    for (i, value) in values.into_iter().enumerate() {
+      assert_eq!(expected(i), value);
+    }
+
+    // the join takes the index in chunks, sparse keys seek past the entries between them
+    // with the smallest chunk and dense keys grow it
+    let max_chunk = Arc::new(AtomicUsize::new(0));
+    let inner = std::mem::replace(&mut *engine.index.write(), Box::new(BTree::new()));
+    *engine.index.write() = Box::new(ChunkCountingIndex {
+      inner,
+      max_chunk: max_chunk.clone(),
+    });
+    let ids: Vec<usize> = (0..1000).step_by(100).map(|i| i + 4).collect();
+    let keys: Vec<Bytes> = ids.iter().map(|i| get_test_key(*i)).collect();
+    let values = engine.get_many_ordered(&keys).unwrap();
+    for (i, value) in ids.iter().zip(values) {
+      assert_eq!(expected(*i), value);
+    }
+    assert_eq!(GET_MANY_MIN_CHUNK_LEN, max_chunk.load(Ordering::SeqCst));
+    let keys: Vec<Bytes> = (0..1000).map(get_test_key).collect();
+    assert_eq!(1000, engine.get_many_ordered(&keys).unwrap().len());
+    let max_chunk = max_chunk.load(Ordering::SeqCst);
+    assert!(max_chunk > GET_MANY_MIN_CHUNK_LEN && max_chunk <= GET_MANY_MAX_CHUNK_LEN);
+
+    // unsorted keys fall back to a get per key
+    let ids: Vec<usize> = vec![12, 3, 998, 2];
+    let keys: Vec<Bytes> = ids.iter().map(|i| get_test_key(*i)).collect();
+    let values = engine.get_many_ordered(&keys).unwrap();
+    for (i, value) in ids.iter().zip(values) {
+      assert_eq!(expected(*i), value);
+    }
+
+    assert!(engine.get_many_ordered(&[]).unwrap().is_empty());
+    assert_eq!(
+      Errors::KeyIsEmpty,
+      engine
+        .get_many_ordered(&[get_test_key(2), Bytes::new()])
+        .err()
+        .unwrap()
+    );
+    std::mem::drop(engine);
+
+    fs::remove_dir_all(opts.clone().dir_path).unwrap();
+  }
+}
+
+#[test]
+fn test_engine_group_commit() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-group-commit");
+  opts.sync_writes = true;
+  let engine = Arc::new(Engine::open(opts.clone()).expect("fail to open engine"));
+  let file_id = engine.active_data_file.read().get_file_id();
+  let file_name = get_data_file_name(&opts.dir_path, file_id, &opts.file_suffix);
+
+  // counts syncs, each taking a while as a disk flush would, and marks the bytes they made
+  // durable
+  let syncs = Arc::new(AtomicUsize::new(0));
+  let synced = Arc::new(AtomicU64::new(0));
+  engine.active_data_file.write().replace_io_manager(Box::new(
+    HookedIO::new(Box::new(FileIO::new(&file_name).unwrap())).on_synced({
+      let syncs = syncs.clone();
+      let synced = synced.clone();
+      move |size| {
+        syncs.fetch_add(1, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(2));
+        synced.fetch_max(size, Ordering::SeqCst);
+        Ok(())
+      }
+    }),
+  ));
+
+  // writers appending during a sync share the next one, and none is acked before a sync
+  // covers its record
+  let mut handles = vec![];
+  for t in 0..8 {
+    let engine = engine.clone();
+    let synced = synced.clone();
+    handles.push(thread::spawn(move || {
+      for i in t * 50..(t + 1) * 50 {
+        engine.put(get_test_key(i), get_test_value(i)).unwrap();
+        let pos = engine.index.read().get(get_test_key(i).to_vec()).unwrap();
+        assert_eq!(file_id, pos.file_id);
+        assert!(pos.offset + pos.size as u64 <= synced.load(Ordering::SeqCst));
+      }
+    }));
+  }
+  for handle in handles {
+    handle.join().unwrap();
+  }
+  assert!(syncs.load(Ordering::SeqCst) < 200);
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_db_handle() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-db-handle");
+  let db = Db::open(opts.clone()).expect("fail to open engine");
+
+  let mut handles = vec![];
+  for t in 0..4 {
+    let db = db.clone();
+    handles.push(thread::spawn(move || {
+      for i in t * 1000..(t + 1) * 1000 {
+        db.put(get_test_key(i), get_test_value(i)).unwrap();
+        assert_eq!(get_test_value(i), db.get(get_test_key(i)).unwrap());
+      }
+      // not the last handle, the engine stays open
+      db.close().unwrap();
+    }));
+  }
+  for handle in handles {
+    handle.join().unwrap();
+  }
+
+  assert_eq!(4000, db.list_keys().unwrap().len());
+  assert_eq!(
+    Errors::DatabaseIsUsing,
+    Engine::open(opts.clone()).err().unwrap()
+  );
+
+  // last handle closes the engine
+  db.close().unwrap();
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(4000, engine.list_keys().unwrap().len());
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_put_if_absent() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-put-if-absent");
+  let engine = Arc::new(Engine::open(opts.clone()).expect("fail to open engine"));
+
+  // many threads race to insert the same key
+  let mut handles = vec![];
+  for i in 0..16 {
+    let eng = engine.clone();
+    handles.push(thread::spawn(move || {
+      let inserted = eng
+        .put_if_absent(get_test_key(1), get_test_value(i))
+        .unwrap();
+      (i, inserted)
+    }));
+  }
+  let winners: Vec<usize> = handles
+    .into_iter()
+    .map(|handle| handle.join().unwrap())
+    .filter(|(_, inserted)| *inserted)
+    .map(|(i, _)| i)
+    .collect();
+  assert_eq!(1, winners.len());
+  assert_eq!(
+    get_test_value(winners[0]),
+    engine.get(get_test_key(1)).unwrap()
+  );
+
+  // inserted again once deleted
+  engine.delete(get_test_key(1)).unwrap();
+  assert!(engine
+    .put_if_absent(get_test_key(1), get_test_value(100))
+    .unwrap());
+  assert!(!engine
+    .put_if_absent(get_test_key(1), get_test_value(101))
+    .unwrap());
+  assert_eq!(get_test_value(100), engine.get(get_test_key(1)).unwrap());
+  assert_eq!(
+    Errors::KeyIsEmpty,
+    engine
+      .put_if_absent(Bytes::new(), get_test_value(1))
+      .unwrap_err()
+  );
+
+  // a put racing the check is never overwritten, whichever goes first the put is kept
+  let putter = thread::spawn({
+    let engine = engine.clone();
+    move || {
+      for i in 1000..3000 {
+        engine.put(get_test_key(i), Bytes::from("put")).unwrap();
+      }
+    }
+  });
+  for i in 1000..3000 {
+    engine
+      .put_if_absent(get_test_key(i), Bytes::from("absent"))
+      .unwrap();
+  }
+  putter.join().unwrap();
+  for i in 1000..3000 {
+    assert_eq!(Bytes::from("put"), engine.get(get_test_key(i)).unwrap());
+  }
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_append() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-append");
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  // a missing key starts from an empty value
+  engine
+    .append(get_test_key(1), Bytes::from("line-1\n"))
+    .unwrap();
+  engine
+    .append(get_test_key(1), Bytes::from("line-2\n"))
+    .unwrap();
+  engine.append(get_test_key(1), Bytes::new()).unwrap();
+  engine
+    .append(get_test_key(1), Bytes::from("line-3\n"))
+    .unwrap();
+  assert_eq!(
+    Bytes::from("line-1\nline-2\nline-3\n"),
+    engine.get(get_test_key(1)).unwrap()
+  );
+
+  engine.put(get_test_key(2), Bytes::from("head")).unwrap();
+  engine
+    .append(get_test_key(2), Bytes::from("-tail"))
+    .unwrap();
+  assert_eq!(
+    Bytes::from("head-tail"),
+    engine.get(get_test_key(2)).unwrap()
+  );
+  assert_eq!(
+    Errors::KeyIsEmpty,
+    engine.append(Bytes::new(), Bytes::from("x")).unwrap_err()
+  );
+
+  // concurrent appends don't lose each other's updates
+  let engine = Arc::new(engine);
+  let handles: Vec<_> = (0..4)
+    .map(|_| {
+      let engine = engine.clone();
+      thread::spawn(move || {
+        for _ in 0..250 {
+          engine.append(get_test_key(3), Bytes::from("x")).unwrap();
+        }
+      })
+    })
+    .collect();
+  for handle in handles {
+    handle.join().unwrap();
+  }
+  assert_eq!(1000, engine.get(get_test_key(3)).unwrap().len());
+  let engine = Arc::try_unwrap(engine).ok().unwrap();
+
+  // survives a restart
+  engine.close().unwrap();
+  std::mem::drop(engine);
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(
+    Bytes::from("line-1\nline-2\nline-3\n"),
+    engine.get(get_test_key(1)).unwrap()
+  );
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_info() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-info");
+  opts.data_file_size = 64 * 1024 * 1024;
+  opts.file_merge_threshold = 0 as f32;
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  let info = engine.info();
+  assert!(info.last_merge_at.is_none());
+  assert!(info.created_at <= std::time::SystemTime::now());
+  std::mem::drop(engine);
+
+  // creation time is stable across reopen
+  thread::sleep(Duration::from_millis(10));
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  assert_eq!(info, engine.info());
+
+  for i in 0..100 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in 0..50 {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+  engine.merge().unwrap();
+  let merged = engine.info();
+  assert_eq!(info.created_at, merged.created_at);
+  assert!(merged.last_merge_at.unwrap() >= info.created_at);
+  std::mem::drop(engine);
+
+  // merge time is persisted
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  assert_eq!(merged, engine.info());
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_open_releases_data_file_locks() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-open-locks");
+  opts.data_file_size = 64 * 1024;
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  for i in 0..5000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  let wb = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  for i in 0..10 {
+    wb.put(get_test_key(i), Bytes::from("txn value")).unwrap();
+  }
+  wb.commit().unwrap();
+  std::mem::drop(engine);
+
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  assert!(engine.old_data_files.read().len() > 1);
+
+  // no data file guard is left held by the index loading
+  assert!(engine.active_data_file.try_write().is_some());
+  assert!(engine.old_data_files.try_write().is_some());
+
+  // active file write offset is restored, new writes append after the existing ones
+  {
+    let active_file = engine.active_data_file.read();
+    assert_eq!(
+      active_file.logical_size(),
+      active_file.physical_size().unwrap()
+    );
+  }
+  engine
+    .put(get_test_key(5000), get_test_value(5000))
+    .unwrap();
+  std::mem::drop(engine);
+
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  assert_eq!(5001, engine.list_keys().unwrap().len());
+  for i in 0..10 {
+    assert_eq!(
+      Bytes::from("txn value"),
+      engine.get(get_test_key(i)).unwrap()
+    );
+  }
+  for i in 10..=5000 {
+    assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+  }
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_value_codec() {
+  struct ReverseCodec;
+  impl ValueCodec for ReverseCodec {
+    fn encode(&self, value: &[u8]) -> Vec<u8> {
+      value.iter().rev().copied().collect()
+    }
+    fn decode(&self, value: &[u8]) -> Result<Vec<u8>> {
+      Ok(value.iter().rev().copied().collect())
+    }
+  }
+  let reversed = |value: Bytes| value.iter().rev().copied().collect::<Bytes>();
+
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-value-codec");
+  opts.data_file_size = 64 * 1024;
+  opts.file_merge_threshold = 0 as f32;
+  opts.value_codec = Some(Arc::new(ReverseCodec));
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+  for i in 0..1000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in 0..500 {
+    engine.put(get_test_key(i), get_test_value(i + 1)).unwrap();
+  }
+  let wb = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  wb.put(get_test_key(1000), get_test_value(1000)).unwrap();
+  wb.commit().unwrap();
+
+  assert_eq!(get_test_value(1), engine.get(get_test_key(0)).unwrap());
+  assert_eq!(
+    get_test_value(1000),
+    engine.get(get_test_key(1000)).unwrap()
+  );
+  let iter = engine.iter(option::IteratorOptions::default()).unwrap();
+  assert_eq!(Some((get_test_key(0), get_test_value(1))), iter.next());
+  let mut scan = engine.scan().unwrap();
+  let (key, value) = scan.next().unwrap().unwrap();
+  assert_eq!(engine.get(key).unwrap(), value);
+  std::mem::drop(scan);
+  std::mem::drop(iter);
+
+  // merged values are decoded with the same codec
+  engine.merge().unwrap();
+  std::mem::drop(engine);
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  for i in 0..1001 {
+    let expected = match i < 500 {
+      true => get_test_value(i + 1),
+      false => get_test_value(i),
+    };
+    assert_eq!(expected, engine.get(get_test_key(i)).unwrap());
+  }
+  std::mem::drop(engine);
+
+  // values are stored encoded
+  opts.value_codec = None;
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  assert_eq!(
+    reversed(get_test_value(1)),
+    engine.get(get_test_key(0)).unwrap()
+  );
+  assert_eq!(
+    reversed(get_test_value(1000)),
+    engine.get(get_test_key(1000)).unwrap()
+  );
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_rebuild_index() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-rebuild-index");
+  opts.data_file_size = 64 * 1024;
+  opts.index_type = option::IndexType::BPlusTree;
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  for i in 0..2000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in 0..100 {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+  let wb = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  wb.put(get_test_key(100), Bytes::from("txn value")).unwrap();
+  wb.commit().unwrap();
+
+  // migrate the populated B+Tree database to BTree
+  engine.rebuild_index(option::IndexType::BTree).unwrap();
+  assert_eq!(option::IndexType::BTree, engine.index_type());
+  assert_eq!(1900, engine.list_keys().unwrap().len());
+  for i in 0..100 {
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.get(get_test_key(i)).err().unwrap()
+    );
+  }
+  assert_eq!(
+    Bytes::from("txn value"),
+    engine.get(get_test_key(100)).unwrap()
+  );
+  for i in 101..2000 {
+    assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+  }
+  engine
+    .put(get_test_key(2000), get_test_value(2000))
+    .unwrap();
+  std::mem::drop(engine);
+
+  // B+Tree index file missed the write after migration, it's rebuilt on open
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  assert_eq!(1901, engine.list_keys().unwrap().len());
+  assert_eq!(
+    get_test_value(2000),
+    engine.get(get_test_key(2000)).unwrap()
+  );
+  std::mem::drop(engine);
+
+  // migrate back to B+Tree from an in-memory index
+  opts.index_type = option::IndexType::SkipList;
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  engine.rebuild_index(option::IndexType::BPlusTree).unwrap();
+  assert_eq!(option::IndexType::BPlusTree, engine.index_type());
+  engine
+    .put(get_test_key(2001), get_test_value(2001))
+    .unwrap();
+  assert_eq!(1902, engine.list_keys().unwrap().len());
+  std::mem::drop(engine);
+
+  opts.index_type = option::IndexType::BPlusTree;
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  assert_eq!(1902, engine.list_keys().unwrap().len());
+  assert_eq!(
+    Bytes::from("txn value"),
+    engine.get(get_test_key(100)).unwrap()
+  );
+  assert_eq!(
+    get_test_value(2001),
+    engine.get(get_test_key(2001)).unwrap()
+  );
+  std::mem::drop(engine);
+
+  // writes racing the rebuild all make it into the new index, between in-memory indexes the
+  // B+Tree index file isn't marked stale
+  opts.index_type = option::IndexType::BTree;
+  let dirty_file = opts.dir_path.join(BPTREE_DIRTY_FILE_NAME);
+  let _ = fs::remove_file(&dirty_file);
+  let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+  let writer = thread::spawn({
+    let engine = engine.clone();
+    move || {
+      for i in 3000..6000 {
+        engine.put(get_test_key(i), get_test_value(i)).unwrap();
+      }
+    }
+  });
+  engine.rebuild_index(option::IndexType::SkipList).unwrap();
+  writer.join().unwrap();
+  assert!(!dirty_file.is_file());
+  assert_eq!(option::IndexType::SkipList, engine.index_type());
+  assert_eq!(1902 + 3000, engine.list_keys().unwrap().len());
+  for i in 3000..6000 {
+    assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+  }
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_shrink_index() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-shrink-index");
+  opts.index_type = option::IndexType::BPlusTree;
+  opts.bptree_flush_policy = option::FlushPolicy::EveryOps(1000);
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  // the B+Tree file grows in steps of at least 8MB, long keys take the index past the first one
+  let key = |i: usize| Bytes::from(format!("key-{:0>92}", i));
+  for i in 0..100000 {
+    engine.put(key(i), get_test_value(i)).unwrap();
+  }
+  for i in 1000..100000 {
+    engine.delete(key(i)).unwrap();
+  }
+  engine.sync().unwrap();
+
+  let index_file = opts.dir_path.join(BPTREE_INDEX_FILE_NAME);
+  let pre_size = fs::metadata(&index_file).unwrap().len();
+  engine.shrink_index().unwrap();
+  assert!(fs::metadata(&index_file).unwrap().len() < pre_size);
+
+  // the shrunk index serves reads and writes as before
+  assert_eq!(1000, engine.list_keys().unwrap().len());
+  for i in 0..1000 {
+    assert_eq!(get_test_value(i), engine.get(key(i)).unwrap());
+  }
+  assert_eq!(Errors::KeyNotFound, engine.get(key(1000)).err().unwrap());
+  engine.put(key(100000), get_test_value(100000)).unwrap();
+  engine.close().unwrap();
+  std::mem::drop(engine);
+
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  assert_eq!(1001, engine.list_keys().unwrap().len());
+  assert_eq!(get_test_value(100000), engine.get(key(100000)).unwrap());
+  std::mem::drop(engine);
+
+  // nothing to shrink for an in-memory index
+  opts.index_type = option::IndexType::BTree;
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  engine.shrink_index().unwrap();
+  assert_eq!(1001, engine.list_keys().unwrap().len());
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_fingerprint() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-fingerprint");
+  opts.data_file_size = 64 * 1024;
+  opts.max_key_size = 1024;
+  opts.max_value_size = 4 * 1024; // 4KB
+  opts.file_merge_threshold = 0 as f32;
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  for _ in 0..3 {
+    for i in 0..2000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+  }
+  for i in 0..500 {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+  let fingerprint = engine.fingerprint().unwrap();
+
+  // merged copy
+  engine.merge().unwrap();
+  std::mem::drop(engine);
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  assert_eq!(fingerprint, engine.fingerprint().unwrap());
+
+  // same contents written once in reverse order, into larger data files
+  let mut other_opts = opts.clone();
+  other_opts.dir_path = PathBuf::from("/tmp/bitkv-rs-fingerprint-other");
+  other_opts.data_file_size = 1024 * 1024; // 1MB
+  let other = Engine::open(other_opts.clone()).expect("failed to open engine");
+  for i in (500..2000).rev() {
+    other
+      .put(get_test_key(i), engine.get(get_test_key(i)).unwrap())
+      .unwrap();
+  }
+  assert_eq!(fingerprint, other.fingerprint().unwrap());
+
+  // a single differing value
+  other
+    .put(get_test_key(1000), Bytes::from("other value"))
+    .unwrap();
+  assert_ne!(fingerprint, other.fingerprint().unwrap());
+  std::mem::drop(other);
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+  fs::remove_dir_all(other_opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_append_only() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-append-only");
+  opts.append_only = true;
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  for i in 0..100 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+
+  // keys out of order or written again are rejected
+  assert_eq!(
+    Errors::KeyNotAscending,
+    engine
+      .put(get_test_key(50), get_test_value(50))
+      .err()
+      .unwrap()
+  );
+  assert_eq!(
+    Errors::KeyNotAscending,
+    engine
+      .put(get_test_key(99), get_test_value(99))
+      .err()
+      .unwrap()
+  );
+  assert_eq!(
+    Errors::KeyNotAscending,
+    engine
+      .put_batch_sorted_into_file(&[
+        (get_test_key(101), get_test_value(101)),
+        (get_test_key(100), get_test_value(100)),
+      ])
+      .err()
+      .unwrap()
+  );
+  let wb = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  wb.put(get_test_key(100), get_test_value(100)).unwrap();
+  wb.put(get_test_key(10), get_test_value(10)).unwrap();
+  assert_eq!(Errors::KeyNotAscending, wb.commit().err().unwrap());
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.get(get_test_key(100)).err().unwrap()
+  );
+
+  // greater keys go on, by put, sorted entries and batches
+  engine
+    .put_batch_sorted_into_file(&[
+      (get_test_key(100), get_test_value(100)),
+      (get_test_key(101), get_test_value(101)),
+    ])
+    .unwrap();
+  let wb = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  wb.put(get_test_key(103), get_test_value(103)).unwrap();
+  wb.put(get_test_key(102), get_test_value(102)).unwrap();
+  wb.commit().unwrap();
+  engine.delete(get_test_key(0)).unwrap();
+
+  assert_eq!(103, engine.list_keys().unwrap().len());
+  assert_eq!(get_test_value(42), engine.get(get_test_key(42)).unwrap());
+  let iter = engine.iter(option::IteratorOptions::default()).unwrap();
+  assert_eq!(get_test_key(1), iter.next().unwrap().0);
+  std::mem::drop(iter);
+  engine.close().unwrap();
+  std::mem::drop(engine);
+
+  // ordering goes on from the greatest key after reopen
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  assert_eq!(get_test_value(103), engine.get(get_test_key(103)).unwrap());
+  assert_eq!(
+    Errors::KeyNotAscending,
+    engine
+      .put(get_test_key(103), get_test_value(103))
+      .err()
+      .unwrap()
+  );
+  engine.put(get_test_key(104), get_test_value(104)).unwrap();
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[test]
+fn test_engine_open_discards_unfinished_txn() {
+  let mut opts = Options::default();
+  opts.dir_path = PathBuf::from("/tmp/bitkv-rs-unfinished-txn");
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+  // txn records without a finish record, as if crashed mid commit
+  let write_unfinished = |engine: &Engine, keys: std::ops::Range<usize>| {
+    let seq_no = engine.seq_no.fetch_add(1, Ordering::SeqCst);
+    for i in keys {
+      let mut record = LogRecord {
+        key: log_record_key_with_seq(get_test_key(i).to_vec(), seq_no).unwrap(),
+        value: get_test_value(i).to_vec(),
+        rec_type: LogRecordType::Normal,
+      };
+      engine
+        .append_log_record_unsynced_until(&mut record, None)
+        .unwrap();
+    }
+  };
+
+  // an unfinished batch followed by a committed one, and another unfinished one at the end
+  write_unfinished(&engine, 0..10);
+  let wb = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  for i in 10..20 {
+    wb.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  wb.commit().unwrap();
+  write_unfinished(&engine, 20..30);
+  engine.put(get_test_key(30), get_test_value(30)).unwrap();
+  engine.close().unwrap();
+  std::mem::drop(engine);
+
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  for i in (0..10).chain(20..30) {
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.get(get_test_key(i)).err().unwrap()
+    );
+  }
+  for i in 10..31 {
+    if !(20..30).contains(&i) {
+      assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+    }
+  }
+  assert_eq!(11, engine.list_keys().unwrap().len());
+
+  // batches after the discarded ones commit as usual
+  let wb = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  wb.put(get_test_key(0), get_test_value(0)).unwrap();
+  wb.commit().unwrap();
+  std::mem::drop(engine);
+
+  let engine = Engine::open(opts.clone()).expect("failed to open engine");
+  assert_eq!(get_test_value(0), engine.get(get_test_key(0)).unwrap());
+  std::mem::drop(engine);
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
@@ -1,4 +1,4 @@
-use std::{fmt::Debug, result};
+use core::{fmt::Debug, result};
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -30,7 +30,7 @@ pub enum Errors {
   #[error("database dir path can not be empty")]
   DirPathIsEmpty,
 
-  #[error("database data file size must be greater than 0")]
+  #[error("database data file size must fit a log record")]
   DataFileSizeTooSmall,
 
   #[error("failed to create the database directory")]
@@ -48,6 +48,11 @@ pub enum Errors {
   #[error("invalid crc value, log record maybe corrupted")]
   InvalidLogRecordCrc,
 
+  /// the index points at a log record the data file ends before, so it was lost, e.g. the
+  /// file was cut off after the record was written
+  #[error("log record is cut off by the end of the data file")]
+  TruncatedLogRecord,
+
   #[error("exceed max batch number in one batch write")]
   ExceedMaxBatchNum,
 
@@ -69,8 +74,77 @@ pub enum Errors {
   #[error("disk space is not enough for merge")]
   MergeNoEnoughSpace,
 
+  #[error("merge is cancelled")]
+  MergeCancelled,
+
+  #[error("invalid merge parallelism, must be at least 1")]
+  InvalidMergeParallelism,
+
+  #[error("value dedup requires merge min age of zero and merge parallelism of 1")]
+  InvalidDedupValues,
+
+  #[error("merged data files don't fit below the first unmerged data file")]
+  MergeOutputTooLarge,
+
   #[error("failed to copy the database directory")]
   FailedToCopyDirectory,
+
+  #[error("the key exceeds max key size")]
+  KeyTooLarge,
+
+  #[error("the value exceeds max value size")]
+  ValueTooLarge,
+
+  #[error("invalid max key or value size, must be in range (0, hard limit]")]
+  InvalidMaxKeyValueSize,
+
+  #[error("timed out waiting for lock")]
+  LockTimeout,
+
+  #[error("the engine is opened at a sequence number and is read only")]
+  ReadOnlyEngine,
+
+  #[error("opening at a sequence number is not supported by B+ tree index")]
+  OpenAtSeqUnsupported,
+
+  #[error("blocking engine task failed to complete")]
+  AsyncTaskFailed,
+
+  #[error("the encoded log record exceeds max log record size")]
+  LogRecordTooLarge,
+
+  #[error("failed to decode value with the value codec")]
+  ValueCodecFailed,
+
+  #[error("invalid log record type {found}, log record maybe corrupted")]
+  InvalidLogRecordType { found: u8 },
+
+  #[error("the iterator consistency is unsupported by the index type")]
+  UnsupportedIteratorMode,
+
+  #[error("invalid data file suffix, must be non-empty and not match other database files")]
+  InvalidFileSuffix,
+
+  #[error("the key version doesn't match the expected version")]
+  VersionMismatch,
+
+  #[error("bytes per sync must not exceed the data file size")]
+  InvalidBytesPerSync,
+
+  #[error("invalid b+ tree flush policy, must commit after at least 1 op")]
+  InvalidFlushPolicy,
+
+  #[error("the database directory is not writable")]
+  DatabaseDirNotWritable,
+
+  #[error("no database found in the directory")]
+  DatabaseNotFound,
+
+  #[error("append only database requires keys greater than every key written before")]
+  KeyNotAscending,
+
+  #[error("failed to write the export")]
+  FailedToWriteExport,
 }
 
 pub type Result<T> = result::Result<T, Errors>;
@@ -0,0 +1,188 @@
+use std::{
+  collections::{HashMap, VecDeque},
+  fs,
+  path::PathBuf,
+  sync::{Arc, Weak},
+};
+
+use bytes::Bytes;
+use parking_lot::{Mutex, RwLock};
+
+use super::{open_io_manager, IOManager};
+use crate::{
+  errors::{Errors, Result},
+  option::IOManagerType,
+};
+use log::error;
+
+// open handle of a cached file, None while closed
+type HandleSlot = RwLock<Option<Box<dyn IOManager>>>;
+
+/// Bounds the number of open handles of `CachedIO` files, the least recently used
+/// handle is closed when the limit is hit
+pub struct FileCache {
+  capacity: usize,
+  state: Mutex<FileCacheState>,
+}
+
+struct FileCacheState {
+  next_id: u64,
+  lru: VecDeque<u64>, // ids of open handles, least recently used first
+  slots: HashMap<u64, Weak<HandleSlot>>,
+}
+
+impl FileCache {
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      capacity: capacity.max(1),
+      state: Mutex::new(FileCacheState {
+        next_id: 0,
+        lru: VecDeque::new(),
+        slots: HashMap::new(),
+      }),
+    }
+  }
+
+  fn register(&self, slot: &Arc<HandleSlot>) -> u64 {
+    let mut state = self.state.lock();
+    let id = state.next_id;
+    state.next_id += 1;
+    state.slots.insert(id, Arc::downgrade(slot));
+    id
+  }
+
+  fn unregister(&self, id: u64) {
+    let mut state = self.state.lock();
+    state.slots.remove(&id);
+    state.lru.retain(|x| *x != id);
+  }
+
+  // mark id as most recently used, return the handles to close to stay within capacity
+  fn touch(&self, id: u64) -> Vec<Arc<HandleSlot>> {
+    let mut state = self.state.lock();
+    if state.lru.back() != Some(&id) {
+      state.lru.retain(|x| *x != id);
+      state.lru.push_back(id);
+    }
+
+    let mut victims = Vec::new();
+    while state.lru.len() > self.capacity {
+      let victim = state.lru.pop_front().unwrap();
+      if let Some(slot) = state.slots.get(&victim).and_then(|slot| slot.upgrade()) {
+        victims.push(slot);
+      }
+    }
+    victims
+  }
+
+  /// close every open handle, e.g. those opened to load the index, the files are reopened
+  /// on their next access
+  pub fn close_all(&self) {
+    let slots: Vec<Arc<HandleSlot>> = {
+      let mut state = self.state.lock();
+      state.lru.clear();
+      state
+        .slots
+        .values()
+        .filter_map(|slot| slot.upgrade())
+        .collect()
+    };
+    // slot locks are never taken under the cache lock
+    for slot in slots {
+      *slot.write() = None;
+    }
+  }
+}
+
+/// IO manager opening its file on demand, the handle may be closed by the `FileCache`
+/// any time it isn't in use and is reopened on the next access
+pub struct CachedIO {
+  file_name: PathBuf,
+  io_type: IOManagerType,
+  id: u64,
+  slot: Arc<HandleSlot>,
+  cache: Arc<FileCache>,
+}
+
+impl CachedIO {
+  pub fn new(file_name: PathBuf, io_type: IOManagerType, cache: &Arc<FileCache>) -> Self {
+    let slot = Arc::new(RwLock::new(None));
+    let id = cache.register(&slot);
+    Self {
+      file_name,
+      io_type,
+      id,
+      slot,
+      cache: cache.clone(),
+    }
+  }
+
+  // run f with the open handle, reopening the file if it was closed. Fails without running f
+  // if the file can't be opened again, e.g. removed by a merge meanwhile
+  fn with_handle<R>(&self, f: impl FnOnce(&dyn IOManager) -> R) -> Result<R> {
+    loop {
+      // close evicted handles before taking our own slot, a slot lock is never held while
+      // waiting for the cache lock
+      for victim in self.cache.touch(self.id) {
+        *victim.write() = None;
+      }
+
+      {
+        let slot = self.slot.read();
+        if let Some(io_manager) = slot.as_ref() {
+          return Ok(f(io_manager.as_ref()));
+        }
+      }
+
+      // closed, reopen unless another reader got here first. A concurrent eviction may have
+      // dropped the handle from the LRU since the touch above, the next round touches it
+      // again before it's used, so it's always counted against the capacity
+      let mut slot = self.slot.write();
+      if slot.is_none() {
+        *slot = Some(open_io_manager(&self.file_name, &self.io_type)?);
+      }
+    }
+  }
+}
+
+impl Drop for CachedIO {
+  fn drop(&mut self) {
+    self.cache.unregister(self.id);
+  }
+}
+
+impl IOManager for CachedIO {
+  fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+    self.with_handle(|io_manager| io_manager.read(buf, offset))?
+  }
+
+  fn write(&self, buf: &[u8]) -> Result<usize> {
+    self.with_handle(|io_manager| io_manager.write(buf))?
+  }
+
+  fn sync(&self) -> Result<()> {
+    self.with_handle(|io_manager| io_manager.sync())?
+  }
+
+  fn size(&self) -> Result<u64> {
+    // a closed file isn't opened just for its size
+    if let Some(io_manager) = self.slot.read().as_ref() {
+      return io_manager.size();
+    }
+    match fs::metadata(&self.file_name) {
+      Ok(metadata) => Ok(metadata.len()),
+      Err(e) => {
+        error!("failed to read data file metadata err: {}", e);
+        Err(Errors::FailedToReadFromDataFile)
+      }
+    }
+  }
+
+  fn read_shared(&self, offset: u64, len: usize) -> Option<Bytes> {
+    // a file failing to open again is reported by the `read` falling back
+    self
+      .with_handle(|io_manager| io_manager.read_shared(offset, len))
+      .ok()
+      .flatten()
+  }
+}
@@ -5,12 +5,19 @@ use log::error;
 use parking_lot::RwLock;
 use std::{
   fs::{File, OpenOptions},
-  io::Write,
+  io::{self, ErrorKind, Write},
   os::unix::fs::FileExt,
   path::Path,
   sync::Arc,
+  thread,
+  time::Duration,
 };
 
+// retries of a read, write or sync failing with a transient error, the backoff doubles
+// after every retry
+const IO_RETRIES: u32 = 3;
+const IO_RETRY_BACKOFF: Duration = Duration::from_millis(1);
+
 /// FileIO standard system file I/O
 pub struct FileIO {
   fd: Arc<RwLock<File>>, //system file descriptor
@@ -18,11 +25,26 @@ pub struct FileIO {
 
 impl FileIO {
   pub fn new<P>(file_name: P) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    Self::open(file_name, true)
+  }
+
+  /// open a file that must already exist, `Errors::DataFileNotFound` if it doesn't
+  pub fn open_existing<P>(file_name: P) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    Self::open(file_name, false)
+  }
+
+  fn open<P>(file_name: P, create: bool) -> Result<Self>
   where
     P: AsRef<Path>,
   {
     match OpenOptions::new()
-      .create(true)
+      .create(create)
       .read(true)
       .append(true)
       .open(file_name)
@@ -30,6 +52,7 @@ impl FileIO {
       Ok(file) => Ok(FileIO {
         fd: Arc::new(RwLock::new(file)),
       }),
+      Err(e) if e.kind() == ErrorKind::NotFound => Err(Errors::DataFileNotFound),
       Err(e) => {
         error!("failed to open data file error: {}", e);
         Err(Errors::FailedToOpenDataFile)
@@ -41,7 +64,7 @@ impl FileIO {
 impl IOManager for FileIO {
   fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
     let read_guard = self.fd.read();
-    match read_guard.read_at(buf, offset) {
+    match retry_io(|| read_guard.read_at(buf, offset)) {
       Ok(n) => Ok(n),
       Err(e) => {
         error!("read from date file error: {}", e);
@@ -52,7 +75,7 @@ impl IOManager for FileIO {
 
   fn write(&self, buf: &[u8]) -> Result<usize> {
     let mut write_guard = self.fd.write();
-    match write_guard.write(buf) {
+    match retry_io(|| write_guard.write(buf)) {
       Ok(n) => Ok(n),
       Err(e) => {
         error!("write to data file error: {}", e);
@@ -63,19 +86,48 @@ impl IOManager for FileIO {
 
   fn sync(&self) -> Result<()> {
     let read_guard = self.fd.read();
-    if let Err(e) = read_guard.sync_all() {
+    if let Err(e) = retry_io(|| read_guard.sync_all()) {
       error!("failed to sync data file err: {}", e);
       return Err(Errors::FailedToSyncToDataFile);
     }
     Ok(())
   }
 
-  fn size(&self) -> u64 {
+  fn size(&self) -> Result<u64> {
     let read_guard = self.fd.read();
-    read_guard.metadata().unwrap().len()
+    match read_guard.metadata() {
+      Ok(metadata) => Ok(metadata.len()),
+      Err(e) => {
+        error!("failed to read data file metadata err: {}", e);
+        Err(Errors::FailedToReadFromDataFile)
+      }
+    }
+  }
+}
+
+// run op until it succeeds, fails with an error that isn't transient, or runs out of retries
+fn retry_io<T, F>(mut op: F) -> io::Result<T>
+where
+  F: FnMut() -> io::Result<T>,
+{
+  let mut backoff = IO_RETRY_BACKOFF;
+  let mut retries = 0;
+  loop {
+    match op() {
+      Err(e) if is_transient(&e) && retries < IO_RETRIES => {
+        thread::sleep(backoff);
+        backoff *= 2;
+        retries += 1;
+      }
+      res => return res,
+    }
   }
 }
 
+fn is_transient(e: &io::Error) -> bool {
+  matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock)
+}
+
 #[cfg(test)]
 mod tests {
   use std::{fs, path::PathBuf};
@@ -167,10 +219,82 @@ mod tests {
     assert!(res2.is_ok());
     assert_eq!(5, res2.ok().unwrap());
 
-    let size = fio.size();
+    let size = fio.size().unwrap();
     assert_eq!(10, size);
 
     let res3 = fs::remove_file(path);
     assert!(res3.is_ok());
   }
+
+  #[test]
+  fn test_file_io_open_existing() {
+    let path = PathBuf::from("/tmp/e.data");
+    let res1 = FileIO::open_existing(&path);
+    assert_eq!(Errors::DataFileNotFound, res1.err().unwrap());
+    assert!(!path.exists());
+
+    let fio = FileIO::new(&path).unwrap();
+    fio.write("key-a".as_bytes()).unwrap();
+    let res2 = FileIO::open_existing(&path);
+    assert_eq!(5, res2.unwrap().size().unwrap());
+
+    let res3 = fs::remove_file(path);
+    assert!(res3.is_ok());
+  }
+
+  // fails with the given error kinds in turn, then writes everything
+  struct FlakyFile {
+    errors: Vec<ErrorKind>,
+    attempts: usize,
+    written: Vec<u8>,
+  }
+
+  impl Write for FlakyFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.attempts += 1;
+      if self.attempts <= self.errors.len() {
+        return Err(io::Error::from(self.errors[self.attempts - 1]));
+      }
+      self.written.extend_from_slice(buf);
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn test_file_io_retry() {
+    let mut file = FlakyFile {
+      errors: vec![ErrorKind::Interrupted, ErrorKind::Interrupted],
+      attempts: 0,
+      written: Vec::new(),
+    };
+    let res = retry_io(|| file.write(b"key-a"));
+    assert_eq!(5, res.unwrap());
+    assert_eq!(3, file.attempts);
+    assert_eq!(b"key-a".to_vec(), file.written);
+
+    // gives up after the last retry
+    let mut file = FlakyFile {
+      errors: vec![ErrorKind::WouldBlock; IO_RETRIES as usize + 1],
+      attempts: 0,
+      written: Vec::new(),
+    };
+    let res = retry_io(|| file.write(b"key-a"));
+    assert_eq!(ErrorKind::WouldBlock, res.unwrap_err().kind());
+    assert_eq!(IO_RETRIES as usize + 1, file.attempts);
+
+    // other errors aren't retried
+    let mut file = FlakyFile {
+      errors: vec![ErrorKind::PermissionDenied],
+      attempts: 0,
+      written: Vec::new(),
+    };
+    let res = retry_io(|| file.write(b"key-a"));
+    assert_eq!(ErrorKind::PermissionDenied, res.unwrap_err().kind());
+    assert_eq!(1, file.attempts);
+    assert!(file.written.is_empty());
+  }
 }
@@ -0,0 +1,66 @@
+use crate::errors::Result;
+
+use super::IOManager;
+
+type Hook = Box<dyn Fn(u64) -> Result<()> + Send + Sync>;
+
+/// IO manager for tests wrapping another one, hooks watch or fail the calls going through it
+pub(crate) struct HookedIO {
+  inner: Box<dyn IOManager>,
+  read_hook: Option<Hook>,
+  synced_hook: Option<Hook>,
+}
+
+impl HookedIO {
+  pub(crate) fn new(inner: Box<dyn IOManager>) -> Self {
+    Self {
+      inner,
+      read_hook: None,
+      synced_hook: None,
+    }
+  }
+
+  /// called with the offset before every read, an error fails the read
+  pub(crate) fn on_read<F>(mut self, hook: F) -> Self
+  where
+    F: Fn(u64) -> Result<()> + Send + Sync + 'static,
+  {
+    self.read_hook = Some(Box::new(hook));
+    self
+  }
+
+  /// called with the file size each sync made durable, after the sync and before it returns
+  pub(crate) fn on_synced<F>(mut self, hook: F) -> Self
+  where
+    F: Fn(u64) -> Result<()> + Send + Sync + 'static,
+  {
+    self.synced_hook = Some(Box::new(hook));
+    self
+  }
+}
+
+impl IOManager for HookedIO {
+  fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+    if let Some(hook) = &self.read_hook {
+      hook(offset)?;
+    }
+    self.inner.read(buf, offset)
+  }
+
+  fn write(&self, buf: &[u8]) -> Result<usize> {
+    self.inner.write(buf)
+  }
+
+  fn sync(&self) -> Result<()> {
+    let size = self.inner.size()?;
+    self.inner.sync()?;
+    match &self.synced_hook {
+      Some(hook) => hook(size),
+      None => Ok(()),
+    }
+  }
+
+  fn size(&self) -> Result<u64> {
+    self.inner.size()
+  }
+}
@@ -1,35 +1,87 @@
-use std::{fs::OpenOptions, path::Path, sync::Arc};
+use std::{
+  fs::{File, OpenOptions},
+  io::ErrorKind,
+  path::Path,
+  ptr, slice,
+  sync::Arc,
+};
 
+use bytes::Bytes;
 use log::error;
-use memmap2::Mmap;
+use memmap2::MmapRaw;
 use parking_lot::Mutex;
 
 use crate::errors::{Errors, Result};
 
 use super::IOManager;
 
+// minimum number of bytes the file is extended by when the mapping is full
+const MMAP_GROW_SIZE: u64 = 64 * 1024;
+
+/// Memory mapped file I/O
+///
+/// The file is preallocated ahead of writes, so the mapping length may exceed the data
+/// actually written. `write_off` tracks the logical end of file, which is what `size` reports
+/// and what reads are bounded by. The preallocated tail is truncated away on drop.
+///
+/// The mapping is shared with bytes returned by `read_shared`, a remap replaces it rather
+/// than unmapping, so those bytes stay valid. Writes only touch bytes past `write_off`,
+/// which no shared read can reference.
+///
+/// An empty file isn't mapped until the first write grows it.
 pub struct MMapIO {
-  //
-  map: Arc<Mutex<Mmap>>,
+  map: Mutex<Option<Arc<MmapRaw>>>, // mapped file content, None while the file is empty
+  fd: File,                         // system file descriptor, used to grow and truncate the file
+  write_off: Arc<Mutex<u64>>,       // logical end of file
 }
 
 impl MMapIO {
   pub fn new<P>(file_name: P) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    Self::open(file_name, true)
+  }
+
+  /// open a file that must already exist, `Errors::DataFileNotFound` if it doesn't
+  pub fn open_existing<P>(file_name: P) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    Self::open(file_name, false)
+  }
+
+  fn open<P>(file_name: P, create: bool) -> Result<Self>
   where
     P: AsRef<Path>,
   {
     match OpenOptions::new()
-      .create(true)
+      .create(create)
       .read(true)
-      .append(true)
+      .write(true)
+      .truncate(false)
       .open(file_name)
     {
       Ok(file) => {
-        let map = unsafe { Mmap::map(&file).expect("failed to map file") };
+        let write_off = file.metadata().unwrap().len();
+        // a zero length mapping is invalid, reads of an empty file are EOF anyway
+        let map = match write_off {
+          0 => None,
+          _ => match MmapRaw::map_raw(&file) {
+            Ok(map) => Some(Arc::new(map)),
+            Err(e) => {
+              error!("failed to map data file error: {}", e);
+              return Err(Errors::FailedToOpenDataFile);
+            }
+          },
+        };
         Ok(MMapIO {
-          map: Arc::new(Mutex::new(map)),
+          map: Mutex::new(map),
+          fd: file,
+          write_off: Arc::new(Mutex::new(write_off)),
         })
       }
+      Err(e) if e.kind() == ErrorKind::NotFound => Err(Errors::DataFileNotFound),
       Err(e) => {
         error!("failed to open data file error: {}", e);
         Err(Errors::FailedToOpenDataFile)
@@ -41,27 +93,120 @@ impl MMapIO {
 impl IOManager for MMapIO {
   fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
     let map_arr = self.map.lock();
-    let end = offset + buf.len() as u64;
-    if end > map_arr.len() as u64 {
+    let write_off = *self.write_off.lock();
+    let Some(map_arr) = map_arr.as_ref().filter(|_| offset < write_off) else {
       return Err(Errors::ReadDataFileEOF);
-    }
+    };
 
-    let val = &map_arr[offset as usize..end as usize];
-    buf.copy_from_slice(val);
-    Ok(val.len())
+    // bytes beyond the logical end of file are left zeroed like a positioned read
+    let end = write_off.min(offset + buf.len() as u64);
+    let n = (end - offset) as usize;
+    let src = unsafe { slice::from_raw_parts(map_arr.as_ptr().add(offset as usize), n) };
+    buf[..n].copy_from_slice(src);
+    buf[n..].fill(0);
+    Ok(n)
   }
 
-  fn write(&self, _buf: &[u8]) -> Result<usize> {
-    unimplemented!()
+  fn write(&self, buf: &[u8]) -> Result<usize> {
+    if buf.is_empty() {
+      return Ok(0);
+    }
+    let mut map_arr = self.map.lock();
+    let mut write_off = self.write_off.lock();
+    let end = *write_off + buf.len() as u64;
+
+    // extend the file and remap when the mapping is full, or map it on the first write
+    let map_len = map_arr.as_ref().map_or(0, |map| map.len() as u64);
+    if end > map_len {
+      let new_len = end.max(map_len * 2).max(MMAP_GROW_SIZE);
+      if let Err(e) = self.fd.set_len(new_len) {
+        error!("failed to extend data file error: {}", e);
+        return Err(Errors::FailedToWriteToDataFile);
+      }
+      *map_arr = match MmapRaw::map_raw(&self.fd) {
+        Ok(map) => Some(Arc::new(map)),
+        Err(e) => {
+          error!("failed to remap data file error: {}", e);
+          return Err(Errors::FailedToWriteToDataFile);
+        }
+      };
+    }
+
+    let map_arr = map_arr.as_ref().unwrap();
+    unsafe {
+      let dst = map_arr.as_mut_ptr().add(*write_off as usize);
+      ptr::copy_nonoverlapping(buf.as_ptr(), dst, buf.len());
+    }
+    *write_off = end;
+    Ok(buf.len())
   }
 
   fn sync(&self) -> Result<()> {
-    unimplemented!()
+    let map_arr = self.map.lock();
+    let write_off = *self.write_off.lock();
+    let Some(map_arr) = map_arr.as_ref().filter(|_| write_off > 0) else {
+      return Ok(());
+    };
+
+    // only the written region needs to be flushed
+    if let Err(e) = map_arr.flush_range(0, write_off as usize) {
+      error!("failed to sync data file err: {}", e);
+      return Err(Errors::FailedToSyncToDataFile);
+    }
+    Ok(())
+  }
+
+  fn size(&self) -> Result<u64> {
+    Ok(*self.write_off.lock())
+  }
+
+  fn read_shared(&self, offset: u64, len: usize) -> Option<Bytes> {
+    let map_arr = self.map.lock();
+    let write_off = *self.write_off.lock();
+    if offset + len as u64 > write_off {
+      return None;
+    }
+
+    Some(Bytes::from_owner(MMapSlice {
+      map: map_arr.as_ref()?.clone(),
+      offset: offset as usize,
+      len,
+    }))
+  }
+}
+
+// A region of a mapping, keeps the mapping alive while referenced by `Bytes`
+struct MMapSlice {
+  map: Arc<MmapRaw>,
+  offset: usize,
+  len: usize,
+}
+
+impl AsRef<[u8]> for MMapSlice {
+  fn as_ref(&self) -> &[u8] {
+    // region is below the logical end of file, it is never written again
+    unsafe { slice::from_raw_parts(self.map.as_ptr().add(self.offset), self.len) }
   }
+}
 
-  fn size(&self) -> u64 {
+impl Drop for MMapIO {
+  fn drop(&mut self) {
+    // truncate the preallocated tail so the file ends at the logical boundary
     let map_arr = self.map.lock();
-    map_arr.len() as u64
+    let write_off = *self.write_off.lock();
+    let Some(map_arr) = map_arr.as_ref() else {
+      return;
+    };
+    if map_arr.len() as u64 > write_off {
+      if write_off > 0 {
+        if let Err(e) = map_arr.flush_range(0, write_off as usize) {
+          error!("failed to sync data file err: {}", e);
+        }
+      }
+      if let Err(e) = self.fd.set_len(write_off) {
+        error!("failed to truncate data file error: {}", e);
+      }
+    }
   }
 }
 
@@ -116,7 +261,7 @@ mod tests {
     let read_res1 = mmap_io1.read(&mut buf1, 0);
     assert!(read_res1.is_err());
 
-    let size1 = mmap_io1.size();
+    let size1 = mmap_io1.size().unwrap();
     assert_eq!(0, size1);
 
     let fio_res = FileIO::new(&path);
@@ -130,10 +275,98 @@ mod tests {
     let mmap_res2 = MMapIO::new(&path);
     assert!(mmap_res2.is_ok());
     let mmap_io2 = mmap_res2.ok().unwrap();
-    let size2 = mmap_io2.size();
+    let size2 = mmap_io2.size().unwrap();
     assert_eq!(35, size2);
 
     let remove_res = fs::remove_file(&path);
     assert!(remove_res.is_ok());
   }
+
+  #[test]
+  fn test_mmap_write() {
+    let path = PathBuf::from("/tmp/mmap-test-write.data");
+
+    let mmap_io1 = MMapIO::new(&path).unwrap();
+    let write_res1 = mmap_io1.write(b"hello world");
+    assert_eq!(11, write_res1.ok().unwrap());
+    let write_res2 = mmap_io1.write(b"good morning");
+    assert_eq!(12, write_res2.ok().unwrap());
+
+    // size is the logical write offset, not the preallocated file length
+    assert_eq!(23, mmap_io1.size().unwrap());
+    assert!(fs::metadata(&path).unwrap().len() > 23);
+    assert!(mmap_io1.sync().is_ok());
+
+    let mut buf1 = [0u8; 12];
+    assert_eq!(12, mmap_io1.read(&mut buf1, 11).unwrap());
+    assert_eq!(b"good morning", &buf1);
+    let mut buf2 = [0u8; 10];
+    let read_res2 = mmap_io1.read(&mut buf2, 23);
+    assert_eq!(Errors::ReadDataFileEOF, read_res2.err().unwrap());
+
+    // preallocated tail is truncated on drop
+    std::mem::drop(mmap_io1);
+    assert_eq!(23, fs::metadata(&path).unwrap().len());
+
+    let mmap_io2 = MMapIO::new(&path).unwrap();
+    assert_eq!(23, mmap_io2.size().unwrap());
+
+    let remove_res = fs::remove_file(&path);
+    assert!(remove_res.is_ok());
+  }
+
+  #[test]
+  fn test_mmap_empty_file() {
+    let path = PathBuf::from("/tmp/mmap-test-empty.data");
+
+    // empty file isn't mapped, reads are EOF
+    let mmap_io1 = MMapIO::new(&path).unwrap();
+    let mut buf = [0u8; 10];
+    assert_eq!(
+      Errors::ReadDataFileEOF,
+      mmap_io1.read(&mut buf, 0).unwrap_err()
+    );
+    assert_eq!(0, mmap_io1.size().unwrap());
+    assert!(mmap_io1.read_shared(0, 0).is_none());
+    assert!(mmap_io1.sync().is_ok());
+    std::mem::drop(mmap_io1);
+    assert_eq!(0, fs::metadata(&path).unwrap().len());
+
+    // first write grows the file from zero
+    let mmap_io2 = MMapIO::new(&path).unwrap();
+    assert_eq!(11, mmap_io2.write(b"hello world").unwrap());
+    let mut buf = [0u8; 11];
+    assert_eq!(11, mmap_io2.read(&mut buf, 0).unwrap());
+    assert_eq!(b"hello world", &buf);
+    std::mem::drop(mmap_io2);
+    assert_eq!(11, fs::metadata(&path).unwrap().len());
+
+    let remove_res = fs::remove_file(&path);
+    assert!(remove_res.is_ok());
+  }
+
+  #[test]
+  fn test_mmap_read_shared() {
+    let path = PathBuf::from("/tmp/mmap-test-read-shared.data");
+
+    let mmap_io = MMapIO::new(&path).unwrap();
+    mmap_io.write(b"hello world").unwrap();
+    let shared1 = mmap_io.read_shared(0, 11).unwrap();
+    assert_eq!(&b"hello world"[..], &shared1[..]);
+
+    // out of the written range
+    assert!(mmap_io.read_shared(6, 10).is_none());
+
+    // shared bytes stay valid after the file grows and is remapped
+    mmap_io.write(&vec![b'a'; 256 * 1024]).unwrap();
+    let shared2 = mmap_io.read_shared(11, 256 * 1024).unwrap();
+    assert!(shared2.iter().all(|b| *b == b'a'));
+    assert_eq!(&b"hello world"[..], &shared1[..]);
+
+    std::mem::drop(mmap_io);
+    assert_eq!(&b"hello world"[..], &shared1[..]);
+
+    let remove_res = fs::remove_file(&path);
+    assert!(remove_res.is_ok());
+  }
 }
@@ -1,8 +1,13 @@
+pub mod cached;
 pub mod file_io;
+#[cfg(test)]
+pub(crate) mod hooked;
 pub mod mmap;
 
 use std::path::PathBuf;
 
+use bytes::Bytes;
+
 use crate::{errors::Result, option::IOManagerType};
 
 use self::{file_io::FileIO, mmap::MMapIO};
@@ -19,7 +24,13 @@ pub trait IOManager: Sync + Send {
   fn sync(&self) -> Result<()>;
 
   /// get file size
-  fn size(&self) -> u64;
+  fn size(&self) -> Result<u64>;
+
+  /// read len bytes from offset without copying, None if unsupported by the IO type
+  /// or the range is out of the file
+  fn read_shared(&self, _offset: u64, _len: usize) -> Option<Bytes> {
+    None
+  }
 }
 
 /// Initialize IO manager by filename
@@ -29,3 +40,12 @@ pub fn new_io_manager(filename: &PathBuf, io_type: &IOManagerType) -> Box<dyn IO
     IOManagerType::MemoryMap => Box::new(MMapIO::new(filename).unwrap()),
   }
 }
+
+/// Initialize IO manager of a file that must already exist, the file is never created.
+/// `Errors::DataFileNotFound` if it doesn't exist, e.g. removed by a merge
+pub fn open_io_manager(filename: &PathBuf, io_type: &IOManagerType) -> Result<Box<dyn IOManager>> {
+  Ok(match *io_type {
+    IOManagerType::StandardFileIO => Box::new(FileIO::open_existing(filename)?),
+    IOManagerType::MemoryMap => Box::new(MMapIO::open_existing(filename)?),
+  })
+}
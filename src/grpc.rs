@@ -0,0 +1,238 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use crate::{db::Engine, errors::Errors, option::IteratorOptions};
+
+use self::pb::{
+  bitkv_server::Bitkv, DeleteRequest, DeleteResponse, GetRequest, GetResponse, KeyValue,
+  PutRequest, PutResponse, ScanRequest, StatRequest, StatResponse,
+};
+
+pub mod pb {
+  tonic::include_proto!("bitkv");
+}
+
+// number of scanned key/value pairs buffered ahead of a slow client
+const SCAN_CHANNEL_SIZE: usize = 64;
+
+/// gRPC service backed by a shared engine
+pub struct BitkvService {
+  engine: Arc<Engine>,
+}
+
+impl BitkvService {
+  pub fn new(engine: Arc<Engine>) -> Self {
+    Self { engine }
+  }
+
+  /// wrap into a tonic server, ready to be added to a router
+  pub fn into_server(self) -> pb::bitkv_server::BitkvServer<Self> {
+    pb::bitkv_server::BitkvServer::new(self)
+  }
+}
+
+// map engine errors to gRPC status codes
+fn to_status(e: Errors) -> Status {
+  match e {
+    Errors::KeyIsEmpty | Errors::KeyTooLarge | Errors::ValueTooLarge => {
+      Status::invalid_argument(e.to_string())
+    }
+    Errors::KeyNotFound => Status::not_found(e.to_string()),
+    Errors::ReadOnlyEngine => Status::failed_precondition(e.to_string()),
+    _ => Status::internal(e.to_string()),
+  }
+}
+
+#[tonic::async_trait]
+impl Bitkv for BitkvService {
+  async fn put(&self, request: Request<PutRequest>) -> Result<Response<PutResponse>, Status> {
+    let req = request.into_inner();
+    self
+      .engine
+      .put(Bytes::from(req.key), Bytes::from(req.value))
+      .map_err(to_status)?;
+    Ok(Response::new(PutResponse {}))
+  }
+
+  async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+    let req = request.into_inner();
+    let value = self.engine.get(Bytes::from(req.key)).map_err(to_status)?;
+    Ok(Response::new(GetResponse {
+      value: value.to_vec(),
+    }))
+  }
+
+  async fn delete(
+    &self,
+    request: Request<DeleteRequest>,
+  ) -> Result<Response<DeleteResponse>, Status> {
+    let req = request.into_inner();
+    self
+      .engine
+      .delete(Bytes::from(req.key))
+      .map_err(to_status)?;
+    Ok(Response::new(DeleteResponse {}))
+  }
+
+  type ScanStream = ReceiverStream<Result<KeyValue, Status>>;
+
+  async fn scan(
+    &self,
+    request: Request<ScanRequest>,
+  ) -> Result<Response<Self::ScanStream>, Status> {
+    let req = request.into_inner();
+    let (tx, rx) = mpsc::channel(SCAN_CHANNEL_SIZE);
+    let engine = self.engine.clone();
+
+    // walk the iterator on a blocking thread, the bounded channel keeps
+    // large result sets from being buffered fully
+    tokio::task::spawn_blocking(move || {
+      let iter = match engine.iter(IteratorOptions {
+        prefix: req.prefix,
+        start_key: Some(req.start_key).filter(|key| !key.is_empty()),
+        end_key: Some(req.end_key).filter(|key| !key.is_empty()),
+        ..Default::default()
+      }) {
+        Ok(iter) => iter,
+        Err(e) => {
+          let _ = tx.blocking_send(Err(to_status(e)));
+          return;
+        }
+      };
+
+      while let Some((key, value)) = iter.next() {
+        let kv = KeyValue {
+          key: key.to_vec(),
+          value: value.to_vec(),
+        };
+        // client went away
+        if tx.blocking_send(Ok(kv)).is_err() {
+          break;
+        }
+      }
+    });
+
+    Ok(Response::new(ReceiverStream::new(rx)))
+  }
+
+  async fn stat(&self, _request: Request<StatRequest>) -> Result<Response<StatResponse>, Status> {
+    let stat = self.engine.get_engine_stat().map_err(to_status)?;
+    Ok(Response::new(StatResponse {
+      key_num: stat.key_num as u64,
+      data_file_num: stat.data_file_num as u64,
+      reclaim_size: stat.reclaim_size as u64,
+      disk_size: stat.disk_size,
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use tokio::net::TcpListener;
+  use tokio_stream::{wrappers::TcpListenerStream, StreamExt};
+  use tonic::{transport::Server, Code};
+
+  use crate::option::Options;
+
+  use super::{pb::bitkv_client::BitkvClient, *};
+
+  #[tokio::test]
+  async fn test_grpc_put_get_scan() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-grpc");
+    let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(
+      Server::builder()
+        .add_service(BitkvService::new(engine.clone()).into_server())
+        .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    let mut client = BitkvClient::connect(format!("http://{}", addr))
+      .await
+      .unwrap();
+
+    // put keys under two prefixes
+    for i in 0..100 {
+      let key = format!("user:{:03}", i).into_bytes();
+      let value = format!("value-{}", i).into_bytes();
+      client.put(PutRequest { key, value }).await.unwrap();
+    }
+    client
+      .put(PutRequest {
+        key: b"order:001".to_vec(),
+        value: b"value-order".to_vec(),
+      })
+      .await
+      .unwrap();
+
+    let get_res = client
+      .get(GetRequest {
+        key: b"user:007".to_vec(),
+      })
+      .await
+      .unwrap();
+    assert_eq!(b"value-7".to_vec(), get_res.into_inner().value);
+
+    // scan a prefix
+    let mut stream = client
+      .scan(ScanRequest {
+        prefix: b"user:".to_vec(),
+        ..Default::default()
+      })
+      .await
+      .unwrap()
+      .into_inner();
+    let mut count = 0;
+    while let Some(kv) = stream.next().await {
+      let kv = kv.unwrap();
+      assert_eq!(format!("user:{:03}", count).into_bytes(), kv.key);
+      assert_eq!(format!("value-{}", count).into_bytes(), kv.value);
+      count += 1;
+    }
+    assert_eq!(100, count);
+
+    // scan a range within the prefix
+    let stream = client
+      .scan(ScanRequest {
+        prefix: b"user:".to_vec(),
+        start_key: b"user:010".to_vec(),
+        end_key: b"user:020".to_vec(),
+      })
+      .await
+      .unwrap()
+      .into_inner();
+    let keys: Vec<Vec<u8>> = stream.map(|kv| kv.unwrap().key).collect().await;
+    assert_eq!(10, keys.len());
+    assert_eq!(b"user:010".to_vec(), keys[0]);
+
+    // delete and missing key
+    client
+      .delete(DeleteRequest {
+        key: b"user:007".to_vec(),
+      })
+      .await
+      .unwrap();
+    let get_res = client
+      .get(GetRequest {
+        key: b"user:007".to_vec(),
+      })
+      .await;
+    assert_eq!(Code::NotFound, get_res.err().unwrap().code());
+
+    let stat = client.stat(StatRequest {}).await.unwrap().into_inner();
+    assert_eq!(100, stat.key_num);
+
+    server.abort();
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.dir_path).unwrap();
+  }
+}
@@ -1,46 +1,243 @@
-use std::{fs, path::Path, sync::Arc};
+use std::{collections::BTreeMap, fs, ops::Bound, path::Path, sync::Arc};
 
 use bytes::Bytes;
-use jammdb::DB;
+use jammdb::{Tx, DB};
+use parking_lot::{Mutex, RwLock};
 
 use crate::{
   data::log_record::{decode_log_record_pos, LogRecordPos},
-  errors::Result,
-  option::IteratorOptions,
+  errors::{Errors, Result},
+  option::{Consistency, FlushPolicy, IteratorOptions},
 };
 
 use super::{IndexIterator, Indexer};
 
-const BPTREE_INDEX_FILE_NAME: &str = "bptree-index";
-const BPTREE_BUCKET_NAME: &str = "bitcask-index";
+pub(crate) const BPTREE_INDEX_FILE_NAME: &str = "bptree-index";
+pub(crate) const BPTREE_BUCKET_NAME: &str = "bitcask-index";
+// index file being built by `Engine::shrink_index`, renamed over the index file once complete
+pub(crate) const BPTREE_SHRINK_FILE_NAME: &str = "bptree-index-shrink";
+// key of the number of keys committed to the tree, in the meta bucket next to the keys
+const BPTREE_KEY_COUNT_KEY: &[u8] = b"key-count";
 
 // B+ tree indexer implementation
+//
+// Reads and iterators see a snapshot of every write committed before their read transaction
+// begins. `commit_lock` is held exclusively across each write transaction, so beginning a read
+// transaction under it never races with a put/delete that has already started.
+//
+// Unless the flush policy is `FlushPolicy::Always`, puts and deletes are buffered in `pending`
+// and committed together, reads look into `pending` before the tree. `pending` is locked across
+// the tree read so a flush never hides an entry in between.
+//
+// The number of keys committed to the tree is stored in a meta bucket by the same write
+// transactions that change them. `key_count` starts from it and is kept up to date by puts
+// and deletes, pending ones included.
 pub struct BPlusTree {
   tree: Arc<DB>,
+  bucket_name: String,
+  meta_bucket_name: String,
+  commit_lock: RwLock<()>,
+  flush_policy: FlushPolicy,
+  pending: RwLock<PendingWrites>,
+  key_count: Mutex<usize>,
+}
+
+// index changes not yet committed to the tree, None marks a deleted key
+#[derive(Default)]
+struct PendingWrites {
+  entries: BTreeMap<Vec<u8>, Option<LogRecordPos>>,
+  ops: usize, // number of puts and deletes since last flush
 }
 
 impl BPlusTree {
-  pub fn new<P>(dir_path: P) -> Self
+  /// open the b+ tree stored in `file_name` under `dir_path`, keys live in `bucket_name`.
+  /// The engine uses `BPTREE_INDEX_FILE_NAME` and `BPTREE_BUCKET_NAME`.
+  pub fn new<P>(dir_path: P, file_name: &str, bucket_name: &str) -> Self
   where
     P: AsRef<Path>,
   {
     if !dir_path.as_ref().exists() {
       fs::create_dir_all(&dir_path).expect("fail to create b+ tree dir");
     }
-    let path = dir_path.as_ref().join(BPTREE_INDEX_FILE_NAME);
+    let path = dir_path.as_ref().join(file_name);
     let bptree = DB::open(path.as_path()).expect("fail to open b+ tree");
     let tree = Arc::new(bptree);
+    let meta_bucket_name = format!("{}-meta", bucket_name);
     let tx = tree.tx(true).expect("failed to begin tx");
-    tx.get_or_create_bucket(BPTREE_BUCKET_NAME).unwrap();
+    let bucket = tx.get_or_create_bucket(bucket_name).unwrap();
+    let meta_bucket = tx.get_or_create_bucket(meta_bucket_name.as_str()).unwrap();
+    // index files written before the count was stored are counted once
+    let key_count = match meta_bucket.get_kv(BPTREE_KEY_COUNT_KEY) {
+      Some(kv) => decode_key_count(kv.value()),
+      None => {
+        let key_count = bucket.cursor().count() as u64;
+        meta_bucket
+          .put(
+            BPTREE_KEY_COUNT_KEY.to_vec(),
+            key_count.to_le_bytes().to_vec(),
+          )
+          .unwrap();
+        key_count
+      }
+    };
     tx.commit().unwrap();
-    Self { tree }
+    Self {
+      tree,
+      bucket_name: bucket_name.to_string(),
+      meta_bucket_name,
+      commit_lock: RwLock::new(()),
+      flush_policy: FlushPolicy::Always,
+      pending: RwLock::new(PendingWrites::default()),
+      key_count: Mutex::new(key_count as usize),
+    }
+  }
+
+  /// set when buffered puts and deletes are committed to disk
+  pub fn with_flush_policy(mut self, flush_policy: FlushPolicy) -> Self {
+    self.flush_policy = flush_policy;
+    self
+  }
+
+  // begin a read transaction reflecting all writes committed up to now
+  fn read_tx(&self) -> Tx<'_> {
+    let _guard = self.commit_lock.read();
+    self.tree.tx(false).expect("failed to begin tx")
+  }
+
+  // read a key from the committed tree only
+  fn tree_get(&self, key: &[u8]) -> Option<LogRecordPos> {
+    let tx = self.read_tx();
+    let bucket = tx.get_bucket(self.bucket_name.as_str()).unwrap();
+    bucket
+      .get_kv(key)
+      .map(|kv| decode_log_record_pos(kv.value().to_vec()))
+  }
+
+  // buffer a put or delete, flushing if the policy says so, return the previous position
+  fn put_pending(&self, key: Vec<u8>, pos: Option<LogRecordPos>) -> Result<Option<LogRecordPos>> {
+    let mut pending = self.pending.write();
+    let prev = match pending.entries.insert(key.clone(), pos) {
+      Some(prev) => prev,
+      None => self.tree_get(&key),
+    };
+    pending.ops += 1;
+    self.update_key_count(prev, pos.is_some());
+    if let FlushPolicy::EveryOps(n) = self.flush_policy {
+      if pending.ops >= n {
+        self.flush_pending(&mut pending)?;
+      }
+    }
+    Ok(prev)
+  }
+
+  // count a key going from prev to live or deleted
+  fn update_key_count(&self, prev: Option<LogRecordPos>, is_live: bool) {
+    let mut count = self.key_count.lock();
+    match (prev.is_some(), is_live) {
+      (false, true) => *count += 1,
+      (true, false) => *count -= 1,
+      _ => {}
+    }
+  }
+
+  // add delta to the number of keys stored in the meta bucket, within the write transaction
+  // changing the keys
+  fn add_tree_key_count(&self, tx: &Tx<'_>, delta: i64) -> Result<()> {
+    if delta == 0 {
+      return Ok(());
+    }
+    let meta_bucket = tx
+      .get_bucket(self.meta_bucket_name.as_str())
+      .map_err(|_| Errors::IndexUpdateFailed)?;
+    let count = meta_bucket
+      .get_kv(BPTREE_KEY_COUNT_KEY)
+      .map_or(0, |kv| decode_key_count(kv.value()));
+    meta_bucket
+      .put(
+        BPTREE_KEY_COUNT_KEY.to_vec(),
+        count.saturating_add_signed(delta).to_le_bytes().to_vec(),
+      )
+      .map_err(|_| Errors::IndexUpdateFailed)?;
+    Ok(())
+  }
+
+  // commit all buffered changes in one write transaction
+  fn flush_pending(&self, pending: &mut PendingWrites) -> Result<()> {
+    pending.ops = 0;
+    if pending.entries.is_empty() {
+      return Ok(());
+    }
+    let _guard = self.commit_lock.write();
+    let tx = self.tree.tx(true).map_err(|_| Errors::IndexUpdateFailed)?;
+    let bucket = tx
+      .get_bucket(self.bucket_name.as_str())
+      .map_err(|_| Errors::IndexUpdateFailed)?;
+    let mut delta = 0;
+    for (key, pos) in pending.entries.iter() {
+      match pos {
+        Some(pos) => {
+          if bucket.get_kv(key).is_none() {
+            delta += 1;
+          }
+          bucket
+            .put(key.clone(), pos.encode())
+            .map_err(|_| Errors::IndexUpdateFailed)?;
+        }
+        // deleting a key which is only in pending is fine
+        None => {
+          if bucket.delete(key).is_ok() {
+            delta -= 1;
+          }
+        }
+      }
+    }
+    self.add_tree_key_count(&tx, delta)?;
+    tx.commit().map_err(|_| Errors::IndexUpdateFailed)?;
+    pending.entries.clear();
+    Ok(())
+  }
+
+  // all live keys and positions in order, pending changes applied
+  fn snapshot(&self) -> Vec<(Vec<u8>, LogRecordPos)> {
+    let pending = self.pending.read();
+    let tx = self.read_tx();
+    let bucket = tx
+      .get_bucket(self.bucket_name.as_str())
+      .expect("failed to get bucket");
+
+    let mut items = Vec::new();
+    for data in bucket.cursor() {
+      let key = data.key();
+      if !pending.entries.contains_key(key) {
+        let pos = decode_log_record_pos(data.kv().value().to_vec());
+        items.push((key.to_vec(), pos));
+      }
+    }
+    if pending.entries.is_empty() {
+      return items;
+    }
+
+    for (key, pos) in pending.entries.iter() {
+      if let Some(pos) = pos {
+        items.push((key.clone(), *pos));
+      }
+    }
+    items.sort_by(|a, b| a.0.cmp(&b.0));
+    items
   }
 }
 
 impl Indexer for BPlusTree {
-  fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
-    let tx = self.tree.tx(true).expect("failed to begin tx");
-    let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
+  fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
+    if self.flush_policy != FlushPolicy::Always {
+      return self.put_pending(key, Some(pos));
+    }
+
+    let _guard = self.commit_lock.write();
+    let tx = self.tree.tx(true).map_err(|_| Errors::IndexUpdateFailed)?;
+    let bucket = tx
+      .get_bucket(self.bucket_name.as_str())
+      .map_err(|_| Errors::IndexUpdateFailed)?;
     let mut result = None;
     // get previous value
     if let Some(kv) = bucket.get_kv(&key) {
@@ -51,72 +248,180 @@ impl Indexer for BPlusTree {
     // put new value
     bucket
       .put(key, pos.encode())
-      .expect("failed to put k/v pair");
+      .map_err(|_| Errors::IndexUpdateFailed)?;
+    if result.is_none() {
+      self.add_tree_key_count(&tx, 1)?;
+    }
 
-    tx.commit().unwrap();
-    result
+    tx.commit().map_err(|_| Errors::IndexUpdateFailed)?;
+    self.update_key_count(result, true);
+    Ok(result)
   }
 
   fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
-    let tx = self.tree.tx(false).expect("failed to begin tx");
-    let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
-    bucket
-      .get_kv(&key)
-      .map(|kv| decode_log_record_pos(kv.value().to_vec()))
+    let pending = self.pending.read();
+    if let Some(pos) = pending.entries.get(&key) {
+      return *pos;
+    }
+    self.tree_get(&key)
   }
 
-  fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
-    let tx = self.tree.tx(true).expect("failed to begin tx");
-    let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
+  fn delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
+    if self.flush_policy != FlushPolicy::Always {
+      return self.put_pending(key, None);
+    }
+
+    let _guard = self.commit_lock.write();
+    let tx = self.tree.tx(true).map_err(|_| Errors::IndexUpdateFailed)?;
+    let bucket = tx
+      .get_bucket(self.bucket_name.as_str())
+      .map_err(|_| Errors::IndexUpdateFailed)?;
     let mut result = None;
 
     // get previous value
     if let Ok(kv) = bucket.delete(&key) {
       let prev_pos = decode_log_record_pos(kv.value().to_vec());
       result = Some(prev_pos);
+      self.add_tree_key_count(&tx, -1)?;
     }
-    tx.commit().unwrap();
-    result
+    tx.commit().map_err(|_| Errors::IndexUpdateFailed)?;
+    self.update_key_count(result, false);
+    Ok(result)
   }
 
   fn list_keys(&self) -> Result<Vec<Bytes>> {
-    let tx = self.tree.tx(false).expect("failed to begin tx");
+    let keys = self
+      .snapshot()
+      .into_iter()
+      .map(|(key, _)| Bytes::from(key))
+      .collect();
+    Ok(keys)
+  }
+
+  fn contains_prefix(&self, prefix: &[u8]) -> bool {
+    let pending = self.pending.read();
+    let in_pending = pending
+      .entries
+      .range(prefix.to_vec()..)
+      .take_while(|(key, _)| key.starts_with(prefix))
+      .any(|(_, pos)| pos.is_some());
+    if in_pending {
+      return true;
+    }
+
+    let tx = self.read_tx();
     let bucket = tx
-      .get_bucket(BPTREE_BUCKET_NAME)
+      .get_bucket(self.bucket_name.as_str())
       .expect("failed to get bucket");
-    let mut keys = Vec::new();
+    // skip keys deleted in pending
+    for data in bucket.range(prefix..) {
+      if !data.key().starts_with(prefix) {
+        return false;
+      }
+      if !pending.entries.contains_key(data.key()) {
+        return true;
+      }
+    }
+    false
+  }
 
-    for data in bucket.cursor() {
-      keys.push(Bytes::copy_from_slice(data.key()));
+  fn count_range(&self, start: &[u8], end: &[u8]) -> Result<usize> {
+    if start >= end {
+      return Ok(0);
     }
-    Ok(keys)
+    let pending = self.pending.read();
+    let range = (Bound::Included(start), Bound::Excluded(end));
+    let pending_count = pending
+      .entries
+      .range::<[u8], _>(range)
+      .filter(|(_, pos)| pos.is_some())
+      .count();
+
+    let tx = self.read_tx();
+    let bucket = tx
+      .get_bucket(self.bucket_name.as_str())
+      .expect("failed to get bucket");
+    // keys in pending are counted above, whether put or deleted
+    let tree_count = bucket
+      .range(start..)
+      .take_while(|data| data.key() < end)
+      .filter(|data| !pending.entries.contains_key(data.key()))
+      .count();
+    Ok(pending_count + tree_count)
+  }
+
+  fn estimate_len(&self) -> usize {
+    *self.key_count.lock()
   }
 
-  fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
-    let tx = self.tree.tx(false).expect("failed to begin tx");
+  fn range_from(&self, start: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, LogRecordPos)>> {
+    let pending = self.pending.read();
+    let tx = self.read_tx();
     let bucket = tx
-      .get_bucket(BPTREE_BUCKET_NAME)
+      .get_bucket(self.bucket_name.as_str())
       .expect("failed to get bucket");
+
+    // merge the tree entries with the pending ones, which take precedence
+    let mut tree_items = bucket
+      .range(start..)
+      .filter(|data| !pending.entries.contains_key(data.key()))
+      .map(|data| {
+        let pos = decode_log_record_pos(data.kv().value().to_vec());
+        (data.key().to_vec(), pos)
+      })
+      .peekable();
+    let mut pending_items = pending
+      .entries
+      .range::<[u8], _>((Bound::Included(start), Bound::Unbounded))
+      .filter_map(|(key, pos)| pos.map(|pos| (key.clone(), pos)))
+      .peekable();
     let mut items = Vec::new();
+    while items.len() < limit {
+      let item = match (tree_items.peek(), pending_items.peek()) {
+        (Some(tree_item), Some(pending_item)) if tree_item.0 < pending_item.0 => tree_items.next(),
+        (Some(_), None) => tree_items.next(),
+        _ => pending_items.next(),
+      };
+      match item {
+        Some(item) => items.push(item),
+        None => break,
+      }
+    }
+    Ok(items)
+  }
 
-    for data in bucket.cursor() {
-      let key = data.key().to_vec();
-      let pos = decode_log_record_pos(data.kv().value().to_vec());
-      items.push((key, pos));
+  // the iterator is a snapshot of all writes committed before it is created
+  fn iterator(&self, options: IteratorOptions) -> Result<Box<dyn IndexIterator>> {
+    // read transactions only see the tree as of their start
+    if options.consistency == Consistency::Live {
+      return Err(Errors::UnsupportedIteratorMode);
     }
 
+    let mut items = self.snapshot();
+    items.retain(|(key, _)| options.in_range(key));
+
     if options.reverse {
       items.reverse();
     }
 
-    Box::new(BPTreeIterator {
+    Ok(Box::new(BPTreeIterator {
       items,
       curr_index: 0,
       options,
-    })
+    }))
+  }
+
+  fn sync(&self) -> Result<()> {
+    let mut pending = self.pending.write();
+    self.flush_pending(&mut pending)
   }
 }
 
+// number of keys stored in the meta bucket, 0 if unreadable
+fn decode_key_count(value: &[u8]) -> u64 {
+  value.try_into().map_or(0, u64::from_le_bytes)
+}
+
 /// B+ tree Index Iterator
 pub struct BPTreeIterator {
   items: Vec<(Vec<u8>, LogRecordPos)>, // store key and index
@@ -142,12 +447,27 @@ impl IndexIterator for BPTreeIterator {
     };
   }
 
+  fn seek_to_last(&mut self) {
+    // entries with the prefix are contiguous, the last one is the final match
+    let prefix = &self.options.prefix;
+    self.curr_index = self
+      .items
+      .iter()
+      .rposition(|(key, _)| prefix.is_empty() || key.starts_with(prefix))
+      .unwrap_or(self.items.len());
+  }
+
   fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
     if self.curr_index >= self.items.len() {
       return None;
     }
 
     while let Some(item) = self.items.get(self.curr_index) {
+      // no more keys with the prefix, keep returning None
+      if self.options.past_prefix(&item.0) {
+        self.curr_index = self.items.len();
+        return None;
+      }
       self.curr_index += 1;
       let prefix = &self.options.prefix;
       if prefix.is_empty() || item.0.starts_with(prefix) {
@@ -168,55 +488,65 @@ mod tests {
   fn test_bptree_put() {
     let path = PathBuf::from("/tmp/bptree-put");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
-    let res1 = bptree.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let bptree = BPlusTree::new(&path, BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME);
+    let res1 = bptree
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = bptree.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res2 = bptree
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = bptree.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res3 = bptree
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
 
-    let res4 = bptree.put(
-      "ddee".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res4 = bptree
+      .put(
+        "ddee".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res4.is_none());
 
-    let res5 = bptree.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res5 = bptree
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res5.is_some());
     let v1 = res5.unwrap();
     assert_eq!(
@@ -235,58 +565,66 @@ mod tests {
   fn test_bptree_get() {
     let path = PathBuf::from("/tmp/bptree-get");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
+    let bptree = BPlusTree::new(&path, BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME);
 
     let res = bptree.get(b"not exists".to_vec());
     assert!(res.is_none());
 
-    let res1 = bptree.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res1 = bptree
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
     let v1 = bptree.get(b"aacd".to_vec());
     assert!(v1.is_some());
 
-    let res2 = bptree.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1233,
-        size: 12,
-      },
-    );
+    let res2 = bptree
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1233,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
     let v2 = bptree.get(b"acdd".to_vec());
     assert!(v2.is_some());
 
-    let res3 = bptree.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1234,
-        size: 12,
-      },
-    );
+    let res3 = bptree
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1234,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
 
     let v3 = bptree.get(b"aacd".to_vec());
     assert!(v3.is_some());
 
-    let res4 = bptree.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1235,
-        size: 12,
-      },
-    );
+    let res4 = bptree
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1235,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res4.is_some());
     assert_eq!(
       res4.unwrap(),
@@ -307,22 +645,24 @@ mod tests {
   fn test_bptree_delete() {
     let path = PathBuf::from("/tmp/bptree-delete");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
+    let bptree = BPlusTree::new(&path, BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME);
 
-    let res = bptree.delete(b"not exists".to_vec());
+    let res = bptree.delete(b"not exists".to_vec()).unwrap();
     assert!(res.is_none());
 
-    let res1 = bptree.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res1 = bptree
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let d1 = bptree.delete(b"aacd".to_vec());
+    let d1 = bptree.delete(b"aacd".to_vec()).unwrap();
     assert!(d1.is_some());
     let r1 = d1.unwrap();
     assert_eq!(
@@ -344,39 +684,45 @@ mod tests {
   fn test_bptree_list_keys() {
     let path = PathBuf::from("/tmp/bptree-list-keys");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
+    let bptree = BPlusTree::new(&path, BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME);
 
     let keys = bptree.list_keys().unwrap();
     assert!(keys.is_empty());
 
-    let res1 = bptree.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res1 = bptree
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = bptree.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1233,
-        size: 12,
-      },
-    );
+    let res2 = bptree
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1233,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = bptree.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1234,
-        size: 12,
-      },
-    );
+    let res3 = bptree
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1234,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
 
     let keys = bptree.list_keys().unwrap();
@@ -389,40 +735,46 @@ mod tests {
   fn test_bptree_iterator() {
     let path = PathBuf::from("/tmp/bptree-iterator");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
-
-    let res1 = bptree.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let bptree = BPlusTree::new(&path, BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME);
+
+    let res1 = bptree
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = bptree.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1233,
-        size: 12,
-      },
-    );
+    let res2 = bptree
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1233,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = bptree.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1234,
-        size: 12,
-      },
-    );
+    let res3 = bptree
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1234,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
     let mut opt = IteratorOptions::default();
     opt.reverse = true;
-    let mut iter1 = bptree.iterator(opt);
+    let mut iter1 = bptree.iterator(opt).unwrap();
     while let Some((key, _)) = iter1.next() {
       assert!(!key.is_empty());
     }
@@ -434,39 +786,45 @@ mod tests {
   fn test_bptree_iterator_rewind() {
     let path = PathBuf::from("/tmp/bptree-iterator-rewind");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
-
-    let res1 = bptree.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let bptree = BPlusTree::new(&path, BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME);
+
+    let res1 = bptree
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = bptree.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1233,
-        size: 12,
-      },
-    );
+    let res2 = bptree
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1233,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = bptree.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1234,
-        size: 12,
-      },
-    );
+    let res3 = bptree
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1234,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
 
-    let mut iter1 = bptree.iterator(IteratorOptions::default());
+    let mut iter1 = bptree.iterator(IteratorOptions::default()).unwrap();
     while let Some((key, _)) = iter1.next() {
       assert!(!key.is_empty());
     }
@@ -483,39 +841,45 @@ mod tests {
   fn test_bptree_iterator_seek() {
     let path = PathBuf::from("/tmp/bptree-iterator-seek");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
-
-    let res1 = bptree.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let bptree = BPlusTree::new(&path, BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME);
+
+    let res1 = bptree
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = bptree.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1233,
-        size: 12,
-      },
-    );
+    let res2 = bptree
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1233,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = bptree.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1234,
-        size: 12,
-      },
-    );
+    let res3 = bptree
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1234,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
 
-    let mut iter1 = bptree.iterator(IteratorOptions::default());
+    let mut iter1 = bptree.iterator(IteratorOptions::default()).unwrap();
     iter1.seek("acdd".as_bytes().to_vec());
     let (key, _) = iter1.next().unwrap();
     assert_eq!(key, "acdd".as_bytes());
@@ -527,39 +891,45 @@ mod tests {
   fn test_bptree_iterator_next() {
     let path = PathBuf::from("/tmp/bptree-iterator-next");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
-
-    let res1 = bptree.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let bptree = BPlusTree::new(&path, BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME);
+
+    let res1 = bptree
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = bptree.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1233,
-        size: 12,
-      },
-    );
+    let res2 = bptree
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1233,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = bptree.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1234,
-        size: 12,
-      },
-    );
+    let res3 = bptree
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1234,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
 
-    let mut iter1 = bptree.iterator(IteratorOptions::default());
+    let mut iter1 = bptree.iterator(IteratorOptions::default()).unwrap();
     let (key1, _) = iter1.next().unwrap();
     assert_eq!(key1, "aacd".as_bytes());
 
@@ -573,4 +943,258 @@ mod tests {
 
     fs::remove_dir_all(path).unwrap();
   }
+
+  #[test]
+  fn test_bptree_iterator_sees_committed_writes() {
+    let path = PathBuf::from("/tmp/bptree-iterator-committed");
+    fs::create_dir_all(&path).unwrap();
+    let bptree = Arc::new(BPlusTree::new(
+      &path,
+      BPTREE_INDEX_FILE_NAME,
+      BPTREE_BUCKET_NAME,
+    ));
+
+    // an iterator created before the put doesn't see it
+    let mut iter1 = bptree.iterator(IteratorOptions::default()).unwrap();
+    assert!(iter1.next().is_none());
+
+    let res1 = bptree
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
+    assert!(res1.is_none());
+
+    // a put committed just before iterator() is visible
+    let mut iter2 = bptree.iterator(IteratorOptions::default()).unwrap();
+    let (key1, _) = iter2.next().unwrap();
+    assert_eq!(key1, "aacd".as_bytes());
+    assert!(iter2.next().is_none());
+
+    // puts committed by another thread are visible once it finishes
+    let bptree2 = bptree.clone();
+    let handle = std::thread::spawn(move || {
+      for i in 0..100 {
+        bptree2
+          .put(
+            format!("key-{:03}", i).into_bytes(),
+            LogRecordPos {
+              file_id: 1123,
+              offset: i,
+              size: 12,
+            },
+          )
+          .unwrap();
+      }
+    });
+    handle.join().unwrap();
+
+    let mut iter3 = bptree.iterator(IteratorOptions::default()).unwrap();
+    let mut count = 0;
+    while iter3.next().is_some() {
+      count += 1;
+    }
+    assert_eq!(101, count);
+
+    fs::remove_dir_all(path).unwrap();
+  }
+
+  #[test]
+  fn test_bptree_multiple_in_one_dir() {
+    let path = PathBuf::from("/tmp/bptree-multiple");
+    fs::create_dir_all(&path).unwrap();
+    let bptree1 = BPlusTree::new(&path, BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME);
+    let bptree2 = BPlusTree::new(&path, "secondary-index", "secondary");
+
+    let pos1 = LogRecordPos {
+      file_id: 1,
+      offset: 10,
+      size: 12,
+    };
+    let pos2 = LogRecordPos {
+      file_id: 2,
+      offset: 20,
+      size: 12,
+    };
+    assert!(bptree1
+      .put("aacd".as_bytes().to_vec(), pos1)
+      .unwrap()
+      .is_none());
+    assert!(bptree2
+      .put("aacd".as_bytes().to_vec(), pos2)
+      .unwrap()
+      .is_none());
+    assert!(bptree2
+      .put("bbcd".as_bytes().to_vec(), pos2)
+      .unwrap()
+      .is_none());
+
+    // each tree only sees its own keys
+    assert_eq!(Some(pos1), bptree1.get("aacd".as_bytes().to_vec()));
+    assert_eq!(Some(pos2), bptree2.get("aacd".as_bytes().to_vec()));
+    assert!(bptree1.get("bbcd".as_bytes().to_vec()).is_none());
+    assert_eq!(1, bptree1.list_keys().unwrap().len());
+    assert_eq!(2, bptree2.list_keys().unwrap().len());
+
+    assert!(bptree1
+      .delete("aacd".as_bytes().to_vec())
+      .unwrap()
+      .is_some());
+    assert_eq!(Some(pos2), bptree2.get("aacd".as_bytes().to_vec()));
+
+    assert!(path.join(BPTREE_INDEX_FILE_NAME).is_file());
+    assert!(path.join("secondary-index").is_file());
+
+    fs::remove_dir_all(path).unwrap();
+  }
+
+  #[test]
+  fn test_bptree_flush_policy() {
+    let path = PathBuf::from("/tmp/bptree-flush-policy");
+    fs::create_dir_all(&path).unwrap();
+    let pos = |offset| LogRecordPos {
+      file_id: 1,
+      offset,
+      size: 12,
+    };
+
+    {
+      let bptree = BPlusTree::new(&path, BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME)
+        .with_flush_policy(FlushPolicy::Manual);
+      assert!(bptree.put(b"aa".to_vec(), pos(1)).unwrap().is_none());
+      assert!(bptree.put(b"ab".to_vec(), pos(2)).unwrap().is_none());
+      assert!(bptree.sync().is_ok());
+
+      // buffered changes are visible before they are committed
+      assert_eq!(Some(pos(1)), bptree.put(b"aa".to_vec(), pos(3)).unwrap());
+      assert!(bptree.put(b"ba".to_vec(), pos(4)).unwrap().is_none());
+      assert_eq!(Some(pos(2)), bptree.delete(b"ab".to_vec()).unwrap());
+      assert!(bptree.delete(b"ab".to_vec()).unwrap().is_none());
+      assert!(bptree.tree_get(b"ba").is_none());
+      assert_eq!(Some(pos(2)), bptree.tree_get(b"ab"));
+
+      assert_eq!(Some(pos(3)), bptree.get(b"aa".to_vec()));
+      assert!(bptree.get(b"ab".to_vec()).is_none());
+      assert_eq!(Some(pos(4)), bptree.get(b"ba".to_vec()));
+      assert_eq!(
+        vec![Bytes::from("aa"), Bytes::from("ba")],
+        bptree.list_keys().unwrap()
+      );
+      assert!(bptree.contains_prefix(b"a"));
+      assert!(bptree.contains_prefix(b"b"));
+      assert!(!bptree.contains_prefix(b"ab"));
+
+      let mut iter = bptree
+        .iterator(IteratorOptions {
+          reverse: true,
+          ..Default::default()
+        })
+        .unwrap();
+      assert_eq!(b"ba".to_vec(), *iter.next().unwrap().0);
+      assert_eq!(b"aa".to_vec(), *iter.next().unwrap().0);
+      assert!(iter.next().is_none());
+
+      // chunks merge buffered changes with the tree in key order
+      assert!(bptree.put(b"ac".to_vec(), pos(5)).unwrap().is_none());
+      assert_eq!(
+        vec![(b"aa".to_vec(), pos(3)), (b"ac".to_vec(), pos(5))],
+        bptree.range_from(b"", 2).unwrap()
+      );
+      assert_eq!(
+        vec![(b"ac".to_vec(), pos(5)), (b"ba".to_vec(), pos(4))],
+        bptree.range_from(b"ab", 10).unwrap()
+      );
+      assert_eq!(Some(pos(5)), bptree.delete(b"ac".to_vec()).unwrap());
+
+      assert!(bptree.sync().is_ok());
+      assert_eq!(Some(pos(4)), bptree.tree_get(b"ba"));
+      assert!(bptree.tree_get(b"ab").is_none());
+    }
+
+    // committed every 10 ops
+    {
+      let bptree = BPlusTree::new(&path, BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME)
+        .with_flush_policy(FlushPolicy::EveryOps(10));
+      assert_eq!(2, bptree.list_keys().unwrap().len());
+      for i in 0..25 {
+        bptree
+          .put(format!("key-{:03}", i).into_bytes(), pos(i))
+          .unwrap();
+      }
+      assert!(bptree.tree_get(b"key-019").is_some());
+      assert!(bptree.tree_get(b"key-020").is_none());
+      assert_eq!(27, bptree.list_keys().unwrap().len());
+    }
+
+    fs::remove_dir_all(path).unwrap();
+  }
+
+  #[test]
+  fn test_bptree_key_count() {
+    let path = PathBuf::from("/tmp/bptree-key-count");
+    fs::create_dir_all(&path).unwrap();
+    let pos = |offset| LogRecordPos {
+      file_id: 1,
+      offset,
+      size: 12,
+    };
+    // count in the meta bucket, as committed to the tree
+    let stored_count = |bptree: &BPlusTree| {
+      let tx = bptree.read_tx();
+      let meta_bucket = tx.get_bucket(bptree.meta_bucket_name.as_str()).unwrap();
+      let kv = meta_bucket.get_kv(BPTREE_KEY_COUNT_KEY).unwrap();
+      decode_key_count(kv.value())
+    };
+
+    // an index file from before the count was stored is counted once
+    {
+      let tree = DB::open(path.join(BPTREE_INDEX_FILE_NAME)).unwrap();
+      let tx = tree.tx(true).unwrap();
+      let bucket = tx.get_or_create_bucket(BPTREE_BUCKET_NAME).unwrap();
+      for i in 0..10 {
+        bucket
+          .put(format!("key-{:03}", i).into_bytes(), pos(i).encode())
+          .unwrap();
+      }
+      tx.commit().unwrap();
+    }
+    {
+      let bptree = BPlusTree::new(&path, BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME)
+        .with_flush_policy(FlushPolicy::Manual);
+      assert_eq!(10, stored_count(&bptree));
+      assert_eq!(10, bptree.estimate_len());
+
+      // pending changes count right away and are stored as they are committed
+      for i in 5..15 {
+        bptree
+          .put(format!("key-{:03}", i).into_bytes(), pos(i))
+          .unwrap();
+      }
+      bptree.delete(b"key-000".to_vec()).unwrap();
+      bptree.delete(b"missing".to_vec()).unwrap();
+      assert_eq!(14, bptree.estimate_len());
+      assert_eq!(10, stored_count(&bptree));
+      bptree.sync().unwrap();
+      assert_eq!(14, stored_count(&bptree));
+    }
+
+    // the stored count is all a reopened index needs
+    {
+      let bptree = BPlusTree::new(&path, BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME);
+      assert_eq!(14, bptree.estimate_len());
+      bptree.put(b"key-100".to_vec(), pos(100)).unwrap();
+      bptree.put(b"key-100".to_vec(), pos(101)).unwrap();
+      bptree.delete(b"key-001".to_vec()).unwrap();
+      assert_eq!(14, bptree.estimate_len());
+      assert_eq!(14, stored_count(&bptree));
+      assert_eq!(14, bptree.list_keys().unwrap().len());
+    }
+
+    fs::remove_dir_all(path).unwrap();
+  }
 }
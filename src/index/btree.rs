@@ -1,7 +1,15 @@
-use crate::{data::log_record::LogRecordPos, errors::Result, option::IteratorOptions};
+use crate::{
+  data::log_record::LogRecordPos,
+  errors::{Errors, Result},
+  option::{Consistency, IteratorOptions},
+};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
 use bytes::Bytes;
+use core::ops::Bound;
+#[cfg(feature = "std")]
 use parking_lot::RwLock;
-use std::{collections::BTreeMap, sync::Arc};
+#[cfg(not(feature = "std"))]
+use spin::RwLock;
 
 use super::{IndexIterator, Indexer};
 
@@ -19,11 +27,17 @@ impl BTree {
   }
 }
 
+impl Default for BTree {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 #[allow(clippy::clone_on_copy)]
 impl Indexer for BTree {
-  fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
+  fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
     let mut write_guard = self.tree.write();
-    write_guard.insert(key, pos)
+    Ok(write_guard.insert(key, pos))
   }
 
   fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
@@ -31,9 +45,9 @@ impl Indexer for BTree {
     read_guard.get(&key).copied()
   }
 
-  fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+  fn delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
     let mut write_guard = self.tree.write();
-    write_guard.remove(&key)
+    Ok(write_guard.remove(&key))
   }
 
   fn list_keys(&self) -> Result<Vec<Bytes>> {
@@ -46,24 +60,65 @@ impl Indexer for BTree {
     Ok(keys)
   }
 
-  fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
+  fn contains_prefix(&self, prefix: &[u8]) -> bool {
+    let read_guard = self.tree.read();
+    // first key greater than or equal to prefix is the only candidate
+    match read_guard.range(prefix.to_vec()..).next() {
+      Some((key, _)) => key.starts_with(prefix),
+      None => false,
+    }
+  }
+
+  fn count_range(&self, start: &[u8], end: &[u8]) -> Result<usize> {
+    if start >= end {
+      return Ok(0);
+    }
+    let read_guard = self.tree.read();
+    Ok(
+      read_guard
+        .range::<[u8], _>((Bound::Included(start), Bound::Excluded(end)))
+        .count(),
+    )
+  }
+
+  fn estimate_len(&self) -> usize {
+    self.tree.read().len()
+  }
+
+  fn range_from(&self, start: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, LogRecordPos)>> {
     let read_guard = self.tree.read();
-    let mut items = Vec::with_capacity(read_guard.len());
+    let items = read_guard
+      .range::<[u8], _>((Bound::Included(start), Bound::Unbounded))
+      .take(limit)
+      .map(|(key, pos)| (key.clone(), *pos))
+      .collect();
+    Ok(items)
+  }
 
-    // copy all items from BTreeMap to Vec
-    for (key, value) in read_guard.iter() {
-      items.push((key.clone(), value.clone()));
+  fn iterator(&self, options: IteratorOptions) -> Result<Box<dyn IndexIterator>> {
+    // a live walk would hold the tree lock for the iterator's lifetime
+    if options.consistency == Consistency::Live {
+      return Err(Errors::UnsupportedIteratorMode);
+    }
+
+    // copy the items within range from BTreeMap to Vec, the keys before start_key are skipped
+    let read_guard = self.tree.read();
+    let mut items = Vec::new();
+    if let Some(bounds) = options.key_bounds() {
+      for (key, value) in read_guard.range::<[u8], _>(bounds) {
+        items.push((key.clone(), *value));
+      }
     }
 
     if options.reverse {
       items.reverse();
     }
 
-    Box::new(BTreeIterator {
+    Ok(Box::new(BTreeIterator {
       items,
       curr_index: 0,
       options,
-    })
+    }))
   }
 }
 
@@ -92,12 +147,27 @@ impl IndexIterator for BTreeIterator {
     };
   }
 
+  fn seek_to_last(&mut self) {
+    // entries with the prefix are contiguous, the last one is the final match
+    let prefix = &self.options.prefix;
+    self.curr_index = self
+      .items
+      .iter()
+      .rposition(|(key, _)| prefix.is_empty() || key.starts_with(prefix))
+      .unwrap_or(self.items.len());
+  }
+
   fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
     if self.curr_index >= self.items.len() {
       return None;
     }
 
     while let Some(item) = self.items.get(self.curr_index) {
+      // no more keys with the prefix, keep returning None
+      if self.options.past_prefix(&item.0) {
+        self.curr_index = self.items.len();
+        return None;
+      }
       self.curr_index += 1;
       let prefix = &self.options.prefix;
       if prefix.is_empty() || item.0.starts_with(prefix) {
@@ -116,58 +186,68 @@ mod tests {
   #[test]
   fn test_btree_put() {
     let bt = BTree::new();
-    let res1 = bt.put(
-      "".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1,
-        offset: 10,
-        size: 12,
-      },
-    );
+    let res1 = bt
+      .put(
+        "".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1,
+          offset: 10,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = bt.put(
-      "aa".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 11,
-        offset: 22,
-        size: 12,
-      },
-    );
+    let res2 = bt
+      .put(
+        "aa".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 11,
+          offset: 22,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = bt.put(
-      "aa".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 114,
-        offset: 2223,
-        size: 12,
-      },
-    );
+    let res3 = bt
+      .put(
+        "aa".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 114,
+          offset: 2223,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_some());
   }
 
   #[test]
   fn test_get() {
     let bt = BTree::new();
-    let res1 = bt.put(
-      "".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1,
-        offset: 10,
-        size: 12,
-      },
-    );
+    let res1 = bt
+      .put(
+        "".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1,
+          offset: 10,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = bt.put(
-      "aa".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 11,
-        offset: 22,
-        size: 12,
-      },
-    );
+    let res2 = bt
+      .put(
+        "aa".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 11,
+          offset: 22,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
     let pos1 = bt.get("".as_bytes().to_vec()).unwrap();
@@ -195,27 +275,31 @@ mod tests {
   #[test]
   fn test_delete() {
     let bt = BTree::new();
-    let res1 = bt.put(
-      "".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1,
-        offset: 10,
-        size: 12,
-      },
-    );
+    let res1 = bt
+      .put(
+        "".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1,
+          offset: 10,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = bt.put(
-      "aa".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 11,
-        offset: 22,
-        size: 12,
-      },
-    );
+    let res2 = bt
+      .put(
+        "aa".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 11,
+          offset: 22,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = bt.delete("".as_bytes().to_vec());
+    let res3 = bt.delete("".as_bytes().to_vec()).unwrap();
     assert!(res3.is_some());
 
     let v1 = res3.unwrap();
@@ -228,7 +312,7 @@ mod tests {
       }
     );
 
-    let res4 = bt.delete("aa".as_bytes().to_vec());
+    let res4 = bt.delete("aa".as_bytes().to_vec()).unwrap();
     assert!(res4.is_some());
 
     let v2 = res4.unwrap();
@@ -241,7 +325,7 @@ mod tests {
       }
     );
 
-    let res5 = bt.delete("".as_bytes().to_vec());
+    let res5 = bt.delete("".as_bytes().to_vec()).unwrap();
     assert!(res5.is_none());
   }
 
@@ -250,7 +334,7 @@ mod tests {
     let bt = BTree::new();
 
     // no items
-    let mut iter1 = bt.iterator(IteratorOptions::default());
+    let mut iter1 = bt.iterator(IteratorOptions::default()).unwrap();
     iter1.seek("aa".as_bytes().to_vec());
     let res1 = iter1.next();
     assert!(res1.is_none());
@@ -263,13 +347,14 @@ mod tests {
         offset: 10,
         size: 12,
       },
-    );
-    let mut iter2 = bt.iterator(IteratorOptions::default());
+    )
+    .unwrap();
+    let mut iter2 = bt.iterator(IteratorOptions::default()).unwrap();
     iter2.seek("aa".as_bytes().to_vec());
     let res2 = iter2.next();
     assert!(res2.is_some());
 
-    let mut iter3 = bt.iterator(IteratorOptions::default());
+    let mut iter3 = bt.iterator(IteratorOptions::default()).unwrap();
     iter3.seek("zz".as_bytes().to_vec());
     let res3 = iter3.next();
     assert!(res3.is_none());
@@ -282,7 +367,8 @@ mod tests {
         offset: 10,
         size: 12,
       },
-    );
+    )
+    .unwrap();
     bt.put(
       "ccde".as_bytes().to_vec(),
       LogRecordPos {
@@ -290,7 +376,8 @@ mod tests {
         offset: 10,
         size: 12,
       },
-    );
+    )
+    .unwrap();
     bt.put(
       "dcde".as_bytes().to_vec(),
       LogRecordPos {
@@ -298,33 +385,36 @@ mod tests {
         offset: 10,
         size: 12,
       },
-    );
-    let mut iter4 = bt.iterator(IteratorOptions::default());
+    )
+    .unwrap();
+    let mut iter4 = bt.iterator(IteratorOptions::default()).unwrap();
     iter4.seek("c".as_bytes().to_vec());
     while let Some(item) = iter4.next() {
-      assert!(item.0.len() > 0);
+      assert!(!item.0.is_empty());
     }
 
-    let mut iter5 = bt.iterator(IteratorOptions::default());
+    let mut iter5 = bt.iterator(IteratorOptions::default()).unwrap();
     iter5.seek("ccde".as_bytes().to_vec());
     while let Some(item) = iter5.next() {
-      assert!(item.0.len() > 0);
+      assert!(!item.0.is_empty());
     }
 
-    let mut iter6 = bt.iterator(IteratorOptions::default());
+    let mut iter6 = bt.iterator(IteratorOptions::default()).unwrap();
     iter6.seek("zz".as_bytes().to_vec());
     let res6 = iter6.next();
     assert!(res6.is_none());
 
     // reverse order
     let reverse = true;
-    let mut iter7 = bt.iterator(IteratorOptions {
-      reverse,
-      ..Default::default()
-    });
+    let mut iter7 = bt
+      .iterator(IteratorOptions {
+        reverse,
+        ..Default::default()
+      })
+      .unwrap();
     iter7.seek("b".as_bytes().to_vec());
     while let Some(item) = iter7.next() {
-      assert!(item.0.len() > 0);
+      assert!(!item.0.is_empty());
     }
   }
 
@@ -333,7 +423,7 @@ mod tests {
     let bt = BTree::new();
 
     // no items
-    let mut iter1 = bt.iterator(IteratorOptions::default());
+    let mut iter1 = bt.iterator(IteratorOptions::default()).unwrap();
     let res1 = iter1.next();
     assert!(res1.is_none());
 
@@ -345,10 +435,11 @@ mod tests {
         offset: 10,
         size: 12,
       },
-    );
+    )
+    .unwrap();
     let mut iter_opt1 = IteratorOptions::default();
     iter_opt1.reverse = true;
-    let mut iter2 = bt.iterator(iter_opt1);
+    let mut iter2 = bt.iterator(iter_opt1).unwrap();
     assert!(iter2.next().is_some());
 
     // multiple items
@@ -359,7 +450,8 @@ mod tests {
         offset: 10,
         size: 12,
       },
-    );
+    )
+    .unwrap();
     bt.put(
       "ccde".as_bytes().to_vec(),
       LogRecordPos {
@@ -367,7 +459,8 @@ mod tests {
         offset: 10,
         size: 12,
       },
-    );
+    )
+    .unwrap();
     bt.put(
       "dcde".as_bytes().to_vec(),
       LogRecordPos {
@@ -375,18 +468,19 @@ mod tests {
         offset: 10,
         size: 12,
       },
-    );
+    )
+    .unwrap();
     let mut iter_opt2 = IteratorOptions::default();
     iter_opt2.reverse = true;
-    let mut iter3 = bt.iterator(iter_opt2);
+    let mut iter3 = bt.iterator(iter_opt2).unwrap();
     while let Some(item) = iter3.next() {
-      assert!(item.0.len() > 0);
+      assert!(!item.0.is_empty());
     }
 
     // prefix filter
     let mut iter_opt3 = IteratorOptions::default();
     iter_opt3.prefix = "c".as_bytes().to_vec();
-    let mut iter4 = bt.iterator(iter_opt3);
+    let mut iter4 = bt.iterator(iter_opt3).unwrap();
     while let Some(item) = iter4.next() {
       // assert!(item.0.len() > 0);
       println!("{:?}", String::from_utf8(item.0.to_vec()));
@@ -0,0 +1,130 @@
+use crate::{data::log_record::LogRecordPos, errors::Result, option::IteratorOptions};
+use bytes::Bytes;
+
+use super::{IndexIterator, Indexer};
+
+// Indexer wrapper for case insensitive keys, ASCII letters of every key are lowercased before
+// reaching the wrapped indexer. Iterator bounds and seeks are lowercased the same way, so ranges
+// compare against the lowercased keys
+pub struct CaseFolded {
+  inner: Box<dyn Indexer>,
+}
+
+impl CaseFolded {
+  pub fn new(inner: Box<dyn Indexer>) -> Self {
+    Self { inner }
+  }
+}
+
+impl Indexer for CaseFolded {
+  fn put(&self, mut key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
+    key.make_ascii_lowercase();
+    self.inner.put(key, pos)
+  }
+
+  fn get(&self, mut key: Vec<u8>) -> Option<LogRecordPos> {
+    key.make_ascii_lowercase();
+    self.inner.get(key)
+  }
+
+  fn delete(&self, mut key: Vec<u8>) -> Result<Option<LogRecordPos>> {
+    key.make_ascii_lowercase();
+    self.inner.delete(key)
+  }
+
+  fn list_keys(&self) -> Result<Vec<Bytes>> {
+    self.inner.list_keys()
+  }
+
+  fn contains_prefix(&self, prefix: &[u8]) -> bool {
+    self.inner.contains_prefix(&prefix.to_ascii_lowercase())
+  }
+
+  fn count_range(&self, start: &[u8], end: &[u8]) -> Result<usize> {
+    self
+      .inner
+      .count_range(&start.to_ascii_lowercase(), &end.to_ascii_lowercase())
+  }
+
+  fn estimate_len(&self) -> usize {
+    self.inner.estimate_len()
+  }
+
+  fn range_from(&self, start: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, LogRecordPos)>> {
+    self.inner.range_from(&start.to_ascii_lowercase(), limit)
+  }
+
+  fn iterator(&self, mut options: IteratorOptions) -> Result<Box<dyn IndexIterator>> {
+    options.prefix.make_ascii_lowercase();
+    for key in [&mut options.start_key, &mut options.end_key]
+      .into_iter()
+      .flatten()
+    {
+      key.make_ascii_lowercase();
+    }
+    Ok(Box::new(CaseFoldedIterator {
+      inner: self.inner.iterator(options)?,
+    }))
+  }
+
+  fn sync(&self) -> Result<()> {
+    self.inner.sync()
+  }
+}
+
+/// Case folded Index Iterator, seeks to the lowercased key
+pub struct CaseFoldedIterator {
+  inner: Box<dyn IndexIterator>,
+}
+
+impl IndexIterator for CaseFoldedIterator {
+  fn rewind(&mut self) {
+    self.inner.rewind();
+  }
+
+  fn seek(&mut self, mut key: Vec<u8>) {
+    key.make_ascii_lowercase();
+    self.inner.seek(key);
+  }
+
+  fn seek_to_last(&mut self) {
+    self.inner.seek_to_last();
+  }
+
+  fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
+    self.inner.next()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use crate::index::btree::BTree;
+
+  #[test]
+  fn test_case_folded_put_get_delete() {
+    let cf = CaseFolded::new(Box::new(BTree::new()));
+    let pos = LogRecordPos {
+      file_id: 1,
+      offset: 10,
+      size: 12,
+    };
+    assert!(cf.put(b"User".to_vec(), pos).unwrap().is_none());
+    assert_eq!(Some(pos), cf.get(b"user".to_vec()));
+    assert_eq!(Some(pos), cf.get(b"USER".to_vec()));
+    assert!(cf.contains_prefix(b"US"));
+    assert_eq!(1, cf.count_range(b"U", b"V").unwrap());
+
+    let mut iter = cf
+      .iterator(IteratorOptions {
+        prefix: b"US".to_vec(),
+        ..Default::default()
+      })
+      .unwrap();
+    assert_eq!(b"user".to_vec(), *iter.next().unwrap().0);
+
+    assert_eq!(Some(pos), cf.delete(b"uSeR".to_vec()).unwrap());
+    assert!(cf.get(b"user".to_vec()).is_none());
+  }
+}
@@ -1,40 +1,106 @@
+#[cfg(feature = "std")]
 pub mod bptree;
 pub mod btree;
+#[cfg(feature = "std")]
+pub mod case_folded;
+#[cfg(feature = "std")]
 pub mod skiplist;
+#[cfg(feature = "std")]
+pub mod sorted_vec;
 
-use std::path::PathBuf;
+#[cfg(feature = "std")]
+use std::path::Path;
 
+use alloc::{boxed::Box, vec::Vec};
 use bytes::Bytes;
 
-use crate::{
-  data::log_record::LogRecordPos,
-  errors::Result,
-  option::{IndexType, IteratorOptions},
-};
+#[cfg(feature = "std")]
+use crate::option::{IndexType, Options};
+use crate::{data::log_record::LogRecordPos, errors::Result, option::IteratorOptions};
 
 // Abstract interface specifies methods for interchangeable indexing data structures
 pub trait Indexer: Sync + Send {
-  /// Store key's position into indexer
-  fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos>;
+  /// Store key's position into indexer, return the previous position
+  fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>>;
 
   /// Retrieve key's position
   fn get(&self, key: Vec<u8>) -> Option<LogRecordPos>;
 
-  /// Delete the position in indexer by key
-  fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos>;
+  /// Delete the position in indexer by key, return the deleted position
+  fn delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>>;
 
   /// List all keys in the indexer
   fn list_keys(&self) -> Result<Vec<Bytes>>;
 
-  /// Create an iterator for the indexer
-  fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator>;
+  /// Whether any key in the indexer starts with the prefix
+  fn contains_prefix(&self, prefix: &[u8]) -> bool;
+
+  /// Number of keys within [start, end), unordered indexers scan all keys
+  fn count_range(&self, start: &[u8], end: &[u8]) -> Result<usize> {
+    let keys = self.list_keys()?;
+    Ok(
+      keys
+        .iter()
+        .filter(|key| key.as_ref() >= start && key.as_ref() < end)
+        .count(),
+    )
+  }
+
+  /// Number of keys, exact for in-memory indexers. Disk-backed indexers may return an
+  /// estimate rather than walk every key
+  fn estimate_len(&self) -> usize {
+    self.list_keys().map(|keys| keys.len()).unwrap_or(0)
+  }
+
+  /// Up to limit entries in ascending key order, from the first key at or past start. Walking
+  /// the index a chunk at a time holds only limit entries, where `iterator` may copy the
+  /// whole index
+  fn range_from(&self, start: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, LogRecordPos)>> {
+    let mut index_iter = self.iterator(IteratorOptions::default())?;
+    index_iter.seek(start.to_vec());
+    let mut items = Vec::new();
+    while items.len() < limit {
+      match index_iter.next() {
+        Some((key, pos)) => items.push((key.clone(), *pos)),
+        None => break,
+      }
+    }
+    Ok(items)
+  }
+
+  /// Create an iterator for the indexer, `Errors::UnsupportedIteratorMode` if the indexer
+  /// can't provide the consistency in options
+  fn iterator(&self, options: IteratorOptions) -> Result<Box<dyn IndexIterator>>;
+
+  /// Persist buffered changes, in-memory indexers have nothing to do
+  fn sync(&self) -> Result<()> {
+    Ok(())
+  }
 }
 
-pub fn new_indexer(index_type: &IndexType, dir_path: &PathBuf) -> Box<dyn Indexer> {
-  match *index_type {
+/// Create the indexer for index_type in dir_path, append only databases keep the in-memory index
+/// types in a sorted vector since their keys arrive in order. Case insensitive databases wrap the
+/// indexer to lowercase their keys
+#[cfg(feature = "std")]
+pub fn new_indexer(index_type: &IndexType, dir_path: &Path, options: &Options) -> Box<dyn Indexer> {
+  let indexer: Box<dyn Indexer> = match *index_type {
+    IndexType::BTree | IndexType::SkipList if options.append_only => {
+      Box::new(sorted_vec::SortedVec::new())
+    }
     IndexType::BTree => Box::new(btree::BTree::new()),
     IndexType::SkipList => Box::new(skiplist::SkipList::new()),
-    IndexType::BPlusTree => Box::new(bptree::BPlusTree::new(dir_path)),
+    IndexType::BPlusTree => Box::new(
+      bptree::BPlusTree::new(
+        dir_path,
+        bptree::BPTREE_INDEX_FILE_NAME,
+        bptree::BPTREE_BUCKET_NAME,
+      )
+      .with_flush_policy(options.bptree_flush_policy),
+    ),
+  };
+  match options.key_case_insensitive {
+    true => Box::new(case_folded::CaseFolded::new(indexer)),
+    false => indexer,
   }
 }
 
@@ -46,6 +112,9 @@ pub trait IndexIterator: Sync + Send {
   // `Seek` search for the first entry with a key greater than or equal to the given key
   fn seek(&mut self, key: Vec<u8>);
 
+  // `SeekToLast` move to the last entry in iteration order, so `next` returns it
+  fn seek_to_last(&mut self);
+
   // `Next` move to the next entry, when the iterator is exhausted, return None
   fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)>;
 }
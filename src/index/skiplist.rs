@@ -1,10 +1,14 @@
 #![allow(clippy::clone_on_copy)]
-use std::sync::Arc;
+use std::{ops::Bound, sync::Arc};
 
 use bytes::Bytes;
-use crossbeam_skiplist::SkipMap;
+use crossbeam_skiplist::{map::Entry, SkipMap};
 
-use crate::{data::log_record::LogRecordPos, errors::Result, option::IteratorOptions};
+use crate::{
+  data::log_record::LogRecordPos,
+  errors::Result,
+  option::{Consistency, IteratorOptions},
+};
 
 use super::{IndexIterator, Indexer};
 
@@ -22,14 +26,14 @@ impl SkipList {
 }
 
 impl Indexer for SkipList {
-  fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
+  fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
     let mut result = None;
     if let Some(entry) = self.skl.get(&key) {
       result = Some(*entry.value());
     }
 
     self.skl.insert(key, pos);
-    result
+    Ok(result)
   }
 
   fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
@@ -39,11 +43,11 @@ impl Indexer for SkipList {
     None
   }
 
-  fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+  fn delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
     if let Some(entry) = self.skl.remove(&key) {
-      return Some(*entry.value());
+      return Ok(Some(*entry.value()));
     }
-    None
+    Ok(None)
   }
 
   fn list_keys(&self) -> Result<Vec<Bytes>> {
@@ -54,23 +58,66 @@ impl Indexer for SkipList {
     Ok(keys)
   }
 
-  fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
-    let mut items = Vec::with_capacity(self.skl.len());
+  fn contains_prefix(&self, prefix: &[u8]) -> bool {
+    // first key greater than or equal to prefix is the only candidate
+    match self.skl.lower_bound(Bound::Included(prefix)) {
+      Some(entry) => entry.key().starts_with(prefix),
+      None => false,
+    }
+  }
+
+  fn count_range(&self, start: &[u8], end: &[u8]) -> Result<usize> {
+    if start >= end {
+      return Ok(0);
+    }
+    Ok(
+      self
+        .skl
+        .range::<[u8], _>((Bound::Included(start), Bound::Excluded(end)))
+        .count(),
+    )
+  }
+
+  fn estimate_len(&self) -> usize {
+    self.skl.len()
+  }
+
+  fn range_from(&self, start: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, LogRecordPos)>> {
+    let items = self
+      .skl
+      .range::<[u8], _>((Bound::Included(start), Bound::Unbounded))
+      .take(limit)
+      .map(|entry| (entry.key().clone(), *entry.value()))
+      .collect();
+    Ok(items)
+  }
+
+  fn iterator(&self, options: IteratorOptions) -> Result<Box<dyn IndexIterator>> {
+    // the skiplist is safe to walk while it changes
+    if options.consistency == Consistency::Live {
+      return Ok(Box::new(SkipListLiveIterator::new(
+        self.skl.clone(),
+        options,
+      )));
+    }
 
-    // copy all items from SkipList to Vec
-    for entry in self.skl.iter() {
-      items.push((entry.key().clone(), entry.value().clone()));
+    // copy the items within range from SkipList to Vec, the keys before start_key are skipped
+    let mut items = Vec::new();
+    if let Some(bounds) = options.key_bounds() {
+      for entry in self.skl.range::<[u8], _>(bounds) {
+        items.push((entry.key().clone(), *entry.value()));
+      }
     }
 
     if options.reverse {
       items.reverse();
     }
 
-    Box::new(SkipListIterator {
+    Ok(Box::new(SkipListIterator {
       items,
       curr_index: 0,
       options,
-    })
+    }))
   }
 }
 
@@ -99,12 +146,27 @@ impl IndexIterator for SkipListIterator {
     };
   }
 
+  fn seek_to_last(&mut self) {
+    // entries with the prefix are contiguous, the last one is the final match
+    let prefix = &self.options.prefix;
+    self.curr_index = self
+      .items
+      .iter()
+      .rposition(|(key, _)| prefix.is_empty() || key.starts_with(prefix))
+      .unwrap_or(self.items.len());
+  }
+
   fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
     if self.curr_index >= self.items.len() {
       return None;
     }
 
     while let Some(item) = self.items.get(self.curr_index) {
+      // no more keys with the prefix, keep returning None
+      if self.options.past_prefix(&item.0) {
+        self.curr_index = self.items.len();
+        return None;
+      }
       self.curr_index += 1;
       let prefix = &self.options.prefix;
       if prefix.is_empty() || item.0.starts_with(prefix) {
@@ -115,6 +177,113 @@ impl IndexIterator for SkipListIterator {
   }
 }
 
+/// SkipList Index Iterator walking the skiplist as it changes, without copying it
+///
+/// The cursor owns the last key returned and each step seeks the skiplist past it, so no entry
+/// of the map is held between steps and keys removed meanwhile are just not found again.
+pub struct SkipListLiveIterator {
+  skl: Arc<SkipMap<Vec<u8>, LogRecordPos>>,
+  cursor: LiveCursor,
+  options: IteratorOptions, // iterator options
+}
+
+// where the next entry of a live iterator is
+enum LiveCursor {
+  Start,                        // the first entry in iteration order
+  Seek(Vec<u8>),                // the first entry at or past the key in iteration order
+  After(Vec<u8>, LogRecordPos), // the entry following the key last returned with its position
+}
+
+impl SkipListLiveIterator {
+  fn new(skl: Arc<SkipMap<Vec<u8>, LogRecordPos>>, options: IteratorOptions) -> Self {
+    Self {
+      skl,
+      cursor: LiveCursor::Start,
+      options,
+    }
+  }
+
+  // entry the cursor leads to, None if there is none yet
+  fn peek(&self) -> Option<Entry<'_, Vec<u8>, LogRecordPos>> {
+    let reverse = self.options.reverse;
+    match &self.cursor {
+      LiveCursor::Start => match reverse {
+        true => self.skl.back(),
+        false => self.skl.front(),
+      },
+      LiveCursor::Seek(key) => match reverse {
+        true => self.skl.upper_bound(Bound::Included(key.as_slice())),
+        false => self.skl.lower_bound(Bound::Included(key.as_slice())),
+      },
+      LiveCursor::After(key, _) => match reverse {
+        true => self.skl.upper_bound(Bound::Excluded(key.as_slice())),
+        false => self.skl.lower_bound(Bound::Excluded(key.as_slice())),
+      },
+    }
+  }
+}
+
+impl IndexIterator for SkipListLiveIterator {
+  fn rewind(&mut self) {
+    self.cursor = LiveCursor::Start;
+  }
+
+  fn seek(&mut self, key: Vec<u8>) {
+    self.cursor = LiveCursor::Seek(key);
+  }
+
+  fn seek_to_last(&mut self) {
+    // walk back from the end of the iteration order to the final match
+    let last = {
+      let matches = |key: &Vec<u8>| {
+        let prefix = &self.options.prefix;
+        (prefix.is_empty() || key.starts_with(prefix)) && self.options.in_range(key)
+      };
+      match self.options.reverse {
+        true => self.skl.iter().find(|entry| matches(entry.key())),
+        false => self.skl.iter().rev().find(|entry| matches(entry.key())),
+      }
+      .map(|entry| entry.key().clone())
+    };
+    self.cursor = match last {
+      Some(key) => LiveCursor::Seek(key),
+      // nothing matches, skip past every current entry
+      None => match self.options.reverse {
+        true => self.skl.front(),
+        false => self.skl.back(),
+      }
+      .map_or(LiveCursor::Start, |entry| {
+        LiveCursor::After(entry.key().clone(), entry.value().clone())
+      }),
+    };
+  }
+
+  fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
+    loop {
+      // the cursor stays put if there is no next entry yet, later writes may add one
+      let (key, pos) = self
+        .peek()
+        .map(|entry| (entry.key().clone(), entry.value().clone()))?;
+
+      // no more keys with the prefix, every later key is past it too
+      if self.options.past_prefix(&key) {
+        self.cursor = LiveCursor::After(key, pos);
+        return None;
+      }
+      let prefix = &self.options.prefix;
+      let matched = (prefix.is_empty() || key.starts_with(prefix)) && self.options.in_range(&key);
+      self.cursor = LiveCursor::After(key, pos);
+      if matched {
+        break;
+      }
+    }
+    match &self.cursor {
+      LiveCursor::After(key, pos) => Some((key, pos)),
+      _ => None,
+    }
+  }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -123,54 +292,64 @@ mod tests {
   #[test]
   fn test_skl_put() {
     let skl = SkipList::new();
-    let res1 = skl.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res1 = skl
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = skl.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res2 = skl
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = skl.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res3 = skl
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
 
-    let res4 = skl.put(
-      "ddee".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res4 = skl
+      .put(
+        "ddee".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res4.is_none());
 
-    let res5 = skl.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res5 = skl
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res5.is_some());
     let v1 = res5.unwrap();
     assert_eq!(
@@ -190,53 +369,61 @@ mod tests {
     let res = skl.get(b"not exists".to_vec());
     assert!(res.is_none());
 
-    let res1 = skl.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res1 = skl
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
     let v1 = skl.get(b"aacd".to_vec());
     assert!(v1.is_some());
 
-    let res2 = skl.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1233,
-        size: 12,
-      },
-    );
+    let res2 = skl
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1233,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
     let v2 = skl.get(b"acdd".to_vec());
     assert!(v2.is_some());
 
-    let res3 = skl.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1234,
-        size: 12,
-      },
-    );
+    let res3 = skl
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1234,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
 
     let v3 = skl.get(b"aacd".to_vec());
     assert!(v3.is_some());
 
-    let res4 = skl.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1235,
-        size: 12,
-      },
-    );
+    let res4 = skl
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1235,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res4.is_some());
     let v1 = res4.unwrap();
     assert_eq!(
@@ -256,20 +443,22 @@ mod tests {
   fn test_skl_delete() {
     let skl = SkipList::new();
 
-    let res = skl.delete(b"not exists".to_vec());
+    let res = skl.delete(b"not exists".to_vec()).unwrap();
     assert!(res.is_none());
 
-    let res1 = skl.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res1 = skl
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let r1 = skl.delete(b"aacd".to_vec());
+    let r1 = skl.delete(b"aacd".to_vec()).unwrap();
     assert!(r1.is_some());
     let v1 = r1.unwrap();
     assert_eq!(
@@ -292,34 +481,40 @@ mod tests {
     let keys = skl.list_keys().unwrap();
     assert!(keys.is_empty());
 
-    let res1 = skl.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res1 = skl
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = skl.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1233,
-        size: 12,
-      },
-    );
+    let res2 = skl
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1233,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = skl.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1234,
-        size: 12,
-      },
-    );
+    let res3 = skl
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1234,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
 
     let keys = skl.list_keys().unwrap();
@@ -330,38 +525,44 @@ mod tests {
   fn test_skl_iterator() {
     let skl = SkipList::new();
 
-    let res1 = skl.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res1 = skl
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = skl.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1233,
-        size: 12,
-      },
-    );
+    let res2 = skl
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1233,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = skl.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1234,
-        size: 12,
-      },
-    );
+    let res3 = skl
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1234,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
     let mut opt = IteratorOptions::default();
     opt.reverse = true;
-    let mut iter1 = skl.iterator(opt);
+    let mut iter1 = skl.iterator(opt).unwrap();
     while let Some((key, _)) = iter1.next() {
       assert!(!key.is_empty());
     }
@@ -371,38 +572,44 @@ mod tests {
   fn test_skl_iterator_rewind() {
     let skl = SkipList::new();
 
-    let res1 = skl.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res1 = skl
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = skl.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1233,
-        size: 12,
-      },
-    );
+    let res2 = skl
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1233,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = skl.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1234,
-        size: 12,
-      },
-    );
+    let res3 = skl
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1234,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
 
-    let mut iter1 = skl.iterator(IteratorOptions::default());
-    let mut iter2 = skl.iterator(IteratorOptions::default());
+    let mut iter1 = skl.iterator(IteratorOptions::default()).unwrap();
+    let mut iter2 = skl.iterator(IteratorOptions::default()).unwrap();
     iter1.next();
     iter1.next();
     iter1.rewind();
@@ -421,37 +628,43 @@ mod tests {
   fn test_skl_iterator_seek() {
     let skl = SkipList::new();
 
-    let res1 = skl.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res1 = skl
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = skl.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1233,
-        size: 12,
-      },
-    );
+    let res2 = skl
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1233,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = skl.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1234,
-        size: 12,
-      },
-    );
+    let res3 = skl
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1234,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
 
-    let mut iter1 = skl.iterator(IteratorOptions::default());
+    let mut iter1 = skl.iterator(IteratorOptions::default()).unwrap();
     iter1.seek(b"acdd".to_vec());
     let mut count = 0;
     while let Some((key, _)) = iter1.next() {
@@ -465,37 +678,43 @@ mod tests {
   fn test_bptree_iterator_next() {
     let skl = SkipList::new();
 
-    let res1 = skl.put(
-      "aacd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1232,
-        size: 12,
-      },
-    );
+    let res1 = skl
+      .put(
+        "aacd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1232,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res1.is_none());
 
-    let res2 = skl.put(
-      "acdd".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1233,
-        size: 12,
-      },
-    );
+    let res2 = skl
+      .put(
+        "acdd".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1233,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res2.is_none());
 
-    let res3 = skl.put(
-      "bbae".as_bytes().to_vec(),
-      LogRecordPos {
-        file_id: 1123,
-        offset: 1234,
-        size: 12,
-      },
-    );
+    let res3 = skl
+      .put(
+        "bbae".as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1123,
+          offset: 1234,
+          size: 12,
+        },
+      )
+      .unwrap();
     assert!(res3.is_none());
 
-    let mut iter1 = skl.iterator(IteratorOptions::default());
+    let mut iter1 = skl.iterator(IteratorOptions::default()).unwrap();
     let mut count = 0;
     while let Some((key, _)) = iter1.next() {
       count += 1;
@@ -503,4 +722,67 @@ mod tests {
     }
     assert_eq!(count, 3);
   }
+
+  #[test]
+  fn test_skl_live_iterator_concurrent_delete() {
+    let skl = SkipList::new();
+    let pos = |i: usize| LogRecordPos {
+      file_id: 0,
+      offset: i as u64,
+      size: 12,
+    };
+    for i in 0..10000 {
+      skl
+        .put(format!("key-{:05}", i).into_bytes(), pos(i))
+        .unwrap();
+    }
+    let options = || IteratorOptions {
+      consistency: Consistency::Live,
+      ..Default::default()
+    };
+
+    // the key under the cursor and the next one are removed, iteration goes on after them
+    let mut iter = skl.iterator(options()).unwrap();
+    assert_eq!(b"key-00000".to_vec(), *iter.next().unwrap().0);
+    skl.delete(b"key-00000".to_vec()).unwrap();
+    skl.delete(b"key-00001".to_vec()).unwrap();
+    let (key, value) = iter.next().unwrap();
+    assert_eq!(b"key-00002".to_vec(), *key);
+    assert_eq!(pos(2), *value);
+    std::mem::drop(iter);
+
+    // iterators walk while every key is deleted underneath them
+    let skl = Arc::new(skl);
+    let deleter = {
+      let skl = skl.clone();
+      std::thread::spawn(move || {
+        for i in (0..10000).rev() {
+          skl.delete(format!("key-{:05}", i).into_bytes()).unwrap();
+        }
+      })
+    };
+    let walkers = (0..4)
+      .map(|_| {
+        let mut iter = skl.iterator(options()).unwrap();
+        std::thread::spawn(move || {
+          let mut prev: Option<Vec<u8>> = None;
+          while let Some((key, value)) = iter.next() {
+            // keys keep ascending and each carries its own value
+            assert!(prev.as_ref().map_or(true, |prev| prev < key));
+            let i: usize = String::from_utf8(key[4..].to_vec())
+              .unwrap()
+              .parse()
+              .unwrap();
+            assert_eq!(pos(i), *value);
+            prev = Some(key.clone());
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+    deleter.join().unwrap();
+    for walker in walkers {
+      walker.join().unwrap();
+    }
+    assert_eq!(0, skl.estimate_len());
+  }
 }
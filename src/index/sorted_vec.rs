@@ -0,0 +1,253 @@
+use crate::{
+  data::log_record::LogRecordPos,
+  errors::{Errors, Result},
+  option::{Consistency, IteratorOptions},
+};
+use bytes::Bytes;
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+use super::{IndexIterator, Indexer};
+
+// Sorted vector indexer for append only databases. Keys arrive in ascending order, so a put
+// pushes onto the end and the entries take no more room than the keys and positions. Puts of
+// smaller keys and deletes shift the entries after them, they're only expected while loading
+// merged files or for the odd delete.
+pub struct SortedVec {
+  items: Arc<RwLock<Vec<(Vec<u8>, LogRecordPos)>>>,
+}
+
+impl SortedVec {
+  pub fn new() -> Self {
+    Self {
+      items: Arc::new(RwLock::new(Vec::new())),
+    }
+  }
+}
+
+// entries within [start, end) of the sorted items
+fn range_of(items: &[(Vec<u8>, LogRecordPos)], start: &[u8], end: &[u8]) -> usize {
+  let lower = items.partition_point(|(key, _)| key.as_slice() < start);
+  let upper = items.partition_point(|(key, _)| key.as_slice() < end);
+  upper.saturating_sub(lower)
+}
+
+impl Indexer for SortedVec {
+  fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
+    let mut items = self.items.write();
+    if items.last().map_or(true, |(last, _)| *last < key) {
+      items.push((key, pos));
+      return Ok(None);
+    }
+    match items.binary_search_by(|(x, _)| x.cmp(&key)) {
+      Ok(i) => Ok(Some(std::mem::replace(&mut items[i].1, pos))),
+      Err(i) => {
+        items.insert(i, (key, pos));
+        Ok(None)
+      }
+    }
+  }
+
+  fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    let items = self.items.read();
+    match items.binary_search_by(|(x, _)| x.cmp(&key)) {
+      Ok(i) => Some(items[i].1),
+      Err(_) => None,
+    }
+  }
+
+  fn delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
+    let mut items = self.items.write();
+    match items.binary_search_by(|(x, _)| x.cmp(&key)) {
+      Ok(i) => Ok(Some(items.remove(i).1)),
+      Err(_) => Ok(None),
+    }
+  }
+
+  fn list_keys(&self) -> Result<Vec<Bytes>> {
+    let items = self.items.read();
+    let keys = items
+      .iter()
+      .map(|(key, _)| Bytes::copy_from_slice(key))
+      .collect();
+    Ok(keys)
+  }
+
+  fn contains_prefix(&self, prefix: &[u8]) -> bool {
+    let items = self.items.read();
+    // first key greater than or equal to prefix is the only candidate
+    let i = items.partition_point(|(key, _)| key.as_slice() < prefix);
+    items.get(i).is_some_and(|(key, _)| key.starts_with(prefix))
+  }
+
+  fn count_range(&self, start: &[u8], end: &[u8]) -> Result<usize> {
+    if start >= end {
+      return Ok(0);
+    }
+    Ok(range_of(&self.items.read(), start, end))
+  }
+
+  fn estimate_len(&self) -> usize {
+    self.items.read().len()
+  }
+
+  fn range_from(&self, start: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, LogRecordPos)>> {
+    let items = self.items.read();
+    let lower = items.partition_point(|(key, _)| key.as_slice() < start);
+    Ok(items[lower..].iter().take(limit).cloned().collect())
+  }
+
+  fn iterator(&self, options: IteratorOptions) -> Result<Box<dyn IndexIterator>> {
+    // a live walk would hold the lock for the iterator's lifetime
+    if options.consistency == Consistency::Live {
+      return Err(Errors::UnsupportedIteratorMode);
+    }
+
+    // the items are sorted, copying starts at start_key and stops at end_key
+    let all_items = self.items.read();
+    let lower = match &options.start_key {
+      Some(start_key) => all_items.partition_point(|(key, _)| key < start_key),
+      None => 0,
+    };
+    let mut items = all_items[lower..]
+      .iter()
+      .take_while(|(key, _)| options.in_range(key))
+      .cloned()
+      .collect::<Vec<_>>();
+    std::mem::drop(all_items);
+
+    if options.reverse {
+      items.reverse();
+    }
+
+    Ok(Box::new(SortedVecIterator {
+      items,
+      curr_index: 0,
+      options,
+    }))
+  }
+}
+
+/// Sorted vector Index Iterator
+pub struct SortedVecIterator {
+  items: Vec<(Vec<u8>, LogRecordPos)>, // store key and index
+  curr_index: usize,                   //current index
+  options: IteratorOptions,            // iterator options
+}
+
+impl IndexIterator for SortedVecIterator {
+  fn rewind(&mut self) {
+    self.curr_index = 0;
+  }
+
+  fn seek(&mut self, key: Vec<u8>) {
+    self.curr_index = match self.items.binary_search_by(|(x, _)| {
+      if self.options.reverse {
+        x.cmp(&key).reverse()
+      } else {
+        x.cmp(&key)
+      }
+    }) {
+      Ok(equal_val) => equal_val,
+      Err(insert_val) => insert_val,
+    };
+  }
+
+  fn seek_to_last(&mut self) {
+    // entries with the prefix are contiguous, the last one is the final match
+    let prefix = &self.options.prefix;
+    self.curr_index = self
+      .items
+      .iter()
+      .rposition(|(key, _)| prefix.is_empty() || key.starts_with(prefix))
+      .unwrap_or(self.items.len());
+  }
+
+  fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
+    if self.curr_index >= self.items.len() {
+      return None;
+    }
+
+    while let Some(item) = self.items.get(self.curr_index) {
+      // no more keys with the prefix, keep returning None
+      if self.options.past_prefix(&item.0) {
+        self.curr_index = self.items.len();
+        return None;
+      }
+      self.curr_index += 1;
+      let prefix = &self.options.prefix;
+      if prefix.is_empty() || item.0.starts_with(prefix) {
+        return Some((&item.0, &item.1));
+      }
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn pos(offset: u64) -> LogRecordPos {
+    LogRecordPos {
+      file_id: 1,
+      offset,
+      size: 12,
+    }
+  }
+
+  #[test]
+  fn test_sorted_vec_put_get_delete() {
+    let sv = SortedVec::new();
+    assert!(sv.put(b"b".to_vec(), pos(1)).unwrap().is_none());
+    assert!(sv.put(b"d".to_vec(), pos(2)).unwrap().is_none());
+    // smaller keys and overwrites keep the entries sorted
+    assert!(sv.put(b"a".to_vec(), pos(3)).unwrap().is_none());
+    assert!(sv.put(b"c".to_vec(), pos(4)).unwrap().is_none());
+    assert_eq!(Some(pos(1)), sv.put(b"b".to_vec(), pos(5)).unwrap());
+
+    assert_eq!(Some(pos(5)), sv.get(b"b".to_vec()));
+    assert_eq!(None, sv.get(b"e".to_vec()));
+    let keys = sv.list_keys().unwrap();
+    assert_eq!(
+      vec![
+        Bytes::from("a"),
+        Bytes::from("b"),
+        Bytes::from("c"),
+        Bytes::from("d")
+      ],
+      keys
+    );
+    assert_eq!(2, sv.count_range(b"b", b"d").unwrap());
+    assert!(sv.contains_prefix(b"c"));
+    assert!(!sv.contains_prefix(b"cc"));
+
+    assert_eq!(Some(pos(4)), sv.delete(b"c".to_vec()).unwrap());
+    assert_eq!(None, sv.delete(b"c".to_vec()).unwrap());
+    assert_eq!(3, sv.estimate_len());
+  }
+
+  #[test]
+  fn test_sorted_vec_iterator() {
+    let sv = SortedVec::new();
+    for (i, key) in ["aa", "ab", "ba", "bb"].iter().enumerate() {
+      sv.put(key.as_bytes().to_vec(), pos(i as u64)).unwrap();
+    }
+
+    let mut iter = sv
+      .iterator(IteratorOptions {
+        prefix: b"b".to_vec(),
+        reverse: true,
+        ..Default::default()
+      })
+      .unwrap();
+    assert_eq!(b"bb".to_vec(), *iter.next().unwrap().0);
+    assert_eq!(b"ba".to_vec(), *iter.next().unwrap().0);
+    assert!(iter.next().is_none());
+
+    let mut iter = sv.iterator(IteratorOptions::default()).unwrap();
+    iter.seek(b"b".to_vec());
+    assert_eq!(b"ba".to_vec(), *iter.next().unwrap().0);
+  }
+}
@@ -1,27 +1,283 @@
 use bytes::Bytes;
-use parking_lot::RwLock;
-use std::sync::Arc;
+use parking_lot::{Mutex, RwLock};
+use std::{
+  collections::HashSet,
+  io::{BufWriter, Write},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver},
+    Arc,
+  },
+  thread::{self, JoinHandle},
+};
 
-use crate::{db::Engine, errors::Result, index::IndexIterator, option::IteratorOptions};
+use crate::{
+  batch::parse_log_record_key,
+  data::{
+    data_file::{DataFile, ScanBuffer},
+    log_record::{LogRecord, LogRecordPos, LogRecordType},
+  },
+  db::{Engine, ValueReader},
+  errors::{Errors, Result},
+  index::IndexIterator,
+  option::{
+    ExportFormat, IOManagerType, IteratorOptions, EXPORT_BUFFER_SIZE, EXPORT_INDEX_CHUNK_LEN,
+  },
+  util::base64,
+};
 
 /// Iterator interface
 pub struct Iterator<'a> {
   index_iter: Arc<RwLock<Box<dyn IndexIterator>>>, // index iterator
   engine: &'a Engine,
+  prefix_len: usize,             // length of the prefix in iterator options
+  stop_at_separator: Option<u8>, // key level boundary after the prefix
+  reverse: bool,                 // whether keys are yielded in descending order
+  value_filter: Option<Arc<dyn Fn(&[u8]) -> bool + Send + Sync>>, // predicate on values
+  prefetch: usize,               // number of values to read ahead, 0 if disabled
+  prefetcher: Mutex<Option<Prefetcher>>, // running read ahead thread, started on `next`
+  peeked: Mutex<Option<(Bytes, Bytes)>>, // entry returned by `peek`, taken by the next `next`
+}
+
+// entry read ahead by the prefetcher, the value read may be retried by the consumer
+type PrefetchItem = (Vec<u8>, LogRecordPos, Result<Bytes>);
+
+/// Background thread advancing the index iterator and reading values ahead of the consumer
+struct Prefetcher {
+  receiver: Receiver<PrefetchItem>,
+  stopped: Arc<AtomicBool>,
+  handle: JoinHandle<()>,
+}
+
+/// Sequential iterator, walks data files in on-disk order and yields live key/value pairs
+pub struct ScanIterator<'a> {
+  data_files: Vec<(DataFile, u64)>, // data files to scan, with the offset to stop at
+  curr_file: usize,                 // current data file
+  offset: u64,                      // current offset in data file
+  scan_buf: Option<ScanBuffer>,     // read-ahead buffer, None if disabled
+  engine: &'a Engine,
+}
+
+/// Write order iterator, yields live key/value pairs in the order keys were first written
+pub struct WriteOrderIterator<'a> {
+  records: RecordIterator, // every log record in write order
+  seen: HashSet<Vec<u8>>,  // keys already yielded or skipped
+  engine: &'a Engine,
+}
+
+/// Record scan iterator, yields log records read from the data files in write order, see
+/// `Engine::scan_all_records`
+pub struct RecordScanIterator<'a> {
+  records: RecordIterator, // every log record in write order
+  options: IteratorOptions,
+  engine: &'a Engine,
+}
+
+/// Log record yielded by `RecordScanIterator`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannedRecord {
+  pub key: Bytes,
+  pub value: Bytes,  // empty for a tombstone
+  pub deleted: bool, // whether the record is a tombstone
+  pub pos: LogRecordPos,
+}
+
+/// Physical record iterator, yields every log record in write order from a position
+pub(crate) struct RecordIterator {
+  data_files: Vec<(DataFile, u64)>, // data files to read, with the offset to stop at
+  curr_file: usize,                 // current data file
+  offset: u64,                      // current offset in data file
+  error: Option<Errors>,            // error opening the data files, yielded first
 }
 
 impl Engine {
-  /// Create a new iterator
-  pub fn iter(&self, options: IteratorOptions) -> Iterator {
-    Iterator {
-      index_iter: Arc::new(RwLock::new(self.index.iterator(options))),
+  /// Create a new iterator, `Errors::UnsupportedIteratorMode` if the index type doesn't
+  /// support `options.consistency`
+  pub fn iter(&self, options: IteratorOptions) -> Result<Iterator<'_>> {
+    let prefix_len = options.prefix.len();
+    let stop_at_separator = options.stop_at_separator;
+    let reverse = options.reverse;
+    let value_filter = options.value_filter.clone();
+    let prefetch = options.prefetch;
+    let index_iter = self.index.read().iterator(options)?;
+    Ok(Iterator {
+      index_iter: Arc::new(RwLock::new(index_iter)),
       engine: self,
+      prefix_len,
+      stop_at_separator,
+      reverse,
+      value_filter,
+      prefetch,
+      prefetcher: Mutex::new(None),
+      peeked: Mutex::new(None),
+    })
+  }
+
+  /// Create a sequential iterator over all data files, reading ahead
+  /// `scan_buffer_size` bytes at a time
+  pub fn scan(&self) -> Result<ScanIterator<'_>> {
+    let dir_path = &self.options.dir_path;
+    let active_file = self.active_data_file.read();
+    let old_files = self.old_data_files.read();
+
+    let mut file_ids: Vec<u32> = old_files.keys().copied().collect();
+    file_ids.sort();
+
+    let mut data_files = Vec::with_capacity(file_ids.len() + 1);
+    for file_id in file_ids {
+      let data_file = self.open_old_data_file(file_id, IOManagerType::StandardFileIO)?;
+      let end = data_file.physical_size()?;
+      data_files.push((data_file, end));
     }
+
+    // active file is still growing, stop at current write offset
+    let data_file = DataFile::new(
+      dir_path,
+      active_file.get_file_id(),
+      &self.options.file_suffix,
+      IOManagerType::StandardFileIO,
+    )?;
+    data_files.push((data_file, active_file.logical_size()));
+
+    let scan_buf = match self.options.scan_buffer_size {
+      0 => None,
+      size => Some(ScanBuffer::new(size)),
+    };
+
+    Ok(ScanIterator {
+      data_files,
+      curr_file: 0,
+      offset: 0,
+      scan_buf,
+      engine: self,
+    })
+  }
+
+  /// Iterate over every log record in write order, starting at offset of data file file_id.
+  /// Unlike `iter` and `scan`, tombstones, stale values and transaction finished records are
+  /// all yielded, keys carry their seq_no and version, see `batch::parse_log_record_key_version`.
+  /// Records written after the call are not yielded, resume from the position after the last
+  /// record instead.
+  ///
+  /// Yields `Errors::DataFileNotFound` if file_id doesn't exist, e.g. it was merged away.
+  pub fn scan_records_from(
+    &self,
+    file_id: u32,
+    offset: u64,
+  ) -> impl std::iter::Iterator<Item = Result<(LogRecordPos, LogRecord)>> {
+    self.records_from(file_id, offset)
+  }
+
+  /// Create an iterator over live key/value pairs in the order keys were first written, by
+  /// data file id then offset, each with its latest value. Keys are deduplicated with a set
+  /// of all keys seen so far. Merged data files keep the write order of the live records.
+  pub fn scan_write_order(&self) -> WriteOrderIterator<'_> {
+    WriteOrderIterator {
+      records: self.records_from(self.first_file_id(), 0),
+      seen: HashSet::new(),
+      engine: self,
+    }
+  }
+
+  /// Create an iterator over the log records of all data files in write order, read from
+  /// disk rather than the index, for debugging and backfills. Every write of a key is yielded,
+  /// stale values included, and tombstones too if `options.include_deleted` is set. Keys have
+  /// their seq_no removed and transaction finished records are skipped. Of the other options
+  /// only `prefix`, `start_key` and `end_key` are honoured.
+  pub fn scan_all_records(&self, options: IteratorOptions) -> RecordScanIterator<'_> {
+    RecordScanIterator {
+      records: self.records_from(self.first_file_id(), 0),
+      options,
+      engine: self,
+    }
+  }
+
+  // id of the oldest data file
+  fn first_file_id(&self) -> u32 {
+    let active_file = self.active_data_file.read();
+    let old_files = self.old_data_files.read();
+    old_files
+      .keys()
+      .copied()
+      .min()
+      .unwrap_or(active_file.get_file_id())
+  }
+
+  fn records_from(&self, file_id: u32, offset: u64) -> RecordIterator {
+    let mut iter = RecordIterator {
+      data_files: Vec::new(),
+      curr_file: 0,
+      offset,
+      error: None,
+    };
+    if let Err(e) = self.record_data_files(file_id, &mut iter.data_files) {
+      iter.data_files.clear();
+      iter.error = Some(e);
+    }
+    iter
+  }
+
+  // open the data files from file_id on, along with the offset to stop at
+  fn record_data_files(&self, file_id: u32, data_files: &mut Vec<(DataFile, u64)>) -> Result<()> {
+    let dir_path = &self.options.dir_path;
+    let active_file = self.active_data_file.read();
+    let old_files = self.old_data_files.read();
+
+    let active_file_id = active_file.get_file_id();
+    if file_id != active_file_id && !old_files.contains_key(&file_id) {
+      return Err(Errors::DataFileNotFound);
+    }
+
+    let mut file_ids: Vec<u32> = old_files
+      .keys()
+      .copied()
+      .filter(|fid| *fid >= file_id)
+      .collect();
+    file_ids.sort();
+    for fid in file_ids {
+      let data_file = self.open_old_data_file(fid, IOManagerType::StandardFileIO)?;
+      let end = data_file.physical_size()?;
+      data_files.push((data_file, end));
+    }
+
+    // active file is still growing, stop at current write offset
+    let data_file = DataFile::new(
+      dir_path,
+      active_file_id,
+      &self.options.file_suffix,
+      IOManagerType::StandardFileIO,
+    )?;
+    data_files.push((data_file, active_file.logical_size()));
+    Ok(())
+  }
+
+  /// whether any key with the given prefix exists in db
+  pub fn contains_prefix(&self, prefix: &[u8]) -> Result<bool> {
+    Ok(self.index.read().contains_prefix(prefix))
   }
 
-  /// list all keys in db
+  /// number of keys within [start, end), counted on the index without reading values
+  pub fn count_range(&self, start: &[u8], end: &[u8]) -> Result<usize> {
+    self.index.read().count_range(start, end)
+  }
+
+  /// number of keys in db, without listing them. The B+Tree index stores its count along
+  /// with its keys
+  pub fn estimate_keys(&self) -> Result<usize> {
+    Ok(self.index.read().estimate_len())
+  }
+
+  /// list all keys in db, as written for case insensitive databases
   pub fn list_keys(&self) -> Result<Vec<Bytes>> {
-    self.index.list_keys()
+    if !self.options.key_case_insensitive {
+      return self.index.read().list_keys();
+    }
+    let mut keys = Vec::new();
+    let mut index_iter = self.index.read().iterator(IteratorOptions::default())?;
+    while let Some((key, pos)) = index_iter.next() {
+      keys.push(Bytes::from(self.original_key(key.clone(), pos)));
+    }
+    Ok(keys)
   }
 
   /// operate on all key-value pairs in db, finish when `f` returns false
@@ -30,7 +286,7 @@ impl Engine {
     Self: Sized,
     F: Fn(Bytes, Bytes) -> bool,
   {
-    let iter = self.iter(IteratorOptions::default());
+    let iter = self.iter(IteratorOptions::default())?;
     while let Some((key, value)) = iter.next() {
       if !f(key, value) {
         break;
@@ -38,30 +294,425 @@ impl Engine {
     }
     Ok(())
   }
+
+  /// hash of all live key/value pairs in key order, two databases with the same keys and
+  /// values have the same fingerprint whatever their data files look like. The hash is
+  /// 64-bit FNV-1a, fixed so fingerprints can be compared across builds
+  pub fn fingerprint(&self) -> Result<u64> {
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut index_iter = self.index.read().iterator(IteratorOptions::default())?;
+    while let Some((key, pos)) = index_iter.next() {
+      let value = self.get_value_by_key_position(key, pos)?;
+      // lengths keep ("ab", "c") apart from ("a", "bc")
+      for field in [&key[..], &value[..]] {
+        hash = fnv1a(hash, &(field.len() as u64).to_be_bytes());
+        hash = fnv1a(hash, field);
+      }
+    }
+    Ok(hash)
+  }
+
+  /// write all live key/value pairs in key order to `w` in `format`, return the number of
+  /// pairs written. Pairs are read and written one at a time through a buffer of
+  /// `EXPORT_BUFFER_SIZE` bytes that's flushed to `w` whenever it fills up, and the index is
+  /// walked `EXPORT_INDEX_CHUNK_LEN` keys at a time, so memory doesn't grow with the size of
+  /// the database. Pairs written meanwhile may or may not be exported
+  pub fn export_to<W: Write>(&self, w: W, format: ExportFormat) -> Result<usize> {
+    let mut writer = BufWriter::with_capacity(EXPORT_BUFFER_SIZE, w);
+    let mut line = Vec::new();
+    let mut count = 0;
+    let mut start = Vec::new();
+    loop {
+      let chunk = self
+        .index
+        .read()
+        .range_from(&start, EXPORT_INDEX_CHUNK_LEN)?;
+      for (key, pos) in chunk.iter() {
+        self.export_pair(&mut writer, &mut line, key, pos, format)?;
+        count += 1;
+      }
+      if chunk.len() < EXPORT_INDEX_CHUNK_LEN {
+        break;
+      }
+      // smallest key past the last one exported
+      start = chunk.into_iter().next_back().unwrap().0;
+      start.push(0);
+    }
+    writer.flush().map_err(|_| Errors::FailedToWriteExport)?;
+    Ok(count)
+  }
+
+  // write a pair of the index to writer in format, line is a reused buffer
+  fn export_pair<W: Write>(
+    &self,
+    writer: &mut BufWriter<W>,
+    line: &mut Vec<u8>,
+    key: &[u8],
+    pos: &LogRecordPos,
+    format: ExportFormat,
+  ) -> Result<()> {
+    let value = self.get_value_by_key_position(key, pos)?;
+    let key = self.original_key(key.to_vec(), pos);
+    let res = match format {
+      ExportFormat::Ndjson => {
+        line.clear();
+        line.extend_from_slice(b"{\"key\":\"");
+        base64::encode_into(&key, line);
+        line.extend_from_slice(b"\",\"value\":\"");
+        base64::encode_into(&value, line);
+        line.extend_from_slice(b"\"}\n");
+        writer.write_all(line)
+      }
+      ExportFormat::LengthDelimited => writer
+        .write_all(&(key.len() as u32).to_be_bytes())
+        .and_then(|_| writer.write_all(&key))
+        .and_then(|_| writer.write_all(&(value.len() as u32).to_be_bytes()))
+        .and_then(|_| writer.write_all(&value)),
+    };
+    res.map_err(|_| Errors::FailedToWriteExport)
+  }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+  bytes
+    .iter()
+    .fold(hash, |hash, b| (hash ^ *b as u64).wrapping_mul(FNV_PRIME))
 }
 
 impl Iterator<'_> {
   // `Rewind` go back to the beginning of the iterator
   pub fn rewind(&self) {
+    self.stop_prefetcher();
+    self.peeked.lock().take();
     let mut index_iter = self.index_iter.write();
     index_iter.rewind();
   }
 
   // `Seek` search for the first entry with a key greater than or equal to the given key
   pub fn seek(&self, key: Vec<u8>) {
+    self.stop_prefetcher();
+    self.peeked.lock().take();
     let mut index_iter = self.index_iter.write();
     index_iter.seek(key);
   }
 
+  // `SeekToLast` move to the last entry in iteration order, honoring reverse and prefix, so
+  // `next` returns it
+  pub fn seek_to_last(&self) {
+    self.stop_prefetcher();
+    self.peeked.lock().take();
+    let mut index_iter = self.index_iter.write();
+    index_iter.seek_to_last();
+  }
+
   // `Next` move to the next entry, when the iterator is exhausted, return None
   pub fn next(&self) -> Option<(Bytes, Bytes)> {
-    let mut index_iter = self.index_iter.write();
-    if let Some(item) = index_iter.next() {
-      let val = self
-        .engine
-        .get_value_by_position(item.1)
-        .expect("failed to get value from data file");
-      return Some((Bytes::from(item.0.to_vec()), val));
+    if let Some(entry) = self.peeked.lock().take() {
+      return Some(entry);
+    }
+    self.read_next()
+  }
+
+  // `Peek` return the entry the next `next` returns without moving past it, repeated peeks
+  // return the same entry
+  pub fn peek(&self) -> Option<(Bytes, Bytes)> {
+    let mut peeked = self.peeked.lock();
+    if peeked.is_none() {
+      *peeked = self.read_next();
+    }
+    peeked.clone()
+  }
+
+  fn read_next(&self) -> Option<(Bytes, Bytes)> {
+    loop {
+      let (key, pos, val) = match self.prefetch {
+        0 => {
+          let (key, pos) = self.advance()?;
+          let val = self.engine.get_value_by_key_position(&key, &pos);
+          (key, pos, val)
+        }
+        _ => {
+          let (key, pos, val) = self.next_prefetched()?;
+          let val = match val {
+            // the data file was swapped out after the read ahead, look the key up again
+            Err(Errors::DataFileNotFound) => self.engine.get_value_by_key_position(&key, &pos),
+            val => val,
+          };
+          (key, pos, val)
+        }
+      };
+      let val = val.expect("failed to get value from data file");
+      // skip entries whose value doesn't match the filter
+      if let Some(value_filter) = &self.value_filter {
+        if !value_filter(&val) {
+          continue;
+        }
+      }
+      let key = self.engine.original_key(key, &pos);
+      return Some((Bytes::from(key), val));
+    }
+  }
+
+  // `NextKey` move to the next entry and return only its key, the value is read only when
+  // a value filter needs it
+  pub fn next_key(&self) -> Option<Bytes> {
+    if let Some((key, _)) = self.peeked.lock().take() {
+      return Some(key);
+    }
+    // the prefetcher owns the position of the index iterator while it runs
+    if self.value_filter.is_some() || self.prefetch > 0 {
+      return self.next().map(|(key, _)| key);
+    }
+    self
+      .advance()
+      .map(|(key, pos)| Bytes::from(self.engine.original_key(key, &pos)))
+  }
+
+  // only advance under the iterator lock, the value is read after releasing it
+  fn advance(&self) -> Option<(Vec<u8>, LogRecordPos)> {
+    advance_index(
+      &self.index_iter,
+      self.prefix_len,
+      self.stop_at_separator,
+      self.reverse,
+    )
+  }
+
+  // take the next entry read ahead, starting the prefetcher if it isn't running
+  fn next_prefetched(&self) -> Option<PrefetchItem> {
+    let mut prefetcher = self.prefetcher.lock();
+    let prefetcher = prefetcher.get_or_insert_with(|| self.start_prefetcher());
+    // the channel is closed once the index iterator is exhausted
+    prefetcher.receiver.recv().ok()
+  }
+
+  fn start_prefetcher(&self) -> Prefetcher {
+    let (sender, receiver) = mpsc::sync_channel(self.prefetch);
+    let stopped = Arc::new(AtomicBool::new(false));
+    let index_iter = self.index_iter.clone();
+    let prefix_len = self.prefix_len;
+    let stop_at_separator = self.stop_at_separator;
+    let reverse = self.reverse;
+    let value_reader = self.engine.value_reader();
+    let thread_stopped = stopped.clone();
+    let handle = thread::spawn(move || {
+      while !thread_stopped.load(Ordering::SeqCst) {
+        let Some((key, pos)) = advance_index(&index_iter, prefix_len, stop_at_separator, reverse)
+        else {
+          return;
+        };
+        let val = value_reader.read(&pos);
+        // the consumer is gone
+        if sender.send((key, pos, val)).is_err() {
+          return;
+        }
+      }
+    });
+    Prefetcher {
+      receiver,
+      stopped,
+      handle,
+    }
+  }
+
+  // stop the prefetcher and wait for it, entries it read ahead are dropped and the index
+  // iterator is left past them
+  fn stop_prefetcher(&self) {
+    if let Some(prefetcher) = self.prefetcher.lock().take() {
+      prefetcher.stopped.store(true, Ordering::SeqCst);
+      // dropping the receiver wakes the prefetcher blocked on a full channel
+      std::mem::drop(prefetcher.receiver);
+      let _ = prefetcher.handle.join();
+    }
+  }
+}
+
+impl Drop for Iterator<'_> {
+  fn drop(&mut self) {
+    self.stop_prefetcher();
+  }
+}
+
+// advance the index iterator to the next entry, skipping keys nested below a separator
+// after the prefix
+fn advance_index(
+  index_iter: &RwLock<Box<dyn IndexIterator>>,
+  prefix_len: usize,
+  stop_at_separator: Option<u8>,
+  reverse: bool,
+) -> Option<(Vec<u8>, LogRecordPos)> {
+  let mut index_iter = index_iter.write();
+  loop {
+    let item = index_iter.next()?;
+    let Some(separator) = stop_at_separator else {
+      return Some((item.0.to_vec(), *item.1));
+    };
+    let level = prefix_len.min(item.0.len());
+    let Some(i) = item.0[level..].iter().position(|b| *b == separator) else {
+      return Some((item.0.to_vec(), *item.1));
+    };
+
+    // seek past the keys nested below the same separator at once rather than one by one.
+    // Forward seeks to the nested prefix followed by 0xFF, reverse to the nested prefix itself.
+    // A key the seek wouldn't move past is stepped over
+    let mut nested = item.0[..level + i + 1].to_vec();
+    let seek = match reverse {
+      true => nested.as_slice() < item.0.as_slice(),
+      false => {
+        nested.push(u8::MAX);
+        nested.as_slice() > item.0.as_slice()
+      }
+    };
+    if seek {
+      index_iter.seek(nested);
+    }
+  }
+}
+
+impl ScanIterator<'_> {
+  // `Next` move to the next live entry in data file order, when all files are exhausted, return None
+  pub fn next(&mut self) -> Result<Option<(Bytes, Bytes)>> {
+    while let Some((data_file, end)) = self.data_files.get(self.curr_file) {
+      if self.offset >= *end {
+        self.curr_file += 1;
+        self.offset = 0;
+        continue;
+      }
+
+      let offset = self.offset;
+      let read_res = match self.scan_buf.as_mut() {
+        Some(scan_buf) => data_file.read_log_record_buffered(offset, scan_buf),
+        None => data_file.read_log_record(offset),
+      };
+      let (log_record, size) = match read_res {
+        Ok(result) => (result.record, result.size),
+        Err(Errors::ReadDataFileEOF) => {
+          self.curr_file += 1;
+          self.offset = 0;
+          continue;
+        }
+        Err(e) => return Err(e),
+      };
+      self.offset += size as u64;
+
+      // only yield the record if index still points to it
+      let (real_key, _) = parse_log_record_key(log_record.key)?;
+      if let Some(pos) = self.engine.index.read().get(real_key.clone()) {
+        if pos.file_id == data_file.get_file_id() && pos.offset == offset {
+          let value = self
+            .engine
+            .decode_record_value(log_record.rec_type, log_record.value)?;
+          return Ok(Some((Bytes::from(real_key), value)));
+        }
+      }
+    }
+    Ok(None)
+  }
+}
+
+impl WriteOrderIterator<'_> {
+  // `Next` move to the next key in first write order, when all files are exhausted, return None
+  pub fn next(&mut self) -> Result<Option<(Bytes, Bytes)>> {
+    for record in self.records.by_ref() {
+      let (_, record) = record?;
+      if record.rec_type == LogRecordType::TxnFinished {
+        continue;
+      }
+      let (real_key, _) = parse_log_record_key(record.key)?;
+      if self.seen.contains(&real_key) {
+        continue;
+      }
+      self.seen.insert(real_key.clone());
+
+      // the key may be deleted since, or its value may live further on
+      let pos = match self.engine.index.read().get(real_key.clone()) {
+        Some(pos) => pos,
+        None => continue,
+      };
+      match self.engine.get_value_by_key_position(&real_key, &pos) {
+        Ok(value) => return Ok(Some((Bytes::from(real_key), value))),
+        Err(Errors::KeyNotFound) => continue,
+        Err(e) => return Err(e),
+      }
+    }
+    Ok(None)
+  }
+}
+
+impl RecordScanIterator<'_> {
+  // `Next` move to the next matching log record in write order, when all files are exhausted,
+  // return None
+  pub fn next(&mut self) -> Result<Option<ScannedRecord>> {
+    for record in self.records.by_ref() {
+      let (pos, record) = record?;
+      let deleted = match record.rec_type {
+        LogRecordType::Normal | LogRecordType::ValueRef => false,
+        LogRecordType::Deleted if self.options.include_deleted => true,
+        _ => continue,
+      };
+      let (real_key, _) = parse_log_record_key(record.key)?;
+      if !real_key.starts_with(&self.options.prefix) || !self.options.in_range(&real_key) {
+        continue;
+      }
+
+      let value = match deleted {
+        true => Bytes::new(),
+        false => self
+          .engine
+          .decode_record_value(record.rec_type, record.value)?,
+      };
+      return Ok(Some(ScannedRecord {
+        key: Bytes::from(real_key),
+        value,
+        deleted,
+        pos,
+      }));
+    }
+    Ok(None)
+  }
+}
+
+impl std::iter::Iterator for RecordIterator {
+  type Item = Result<(LogRecordPos, LogRecord)>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if let Some(e) = self.error.take() {
+      return Some(Err(e));
+    }
+
+    while self.curr_file < self.data_files.len() {
+      let (data_file, end) = &self.data_files[self.curr_file];
+      if self.offset >= *end {
+        self.curr_file += 1;
+        self.offset = 0;
+        continue;
+      }
+
+      let offset = self.offset;
+      let (record, size) = match data_file.read_log_record(offset) {
+        Ok(result) => (result.record, result.size),
+        Err(Errors::ReadDataFileEOF) => {
+          self.curr_file += 1;
+          self.offset = 0;
+          continue;
+        }
+        Err(e) => {
+          // stop after an error, the following records can't be located
+          self.curr_file = self.data_files.len();
+          return Some(Err(e));
+        }
+      };
+      self.offset += size as u64;
+
+      let pos = LogRecordPos {
+        file_id: data_file.get_file_id(),
+        offset,
+        size: size as u32,
+      };
+      return Some(Ok((pos, record)));
     }
     None
   }
@@ -71,7 +722,11 @@ impl Iterator<'_> {
 mod tests {
   use std::path::PathBuf;
 
-  use crate::{option::Options, util};
+  use crate::{
+    index::Indexer,
+    option::{Consistency, FlushPolicy, IndexType, Options},
+    util,
+  };
 
   use super::*;
 
@@ -105,8 +760,8 @@ mod tests {
 
     engine
       .fold(|key, value| {
-        assert!(key.len() > 0);
-        assert!(value.len() > 0);
+        assert!(!key.is_empty());
+        assert!(!value.is_empty());
         true
       })
       .unwrap();
@@ -161,7 +816,7 @@ mod tests {
     let engine = Engine::open(opt.clone()).expect("fail to open engine");
 
     // no items
-    let iter1 = engine.iter(IteratorOptions::default());
+    let iter1 = engine.iter(IteratorOptions::default()).unwrap();
     iter1.seek("aa".as_bytes().to_vec());
     assert!(iter1.next().is_none());
 
@@ -171,7 +826,7 @@ mod tests {
       util::rand_kv::get_test_value(10),
     );
     assert!(put_res1.is_ok());
-    let iter2 = engine.iter(IteratorOptions::default());
+    let iter2 = engine.iter(IteratorOptions::default()).unwrap();
     iter2.seek("a".as_bytes().to_vec());
     assert!(iter2.next().is_some());
 
@@ -193,7 +848,7 @@ mod tests {
     );
     assert!(put_res4.is_ok());
 
-    let iter3 = engine.iter(IteratorOptions::default());
+    let iter3 = engine.iter(IteratorOptions::default()).unwrap();
     iter3.seek("a".as_bytes().to_vec());
     assert_eq!(Bytes::from("aaccc"), iter3.next().unwrap().0);
 
@@ -201,6 +856,63 @@ mod tests {
     std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
   }
 
+  #[test]
+  fn test_iterator_seek_to_last() {
+    for (index_type, consistency) in [
+      (IndexType::BTree, Consistency::Snapshot),
+      (IndexType::SkipList, Consistency::Snapshot),
+      (IndexType::BPlusTree, Consistency::Snapshot),
+      (IndexType::SkipList, Consistency::Live),
+    ] {
+      let mut opt = Options::default();
+      opt.dir_path = PathBuf::from(format!(
+        "/tmp/bitkv-rs-iter-seek-to-last-{:?}-{:?}",
+        index_type, consistency
+      ));
+      opt.index_type = index_type.clone();
+      let engine = Engine::open(opt.clone()).expect("fail to open engine");
+      let last_of = |reverse: bool, prefix: &str| {
+        let iter = engine
+          .iter(IteratorOptions {
+            reverse,
+            prefix: prefix.as_bytes().to_vec(),
+            consistency,
+            ..Default::default()
+          })
+          .unwrap();
+        iter.seek_to_last();
+        let last = iter.next().map(|(key, _)| key);
+        assert!(last.is_none() || iter.next().is_none());
+        last
+      };
+
+      // no items
+      assert_eq!(None, last_of(false, ""));
+      assert_eq!(None, last_of(true, ""));
+
+      for key in ["aa-1", "aa-2", "bb-1", "bb-2", "cc-1"] {
+        engine
+          .put(Bytes::from(key), util::rand_kv::get_test_value(1))
+          .unwrap();
+      }
+
+      // forward and reverse orders
+      assert_eq!(Some(Bytes::from("cc-1")), last_of(false, ""));
+      assert_eq!(Some(Bytes::from("aa-1")), last_of(true, ""));
+
+      // with a prefix filter
+      assert_eq!(Some(Bytes::from("bb-2")), last_of(false, "bb"));
+      assert_eq!(Some(Bytes::from("bb-1")), last_of(true, "bb"));
+      assert_eq!(Some(Bytes::from("aa-2")), last_of(false, "aa"));
+      assert_eq!(Some(Bytes::from("cc-1")), last_of(true, "cc"));
+      assert_eq!(None, last_of(false, "zz"));
+      assert_eq!(None, last_of(true, "zz"));
+
+      std::mem::drop(engine);
+      std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+    }
+  }
+
   #[test]
   fn test_iterator_next() {
     let mut opt = Options::default();
@@ -214,7 +926,7 @@ mod tests {
       util::rand_kv::get_test_value(10),
     );
     assert!(put_res1.is_ok());
-    let iter1 = engine.iter(IteratorOptions::default());
+    let iter1 = engine.iter(IteratorOptions::default()).unwrap();
     iter1.seek("a".as_bytes().to_vec());
     assert!(iter1.next().is_some());
     iter1.rewind();
@@ -240,9 +952,54 @@ mod tests {
 
     let mut iter_opt = IteratorOptions::default();
     iter_opt.reverse = true;
-    let iter2 = engine.iter(iter_opt);
+    let iter2 = engine.iter(iter_opt).unwrap();
     while let Some(item) = iter2.next() {
-      assert!(item.0.len() > 0);
+      assert!(!item.0.is_empty());
+    }
+
+    // delete tested files
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+  }
+
+  #[test]
+  fn test_iterator_peek() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-iter-peek");
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+    for i in 0..3 {
+      engine
+        .put(
+          util::rand_kv::get_test_key(i),
+          util::rand_kv::get_test_value(i),
+        )
+        .unwrap();
+    }
+
+    for prefetch in [0, 2] {
+      let iter = engine
+        .iter(IteratorOptions {
+          prefetch,
+          ..Default::default()
+        })
+        .unwrap();
+
+      // repeated peeks don't advance, next returns the peeked entry
+      let first = iter.peek().unwrap();
+      assert_eq!(util::rand_kv::get_test_key(0), first.0);
+      assert_eq!(Some(first.clone()), iter.peek());
+      assert_eq!(Some(first), iter.next());
+
+      assert_eq!(util::rand_kv::get_test_key(1), iter.peek().unwrap().0);
+      assert_eq!(Some(util::rand_kv::get_test_key(1)), iter.next_key());
+      assert_eq!(util::rand_kv::get_test_key(2), iter.next().unwrap().0);
+      assert!(iter.peek().is_none());
+      assert!(iter.next().is_none());
+
+      // moving the iterator drops the peeked entry
+      iter.rewind();
+      assert_eq!(util::rand_kv::get_test_key(0), iter.peek().unwrap().0);
+      iter.seek(util::rand_kv::get_test_key(2).to_vec());
+      assert_eq!(util::rand_kv::get_test_key(2), iter.next().unwrap().0);
     }
 
     // delete tested files
@@ -279,12 +1036,907 @@ mod tests {
 
     let mut iter_opt = IteratorOptions::default();
     iter_opt.prefix = "dd".as_bytes().to_vec();
-    let iter1 = engine.iter(iter_opt);
+    let iter1 = engine.iter(iter_opt).unwrap();
+    while let Some(item) = iter1.next() {
+      assert!(!item.0.is_empty());
+    }
+
+    // delete tested files
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+  }
+
+  #[test]
+  fn test_iterator_stop_at_separator() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-iter-separator");
+    opt.data_file_size = 64 * 1024 * 1024; // 64MB
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+    for key in [
+      "tenant|u1",
+      "tenant|u1|name",
+      "tenant|u2",
+      "tenant|u2|age",
+      "other|x",
+    ] {
+      let put_res = engine.put(
+        Bytes::from(key.as_bytes().to_vec()),
+        util::rand_kv::get_test_value(10),
+      );
+      assert!(put_res.is_ok());
+    }
+    // nested keys ending at the separator or continuing with 0xFF aren't seeked past
+    for key in [b"tenant|u1|".to_vec(), b"tenant|u1|\xff\xff".to_vec()] {
+      let put_res = engine.put(Bytes::from(key), util::rand_kv::get_test_value(10));
+      assert!(put_res.is_ok());
+    }
+
+    let mut iter_opt = IteratorOptions::default();
+    iter_opt.prefix = "tenant|".as_bytes().to_vec();
+    iter_opt.stop_at_separator = Some(b'|');
+    let iter1 = engine.iter(iter_opt).unwrap();
+    let mut keys = Vec::new();
     while let Some(item) = iter1.next() {
-      assert!(item.0.len() > 0);
+      keys.push(item.0);
+    }
+    assert_eq!(
+      keys,
+      vec![Bytes::from("tenant|u1"), Bytes::from("tenant|u2")]
+    );
+
+    // reverse iteration applies the same boundary
+    let mut iter_opt = IteratorOptions::default();
+    iter_opt.prefix = "tenant|".as_bytes().to_vec();
+    iter_opt.stop_at_separator = Some(b'|');
+    iter_opt.reverse = true;
+    let iter2 = engine.iter(iter_opt).unwrap();
+    let mut keys = Vec::new();
+    while let Some(item) = iter2.next() {
+      keys.push(item.0);
+    }
+    assert_eq!(
+      keys,
+      vec![Bytes::from("tenant|u2"), Bytes::from("tenant|u1")]
+    );
+
+    // delete tested files
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+  }
+
+  #[test]
+  fn test_iterator_value_filter() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-iter-value-filter");
+    opt.data_file_size = 64 * 1024 * 1024; // 64MB
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+    for i in 0..20 {
+      let value = match i % 3 {
+        0 => format!("red-{}", i),
+        _ => format!("blue-{}", i),
+      };
+      engine
+        .put(util::rand_kv::get_test_key(i), Bytes::from(value))
+        .unwrap();
+    }
+
+    for reverse in [false, true] {
+      let iter = engine
+        .iter(IteratorOptions {
+          reverse,
+          value_filter: Some(Arc::new(|value: &[u8]| {
+            value.windows(3).any(|w| w == b"red")
+          })),
+          ..Default::default()
+        })
+        .unwrap();
+      let mut keys = Vec::new();
+      while let Some((key, value)) = iter.next() {
+        assert!(String::from_utf8(value.to_vec())
+          .unwrap()
+          .starts_with("red-"));
+        keys.push(key);
+      }
+      let mut expected = (0..20)
+        .filter(|i| i % 3 == 0)
+        .map(util::rand_kv::get_test_key)
+        .collect::<Vec<_>>();
+      if reverse {
+        expected.reverse();
+      }
+      assert_eq!(expected, keys);
+
+      // keys only, the filter still applies
+      iter.rewind();
+      let mut keys = Vec::new();
+      while let Some(key) = iter.next_key() {
+        keys.push(key);
+      }
+      assert_eq!(expected, keys);
+    }
+
+    // delete tested files
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+  }
+
+  #[test]
+  fn test_iterator_consistency() {
+    for index_type in [IndexType::BTree, IndexType::SkipList, IndexType::BPlusTree] {
+      let mut opt = Options::default();
+      opt.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-iter-consistency-{:?}", index_type));
+      opt.index_type = index_type.clone();
+      let engine = Engine::open(opt.clone()).expect("fail to open engine");
+      for i in [1, 3, 5] {
+        let key = Bytes::from(format!("key-{}", i));
+        engine.put(key, Bytes::from(format!("old-{}", i))).unwrap();
+      }
+
+      // writes after creation aren't seen by a snapshot iterator
+      let iter = engine
+        .iter(IteratorOptions {
+          consistency: Consistency::Snapshot,
+          ..Default::default()
+        })
+        .unwrap();
+      assert_eq!(Bytes::from("key-1"), iter.next().unwrap().0);
+      engine
+        .put(Bytes::from("key-4"), Bytes::from("new-4"))
+        .unwrap();
+      engine
+        .put(Bytes::from("key-5"), Bytes::from("new-5"))
+        .unwrap();
+      engine.delete(Bytes::from("key-3")).unwrap();
+      let mut items = Vec::new();
+      while let Some(item) = iter.next() {
+        items.push(item);
+      }
+      assert_eq!(
+        vec![
+          (Bytes::from("key-3"), Bytes::from("old-3")),
+          (Bytes::from("key-5"), Bytes::from("old-5")),
+        ],
+        items
+      );
+      std::mem::drop(iter);
+
+      // only the skiplist can be walked live, iterators over other index types fail instead
+      // of falling back to a snapshot
+      let live = engine.iter(IteratorOptions {
+        consistency: Consistency::Live,
+        ..Default::default()
+      });
+      if index_type != IndexType::SkipList {
+        assert_eq!(Errors::UnsupportedIteratorMode, live.err().unwrap());
+      } else {
+        let iter = live.unwrap();
+        assert_eq!(Bytes::from("key-1"), iter.next().unwrap().0);
+        engine
+          .put(Bytes::from("key-2"), Bytes::from("new-2"))
+          .unwrap();
+        engine
+          .put(Bytes::from("key-0"), Bytes::from("new-0"))
+          .unwrap();
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_key() {
+          keys.push(key);
+        }
+        assert_eq!(
+          vec![
+            Bytes::from("key-2"),
+            Bytes::from("key-4"),
+            Bytes::from("key-5")
+          ],
+          keys
+        );
+      }
+
+      // delete tested files
+      std::mem::drop(engine);
+      std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+    }
+  }
+
+  #[test]
+  fn test_iterator_range() {
+    for index_type in [IndexType::BTree, IndexType::SkipList, IndexType::BPlusTree] {
+      let mut opt = Options::default();
+      opt.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-iter-range-{:?}", index_type));
+      opt.index_type = index_type;
+      let engine = Engine::open(opt.clone()).expect("fail to open engine");
+      for i in 0..100 {
+        let key = Bytes::from(format!("key-{:03}", i));
+        engine.put(key, util::rand_kv::get_test_value(i)).unwrap();
+      }
+
+      let range_opts = |reverse| IteratorOptions {
+        reverse,
+        start_key: Some(b"key-020".to_vec()),
+        end_key: Some(b"key-050".to_vec()),
+        ..Default::default()
+      };
+      let collect = |iter: &Iterator| {
+        let mut keys = Vec::new();
+        while let Some(item) = iter.next() {
+          keys.push(item.0);
+        }
+        keys
+      };
+
+      let forward = collect(&engine.iter(range_opts(false)).unwrap());
+      assert_eq!(30, forward.len());
+      assert_eq!(Bytes::from("key-020"), forward[0]);
+      assert_eq!(Bytes::from("key-049"), forward[29]);
+
+      // same set in opposite order
+      let mut reverse = collect(&engine.iter(range_opts(true)).unwrap());
+      assert_eq!(Bytes::from("key-049"), reverse[0]);
+      reverse.reverse();
+      assert_eq!(forward, reverse);
+
+      // seeking beyond the bounds lands on the bound
+      let iter1 = engine.iter(range_opts(false)).unwrap();
+      iter1.seek(b"key-000".to_vec());
+      assert_eq!(Bytes::from("key-020"), iter1.next().unwrap().0);
+      let iter2 = engine.iter(range_opts(true)).unwrap();
+      iter2.seek(b"key-099".to_vec());
+      assert_eq!(Bytes::from("key-049"), iter2.next().unwrap().0);
+      iter2.seek(b"key-035".to_vec());
+      assert_eq!(Bytes::from("key-035"), iter2.next().unwrap().0);
+      assert_eq!(Bytes::from("key-034"), iter2.next().unwrap().0);
+      iter2.seek(b"key-010".to_vec());
+      assert!(iter2.next().is_none());
+
+      std::mem::drop(iter1);
+      std::mem::drop(iter2);
+      std::mem::drop(engine);
+      std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+    }
+  }
+
+  #[test]
+  fn test_iterator_reverse_prefix_seek() {
+    for index_type in [IndexType::BTree, IndexType::SkipList, IndexType::BPlusTree] {
+      let mut opt = Options::default();
+      opt.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-iter-rev-prefix-{:?}", index_type));
+      opt.index_type = index_type;
+      let engine = Engine::open(opt.clone()).expect("fail to open engine");
+      for key in ["a-1", "a-2", "b-1", "b-2", "b-3", "b-4", "c-1"] {
+        engine
+          .put(Bytes::from(key), util::rand_kv::get_test_value(1))
+          .unwrap();
+      }
+
+      let prefix_opts = |reverse| IteratorOptions {
+        prefix: b"b-".to_vec(),
+        reverse,
+        ..Default::default()
+      };
+      let collect = |iter: &Iterator| {
+        let mut keys = Vec::new();
+        while let Some(item) = iter.next() {
+          keys.push(String::from_utf8(item.0.to_vec()).unwrap());
+        }
+        keys
+      };
+
+      let iter1 = engine.iter(prefix_opts(true)).unwrap();
+      assert_eq!(vec!["b-4", "b-3", "b-2", "b-1"], collect(&iter1));
+      assert!(iter1.next().is_none());
+
+      // seek inside the prefix range, existing and missing keys
+      iter1.seek(b"b-3".to_vec());
+      assert_eq!(vec!["b-3", "b-2", "b-1"], collect(&iter1));
+      iter1.seek(b"b-25".to_vec());
+      assert_eq!(vec!["b-2", "b-1"], collect(&iter1));
+
+      // seek outside the prefix range
+      iter1.seek(b"c-0".to_vec());
+      assert_eq!(vec!["b-4", "b-3", "b-2", "b-1"], collect(&iter1));
+      iter1.seek(b"a-9".to_vec());
+      assert!(collect(&iter1).is_empty());
+
+      let iter2 = engine.iter(prefix_opts(false)).unwrap();
+      iter2.seek(b"b-25".to_vec());
+      assert_eq!(vec!["b-3", "b-4"], collect(&iter2));
+      iter2.seek(b"a".to_vec());
+      assert_eq!(vec!["b-1", "b-2", "b-3", "b-4"], collect(&iter2));
+
+      std::mem::drop(iter1);
+      std::mem::drop(iter2);
+      std::mem::drop(engine);
+      std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+    }
+  }
+
+  #[test]
+  fn test_iterator_parallel() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-iter-parallel");
+    opt.data_file_size = 64 * 1024;
+    let engine = Arc::new(Engine::open(opt.clone()).expect("fail to open engine"));
+    for i in 0..1000 {
+      engine
+        .put(
+          util::rand_kv::get_test_key(i),
+          util::rand_kv::get_test_value(i),
+        )
+        .unwrap();
+    }
+
+    let handles = (0..8)
+      .map(|t| {
+        let engine = engine.clone();
+        std::thread::spawn(move || {
+          for _ in 0..4 {
+            let reverse = t % 2 == 1;
+            let iters = (0..4)
+              .map(|_| {
+                engine
+                  .iter(IteratorOptions {
+                    reverse,
+                    ..Default::default()
+                  })
+                  .unwrap()
+              })
+              .collect::<Vec<_>>();
+            // interleave the iterators, each one sees every key in order
+            for n in 0..1000 {
+              let i = if reverse { 999 - n } else { n };
+              for iter in iters.iter() {
+                assert_eq!(
+                  Some((
+                    util::rand_kv::get_test_key(i),
+                    util::rand_kv::get_test_value(i)
+                  )),
+                  iter.next()
+                );
+              }
+            }
+            assert!(iters.iter().all(|iter| iter.next().is_none()));
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+    for handle in handles {
+      handle.join().unwrap();
     }
 
     // delete tested files
+    std::mem::drop(engine);
     std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
   }
+
+  #[test]
+  fn test_iterator_prefetch() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-iter-prefetch");
+    opt.data_file_size = 32 * 1024;
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+    for i in 0..1000 {
+      let key = match i % 4 {
+        0 => format!("a|{}|nested", i),
+        _ => format!("a|{:04}", i),
+      };
+      engine
+        .put(Bytes::from(key), util::rand_kv::get_test_value(i))
+        .unwrap();
+    }
+    assert!(engine.get_engine_stat().unwrap().data_file_num > 1);
+
+    let options = |reverse: bool, prefetch: usize| IteratorOptions {
+      prefix: b"a|".to_vec(),
+      reverse,
+      stop_at_separator: Some(b'|'),
+      value_filter: Some(Arc::new(|value: &[u8]| value.ends_with(b"0"))),
+      prefetch,
+      ..Default::default()
+    };
+    let collect = |iter: &Iterator| {
+      let mut items = Vec::new();
+      while let Some(item) = iter.next() {
+        items.push(item);
+      }
+      items
+    };
+
+    for reverse in [false, true] {
+      let expected = collect(&engine.iter(options(reverse, 0)).unwrap());
+      assert!(!expected.is_empty());
+
+      for prefetch in [1, 16] {
+        // prefetched results match a plain scan, in the same order
+        let iter = engine.iter(options(reverse, prefetch)).unwrap();
+        assert_eq!(expected, collect(&iter));
+        assert!(iter.next().is_none());
+
+        // repositioning drops what was read ahead
+        iter.rewind();
+        assert_eq!(expected[0], iter.next().unwrap());
+        assert_eq!(expected[1], iter.next().unwrap());
+        iter.seek(expected[10].0.to_vec());
+        assert_eq!(expected[10], iter.next().unwrap());
+
+        iter.rewind();
+        let keys = std::iter::from_fn(|| iter.next_key()).collect::<Vec<_>>();
+        assert_eq!(
+          expected
+            .iter()
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>(),
+          keys
+        );
+      }
+    }
+
+    // dropping an iterator half way stops its prefetcher
+    let iter = engine.iter(options(false, 4)).unwrap();
+    assert!(iter.next().is_some());
+    std::mem::drop(iter);
+    engine
+      .put(Bytes::from("a|new"), util::rand_kv::get_test_value(1))
+      .unwrap();
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+  }
+
+  #[test]
+  fn test_scan_iterator() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-iter-scan");
+    opt.data_file_size = 1024 * 1024; // 1MB
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+    for i in 0..20000 {
+      let put_res = engine.put(
+        util::rand_kv::get_test_key(i),
+        util::rand_kv::get_test_value(i),
+      );
+      assert!(put_res.is_ok());
+    }
+    for i in 0..5000 {
+      let put_res = engine.put(util::rand_kv::get_test_key(i), Bytes::from("new value"));
+      assert!(put_res.is_ok());
+    }
+    for i in 15000..20000 {
+      let del_res = engine.delete(util::rand_kv::get_test_key(i));
+      assert!(del_res.is_ok());
+    }
+    std::mem::drop(engine);
+
+    // scan with and without read-ahead
+    for scan_buffer_size in [0, 4096] {
+      opt.scan_buffer_size = scan_buffer_size;
+      let engine = Engine::open(opt.clone()).expect("fail to open engine");
+      let mut scan = engine.scan().expect("fail to create scan iterator");
+      let mut count = 0;
+      while let Some((key, value)) = scan.next().unwrap() {
+        assert_eq!(engine.get(key).unwrap(), value);
+        count += 1;
+      }
+      assert_eq!(15000, count);
+    }
+
+    // delete tested files
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+  }
+
+  #[test]
+  fn test_scan_records_from() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-scan-records");
+    opt.data_file_size = 256;
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+    // expected stream of (key, type, value, written in a batch)
+    let mut expected = Vec::new();
+    for i in 0..20 {
+      engine
+        .put(
+          util::rand_kv::get_test_key(i),
+          util::rand_kv::get_test_value(i),
+        )
+        .unwrap();
+      expected.push((
+        util::rand_kv::get_test_key(i).to_vec(),
+        LogRecordType::Normal,
+        util::rand_kv::get_test_value(i).to_vec(),
+        false,
+      ));
+    }
+    for i in 0..5 {
+      engine.delete(util::rand_kv::get_test_key(i)).unwrap();
+      expected.push((
+        util::rand_kv::get_test_key(i).to_vec(),
+        LogRecordType::Deleted,
+        Vec::new(),
+        false,
+      ));
+    }
+    let wb = engine
+      .new_write_batch(crate::option::WriteBatchOptions::default())
+      .unwrap();
+    wb.put(util::rand_kv::get_test_key(5), Bytes::from("txn value"))
+      .unwrap();
+    wb.commit().unwrap();
+    expected.push((
+      util::rand_kv::get_test_key(5).to_vec(),
+      LogRecordType::Normal,
+      b"txn value".to_vec(),
+      true,
+    ));
+    expected.push((
+      b"txn-fin".to_vec(),
+      LogRecordType::TxnFinished,
+      Vec::new(),
+      true,
+    ));
+    assert!(!engine.old_data_files.read().is_empty());
+
+    let records = engine
+      .scan_records_from(0, 0)
+      .collect::<Result<Vec<_>>>()
+      .unwrap();
+    let actual = records
+      .iter()
+      .map(|(_, record)| {
+        let (key, seq_no) = parse_log_record_key(record.key().to_vec()).unwrap();
+        (
+          key,
+          record.rec_type(),
+          record.value().to_vec(),
+          seq_no != crate::batch::NON_TXN_SEQ_NO,
+        )
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(expected, actual);
+
+    // resume right after a record, positions are contiguous across files
+    let (pos, _) = &records[10];
+    let rest = engine
+      .scan_records_from(pos.file_id(), pos.offset() + pos.size() as u64)
+      .collect::<Result<Vec<_>>>()
+      .unwrap();
+    assert_eq!(records.len() - 11, rest.len());
+    for ((pos, _), (rest_pos, _)) in records[11..].iter().zip(rest.iter()) {
+      assert_eq!(pos, rest_pos);
+    }
+
+    let mut missing = engine.scan_records_from(1000, 0);
+    assert_eq!(
+      Errors::DataFileNotFound,
+      missing.next().unwrap().err().unwrap()
+    );
+    assert!(missing.next().is_none());
+
+    // delete tested files
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+  }
+
+  #[test]
+  fn test_scan_all_records_include_deleted() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-scan-all-records");
+    opt.data_file_size = 256;
+    opt.max_key_size = 64;
+    opt.max_value_size = 128;
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+    let key = |i| util::rand_kv::get_test_key(i);
+    for i in 0..5 {
+      engine
+        .put(key(i), util::rand_kv::get_test_value(i))
+        .unwrap();
+    }
+    engine.put(key(1), Bytes::from("new value")).unwrap();
+    engine.delete(key(2)).unwrap();
+    engine
+      .put(Bytes::from("other"), Bytes::from("value"))
+      .unwrap();
+    assert!(!engine.old_data_files.read().is_empty());
+
+    // the live iterator no longer sees the deleted key
+    let iter = engine.iter(IteratorOptions::default()).unwrap();
+    let mut live_keys = Vec::new();
+    while let Some((k, _)) = iter.next() {
+      live_keys.push(k);
+    }
+    assert!(!live_keys.contains(&key(2)));
+    std::mem::drop(iter);
+
+    let scan = |include_deleted| {
+      let mut iter = engine.scan_all_records(IteratorOptions {
+        prefix: b"key-".to_vec(),
+        include_deleted,
+        ..Default::default()
+      });
+      let mut records = Vec::new();
+      while let Some(record) = iter.next().unwrap() {
+        records.push((record.key, record.value, record.deleted));
+      }
+      records
+    };
+    let mut expected = (0..5)
+      .map(|i| (key(i), util::rand_kv::get_test_value(i), false))
+      .collect::<Vec<_>>();
+    expected.push((key(1), Bytes::from("new value"), false));
+    assert_eq!(expected, scan(false));
+
+    // the tombstone is surfaced in write order
+    expected.push((key(2), Bytes::new(), true));
+    assert_eq!(expected, scan(true));
+
+    // delete tested files
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+  }
+
+  #[test]
+  fn test_scan_write_order() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-scan-write-order");
+    opt.data_file_size = 256;
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+    let key = |i| util::rand_kv::get_test_key(i);
+    for i in [5, 2, 9, 1, 7] {
+      engine
+        .put(key(i), util::rand_kv::get_test_value(i))
+        .unwrap();
+    }
+    engine.put(key(2), Bytes::from("new value")).unwrap();
+    engine.delete(key(9)).unwrap();
+    let wb = engine
+      .new_write_batch(crate::option::WriteBatchOptions::default())
+      .unwrap();
+    wb.put(key(3), util::rand_kv::get_test_value(3)).unwrap();
+    wb.commit().unwrap();
+    engine.put(key(5), Bytes::from("newer value")).unwrap();
+    assert!(!engine.old_data_files.read().is_empty());
+
+    let mut iter = engine.scan_write_order();
+    let mut exported = Vec::new();
+    while let Some(kv) = iter.next().unwrap() {
+      exported.push(kv);
+    }
+    assert_eq!(
+      vec![
+        (key(5), Bytes::from("newer value")),
+        (key(2), Bytes::from("new value")),
+        (key(1), util::rand_kv::get_test_value(1)),
+        (key(7), util::rand_kv::get_test_value(7)),
+        (key(3), util::rand_kv::get_test_value(3)),
+      ],
+      exported
+    );
+
+    // delete tested files
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+  }
+
+  #[test]
+  fn test_count_range() {
+    let cases = [
+      (IndexType::BTree, FlushPolicy::Always),
+      (IndexType::SkipList, FlushPolicy::Always),
+      (IndexType::BPlusTree, FlushPolicy::Always),
+      (IndexType::BPlusTree, FlushPolicy::Manual),
+    ];
+    for (i, (index_type, flush_policy)) in cases.into_iter().enumerate() {
+      let mut opt = Options::default();
+      opt.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-iter-count-range-{}", i));
+      opt.index_type = index_type;
+      opt.bptree_flush_policy = flush_policy;
+      let engine = Engine::open(opt.clone()).expect("fail to open engine");
+      assert_eq!(0, engine.count_range(b"key-", b"key.").unwrap());
+
+      for i in 0..200 {
+        let key = Bytes::from(format!("key-{:03}", i));
+        engine.put(key, util::rand_kv::get_test_value(i)).unwrap();
+      }
+      engine.put(Bytes::from("other"), Bytes::from("x")).unwrap();
+      engine.sync().unwrap();
+      // deleted and rewritten after the index is synced
+      for i in (0..200).step_by(7) {
+        engine.delete(Bytes::from(format!("key-{:03}", i))).unwrap();
+      }
+      engine
+        .put(Bytes::from("key-042"), Bytes::from("y"))
+        .unwrap();
+
+      let keys = engine.list_keys().unwrap();
+      for (start, end) in [
+        ("key-050", "key-150"),
+        ("key-", "key."),
+        ("a", "z"),
+        ("key-100", "key-100"),
+        ("key-150", "key-050"),
+      ] {
+        let expected = keys
+          .iter()
+          .filter(|key| key.as_ref() >= start.as_bytes() && key.as_ref() < end.as_bytes())
+          .count();
+        assert_eq!(
+          expected,
+          engine
+            .count_range(start.as_bytes(), end.as_bytes())
+            .unwrap()
+        );
+      }
+      assert_eq!(
+        100 - 14,
+        engine.count_range(b"key-050", b"key-150").unwrap()
+      );
+
+      // delete tested files
+      std::mem::drop(engine);
+      std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+    }
+  }
+
+  // index whose keys can't be listed, counting falls back on listing them
+  struct UnlistableIndex {
+    inner: Box<dyn Indexer>,
+  }
+
+  impl Indexer for UnlistableIndex {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
+      self.inner.put(key, pos)
+    }
+
+    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+      self.inner.get(key)
+    }
+
+    fn delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
+      self.inner.delete(key)
+    }
+
+    fn list_keys(&self) -> Result<Vec<Bytes>> {
+      Err(Errors::FailedToReadFromDataFile)
+    }
+
+    fn contains_prefix(&self, prefix: &[u8]) -> bool {
+      self.inner.contains_prefix(prefix)
+    }
+
+    fn iterator(&self, options: IteratorOptions) -> Result<Box<dyn IndexIterator>> {
+      self.inner.iterator(options)
+    }
+  }
+
+  #[test]
+  fn test_count_range_error() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/bitkv-rs-iter-count-range-error");
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+    engine
+      .put(Bytes::from("key-000"), Bytes::from("x"))
+      .unwrap();
+
+    // keys failing to be listed aren't counted as none
+    let inner = std::mem::replace(
+      &mut *engine.index.write(),
+      Box::new(crate::index::btree::BTree::new()),
+    );
+    *engine.index.write() = Box::new(UnlistableIndex { inner });
+    assert_eq!(
+      Errors::FailedToReadFromDataFile,
+      engine.count_range(b"key-", b"key.").unwrap_err()
+    );
+
+    // delete tested files
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+  }
+
+  #[test]
+  fn test_estimate_keys() {
+    let cases = [
+      (IndexType::BTree, FlushPolicy::Always),
+      (IndexType::SkipList, FlushPolicy::Always),
+      (IndexType::BPlusTree, FlushPolicy::Always),
+      (IndexType::BPlusTree, FlushPolicy::Manual),
+    ];
+    for (i, (index_type, flush_policy)) in cases.into_iter().enumerate() {
+      let mut opt = Options::default();
+      opt.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-iter-estimate-keys-{}", i));
+      opt.index_type = index_type.clone();
+      opt.bptree_flush_policy = flush_policy;
+      let engine = Engine::open(opt.clone()).expect("fail to open engine");
+      assert_eq!(0, engine.estimate_keys().unwrap());
+
+      for i in 0..1000 {
+        engine
+          .put(
+            util::rand_kv::get_test_key(i),
+            util::rand_kv::get_test_value(i),
+          )
+          .unwrap();
+      }
+      engine.sync().unwrap();
+      for i in (0..1000).step_by(3) {
+        engine.delete(util::rand_kv::get_test_key(i)).unwrap();
+      }
+      // overwrites and deletes of missing keys don't change the count
+      engine
+        .put(util::rand_kv::get_test_key(1), Bytes::from("x"))
+        .unwrap();
+      engine.delete(Bytes::from("missing")).unwrap();
+
+      let check = |engine: &Engine| {
+        assert_eq!(
+          engine.list_keys().unwrap().len(),
+          engine.estimate_keys().unwrap()
+        );
+      };
+      check(&engine);
+
+      // counted keys are kept up to date
+      for i in 1000..1100 {
+        engine
+          .put(
+            util::rand_kv::get_test_key(i),
+            util::rand_kv::get_test_value(i),
+          )
+          .unwrap();
+      }
+      engine.delete(util::rand_kv::get_test_key(1)).unwrap();
+      check(&engine);
+
+      std::mem::drop(engine);
+      let engine = Engine::open(opt.clone()).expect("fail to open engine");
+      check(&engine);
+
+      // delete tested files
+      std::mem::drop(engine);
+      std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+    }
+  }
+
+  #[test]
+  fn test_contains_prefix() {
+    for (i, index_type) in [IndexType::BTree, IndexType::SkipList, IndexType::BPlusTree]
+      .into_iter()
+      .enumerate()
+    {
+      let mut opt = Options::default();
+      opt.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-iter-contains-prefix-{}", i));
+      opt.data_file_size = 64 * 1024 * 1024; // 64MB
+      opt.index_type = index_type;
+      let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+      // no items
+      assert!(!engine.contains_prefix(b"user:").unwrap());
+
+      let put_res1 = engine.put(Bytes::from("user:1"), util::rand_kv::get_test_value(10));
+      assert!(put_res1.is_ok());
+      let put_res2 = engine.put(Bytes::from("user:2"), util::rand_kv::get_test_value(11));
+      assert!(put_res2.is_ok());
+      let put_res3 = engine.put(Bytes::from("zoo"), util::rand_kv::get_test_value(12));
+      assert!(put_res3.is_ok());
+
+      // existing and absent namespaces
+      assert!(engine.contains_prefix(b"user:").unwrap());
+      assert!(!engine.contains_prefix(b"order:").unwrap());
+      assert!(!engine.contains_prefix(b"users").unwrap());
+
+      // prefix is itself a full key
+      assert!(engine.contains_prefix(b"user:2").unwrap());
+      assert!(engine.contains_prefix(b"zoo").unwrap());
+      assert!(!engine.contains_prefix(b"zoo1").unwrap());
+
+      // deleted keys are not counted
+      let del_res = engine.delete(Bytes::from("zoo"));
+      assert!(del_res.is_ok());
+      assert!(!engine.contains_prefix(b"zoo").unwrap());
+
+      // delete tested files
+      std::mem::drop(engine);
+      std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
+    }
+  }
 }
@@ -1,14 +1,41 @@
+#![cfg_attr(test, allow(clippy::field_reassign_with_default))]
+// without `std` only log record encoding and the in-memory BTree index are built, tests still
+// link std for the test harness
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+#[macro_use]
+mod trace;
+
 mod data;
 
+#[cfg(feature = "std")]
 mod fio;
 mod index;
+#[cfg(feature = "std")]
 mod iterator;
 
+#[cfg(feature = "async")]
+pub mod async_engine;
+#[cfg(feature = "std")]
 pub mod batch;
+#[cfg(feature = "std")]
+pub mod codec;
+#[cfg(feature = "std")]
 pub mod db;
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod db_test;
 pub mod errors;
+#[cfg(feature = "grpc-server")]
+pub mod grpc;
+#[cfg(feature = "std")]
 pub mod merge;
+#[cfg(all(test, not(feature = "std")))]
+mod no_std_test;
 pub mod option;
+#[cfg(feature = "std")]
 pub mod util;
+
+pub use data::log_record::{LogRecord, LogRecordPos, LogRecordType};
+pub use index::{btree::BTree, IndexIterator, Indexer};
@@ -1,36 +1,143 @@
 #![allow(clippy::field_reassign_with_default)]
 use std::{
+  collections::{HashMap, HashSet, VecDeque},
   fs,
   path::{Path, PathBuf},
-  sync::atomic::Ordering,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    mpsc::{self, SyncSender},
+    Arc,
+  },
+  thread::{self, JoinHandle},
+  time::SystemTime,
 };
 
-use log::error;
+use log::{error, warn};
 
 use crate::{
-  batch::{log_record_key_with_seq, parse_log_record_key, NON_TXN_SEQ_NO},
+  batch::{
+    log_record_key_with_version, parse_log_record_key, parse_log_record_key_version, NON_TXN_SEQ_NO,
+  },
   data::{
     data_file::{
-      get_data_file_name, DataFile, DATA_FILE_NAME_SUFFIX, HINT_FILE_NAME,
-      MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME,
+      get_data_file_name, DataFile, StoredValue, HINT_FILE_NAME, MERGE_CHECKPOINT_FILE_NAME,
+      MERGE_CHECKPOINT_TMP_FILE_NAME, MERGE_FINISHED_FILE_NAME, MERGE_HISTORY_FILE_NAME,
+      MERGE_HISTORY_TMP_FILE_NAME, META_FILE_NAME, META_TMP_FILE_NAME, SEQ_NO_FILE_NAME,
+      SEQ_NO_TMP_FILE_NAME,
     },
-    log_record::{decode_log_record_pos, LogRecord, LogRecordType},
+    log_record::{decode_log_record_pos, LogRecord, LogRecordPos, LogRecordType},
+  },
+  db::{
+    from_millis, meta_time_now, read_file_times, read_live_value, save_file_times, to_millis,
+    Engine, FILE_LOCK_NAME,
   },
-  db::{Engine, FILE_LOCK_NAME},
   errors::{Errors, Result},
-  option::{IOManagerType, Options},
+  index::Indexer,
+  option::{CrcScope, IOManagerType, IndexType, OpenProgress, Options, DEDUP_MIN_VALUE_SIZE},
   util,
 };
 
 const MERGE_DIR_NAME: &str = "merge";
 const MERGE_FIN_KEY: &[u8] = "merge.finished".as_bytes();
+const MERGE_CHECKPOINT_KEY: &[u8] = "merge.checkpoint".as_bytes();
+const MERGE_HISTORY_KEY: &[u8] = "merge.history".as_bytes();
+const MERGE_INITIAL_FILE_ID: u32 = 0;
+// partition directories of a parallel merge within the merge directory
+const MERGE_PARTITION_DIR_PREFIX: &str = "partition-";
+// merged log records queued per partition before the merge waits for its writer
+const MERGE_PARTITION_BOUND: usize = 1024;
+
+/// number of merge reports kept by `Engine::merge_history`
+pub const MERGE_HISTORY_LEN: usize = 16;
+
+/// Summary of a finished merge, takes effect on the next open
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeReport {
+  /// number of data files merged
+  pub files_merged: usize,
+
+  /// number of data files merged before an interruption, which the merge resumed after
+  pub files_skipped: usize,
+
+  /// number of live log records rewritten
+  pub records_kept: usize,
+
+  /// number of stale or deleted log records dropped
+  pub records_dropped: usize,
+
+  /// total size of the dropped log records
+  pub bytes_reclaimed: u64,
+
+  /// when the merge finished, None for a dry run and for reports saved without it
+  pub finished_at: Option<SystemTime>,
+}
+
+impl MergeReport {
+  // fields separated by colons, as saved in the merge history file. The finish time is in
+  // unix millis, 0 if unknown
+  fn encode(&self) -> String {
+    format!(
+      "{}:{}:{}:{}:{}:{}",
+      self.files_merged,
+      self.files_skipped,
+      self.records_kept,
+      self.records_dropped,
+      self.bytes_reclaimed,
+      self.finished_at.map(to_millis).unwrap_or_default()
+    )
+  }
+
+  fn decode(report: &str) -> Option<Self> {
+    let fields: Vec<u64> = report
+      .split(':')
+      .map(|field| field.parse().ok())
+      .collect::<Option<_>>()?;
+    // reports saved before the finish time was recorded have one field less
+    let (fields, finished_at) = match fields.len() {
+      6 => (&fields[..5], fields[5]),
+      _ => (&fields[..], 0),
+    };
+    let [files_merged, files_skipped, records_kept, records_dropped, bytes_reclaimed] = fields[..]
+    else {
+      return None;
+    };
+    Some(Self {
+      files_merged: files_merged as usize,
+      files_skipped: files_skipped as usize,
+      records_kept: records_kept as usize,
+      records_dropped: records_dropped as usize,
+      bytes_reclaimed,
+      finished_at: match finished_at {
+        0 => None,
+        millis => Some(from_millis(millis)),
+      },
+    })
+  }
+}
 
 impl Engine {
   /// merge data directories, produce valid data and create hint file
-  pub fn merge(&self) -> Result<()> {
+  ///
+  /// Transactions stay atomic across a merge: a record is only kept if the index points to it,
+  /// and records of a write batch reach the index only after its finish record is written, so
+  /// merged records are always part of committed transactions and are rewritten as non txn
+  /// records. The merge directory is only applied on open once the merge finished file exists,
+  /// a crash before that leaves it aside and the original data files are loaded as usual.
+  ///
+  /// A merge without partitions checkpoints its progress after each data file. When the next
+  /// merge starts with the same data files, it resumes after the last checkpoint instead of
+  /// merging them again. Keys of the skipped files may have been deleted since, so the resumed
+  /// merge keeps tombstones of keys missing from the index and writes no hint file, which
+  /// can't hold them. Any other merge directory left behind is discarded.
+  pub fn merge(&self) -> Result<MergeReport> {
+    let span = span!(INFO, "merge"; files_merged, records_kept, records_dropped, bytes_reclaimed);
+    if self.is_read_only() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+
     // if engine is empty, just return
     if self.is_engine_empty() {
-      return Ok(());
+      return Ok(MergeReport::default());
     }
 
     // if merge is running, just return
@@ -38,6 +145,8 @@ impl Engine {
     if lock.is_none() {
       return Err(Errors::MergeInProgress);
     }
+    // a cancel issued before this merge started must not affect it
+    self.merge_cancelled.store(false, Ordering::SeqCst);
 
     // determine if the merge is necessary
     let reclaim_size = self.reclaim_size.load(Ordering::SeqCst);
@@ -47,92 +156,324 @@ impl Engine {
       return Err(Errors::MergeThresholdUnreached);
     }
 
-    let available_space = util::file::available_disk_space();
+    let available_space = util::file::available_disk_space(&self.options.dir_path);
     if total_size - reclaim_size as u64 >= available_space {
       return Err(Errors::MergeNoEnoughSpace);
     }
 
     let merge_path = get_merge_path(&self.options.dir_path);
 
-    // if dir exists, remove it
-    if merge_path.is_dir() {
-      fs::remove_dir_all(merge_path.clone()).unwrap();
+    // Retrieve all data files for merging, a write batch must not straddle the rotation,
+    // otherwise its records are dropped by the merge while its finish record is kept
+    let commit_lock = self.batch_commit_lock.lock();
+    let partitions = match self.options.merge_min_age.is_zero() {
+      true => self.options.merge_parallelism,
+      false => 1,
+    };
+    let merge_files = match self.options.merge_min_age.is_zero() {
+      true => self.rotate_merge_files(partitions - 1)?,
+      false => self.cold_merge_files()?,
+    };
+    drop(commit_lock);
+
+    // resume an interrupted merge of the same first data files, or start over
+    let merge_file_ids: Vec<u32> = merge_files.iter().map(|f| f.get_file_id()).collect();
+    let checkpoint = match partitions {
+      1 => read_merge_checkpoint(&merge_path)
+        .filter(|checkpoint| merge_file_ids.starts_with(&checkpoint.file_ids)),
+      _ => None,
+    };
+    if checkpoint.is_none() {
+      // if dir exists, remove it
+      if merge_path.is_dir() {
+        fs::remove_dir_all(merge_path.clone()).map_err(|e| {
+          error!("fail to remove merge dir: {}", e);
+          Errors::FailedToWriteToDataFile
+        })?;
+      }
+
+      // create merge dir
+      if let Err(e) = fs::create_dir(merge_path.clone()) {
+        error!("fail to create merge path {}", e);
+        return Err(Errors::FailedToCreateDatabaseDir);
+      }
     }
+    let resumed = checkpoint.is_some();
 
-    // create merge dir
-    if let Err(e) = fs::create_dir(merge_path.clone()) {
-      error!("fail to create merge path {}", e);
-      return Err(Errors::FailedToCreateDatabaseDir);
+    // every data file is still hot
+    if merge_files.is_empty() {
+      fs::remove_dir_all(merge_path).map_err(|_| Errors::FailedToCreateDatabaseDir)?;
+      return Ok(MergeReport::default());
     }
 
-    // Retrieve all data files for merging
-    let merge_files = self.rotate_merge_files()?;
+    // get latest unmerged file id, the rotation left a gap for the extra files written by
+    // partitions. Merged data files only take the ids below it
+    let non_merge_file_id = merge_files.last().unwrap().get_file_id() + partitions as u32;
+    let budget = MergeFileBudget::new(non_merge_file_id - MERGE_INITIAL_FILE_ID);
+    // output that doesn't fit is dropped, a later merge doesn't resume it
+    let discard_too_large = |e: Errors| {
+      if e == Errors::MergeOutputTooLarge {
+        if let Err(e) = fs::remove_dir_all(&merge_path) {
+          warn!("failed to remove merge dir: {}", e);
+        }
+      }
+      e
+    };
+
+    // open hint file, B+Tree index never reads it
+    let hint_file = match self.options.write_hint_on_merge
+      && self.index_type() != IndexType::BPlusTree
+      && !resumed
+    {
+      true => Some(DataFile::new_hint_file(&merge_path)?),
+      false => None,
+    };
+
+    // write merged records straight to data files, nothing indexes them until the next open
+    let mut merge_output = match &checkpoint {
+      Some(checkpoint) => MergeOutput::resume(&merge_path, &self.options, checkpoint, budget)
+        .map_err(discard_too_large)?,
+      None => MergeOutput::new(&merge_path, &self.options, partitions, hint_file, budget)?,
+    };
 
-    // open a new temporary database instance for merging
-    let mut merge_db_opts = Options::default();
-    merge_db_opts.dir_path = merge_path.clone();
-    merge_db_opts.data_file_size = self.options.data_file_size;
-    let merge_db = Engine::open(merge_db_opts)?;
+    let files_skipped = checkpoint.map_or(0, |checkpoint| checkpoint.file_ids.len());
+    let mut report = MergeReport {
+      files_merged: merge_files.len(),
+      files_skipped,
+      ..Default::default()
+    };
 
-    // open hint file
-    let hint_file = DataFile::new_hint_file(&merge_path)?;
+    // iterate over the data files not merged yet and rewrite valid files
+    for (i, data_file) in merge_files.iter().enumerate().skip(files_skipped) {
+      if self.merge_cancelled.swap(false, Ordering::SeqCst) {
+        // nothing is installed without the merge finished file, just drop the merge dir
+        merge_output.abort();
+        if let Err(e) = fs::remove_dir_all(&merge_path) {
+          warn!("failed to remove cancelled merge dir: {}", e);
+        }
+        return Err(Errors::MergeCancelled);
+      }
 
-    // iterate over all data files and rewrite valid files
-    for data_file in merge_files.iter() {
-      let mut offset = 0;
-      loop {
-        let (mut log_record, size) = match data_file.read_log_record(offset) {
-          Ok(result) => (result.record, result.size),
-          Err(e) => {
-            if e == Errors::ReadDataFileEOF {
-              break;
-            }
-            return Err(e);
-          }
-        };
+      for record in data_file.iter_records() {
+        let (pos, mut log_record, size) = record?;
 
         // deserialize log record and get real key
-        let (real_key, _) = parse_log_record_key(log_record.key.clone());
-        if let Some(index_pos) = self.index.get(real_key.clone()) {
-          // if file id and offset are the same, which means the record is valid
-          if index_pos.file_id == data_file.get_file_id() && index_pos.offset == offset {
-            // remove transaction sequence number
-            log_record.key = log_record_key_with_seq(real_key.clone(), NON_TXN_SEQ_NO);
-            let log_record_pos = merge_db.append_log_record(&mut log_record)?;
-            // update hint file
-            hint_file.write_hint_record(real_key.clone(), log_record_pos)?;
+        let (real_key, _, version) = parse_log_record_key_version(log_record.key.clone())?;
+        let is_valid = self.is_live_record(&real_key, &pos)
+          || (resumed
+            && log_record.rec_type == LogRecordType::Deleted
+            && self.index.read().get(real_key.clone()).is_none());
+        if is_valid {
+          // remove transaction sequence number, the version is kept
+          log_record.key = log_record_key_with_version(real_key.clone(), NON_TXN_SEQ_NO, version)?;
+          // the referenced record goes away with the merged files, the value is written again
+          // and deduplicated among the merged records
+          if log_record.rec_type == LogRecordType::ValueRef {
+            log_record.value =
+              read_live_value(&self.active_data_file, &self.old_data_files, true, &pos)?.to_vec();
+            log_record.rec_type = LogRecordType::Normal;
           }
+          merge_output
+            .append(real_key, log_record)
+            .map_err(discard_too_large)?;
+        }
+
+        if is_valid {
+          report.records_kept += 1;
+        } else {
+          report.records_dropped += 1;
+          report.bytes_reclaimed += size as u64;
         }
-        offset += size as u64;
       }
+
+      // the merged records of the data file survive an interruption from now on
+      merge_output.checkpoint(&merge_path, &merge_file_ids[..=i])?;
     }
 
     // sync all files
-    merge_db.sync()?;
-    hint_file.sync()?;
+    merge_output.finish().map_err(discard_too_large)?;
+    // resumed parts included, counted once the merge finished
+    let merged_size = merged_data_size(&merge_path, &self.options.file_suffix)?;
 
-    // get latest unmerged file id
-    let non_merge_file_id = merge_files.last().unwrap().get_file_id() + 1;
+    // along with the next version, no data file is scanned for the versions of the merged
+    // records if the hint file indexes them
     let merge_fin_file = DataFile::new_merge_fin_file(&merge_path)?;
     let merge_fin_record = LogRecord {
       key: MERGE_FIN_KEY.to_vec(),
-      value: non_merge_file_id.to_string().into_bytes(),
+      value: format!(
+        "{}:{}",
+        non_merge_file_id,
+        self.next_version.load(Ordering::SeqCst)
+      )
+      .into_bytes(),
       rec_type: LogRecordType::Normal,
     };
     let enc_record = merge_fin_record.encode();
     merge_fin_file.write(&enc_record)?;
     merge_fin_file.sync()?;
 
+    // the merged data files count towards the bytes written
+    self.add_bytes_written(merged_size);
+
+    // record the merge time. The merge finished file exists by now, the merge takes effect on
+    // the next open whether the meta file is saved or not
+    let finished_at = meta_time_now();
+    report.finished_at = Some(finished_at);
+    let mut info = self.meta.write();
+    info.last_merge_at = Some(finished_at);
+    if let Err(e) = self.save_meta(&info) {
+      warn!("failed to save last merge time: {}", e);
+    }
+    std::mem::drop(info);
+
+    // the merge is done by now, the history is only informational
+    let mut history = self.merge_history.lock();
+    if history.len() == MERGE_HISTORY_LEN {
+      history.pop_front();
+    }
+    history.push_back(report);
+    if let Err(e) = self.save_merge_history(&history) {
+      warn!("failed to save merge history: {}", e);
+    }
+    std::mem::drop(history);
+
+    record!(span, files_merged = report.files_merged);
+    record!(span, records_kept = report.records_kept);
+    record!(span, records_dropped = report.records_dropped);
+    record!(span, bytes_reclaimed = report.bytes_reclaimed);
+    event!(
+      INFO,
+      "merge finished",
+      files_merged = report.files_merged,
+      bytes_reclaimed = report.bytes_reclaimed
+    );
+    Ok(report)
+  }
+
+  /// Compute the report of a merge started now without running it: the same data files are
+  /// walked to tell live records from stale ones, but nothing is written and no file is
+  /// rotated. The merge threshold and disk space checks are skipped
+  pub fn merge_dry_run(&self) -> Result<MergeReport> {
+    if self.is_engine_empty() {
+      return Ok(MergeReport::default());
+    }
+
+    // a merge would rotate the active file, so it is walked up to its current end. Writes
+    // after that go beyond the walked range, as they would go to the new active file
+    let commit_lock = self.batch_commit_lock.lock();
+    let merge_files = match self.options.merge_min_age.is_zero() {
+      true => {
+        let active_file = self.active_data_file.read();
+        let mut file_ids: Vec<u32> = self.old_data_files.read().keys().copied().collect();
+        file_ids.push(active_file.get_file_id());
+        file_ids.sort();
+
+        let mut merge_files = Vec::new();
+        for file_id in file_ids {
+          let data_file = self.open_old_data_file(file_id, IOManagerType::StandardFileIO)?;
+          let end = match file_id == active_file.get_file_id() {
+            true => active_file.logical_size(),
+            false => u64::MAX,
+          };
+          merge_files.push((data_file, end));
+        }
+        merge_files
+      }
+      false => self
+        .cold_merge_files()?
+        .into_iter()
+        .map(|data_file| (data_file, u64::MAX))
+        .collect(),
+    };
+    drop(commit_lock);
+
+    let mut report = MergeReport {
+      files_merged: merge_files.len(),
+      ..Default::default()
+    };
+    for (data_file, end) in merge_files.iter() {
+      // stop before reading past the end, a record being appended there may be incomplete
+      let mut records = data_file.iter_records();
+      let mut offset = 0;
+      while offset < *end {
+        let (pos, log_record, size) = match records.next() {
+          Some(record) => record?,
+          None => break,
+        };
+        offset = pos.offset + size as u64;
+
+        let (real_key, _) = parse_log_record_key(log_record.key)?;
+        if self.is_live_record(&real_key, &pos) {
+          report.records_kept += 1;
+        } else {
+          report.records_dropped += 1;
+          report.bytes_reclaimed += size as u64;
+        }
+      }
+    }
+    Ok(report)
+  }
+
+  /// reports of the last `MERGE_HISTORY_LEN` merges, oldest first. Compare their reclaimed
+  /// bytes with the bytes written in between to tell whether merges keep up with the writes.
+  /// The history is kept in the database directory across restarts, merges with nothing to do
+  /// aren't part of it
+  pub fn merge_history(&self) -> Vec<MergeReport> {
+    self.merge_history.lock().iter().copied().collect()
+  }
+
+  // replace the merge history file through a temporary file, like the meta file
+  fn save_merge_history(&self, history: &VecDeque<MergeReport>) -> Result<()> {
+    let reports: Vec<String> = history.iter().map(MergeReport::encode).collect();
+    let record = LogRecord {
+      key: MERGE_HISTORY_KEY.to_vec(),
+      value: reports.join(",").into_bytes(),
+      rec_type: LogRecordType::Normal,
+    };
+
+    let dir_path = &self.options.dir_path;
+    let tmp_file_name = dir_path.join(MERGE_HISTORY_TMP_FILE_NAME);
+    if tmp_file_name.is_file() {
+      fs::remove_file(&tmp_file_name).map_err(|_| Errors::FailedToWriteToDataFile)?;
+    }
+    let history_file = DataFile::new_merge_history_tmp_file(dir_path)?;
+    history_file.write(&record.encode())?;
+    history_file.sync()?;
+    if let Err(e) = fs::rename(tmp_file_name, dir_path.join(MERGE_HISTORY_FILE_NAME)) {
+      error!("failed to rename merge history file error: {}", e);
+      return Err(Errors::FailedToWriteToDataFile);
+    }
     Ok(())
   }
 
+  // whether the index points to the log record at pos
+  fn is_live_record(&self, real_key: &[u8], pos: &LogRecordPos) -> bool {
+    match self.index.read().get(real_key.to_vec()) {
+      Some(index_pos) => index_pos.file_id == pos.file_id && index_pos.offset == pos.offset,
+      None => false,
+    }
+  }
+
+  /// cancel the running merge, it stops before the next data file and returns
+  /// `Errors::MergeCancelled`, leaving the database as it was. No-op if no merge is running
+  pub fn cancel_merge(&self) {
+    if self.merging_lock.try_lock().is_none() {
+      self.merge_cancelled.store(true, Ordering::SeqCst);
+    }
+  }
+
   fn is_engine_empty(&self) -> bool {
     let active_file = self.active_data_file.read();
     let old_files = self.old_data_files.read();
-    active_file.get_write_off() == 0 && old_files.len() == 0
+    active_file.logical_size() == 0 && old_files.is_empty()
   }
 
-  fn rotate_merge_files(&self) -> Result<Vec<DataFile>> {
+  // rotate the active file and return all data files before it. The new active file id skips
+  // reserved_ids ids, which merged data files can take
+  fn rotate_merge_files(&self, reserved_ids: usize) -> Result<Vec<DataFile>> {
+    // lock the active file first, in the same order as rotation on append
+    let mut active_file = self.active_data_file.write();
+
     // retrieve old data files id
     let mut merge_file_ids = Vec::new();
     let mut old_files = self.old_data_files.write();
@@ -141,25 +482,27 @@ impl Engine {
     }
 
     // create a new active file for writing
-    let mut active_file = self.active_data_file.write();
 
     // sync active file
     active_file.sync()?;
     let active_file_id = active_file.get_file_id();
+    let reserved_ids = reserved_ids as u32;
+    let new_active_file_id = self
+      .next_file_id
+      .fetch_add(reserved_ids + 1, Ordering::SeqCst)
+      + reserved_ids;
     let new_active_file = DataFile::new(
       &self.options.dir_path,
-      active_file_id + 1,
+      new_active_file_id,
+      &self.options.file_suffix,
       IOManagerType::StandardFileIO,
     )?;
     *active_file = new_active_file;
+    self.record_file_created(new_active_file_id);
 
     // load current active data file to old data files
-    let old_file = DataFile::new(
-      &self.options.dir_path,
-      active_file_id,
-      IOManagerType::StandardFileIO,
-    )?;
-    old_files.insert(active_file_id, old_file);
+    let old_file = self.open_old_data_file(active_file_id, self.old_file_io_type())?;
+    old_files.insert(active_file_id, Arc::new(old_file));
 
     // load id to merge file ids list
     merge_file_ids.push(active_file_id);
@@ -170,19 +513,46 @@ impl Engine {
     // retrieve data files
     let mut merge_files = Vec::new();
     for file_id in merge_file_ids {
-      let data_file = DataFile::new(
-        &self.options.dir_path,
-        file_id,
-        IOManagerType::StandardFileIO,
-      )?;
+      let data_file = self.open_old_data_file(file_id, IOManagerType::StandardFileIO)?;
       merge_files.push(data_file);
     }
 
     Ok(merge_files)
   }
 
+  // old data files created at least `merge_min_age` ago, the active file is never included.
+  // Only the oldest ones up to the first hot file are taken, since a merge replaces every data
+  // file below the first unmerged file id
+  fn cold_merge_files(&self) -> Result<Vec<DataFile>> {
+    let old_files = self.old_data_files.read();
+    let mut file_ids: Vec<u32> = old_files.keys().copied().collect();
+    file_ids.sort();
+
+    let file_times = self.file_times.lock();
+    let mut merge_files = Vec::new();
+    for file_id in file_ids {
+      // every data file gets a time on open or when it's created
+      let Some(created_at) = file_times.get(&file_id) else {
+        break;
+      };
+      let age = SystemTime::now()
+        .duration_since(*created_at)
+        .unwrap_or_default();
+      if age < self.options.merge_min_age {
+        break;
+      }
+      merge_files.push(self.open_old_data_file(file_id, IOManagerType::StandardFileIO)?);
+    }
+    Ok(merge_files)
+  }
+
   /// load index from hint file
-  pub(crate) fn load_index_from_hint_file(&self) -> Result<()> {
+  pub(crate) fn load_index_from_hint_file(
+    &self,
+    index: &dyn Indexer,
+    file_ids: &[u32],
+    progress: &mut OpenProgress,
+  ) -> Result<()> {
     let hint_file_name = self.options.dir_path.join(HINT_FILE_NAME);
 
     // if hint file doesn't exist, just return
@@ -191,29 +561,588 @@ impl Engine {
     }
 
     let hint_file = DataFile::new_hint_file(&self.options.dir_path)?;
-    let mut offset = 0;
-    loop {
-      let (log_record, size) = match hint_file.read_log_record(offset) {
-        Ok(result) => (result.record, result.size),
-        Err(e) => {
-          if e == Errors::ReadDataFileEOF {
-            break;
-          }
-          return Err(e);
-        }
-      };
+    let file_ids: HashSet<u32> = file_ids.iter().copied().collect();
+    let mut records = Vec::new();
+    let mut is_valid = true;
+    for record in hint_file.iter_records() {
+      let (_, log_record, _) = record?;
 
       // deserialize log record and get real key
       let log_record_pos = decode_log_record_pos(log_record.value);
-      self.index.put(log_record.key, log_record_pos);
+      // a position in a missing data file means the hint doesn't match the data
+      if !file_ids.contains(&log_record_pos.file_id) {
+        is_valid = false;
+        break;
+      }
+      records.push((log_record.key, log_record_pos));
+    }
+
+    progress.files_processed += 1;
+    if !is_valid {
+      // without the hint file, all data files are loaded to rebuild the index
+      warn!("hint file references missing data files, rebuilding index from data files");
+      std::mem::drop(hint_file);
+      if let Err(e) = fs::remove_file(hint_file_name) {
+        error!("failed to remove hint file: {}", e);
+        return Err(Errors::DatabaseDirectoryCorrupted);
+      }
+      self.report_open_progress(*progress);
+      return Ok(());
+    }
+
+    // partitions hash the keys apart, append only databases keep their index in a sorted
+    // vector which takes keys out of order one shift at a time. The stable sort keeps the
+    // last position of a key last
+    if self.options.append_only {
+      records.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
 
-      offset += size as u64;
+    event!(DEBUG, "loaded hint file", records = records.len());
+    for (key, log_record_pos) in records {
+      index.put(key, log_record_pos)?;
+      progress.records_loaded += 1;
     }
+    self.report_open_progress(*progress);
 
     Ok(())
   }
 }
 
+// appends merged log records to data files of the merge directory. Unlike a database opened
+// there, it keeps no index of the merged keys, the hint file is their only record
+struct MergeWriter {
+  dir_path: PathBuf,
+  file_suffix: String,
+  data_file_size: u64,
+  crc_scope: CrcScope,
+  active_file: DataFile,
+  // positions of the written records by crc32 of their value, None unless values are
+  // deduplicated
+  values: Option<HashMap<u32, Vec<LogRecordPos>>>,
+  budget: MergeFileBudget,
+}
+
+impl MergeWriter {
+  fn new(dir_path: &Path, options: &Options, budget: MergeFileBudget) -> Result<Self> {
+    let active_file = DataFile::new(
+      dir_path,
+      MERGE_INITIAL_FILE_ID,
+      &options.file_suffix,
+      IOManagerType::StandardFileIO,
+    )?;
+    Ok(Self {
+      dir_path: dir_path.to_path_buf(),
+      file_suffix: options.file_suffix.clone(),
+      data_file_size: options.data_file_size,
+      crc_scope: options.crc_scope,
+      active_file,
+      values: options.dedup_values.then(HashMap::new),
+      budget,
+    })
+  }
+
+  // reopen the data files of an interrupted merge, records written after the checkpoint are
+  // cut off
+  fn resume(
+    dir_path: &Path,
+    options: &Options,
+    checkpoint: &MergeCheckpoint,
+    budget: MergeFileBudget,
+  ) -> Result<Self> {
+    // data files started after the checkpoint
+    let mut next_fid = checkpoint.file_id + 1;
+    loop {
+      let file_name = get_data_file_name(dir_path, next_fid, &options.file_suffix);
+      if !file_name.is_file() {
+        break;
+      }
+      fs::remove_file(file_name).map_err(|_| Errors::FailedToWriteToDataFile)?;
+      next_fid += 1;
+    }
+
+    let file_name = get_data_file_name(dir_path, checkpoint.file_id, &options.file_suffix);
+    let truncated = fs::OpenOptions::new()
+      .create(true)
+      .write(true)
+      .truncate(false)
+      .open(file_name)
+      .and_then(|file| file.set_len(checkpoint.write_off));
+    if let Err(e) = truncated {
+      error!("failed to truncate merge data file error: {}", e);
+      return Err(Errors::FailedToWriteToDataFile);
+    }
+    let active_file = DataFile::new(
+      dir_path,
+      checkpoint.file_id,
+      &options.file_suffix,
+      IOManagerType::StandardFileIO,
+    )?;
+    active_file.set_write_off(checkpoint.write_off);
+    let writer = Self {
+      dir_path: dir_path.to_path_buf(),
+      file_suffix: options.file_suffix.clone(),
+      data_file_size: options.data_file_size,
+      crc_scope: options.crc_scope,
+      active_file,
+      values: options.dedup_values.then(HashMap::new),
+      budget,
+    };
+    writer.budget.take(writer.file_num())?;
+    Ok(writer)
+  }
+
+  // append log record, a value already written is referenced instead when deduplicating
+  fn append(&mut self, log_record: &LogRecord) -> Result<LogRecordPos> {
+    let hash = match self.values.is_some()
+      && log_record.rec_type == LogRecordType::Normal
+      && log_record.value.len() >= DEDUP_MIN_VALUE_SIZE
+    {
+      true => Some(crc32fast::hash(&log_record.value)),
+      false => None,
+    };
+    let Some(hash) = hash else {
+      return self.write(log_record);
+    };
+
+    if let Some(value_pos) = self.find_value(hash, &log_record.value)? {
+      let ref_record = LogRecord {
+        key: log_record.key.clone(),
+        value: value_pos.encode(),
+        rec_type: LogRecordType::ValueRef,
+      };
+      return self.write(&ref_record);
+    }
+    let log_record_pos = self.write(log_record)?;
+    if let Some(values) = self.values.as_mut() {
+      values.entry(hash).or_default().push(log_record_pos);
+    }
+    Ok(log_record_pos)
+  }
+
+  // position of a written record holding value, among those whose value has the crc32 hash
+  fn find_value(&self, hash: u32, value: &[u8]) -> Result<Option<LogRecordPos>> {
+    let Some(candidates) = self.values.as_ref().and_then(|values| values.get(&hash)) else {
+      return Ok(None);
+    };
+    for pos in candidates {
+      let stored = match pos.file_id == self.active_file.get_file_id() {
+        true => self.active_file.read_value(pos.offset, pos.size, true)?,
+        false => DataFile::new(
+          &self.dir_path,
+          pos.file_id,
+          &self.file_suffix,
+          IOManagerType::StandardFileIO,
+        )?
+        .read_value(pos.offset, pos.size, true)?,
+      };
+      if matches!(stored, StoredValue::Inline(stored_value) if stored_value.as_ref() == value) {
+        return Ok(Some(*pos));
+      }
+    }
+    Ok(None)
+  }
+
+  // append log record, moving on to a new data file once the current one is full
+  fn write(&mut self, log_record: &LogRecord) -> Result<LogRecordPos> {
+    log_record.check_size()?;
+    let enc_record = log_record.encode_with_crc_scope(self.crc_scope);
+
+    // the record starts the first data file, or the next one once the current one is full
+    let full = self.active_file.logical_size() + enc_record.len() as u64 > self.data_file_size;
+    self
+      .budget
+      .take(u32::from(self.file_num() == 0) + u32::from(full))?;
+    if full {
+      self.active_file.sync()?;
+      let next_fid = self.active_file.get_file_id() + 1;
+      self.active_file = DataFile::new(
+        &self.dir_path,
+        next_fid,
+        &self.file_suffix,
+        IOManagerType::StandardFileIO,
+      )?;
+    }
+
+    let write_off = self.active_file.get_write_off();
+    self.active_file.write(&enc_record)?;
+    Ok(LogRecordPos {
+      file_id: self.active_file.get_file_id(),
+      offset: write_off,
+      size: enc_record.len() as u32,
+    })
+  }
+
+  // earlier data files are synced when full, only the current one may have unsynced writes
+  fn sync(&self) -> Result<()> {
+    self.active_file.sync()
+  }
+
+  // number of data files written to, the first one stays empty until a record is appended
+  fn file_num(&self) -> u32 {
+    let file_id = self.active_file.get_file_id();
+    match file_id == MERGE_INITIAL_FILE_ID && self.active_file.logical_size() == 0 {
+      true => 0,
+      false => file_id - MERGE_INITIAL_FILE_ID + 1,
+    }
+  }
+}
+
+// data files a merge may write, shared by the writers of its partitions so the output is
+// refused as soon as it no longer fits
+#[derive(Clone)]
+struct MergeFileBudget {
+  used: Arc<AtomicU32>,
+  max: u32,
+}
+
+impl MergeFileBudget {
+  fn new(max: u32) -> Self {
+    Self {
+      used: Arc::new(AtomicU32::new(0)),
+      max,
+    }
+  }
+
+  // take n more data files, `Errors::MergeOutputTooLarge` if they go past the max
+  fn take(&self, n: u32) -> Result<()> {
+    if n == 0 {
+      return Ok(());
+    }
+    match self.used.fetch_add(n, Ordering::SeqCst) + n <= self.max {
+      true => Ok(()),
+      false => Err(Errors::MergeOutputTooLarge),
+    }
+  }
+}
+
+// where merged log records go: one merge writer, or one per partition of the keys each
+// running on its own thread and writing to its own directory. Partition data files are moved
+// into the merge directory once all are done, numbered one partition after another
+enum MergeOutput {
+  Single {
+    writer: MergeWriter,
+    hint_file: Option<DataFile>,
+  },
+  Partitioned {
+    merge_path: PathBuf,
+    file_suffix: String,
+    partitions: Vec<MergePartition>,
+    hint_file: Option<DataFile>,
+  },
+}
+
+// a partition writes a hint file of its own, positions in its data files are rebased when
+// they are moved into the merge directory
+struct MergePartition {
+  dir_path: PathBuf,
+  sender: SyncSender<(Vec<u8>, LogRecord)>,
+  handle: JoinHandle<Result<u32>>,
+}
+
+impl MergeOutput {
+  fn new(
+    merge_path: &Path,
+    options: &Options,
+    partitions: usize,
+    hint_file: Option<DataFile>,
+    budget: MergeFileBudget,
+  ) -> Result<Self> {
+    if partitions <= 1 {
+      return Ok(Self::Single {
+        writer: MergeWriter::new(merge_path, options, budget)?,
+        hint_file,
+      });
+    }
+
+    let mut merge_partitions = Vec::with_capacity(partitions);
+    for i in 0..partitions {
+      let dir_path = merge_path.join(format!("{}{}", MERGE_PARTITION_DIR_PREFIX, i));
+      if let Err(e) = fs::create_dir(&dir_path) {
+        error!("fail to create merge partition path {}", e);
+        return Err(Errors::FailedToCreateDatabaseDir);
+      }
+      let mut writer = MergeWriter::new(&dir_path, options, budget.clone())?;
+      let partition_hint_file = match hint_file.is_some() {
+        true => Some(DataFile::new_hint_file(&dir_path)?),
+        false => None,
+      };
+      let (sender, receiver) = mpsc::sync_channel::<(Vec<u8>, LogRecord)>(MERGE_PARTITION_BOUND);
+      let handle = thread::spawn(move || {
+        for (real_key, log_record) in receiver {
+          let log_record_pos = writer.append(&log_record)?;
+          if let Some(hint_file) = partition_hint_file.as_ref() {
+            hint_file.write_hint_record(real_key, log_record_pos)?;
+          }
+        }
+        writer.sync()?;
+        if let Some(hint_file) = partition_hint_file.as_ref() {
+          hint_file.sync()?;
+        }
+        Ok(writer.file_num())
+      });
+      merge_partitions.push(MergePartition {
+        dir_path,
+        sender,
+        handle,
+      });
+    }
+    Ok(Self::Partitioned {
+      merge_path: merge_path.to_path_buf(),
+      file_suffix: options.file_suffix.clone(),
+      partitions: merge_partitions,
+      hint_file,
+    })
+  }
+
+  // pick up the output of an interrupted merge at its checkpoint. Its hint file is dropped,
+  // hints can't delete the keys whose tombstones a resumed merge keeps
+  fn resume(
+    merge_path: &Path,
+    options: &Options,
+    checkpoint: &MergeCheckpoint,
+    budget: MergeFileBudget,
+  ) -> Result<Self> {
+    let hint_file_name = merge_path.join(HINT_FILE_NAME);
+    if hint_file_name.is_file() {
+      fs::remove_file(hint_file_name).map_err(|_| Errors::FailedToWriteToDataFile)?;
+    }
+    Ok(Self::Single {
+      writer: MergeWriter::resume(merge_path, options, checkpoint, budget)?,
+      hint_file: None,
+    })
+  }
+
+  // sync everything written and record that the live records of file_ids are merged.
+  // Partitions write in the background, a partitioned merge has no checkpoints
+  fn checkpoint(&self, merge_path: &Path, file_ids: &[u32]) -> Result<()> {
+    let Self::Single { writer, hint_file } = self else {
+      return Ok(());
+    };
+    writer.sync()?;
+    if let Some(hint_file) = hint_file.as_ref() {
+      hint_file.sync()?;
+    }
+    let checkpoint = MergeCheckpoint {
+      file_ids: file_ids.to_vec(),
+      file_id: writer.active_file.get_file_id(),
+      write_off: writer.active_file.logical_size(),
+    };
+    write_merge_checkpoint(merge_path, &checkpoint)
+  }
+
+  // write a live log record whose key no longer has a seq_no
+  fn append(&mut self, real_key: Vec<u8>, log_record: LogRecord) -> Result<()> {
+    match self {
+      Self::Single { writer, hint_file } => {
+        let log_record_pos = writer.append(&log_record)?;
+        if let Some(hint_file) = hint_file.as_ref() {
+          hint_file.write_hint_record(real_key, log_record_pos)?;
+        }
+        Ok(())
+      }
+      Self::Partitioned { partitions, .. } => {
+        let partition = &partitions[crc32fast::hash(&real_key) as usize % partitions.len()];
+        if partition.sender.send((real_key, log_record)).is_ok() {
+          return Ok(());
+        }
+        // the partition stopped on an error, which joining returns
+        let partitions = std::mem::take(partitions);
+        join_merge_partitions(partitions).and(Err(Errors::FailedToWriteToDataFile))
+      }
+    }
+  }
+
+  // sync everything written and move partition data and hint files into place
+  fn finish(self) -> Result<()> {
+    match self {
+      Self::Single { writer, hint_file } => {
+        writer.sync()?;
+        if let Some(hint_file) = hint_file.as_ref() {
+          hint_file.sync()?;
+        }
+        Ok(())
+      }
+      Self::Partitioned {
+        merge_path,
+        file_suffix,
+        partitions,
+        hint_file,
+      } => {
+        let dir_paths: Vec<PathBuf> = partitions.iter().map(|p| p.dir_path.clone()).collect();
+        let results = join_merge_partitions(partitions)?;
+
+        let mut next_file_id = MERGE_INITIAL_FILE_ID;
+        for (dir_path, file_num) in dir_paths.iter().zip(results) {
+          for i in 0..file_num {
+            let from = get_data_file_name(dir_path, MERGE_INITIAL_FILE_ID + i, &file_suffix);
+            let to = get_data_file_name(&merge_path, next_file_id + i, &file_suffix);
+            fs::rename(from, to).map_err(|_| Errors::FailedToWriteToDataFile)?;
+          }
+          if let Some(hint_file) = hint_file.as_ref() {
+            let partition_hint_file = DataFile::new_hint_file(dir_path)?;
+            for record in partition_hint_file.iter_records() {
+              let (_, hint_record, _) = record?;
+              let mut log_record_pos = decode_log_record_pos(hint_record.value);
+              log_record_pos.file_id += next_file_id - MERGE_INITIAL_FILE_ID;
+              hint_file.write_hint_record(hint_record.key, log_record_pos)?;
+            }
+          }
+          fs::remove_dir_all(dir_path).map_err(|_| Errors::FailedToWriteToDataFile)?;
+          next_file_id += file_num;
+        }
+        if let Some(hint_file) = hint_file.as_ref() {
+          hint_file.sync()?;
+        }
+        Ok(())
+      }
+    }
+  }
+
+  // stop writing, partition threads are waited for so nothing writes to the merge dir after
+  fn abort(self) {
+    if let Self::Partitioned { partitions, .. } = self {
+      let _ = join_merge_partitions(partitions);
+    }
+  }
+}
+
+// close all partitions and wait for them, return their number of data files in partition
+// order, or the first error
+fn join_merge_partitions(partitions: Vec<MergePartition>) -> Result<Vec<u32>> {
+  let handles: Vec<_> = partitions
+    .into_iter()
+    .map(|partition| {
+      drop(partition.sender);
+      partition.handle
+    })
+    .collect();
+  let mut results = Vec::with_capacity(handles.len());
+  let mut first_err = None;
+  for handle in handles {
+    match handle.join().expect("merge partition thread panicked") {
+      Ok(result) => results.push(result),
+      Err(e) => {
+        first_err.get_or_insert(e);
+      }
+    }
+  }
+  match first_err {
+    Some(e) => Err(e),
+    None => Ok(results),
+  }
+}
+
+// merge history saved in the database directory, empty if there is none. An unreadable
+// history is dropped, it's only informational
+pub(crate) fn load_merge_history(dir_path: &Path) -> VecDeque<MergeReport> {
+  if !dir_path.join(MERGE_HISTORY_FILE_NAME).is_file() {
+    return VecDeque::new();
+  }
+  let history = DataFile::new_merge_history_file(dir_path)
+    .and_then(|history_file| history_file.read_log_record(0))
+    .ok()
+    .and_then(|history_record| String::from_utf8(history_record.record.value).ok());
+  let Some(history) = history else {
+    warn!("failed to read merge history, starting a new one");
+    return VecDeque::new();
+  };
+
+  let reports = history
+    .split(',')
+    .filter(|report| !report.is_empty())
+    .map(MergeReport::decode)
+    .collect::<Option<VecDeque<_>>>();
+  reports.unwrap_or_else(|| {
+    warn!("failed to parse merge history, starting a new one");
+    VecDeque::new()
+  })
+}
+
+// progress of a merge, rewritten in the merge directory after each merged data file
+struct MergeCheckpoint {
+  file_ids: Vec<u32>, // data files whose live records are all merged, in merge order
+  file_id: u32,       // merge data file written last
+  write_off: u64,     // end of the merged records in that file
+}
+
+impl MergeCheckpoint {
+  fn encode(&self) -> Vec<u8> {
+    let file_ids: Vec<String> = self.file_ids.iter().map(|fid| fid.to_string()).collect();
+    format!("{}:{}:{}", self.file_id, self.write_off, file_ids.join(",")).into_bytes()
+  }
+
+  fn decode(value: &[u8]) -> Option<Self> {
+    let value = std::str::from_utf8(value).ok()?;
+    let mut parts = value.splitn(3, ':');
+    let file_id = parts.next()?.parse().ok()?;
+    let write_off = parts.next()?.parse().ok()?;
+    let file_ids = parts
+      .next()?
+      .split(',')
+      .map(|fid| fid.parse().ok())
+      .collect::<Option<Vec<u32>>>()?;
+    Some(Self {
+      file_ids,
+      file_id,
+      write_off,
+    })
+  }
+}
+
+// checkpoint of an interrupted merge, None if the merge dir has none or finished after all
+fn read_merge_checkpoint(merge_path: &Path) -> Option<MergeCheckpoint> {
+  if !merge_path.join(MERGE_CHECKPOINT_FILE_NAME).is_file()
+    || merge_path.join(MERGE_FINISHED_FILE_NAME).is_file()
+  {
+    return None;
+  }
+  let checkpoint_file = DataFile::new_merge_checkpoint_file(merge_path).ok()?;
+  let checkpoint_record = checkpoint_file.read_log_record(0).ok()?;
+  MergeCheckpoint::decode(&checkpoint_record.record.value)
+}
+
+// replace the checkpoint of the merge through a temporary file, so a crash leaves either the old or
+// the new one
+fn write_merge_checkpoint(merge_path: &Path, checkpoint: &MergeCheckpoint) -> Result<()> {
+  let record = LogRecord {
+    key: MERGE_CHECKPOINT_KEY.to_vec(),
+    value: checkpoint.encode(),
+    rec_type: LogRecordType::Normal,
+  };
+
+  let tmp_file_name = merge_path.join(MERGE_CHECKPOINT_TMP_FILE_NAME);
+  if tmp_file_name.is_file() {
+    fs::remove_file(&tmp_file_name).map_err(|_| Errors::FailedToWriteToDataFile)?;
+  }
+  let checkpoint_file = DataFile::new_merge_checkpoint_tmp_file(merge_path)?;
+  checkpoint_file.write(&record.encode())?;
+  checkpoint_file.sync()?;
+  if let Err(e) = fs::rename(tmp_file_name, merge_path.join(MERGE_CHECKPOINT_FILE_NAME)) {
+    error!("failed to rename merge checkpoint file error: {}", e);
+    return Err(Errors::FailedToWriteToDataFile);
+  }
+  Ok(())
+}
+
+// total size of the data files in the merge dir
+fn merged_data_size(merge_path: &Path, file_suffix: &str) -> Result<u64> {
+  let dir = fs::read_dir(merge_path).map_err(|e| {
+    error!("fail to read merge dir: {}", e);
+    Errors::FailedToReadDatabaseDir
+  })?;
+  let mut size = 0;
+  for file in dir.flatten() {
+    if !file.file_name().to_string_lossy().ends_with(file_suffix) {
+      continue;
+    }
+    size += file
+      .metadata()
+      .map_err(|_| Errors::FailedToReadDatabaseDir)?
+      .len();
+  }
+  Ok(size)
+}
+
 fn get_merge_path<P>(dir_path: P) -> PathBuf
 where
   P: AsRef<Path>,
@@ -224,8 +1153,23 @@ where
   parent.to_path_buf().join(merge_name)
 }
 
+// parse the value of the merge finished record, `non_merge_file_id:next_version`. Merges before
+// versions wrote the file id alone, next version is 0 then
+pub(crate) fn parse_merge_fin_value(value: Vec<u8>) -> Result<(u32, u64)> {
+  let v = String::from_utf8(value).map_err(|_| Errors::DatabaseDirectoryCorrupted)?;
+  let parsed = match v.split(':').collect::<Vec<_>>().as_slice() {
+    [file_id] => file_id.parse::<u32>().ok().map(|file_id| (file_id, 0)),
+    [file_id, next_version] => file_id
+      .parse::<u32>()
+      .ok()
+      .zip(next_version.parse::<u64>().ok()),
+    _ => None,
+  };
+  parsed.ok_or(Errors::DatabaseDirectoryCorrupted)
+}
+
 // load merge files
-pub(crate) fn load_merge_files<P>(dir_path: P) -> Result<()>
+pub(crate) fn load_merge_files<P>(dir_path: P, file_suffix: &str) -> Result<()>
 where
   P: AsRef<Path>,
 {
@@ -254,7 +1198,7 @@ where
       merge_finished = true;
     }
 
-    if file_name.ends_with(SEQ_NO_FILE_NAME) {
+    if file_name.ends_with(SEQ_NO_FILE_NAME) || file_name.ends_with(SEQ_NO_TMP_FILE_NAME) {
       continue;
     }
 
@@ -262,54 +1206,104 @@ where
       continue;
     }
 
-    // data file volume is 0 and ends with .data, just skip
-    let meta = file.metadata().unwrap();
-    if file_name.ends_with(DATA_FILE_NAME_SUFFIX) && meta.len() == 0 {
+    // progress of the merge, done with once it finished
+    if file_name.ends_with(MERGE_CHECKPOINT_FILE_NAME)
+      || file_name.ends_with(MERGE_CHECKPOINT_TMP_FILE_NAME)
+    {
+      continue;
+    }
+
+    // metadata of the temporary merge database, the original one is kept
+    if file_name.ends_with(META_FILE_NAME) || file_name.ends_with(META_TMP_FILE_NAME) {
+      continue;
+    }
+
+    // data file volume is 0 and ends with the data file suffix, just skip
+    let meta = file.metadata().map_err(|e| {
+      error!("fail to read merge file metadata: {}", e);
+      Errors::FailedToReadDatabaseDir
+    })?;
+    if file_name.ends_with(file_suffix) && meta.len() == 0 {
       continue;
     }
 
     merge_file_names.push(file.file_name());
   }
 
-  // if merge doesn't finish, remove merge dir and return
+  // if merge doesn't finish, remove merge dir and return, unless the next merge can resume it
   if !merge_finished {
-    fs::remove_dir_all(merge_path.clone()).unwrap();
+    if !merge_path.join(MERGE_CHECKPOINT_FILE_NAME).is_file() {
+      fs::remove_dir_all(merge_path.clone()).map_err(|e| {
+        error!("fail to remove unfinished merge dir: {}", e);
+        Errors::FailedToWriteToDataFile
+      })?;
+    }
     return Ok(());
   }
 
   // open merge finished files, get the latest unmerged file id
   let merge_fin_file = DataFile::new_merge_fin_file(&merge_path)?;
   let merge_fin_record = merge_fin_file.read_log_record(0)?;
-  let v = String::from_utf8(merge_fin_record.record.value).unwrap();
-  let non_merge_file_id = v.parse::<u32>().unwrap();
+  let (non_merge_file_id, _) = parse_merge_fin_value(merge_fin_record.record.value)?;
 
   // remove old data files
   for fid in 0..non_merge_file_id {
-    let file = get_data_file_name(&dir_path, fid);
+    let file = get_data_file_name(&dir_path, fid, file_suffix);
     if file.is_file() {
-      fs::remove_file(file).unwrap();
+      fs::remove_file(file).map_err(|e| {
+        error!("fail to remove merged data file: {}", e);
+        Errors::FailedToWriteToDataFile
+      })?;
     }
   }
 
+  // merged files take the ids of the removed ones, they get their creation times on open
+  let mut file_times = read_file_times(dir_path.as_ref());
+  file_times.retain(|file_id, _| *file_id >= non_merge_file_id);
+  save_file_times(dir_path.as_ref(), &file_times)?;
+
+  // remove hint file of the previous merge, it points into the removed data files
+  let hint_file = dir_path.as_ref().join(HINT_FILE_NAME);
+  if hint_file.is_file() {
+    fs::remove_file(hint_file).map_err(|e| {
+      error!("fail to remove hint file: {}", e);
+      Errors::FailedToWriteToDataFile
+    })?;
+  }
+
   // move temporary merge files to database dir
   for file_name in merge_file_names {
     let src_path = merge_path.join(&file_name);
     let dst_path = dir_path.as_ref().join(&file_name);
-    fs::rename(src_path, dst_path).unwrap();
+    fs::rename(src_path, dst_path).map_err(|e| {
+      error!("fail to move merge file to database dir: {}", e);
+      Errors::FailedToWriteToDataFile
+    })?;
   }
 
   // remove merge dir
-  fs::remove_dir_all(merge_path.clone()).unwrap();
+  fs::remove_dir_all(merge_path.clone()).map_err(|e| {
+    error!("fail to remove merge dir: {}", e);
+    Errors::FailedToWriteToDataFile
+  })?;
 
   Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-  use std::{sync::Arc, thread};
+  use std::{
+    sync::{atomic::AtomicBool, Arc, Barrier},
+    thread,
+    time::Duration,
+  };
 
   use super::*;
-  use crate::util::rand_kv::{get_test_key, get_test_value};
+  use crate::{
+    batch::log_record_key_with_seq,
+    option::{FlushPolicy, WriteBatchOptions},
+    util::rand_kv::{get_test_key, get_test_value},
+  };
   use bytes::Bytes;
 
   #[test]
@@ -352,7 +1346,7 @@ mod tests {
     assert_eq!(keys.len(), 50000);
     for i in 0..50000 {
       let get_res = engine2.get(get_test_key(i));
-      assert!(get_res.ok().unwrap().len() > 0);
+      assert!(!get_res.ok().unwrap().is_empty());
     }
 
     // delete tested files
@@ -385,6 +1379,9 @@ mod tests {
 
     let res1 = engine.merge();
     assert!(res1.is_ok());
+    let report = res1.unwrap();
+    assert_eq!(40000, report.records_kept);
+    assert_eq!(30000, report.records_dropped);
 
     // restart engine
     std::mem::drop(engine);
@@ -493,4 +1490,1230 @@ mod tests {
     // delete tested files
     std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
   }
+
+  #[test]
+  fn test_merge_6() {
+    // read process occurs when merging
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-6");
+    opts.data_file_size = 32 * 1024 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..50000 {
+      let put_res = engine.put(get_test_key(i), get_test_value(i));
+      assert!(put_res.is_ok());
+    }
+    for i in 40000..50000 {
+      let del_res = engine.delete(get_test_key(i));
+      assert!(del_res.is_ok());
+    }
+
+    let eng = Arc::new(engine);
+
+    let mut handles = vec![];
+    let eng1 = eng.clone();
+    let handle1 = thread::spawn(move || {
+      for _ in 0..3 {
+        for i in 0..40000 {
+          let get_res = eng1.get(get_test_key(i));
+          assert_eq!(get_test_value(i), get_res.unwrap());
+        }
+      }
+    });
+    handles.push(handle1);
+
+    let eng2 = eng.clone();
+    let handle2 = thread::spawn(move || {
+      let merge_res = eng2.merge();
+      assert!(merge_res.is_ok());
+    });
+    handles.push(handle2);
+
+    for handle in handles {
+      handle.join().unwrap();
+    }
+
+    // restart engine, reads are served from merged files
+    std::mem::drop(eng);
+
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..40000 {
+      let get_res = engine2.get(get_test_key(i));
+      assert_eq!(get_test_value(i), get_res.unwrap());
+    }
+
+    // delete tested files
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_read_retry() {
+    // a merge swapping out a data file between the index lookup and the read of a key
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-read-retry");
+    opts.data_file_size = 32 * 1024 * 1024;
+    let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+    for i in 0..100 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    let merged_fid = engine.active_data_file.read().get_file_id();
+    engine.rotate_merge_files(0).unwrap();
+
+    let looked_up = Arc::new(Barrier::new(2));
+    let swapped = Arc::new(Barrier::new(2));
+    let reader = thread::spawn({
+      let engine = engine.clone();
+      let looked_up = looked_up.clone();
+      let swapped = swapped.clone();
+      move || {
+        let stale_pos = engine.index.read().get(get_test_key(1).to_vec()).unwrap();
+        looked_up.wait();
+        swapped.wait();
+        assert_eq!(
+          Errors::DataFileNotFound,
+          engine.get_value_by_position(&stale_pos).unwrap_err()
+        );
+        engine.get_value_by_key_position(&get_test_key(1), &stale_pos)
+      }
+    });
+
+    // relocate the live records and drop the merged file, as installing a merge would
+    looked_up.wait();
+    for i in 0..100 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    engine.old_data_files.write().remove(&merged_fid);
+    swapped.wait();
+    assert_eq!(get_test_value(1), reader.join().unwrap().unwrap());
+
+    // a key still pointing at the dropped file has nowhere to be retried
+    let pos = engine.index.read().get(get_test_key(2).to_vec()).unwrap();
+    let stale_pos = LogRecordPos {
+      file_id: merged_fid,
+      ..pos
+    };
+    engine
+      .index
+      .write()
+      .put(get_test_key(2).to_vec(), stale_pos);
+    assert_eq!(
+      Errors::DataFileNotFound,
+      engine.get(get_test_key(2)).unwrap_err()
+    );
+    std::mem::drop(engine);
+
+    // delete tested files
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_report() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-report");
+    opts.data_file_size = 32 * 1024 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // overwrite every key several times
+    for _ in 0..5 {
+      for i in 0..10000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(put_res.is_ok());
+      }
+    }
+
+    let pre_size = util::file::dir_disk_size(&opts.dir_path);
+    let report = engine.merge().expect("failed to merge");
+    assert!(report.files_merged > 0);
+    assert_eq!(10000, report.records_kept);
+    assert_eq!(40000, report.records_dropped);
+    assert!(report.bytes_reclaimed > 0);
+
+    // restart engine to apply merge
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(10000, engine2.list_keys().unwrap().len());
+    std::mem::drop(engine2);
+
+    // disk shrinks by the reclaimed bytes, less the hint file and small meta files
+    let post_size = util::file::dir_disk_size(&opts.dir_path);
+    let hint_size = fs::metadata(opts.dir_path.join(HINT_FILE_NAME))
+      .unwrap()
+      .len();
+    assert!(pre_size - post_size <= report.bytes_reclaimed);
+    assert!(report.bytes_reclaimed - (pre_size - post_size) <= hint_size + 1024);
+
+    // delete tested files
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_dedup_values() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-dedup-values");
+    opts.max_value_size = 1024 * 1024; // 1MB
+    opts.file_merge_threshold = 0 as f32;
+    opts.dedup_values = true;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // the same 1MB value under 100 keys, and one small value which is never deduplicated
+    let value = Bytes::from(vec![7u8; 1024 * 1024]);
+    for i in 0..100 {
+      engine.put(get_test_key(i), value.clone()).unwrap();
+    }
+    engine.put(get_test_key(100), Bytes::from("small")).unwrap();
+    assert!(util::file::dir_disk_size(&opts.dir_path) > 100 * 1024 * 1024);
+
+    engine.merge().expect("failed to merge");
+    std::mem::drop(engine);
+
+    // a single copy is left on disk and every key still reads it
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(util::file::dir_disk_size(&opts.dir_path) < 2 * 1024 * 1024);
+    for i in 0..100 {
+      assert_eq!(value, engine.get(get_test_key(i)).unwrap());
+    }
+    assert_eq!(
+      Some(value.len()),
+      engine.value_len(get_test_key(42)).unwrap()
+    );
+    assert_eq!(Bytes::from("small"), engine.get(get_test_key(100)).unwrap());
+    assert_eq!(101, engine.list_keys().unwrap().len());
+
+    // the key whose record holds the value goes away, the next merge keeps the value once
+    engine.delete(get_test_key(0)).unwrap();
+    engine.put(get_test_key(1), Bytes::from("other")).unwrap();
+    engine.merge().expect("failed to merge");
+    std::mem::drop(engine);
+
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(util::file::dir_disk_size(&opts.dir_path) < 2 * 1024 * 1024);
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.get(get_test_key(0)).err().unwrap()
+    );
+    assert_eq!(Bytes::from("other"), engine.get(get_test_key(1)).unwrap());
+    for i in 2..100 {
+      assert_eq!(value, engine.get(get_test_key(i)).unwrap());
+    }
+    std::mem::drop(engine);
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_dry_run() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-dry-run");
+    opts.data_file_size = 1024 * 1024; // 1MB
+    opts.max_key_size = 1024;
+    opts.max_value_size = 64 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for _ in 0..3 {
+      for i in 0..10000 {
+        engine.put(get_test_key(i), get_test_value(i)).unwrap();
+      }
+    }
+    for i in 0..2000 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+
+    let file_num = engine.get_engine_stat().unwrap().data_file_num;
+    let pre_size = util::file::dir_disk_size(&opts.dir_path);
+    let dry_report = engine.merge_dry_run().expect("failed to run merge dry run");
+    assert_eq!(8000, dry_report.records_kept);
+    assert!(dry_report.bytes_reclaimed > 0);
+
+    // nothing is written or rotated
+    assert!(!get_merge_path(&opts.dir_path).exists());
+    assert_eq!(file_num, engine.get_engine_stat().unwrap().data_file_num);
+    assert_eq!(pre_size, util::file::dir_disk_size(&opts.dir_path));
+
+    let report = engine.merge().expect("failed to merge");
+    assert_eq!(dry_report, report);
+    std::mem::drop(engine);
+
+    // delete tested files
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_parallel() {
+    let cases = [
+      (IndexType::BTree, true),
+      (IndexType::BTree, false),
+      (IndexType::BPlusTree, true),
+    ];
+    for (index_type, write_hint_on_merge) in cases {
+      let mut opts = Options::default();
+      opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-parallel");
+      opts.data_file_size = 256 * 1024;
+      opts.max_key_size = 1024;
+      opts.max_value_size = 4 * 1024; // 4KB
+      opts.file_merge_threshold = 0 as f32;
+      opts.merge_parallelism = 4;
+      opts.index_type = index_type;
+      opts.bptree_flush_policy = FlushPolicy::EveryOps(1000);
+      opts.write_hint_on_merge = write_hint_on_merge;
+      let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+      for _ in 0..3 {
+        for i in 0..10000 {
+          engine.put(get_test_key(i), get_test_value(i)).unwrap();
+        }
+      }
+      for i in 0..1000 {
+        engine.delete(get_test_key(i)).unwrap();
+      }
+      let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .unwrap();
+      wb.put(get_test_key(0), Bytes::from("txn value")).unwrap();
+      wb.commit().unwrap();
+      let fingerprint = engine.fingerprint().unwrap();
+
+      let report = engine.merge().expect("failed to merge");
+      assert_eq!(9001, report.records_kept);
+      // writes during and after the merge go to the new active file
+      engine
+        .put(get_test_key(1), Bytes::from("new value"))
+        .unwrap();
+      std::mem::drop(engine);
+
+      // data files of all partitions are installed, reading back the same contents
+      let engine = Engine::open(opts.clone()).expect("failed to open engine");
+      assert!(!get_merge_path(&opts.dir_path).exists());
+      assert_eq!(
+        Bytes::from("new value"),
+        engine.get(get_test_key(1)).unwrap()
+      );
+      engine.delete(get_test_key(1)).unwrap();
+      assert_eq!(fingerprint, engine.fingerprint().unwrap());
+      assert_eq!(9001, engine.list_keys().unwrap().len());
+      assert_eq!(
+        Bytes::from("txn value"),
+        engine.get(get_test_key(0)).unwrap()
+      );
+      for i in 1000..10000 {
+        assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+      }
+      std::mem::drop(engine);
+
+      // delete tested files
+      std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+  }
+
+  #[test]
+  fn test_merge_parallel_append_only() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-parallel-append-only");
+    opts.data_file_size = 256 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    opts.merge_parallelism = 4;
+    opts.append_only = true;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..20000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in (0..20000).step_by(2) {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+    engine.merge().expect("failed to merge");
+    std::mem::drop(engine);
+
+    // the partition hints interleave the keys, they're loaded in order
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let keys = engine.list_keys().unwrap();
+    let expected: Vec<Bytes> = (1..20000).step_by(2).map(get_test_key).collect();
+    assert_eq!(expected, keys);
+    assert_eq!(
+      Errors::KeyNotAscending,
+      engine
+        .put(get_test_key(19999), get_test_value(19999))
+        .unwrap_err()
+    );
+    engine
+      .put(get_test_key(20000), get_test_value(20000))
+      .unwrap();
+    std::mem::drop(engine);
+
+    // delete tested files
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_file_budget() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-file-budget");
+    opts.data_file_size = 4 * 1024;
+    fs::create_dir_all(opts.dir_path.join("0")).unwrap();
+    fs::create_dir_all(opts.dir_path.join("1")).unwrap();
+    let log_record = |i: usize| LogRecord {
+      key: get_test_key(i).to_vec(),
+      value: vec![b'v'; 1024],
+      rec_type: LogRecordType::Normal,
+    };
+
+    // writing past the data files budgeted is refused as soon as it starts another one
+    let budget = MergeFileBudget::new(2);
+    let mut writer = MergeWriter::new(&opts.dir_path.join("0"), &opts, budget.clone()).unwrap();
+    let mut written = 0;
+    let res = loop {
+      if let Err(e) = writer.append(&log_record(written)) {
+        break e;
+      }
+      written += 1;
+    };
+    assert_eq!(Errors::MergeOutputTooLarge, res);
+    assert_eq!(2, writer.file_num());
+    assert!(written > 2);
+
+    // partitions share the budget, another writer has none left
+    let mut writer = MergeWriter::new(&opts.dir_path.join("1"), &opts, budget).unwrap();
+    assert_eq!(
+      Errors::MergeOutputTooLarge,
+      writer.append(&log_record(0)).unwrap_err()
+    );
+    std::mem::drop(writer);
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[cfg(feature = "tracing")]
+  #[tracing_test::traced_test]
+  #[test]
+  fn test_merge_tracing_span() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-tracing");
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in 0..500 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+    let report = engine.merge().unwrap();
+    assert!(report.bytes_reclaimed > 0);
+
+    // the event finishing the merge carries the merge span with its recorded fields
+    let reclaimed = format!("bytes_reclaimed={}", report.bytes_reclaimed);
+    logs_assert(|lines: &[&str]| {
+      let found = lines.iter().any(|line| {
+        let (spans, event) = line.split_once("}:").unwrap_or_default();
+        event.contains("merge finished") && spans.contains("merge{") && spans.contains(&reclaimed)
+      });
+      match found {
+        true => Ok(()),
+        false => Err(format!("no merge span with {}", reclaimed)),
+      }
+    });
+    std::mem::drop(engine);
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_space_amplification() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-space-amplification");
+    opts.data_file_size = 32 * 1024 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // overwrite every key several times
+    for _ in 0..5 {
+      for i in 0..10000 {
+        engine.put(get_test_key(i), get_test_value(i)).unwrap();
+      }
+    }
+    let stat = engine.get_engine_stat().unwrap();
+    assert_eq!(stat.live_size * 5, stat.data_size);
+    assert!(stat.space_amplification > 4.9);
+
+    // restart engine to apply merge, only live records are left in the data files
+    engine.merge().expect("failed to merge");
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let stat2 = engine2.get_engine_stat().unwrap();
+    assert_eq!(stat.live_size, stat2.live_size);
+    assert_eq!(stat2.live_size, stat2.data_size);
+    assert!(stat2.space_amplification < stat.space_amplification);
+    std::mem::drop(engine2);
+
+    // delete tested files
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_overwrite_ratio() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-overwrite-ratio");
+    opts.data_file_size = 32 * 1024 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine.overwrite_ratio(), 0.0);
+
+    // every key is written 5 times, only the last write is live
+    for _ in 0..5 {
+      for i in 0..10000 {
+        engine.put(get_test_key(i), get_test_value(i)).unwrap();
+      }
+    }
+    let stat = engine.get_engine_stat().unwrap();
+    assert_eq!(stat.total_bytes_written, stat.data_size);
+    assert_eq!(stat.total_bytes_written, stat.live_size * 5);
+    assert_eq!(stat.overwrite_ratio, 5.0);
+    assert_eq!(engine.overwrite_ratio(), 5.0);
+
+    // the merge writes the live records once more, restart to apply it
+    engine.merge().expect("failed to merge");
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let stat2 = engine2.get_engine_stat().unwrap();
+    assert_eq!(stat2.live_size, stat.live_size);
+    assert_eq!(stat2.data_size, stat.live_size);
+    assert_eq!(stat2.total_bytes_written, stat.live_size * 6);
+    assert_eq!(engine2.overwrite_ratio(), 6.0);
+    std::mem::drop(engine2);
+
+    // the counter survives a restart without writes
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    let stat3 = engine3.get_engine_stat().unwrap();
+    assert_eq!(stat3.total_bytes_written, stat2.total_bytes_written);
+    std::mem::drop(engine3);
+
+    // delete tested files
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_concurrent_rotation() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-rotation");
+    opts.data_file_size = 256 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    let eng = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+
+    // writers rotate active files while merges rotate them too
+    let mut handles = vec![];
+    for t in 0..4 {
+      let eng = eng.clone();
+      handles.push(thread::spawn(move || {
+        for i in t * 5000..(t + 1) * 5000 {
+          let put_res = eng.put(get_test_key(i), get_test_value(i));
+          assert!(put_res.is_ok());
+        }
+      }));
+    }
+    let eng_merge = eng.clone();
+    handles.push(thread::spawn(move || {
+      for _ in 0..5 {
+        match eng_merge.merge() {
+          Ok(_) | Err(Errors::MergeInProgress) => {}
+          Err(e) => panic!("failed to merge: {}", e),
+        }
+      }
+    }));
+    for handle in handles {
+      handle.join().unwrap();
+    }
+
+    // every data file has a distinct id, active file is the newest
+    let active_file_id = eng.active_data_file.read().get_file_id();
+    let old_files = eng.old_data_files.read();
+    for (fid, data_file) in old_files.iter() {
+      assert_eq!(*fid, data_file.get_file_id());
+      assert!(*fid < active_file_id);
+    }
+    std::mem::drop(old_files);
+
+    // restart engine, no data is lost
+    std::mem::drop(eng);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(20000, engine2.list_keys().unwrap().len());
+    for i in 0..20000 {
+      assert!(engine2.get(get_test_key(i)).is_ok());
+    }
+    std::mem::drop(engine2);
+
+    // delete tested files
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_txn_atomicity() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-txn");
+    opts.data_file_size = 32 * 1024 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let wb = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .expect("failed to create write batch");
+    for i in 0..100 {
+      wb.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    wb.commit().unwrap();
+    for i in 0..100 {
+      wb.put(get_test_key(i), Bytes::from("new value in txn"))
+        .unwrap();
+    }
+    wb.commit().unwrap();
+
+    // txn records without a finish record, as if crashed mid commit
+    let seq_no = engine.seq_no.fetch_add(1, Ordering::SeqCst);
+    for i in 100..110 {
+      let mut record = LogRecord {
+        key: log_record_key_with_seq(get_test_key(i).to_vec(), seq_no).unwrap(),
+        value: get_test_value(i).to_vec(),
+        rec_type: LogRecordType::Normal,
+      };
+      engine
+        .append_log_record_unsynced_until(&mut record, None)
+        .unwrap();
+    }
+
+    let check = |engine: &Engine| {
+      for i in 0..100 {
+        assert_eq!(
+          Bytes::from("new value in txn"),
+          engine.get(get_test_key(i)).unwrap()
+        );
+      }
+      for i in 100..110 {
+        assert_eq!(
+          Errors::KeyNotFound,
+          engine.get(get_test_key(i)).unwrap_err()
+        );
+      }
+    };
+
+    // crash before the merge finished file is written, merge dir is discarded
+    let report = engine.merge().unwrap();
+    assert_eq!(100, report.records_kept);
+    std::mem::drop(engine);
+    let merge_path = get_merge_path(&opts.dir_path);
+    fs::remove_file(merge_path.join(MERGE_FINISHED_FILE_NAME)).unwrap();
+
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(!merge_path.exists());
+    check(&engine2);
+
+    // finished merge keeps only the committed txn
+    engine2.merge().unwrap();
+    std::mem::drop(engine2);
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    check(&engine3);
+    std::mem::drop(engine3);
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_txn_concurrent_commit() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-txn-concurrent");
+    opts.data_file_size = 32 * 1024 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+
+    let engine2 = engine.clone();
+    let handle = thread::spawn(move || {
+      let wb = engine2
+        .new_write_batch(WriteBatchOptions::default())
+        .expect("failed to create write batch");
+      for round in 0..200 {
+        for i in 0..10 {
+          wb.put(get_test_key(i), Bytes::from(format!("round-{}", round)))
+            .unwrap();
+        }
+        wb.commit().unwrap();
+      }
+    });
+    for _ in 0..10 {
+      engine.merge().unwrap();
+    }
+    handle.join().unwrap();
+    engine.merge().unwrap();
+    std::mem::drop(engine);
+
+    // every key of the last batch survives the merge
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..10 {
+      assert_eq!(
+        Bytes::from("round-199"),
+        engine.get(get_test_key(i)).unwrap()
+      );
+    }
+    std::mem::drop(engine);
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_history() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-history");
+    opts.data_file_size = 64 * 1024;
+    opts.max_key_size = 1024;
+    opts.max_value_size = 4 * 1024; // 4KB
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(engine.merge_history().is_empty());
+
+    for i in 0..10000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in 0..5000 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+    let report1 = engine.merge().expect("failed to merge");
+    assert_eq!(vec![report1], engine.merge_history());
+    assert_eq!(engine.info().last_merge_at, report1.finished_at);
+
+    // the second merge starts from the merged files, only the overwritten keys are reclaimed
+    std::mem::drop(engine);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 5000..8000 {
+      engine.put(get_test_key(i), get_test_value(i + 1)).unwrap();
+    }
+    let report2 = engine.merge().expect("failed to merge");
+
+    // the history survives restarts
+    std::mem::drop(engine);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let history = engine.merge_history();
+    assert_eq!(vec![report1, report2], history);
+    assert_eq!(5000, history[0].records_kept);
+    assert_eq!(10000, history[0].records_dropped);
+    assert_eq!(5000, history[1].records_kept);
+    assert_eq!(3000, history[1].records_dropped);
+    assert!(history[1].bytes_reclaimed > 0);
+    assert!(history[1].bytes_reclaimed < history[0].bytes_reclaimed);
+    assert!(history[0].finished_at <= history[1].finished_at);
+
+    // reports saved without a finish time are still read
+    let legacy = MergeReport::decode("1:0:2:3:4").unwrap();
+    assert_eq!(4, legacy.bytes_reclaimed);
+    assert_eq!(None, legacy.finished_at);
+
+    // the history is bounded, the oldest reports go first
+    for i in 0..MERGE_HISTORY_LEN {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+      engine.merge().expect("failed to merge");
+    }
+    let history = engine.merge_history();
+    assert_eq!(MERGE_HISTORY_LEN, history.len());
+    assert!(!history.contains(&report1));
+
+    // a history that can't be saved doesn't fail the finished merge
+    let tmp_file_name = opts.dir_path.join(MERGE_HISTORY_TMP_FILE_NAME);
+    fs::create_dir(&tmp_file_name).unwrap();
+    engine.put(get_test_key(0), get_test_value(1)).unwrap();
+    let report = engine.merge().expect("failed to merge");
+    assert_eq!(Some(&report), engine.merge_history().last());
+    fs::remove_dir(&tmp_file_name).unwrap();
+
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_resume() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-resume");
+    opts.data_file_size = 8 * 1024;
+    opts.max_key_size = 1024;
+    opts.max_value_size = 4 * 1024; // 4KB
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..2000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    // every record of the fourth data file is stale, the first three are live
+    let open_file = |file_id| {
+      DataFile::new(
+        &opts.dir_path,
+        file_id,
+        &opts.file_suffix,
+        IOManagerType::StandardFileIO,
+      )
+      .unwrap()
+    };
+    for record in open_file(3).iter_records() {
+      let (real_key, _) = parse_log_record_key(record.unwrap().1.key).unwrap();
+      engine
+        .put(Bytes::from(real_key), get_test_value(0))
+        .unwrap();
+    }
+    let first_records: usize = (0..3)
+      .map(|fid| open_file(fid).iter_records().count())
+      .sum();
+
+    // interrupt the merge at the fourth data file by corrupting its first record
+    let file_name = get_data_file_name(&opts.dir_path, 3, &opts.file_suffix);
+    let last_off = open_file(3).read_log_record(0).unwrap().size - 1;
+    let flip_byte = || {
+      let mut content = fs::read(&file_name).unwrap();
+      content[last_off] ^= 0xff;
+      fs::write(&file_name, content).unwrap();
+    };
+    flip_byte();
+    let res1 = engine.merge();
+    assert_eq!(Errors::InvalidLogRecordCrc, res1.err().unwrap());
+    flip_byte();
+
+    // the unfinished merge survives reopening, keys merged before the interruption change
+    std::mem::drop(engine);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(get_merge_path(&opts.dir_path).is_dir());
+    engine.delete(get_test_key(5)).unwrap();
+    engine.put(get_test_key(10), get_test_value(20000)).unwrap();
+
+    // the first three data files aren't read again, the tombstone of key 5 is kept
+    let report = engine.merge().expect("failed to merge");
+    assert_eq!(3, report.files_skipped);
+    assert_eq!(1999 - (first_records - 2) + 1, report.records_kept);
+
+    std::mem::drop(engine);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(1999, engine.list_keys().unwrap().len());
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.get(get_test_key(5)).err().unwrap()
+    );
+    assert_eq!(get_test_value(20000), engine.get(get_test_key(10)).unwrap());
+    assert_eq!(get_test_value(0), engine.get(get_test_key(0)).unwrap());
+    std::mem::drop(engine);
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_cancel() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-cancel");
+    opts.data_file_size = 64 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+
+    for i in 0..20000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in 0..10000 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+
+    // no merge is running, nothing to cancel
+    engine.cancel_merge();
+    assert!(!engine.merge_cancelled.load(Ordering::SeqCst));
+
+    let done = Arc::new(AtomicBool::new(false));
+    let (engine2, done2) = (engine.clone(), done.clone());
+    let handle = thread::spawn(move || {
+      while !done2.load(Ordering::SeqCst) {
+        engine2.cancel_merge();
+      }
+    });
+    let res = engine.merge();
+    done.store(true, Ordering::SeqCst);
+    handle.join().unwrap();
+    assert_eq!(res.err().unwrap(), Errors::MergeCancelled);
+    assert!(!get_merge_path(&opts.dir_path).exists());
+
+    for i in 0..20000 {
+      match i < 10000 {
+        true => assert_eq!(
+          engine.get(get_test_key(i)).err().unwrap(),
+          Errors::KeyNotFound
+        ),
+        false => assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i)),
+      }
+    }
+    std::mem::drop(engine);
+
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine.list_keys().unwrap().len(), 10000);
+    for i in 10000..20000 {
+      assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+    }
+    std::mem::drop(engine);
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_writes_data_and_hint_files_only() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-files-only");
+    opts.data_file_size = 64 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    opts.file_suffix = ".bitkv".to_string();
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..3000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in 0..1500 {
+      engine
+        .put(get_test_key(i), Bytes::from(format!("new-value-{}", i)))
+        .unwrap();
+    }
+    let report = engine.merge().unwrap();
+    assert_eq!(3000, report.records_kept);
+
+    // merged records are only in data files and the hint file, no database is opened
+    // in the merge directory, so there's no second index of the merged keys
+    let merge_path = get_merge_path(&opts.dir_path);
+    let mut data_files = 0;
+    for entry in fs::read_dir(&merge_path).unwrap().flatten() {
+      let file_name = entry.file_name().into_string().unwrap();
+      match file_name.as_str() {
+        HINT_FILE_NAME | MERGE_FINISHED_FILE_NAME => {}
+        _ => {
+          assert!(file_name.ends_with(".bitkv"), "{}", file_name);
+          data_files += 1;
+        }
+      }
+    }
+    assert!(data_files > 1);
+    std::mem::drop(engine);
+
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(3000, engine.list_keys().unwrap().len());
+    for i in 0..3000 {
+      let value = match i < 1500 {
+        true => Bytes::from(format!("new-value-{}", i)),
+        false => get_test_value(i),
+      };
+      assert_eq!(value, engine.get(get_test_key(i)).unwrap());
+    }
+    std::mem::drop(engine);
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_min_age() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-min-age");
+    opts.data_file_size = 64 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    opts.merge_min_age = Duration::from_secs(600);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..3000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in 0..3000 {
+      engine
+        .put(get_test_key(i), Bytes::from(format!("new-value-{}", i)))
+        .unwrap();
+    }
+    let mut file_ids: Vec<u32> = engine.old_data_files.read().keys().copied().collect();
+    file_ids.sort();
+    assert!(file_ids.len() >= 3);
+
+    // all files are hot, nothing to merge
+    let report = engine.merge().unwrap();
+    assert_eq!(0, report.files_merged);
+    assert!(!get_merge_path(&opts.dir_path).exists());
+
+    // the two oldest files were created an hour ago as of their saved creation times, their
+    // modification time doesn't matter
+    std::mem::drop(engine);
+    let last_hour = meta_time_now() - Duration::from_secs(3600);
+    let mut file_times = read_file_times(&opts.dir_path);
+    for file_id in &file_ids[..2] {
+      file_times.insert(*file_id, last_hour);
+    }
+    save_file_times(&opts.dir_path, &file_times).unwrap();
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(file_times, *engine.file_times.lock());
+    let hot_files = file_ids[2..]
+      .iter()
+      .map(|file_id| {
+        let file_name = get_data_file_name(&opts.dir_path, *file_id, &opts.file_suffix);
+        (file_name.clone(), fs::read(file_name).unwrap())
+      })
+      .collect::<Vec<_>>();
+
+    let report = engine.merge().unwrap();
+    assert_eq!(2, report.files_merged);
+    assert!(report.records_kept > 0);
+    assert!(report.records_dropped > 0);
+    std::mem::drop(engine);
+
+    // hot files are left as they were
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for (file_name, content) in hot_files {
+      assert_eq!(content, fs::read(file_name).unwrap());
+    }
+    assert_eq!(3000, engine.list_keys().unwrap().len());
+    for i in 0..3000 {
+      assert_eq!(
+        Bytes::from(format!("new-value-{}", i)),
+        engine.get(get_test_key(i)).unwrap()
+      );
+    }
+    std::mem::drop(engine);
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_without_hint() {
+    for index_type in [IndexType::BTree, IndexType::SkipList, IndexType::BPlusTree] {
+      let mut opts = Options::default();
+      opts.dir_path = PathBuf::from(format!("/tmp/bitkv-rs-merge-no-hint-{:?}", index_type));
+      opts.data_file_size = 32 * 1024 * 1024;
+      opts.file_merge_threshold = 0 as f32;
+      // B+Tree never writes a hint file, even with the default option
+      opts.write_hint_on_merge = index_type == IndexType::BPlusTree;
+      let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+      for i in 0..1000 {
+        engine.put(get_test_key(i), get_test_value(i)).unwrap();
+      }
+      for i in 0..500 {
+        engine
+          .put(get_test_key(i), Bytes::from("new value in merge"))
+          .unwrap();
+      }
+      for i in 900..1000 {
+        engine.delete(get_test_key(i)).unwrap();
+      }
+      engine.merge().unwrap();
+      assert!(!get_merge_path(&opts.dir_path).join(HINT_FILE_NAME).exists());
+      std::mem::drop(engine);
+
+      let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+      assert!(!opts.dir_path.join(HINT_FILE_NAME).exists());
+      assert_eq!(900, engine2.list_keys().unwrap().len());
+      if index_type != IndexType::BPlusTree {
+        for i in 0..500 {
+          assert_eq!(
+            Bytes::from("new value in merge"),
+            engine2.get(get_test_key(i)).unwrap()
+          );
+        }
+        for i in 500..900 {
+          assert_eq!(get_test_value(i), engine2.get(get_test_key(i)).unwrap());
+        }
+      }
+      std::mem::drop(engine2);
+
+      std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+  }
+
+  #[test]
+  fn test_merge_hint_then_without_hint() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-hint-toggle");
+    opts.data_file_size = 32 * 1024 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    engine.merge().unwrap();
+    std::mem::drop(engine);
+
+    // the stale hint file of the first merge is dropped by the second one
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(opts.dir_path.join(HINT_FILE_NAME).is_file());
+    for i in 0..500 {
+      engine2.delete(get_test_key(i)).unwrap();
+    }
+    std::mem::drop(engine2);
+
+    opts.write_hint_on_merge = false;
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    engine3.merge().unwrap();
+    std::mem::drop(engine3);
+
+    let engine4 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(!opts.dir_path.join(HINT_FILE_NAME).exists());
+    assert_eq!(500, engine4.list_keys().unwrap().len());
+    for i in 500..1000 {
+      assert_eq!(get_test_value(i), engine4.get(get_test_key(i)).unwrap());
+    }
+    std::mem::drop(engine4);
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_hint_missing_data_file() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-hint-missing");
+    opts.data_file_size = 32 * 1024 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    engine.merge().unwrap();
+    std::mem::drop(engine);
+
+    // apply the merge, then add a hint record pointing at a data file that doesn't exist
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    std::mem::drop(engine2);
+    let hint_file = DataFile::new_hint_file(&opts.dir_path).unwrap();
+    hint_file
+      .write_hint_record(
+        b"ghost".to_vec(),
+        LogRecordPos {
+          file_id: 999,
+          offset: 0,
+          size: 32,
+        },
+      )
+      .unwrap();
+    hint_file.sync().unwrap();
+    std::mem::drop(hint_file);
+
+    // hint is discarded and the index rebuilt from data files
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(!opts.dir_path.join(HINT_FILE_NAME).exists());
+    assert_eq!(1000, engine3.list_keys().unwrap().len());
+    for i in 0..1000 {
+      assert_eq!(get_test_value(i), engine3.get(get_test_key(i)).unwrap());
+    }
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine3.get(Bytes::from("ghost")).unwrap_err()
+    );
+    std::mem::drop(engine3);
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_concurrent_reads() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-concurrent-reads");
+    opts.data_file_size = 64 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+    for i in 0..20000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in 0..10000 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+
+    // read live keys from several threads for as long as the merge runs
+    let done = Arc::new(AtomicBool::new(false));
+    let mut handles = Vec::new();
+    for t in 0..4 {
+      let (engine, done) = (engine.clone(), done.clone());
+      handles.push(thread::spawn(move || {
+        let (mut reads, mut failures) = (0, 0);
+        let mut i = 10000 + t * 2500;
+        while !done.load(Ordering::SeqCst) || reads < 10000 {
+          match engine.get(get_test_key(i)) {
+            Ok(value) if value == get_test_value(i) => {}
+            _ => failures += 1,
+          }
+          reads += 1;
+          i = 10000 + (i + 1) % 10000;
+        }
+        failures
+      }));
+    }
+    engine.merge().unwrap();
+    done.store(true, Ordering::SeqCst);
+    for handle in handles {
+      assert_eq!(0, handle.join().unwrap());
+    }
+
+    // reads keep working after the merge is applied on reopen
+    std::mem::drop(engine);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 10000..20000 {
+      assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+    }
+    std::mem::drop(engine);
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
+
+  // stored key and crc of the record the index points at for key
+  fn stored_key_and_crc(engine: &Engine, key: &Bytes) -> (Vec<u8>, u32) {
+    let pos = engine.index.read().get(key.to_vec()).unwrap();
+    let opts = &engine.options;
+    let data_file = DataFile::new(
+      &opts.dir_path,
+      pos.file_id,
+      &opts.file_suffix,
+      IOManagerType::StandardFileIO,
+    )
+    .unwrap();
+    let stored_key = data_file.read_log_record(pos.offset).unwrap().record.key;
+    let file_name = get_data_file_name(&opts.dir_path, pos.file_id, &opts.file_suffix);
+    let buf = fs::read(file_name).unwrap();
+    let crc_off = (pos.offset + pos.size as u64) as usize - 4;
+    let crc = u32::from_be_bytes(buf[crc_off..crc_off + 4].try_into().unwrap());
+    (stored_key, crc)
+  }
+
+  #[test]
+  fn test_merge_crc_scope_value_only() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitkv-rs-merge-crc-scope");
+    opts.data_file_size = 64 * 1024;
+    opts.file_merge_threshold = 0 as f32;
+    opts.crc_scope = CrcScope::ValueOnly;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // batch records carry their seq_no in the key, merge rewrites them without it
+    let wb = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .unwrap();
+    for i in 0..1000 {
+      wb.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    wb.commit().unwrap();
+    let before: Vec<_> = (0..1000)
+      .map(|i| stored_key_and_crc(&engine, &get_test_key(i)))
+      .collect();
+
+    engine.merge().unwrap();
+    std::mem::drop(engine);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for (i, (key, crc)) in before.into_iter().enumerate() {
+      let (merged_key, merged_crc) = stored_key_and_crc(&engine, &get_test_key(i));
+      assert_ne!(key, merged_key);
+      assert_eq!(crc, merged_crc);
+      assert_eq!(crc, crc32fast::hash(&get_test_value(i)));
+      assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+    }
+    std::mem::drop(engine);
+
+    // full scope records written later sit next to the value only ones
+    opts.crc_scope = CrcScope::Full;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    engine
+      .put(get_test_key(1000), get_test_value(1000))
+      .unwrap();
+    let (_, crc) = stored_key_and_crc(&engine, &get_test_key(1000));
+    assert_ne!(crc, crc32fast::hash(&get_test_value(1000)));
+    std::mem::drop(engine);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..=1000 {
+      assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+    }
+    std::mem::drop(engine);
+
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+  }
 }
@@ -0,0 +1,89 @@
+// built with `--no-default-features`, only the `no_std` subset of the crate is available
+use crate::{
+  data::log_record::decode_log_record_pos,
+  errors::Errors,
+  option::{CrcScope, IteratorOptions},
+  BTree, Indexer, LogRecord, LogRecordPos, LogRecordType,
+};
+
+#[test]
+fn test_no_std_log_record_encode_decode() {
+  let record = LogRecord::new(
+    "key-a".as_bytes().to_vec(),
+    "value-a".as_bytes().to_vec(),
+    LogRecordType::Normal,
+  );
+  let tombstone = LogRecord::new(
+    "key-b".as_bytes().to_vec(),
+    Vec::new(),
+    LogRecordType::Deleted,
+  );
+  let mut buf = record.encode();
+  let size = buf.len();
+  buf.extend_from_slice(&tombstone.encode_with_crc_scope(CrcScope::ValueOnly));
+
+  // records decode back to back
+  let (decoded, decoded_size) = LogRecord::decode(&buf).unwrap();
+  assert_eq!(size, decoded_size);
+  assert_eq!(record.key(), decoded.key());
+  assert_eq!(record.value(), decoded.value());
+  assert_eq!(LogRecordType::Normal, decoded.rec_type());
+  let (decoded, decoded_size) = LogRecord::decode(&buf[size..]).unwrap();
+  assert_eq!(buf.len() - size, decoded_size);
+  assert_eq!(tombstone.key(), decoded.key());
+  assert!(decoded.value().is_empty());
+  assert_eq!(LogRecordType::Deleted, decoded.rec_type());
+
+  // a cut off record or zeros are the end of the records, a flipped key byte fails the crc
+  assert_eq!(
+    Errors::ReadDataFileEOF,
+    LogRecord::decode(&buf[..size - 1]).unwrap_err()
+  );
+  assert_eq!(
+    Errors::ReadDataFileEOF,
+    LogRecord::decode(&[0u8; 16]).unwrap_err()
+  );
+  let mut corrupt = buf[..size].to_vec();
+  corrupt[3] ^= 0xff;
+  assert_eq!(
+    Errors::InvalidLogRecordCrc,
+    LogRecord::decode(&corrupt).unwrap_err()
+  );
+
+  let pos = LogRecordPos::new(3, 1024, 42);
+  assert_eq!(pos, decode_log_record_pos(pos.encode()));
+}
+
+#[test]
+fn test_no_std_btree_index() {
+  let index = BTree::new();
+  for i in 0..100 {
+    let key = format!("key-{:03}", i).into_bytes();
+    assert!(index
+      .put(key, LogRecordPos::new(1, i * 10, 10))
+      .unwrap()
+      .is_none());
+  }
+  let pos = LogRecordPos::new(1, 420, 10);
+  assert_eq!(Some(pos), index.get("key-042".as_bytes().to_vec()));
+  assert_eq!(
+    Some(pos),
+    index.delete("key-042".as_bytes().to_vec()).unwrap()
+  );
+  assert!(index.get("key-042".as_bytes().to_vec()).is_none());
+  assert_eq!(99, index.estimate_len());
+  assert!(index.contains_prefix("key-09".as_bytes()));
+
+  let mut iter = index
+    .iterator(IteratorOptions {
+      reverse: true,
+      ..Default::default()
+    })
+    .unwrap();
+  iter.seek("key-050".as_bytes().to_vec());
+  let (key, pos) = iter.next().unwrap();
+  assert_eq!("key-050".as_bytes(), key.as_slice());
+  assert_eq!(500, pos.offset());
+  let (key, _) = iter.next().unwrap();
+  assert_eq!("key-049".as_bytes(), key.as_slice());
+}
@@ -1,10 +1,67 @@
-use std::path::PathBuf;
+use alloc::{sync::Arc, vec::Vec};
+use core::ops::Bound;
+#[cfg(feature = "std")]
+use std::{
+  fmt,
+  path::{Path, PathBuf},
+  time::Duration,
+};
 
-#[derive(Debug, Clone)]
+#[cfg(feature = "std")]
+use crate::{
+  batch::MAX_LOG_RECORD_KEY_PREFIX_LEN,
+  codec::ValueCodec,
+  data::{
+    data_file::{
+      DATA_FILE_NAME_SUFFIX, FILE_TIMES_FILE_NAME, FILE_TIMES_TMP_FILE_NAME, HINT_FILE_NAME,
+      MERGE_CHECKPOINT_FILE_NAME, MERGE_CHECKPOINT_TMP_FILE_NAME, MERGE_FINISHED_FILE_NAME,
+      MERGE_HISTORY_FILE_NAME, MERGE_HISTORY_TMP_FILE_NAME, META_FILE_NAME, META_TMP_FILE_NAME,
+      SEQ_NO_FILE_NAME, SEQ_NO_TMP_FILE_NAME,
+    },
+    log_record::max_log_record_header_size,
+  },
+  db::{BPTREE_DIRTY_FILE_NAME, FILE_LOCK_NAME},
+  errors::{Errors, Result},
+  index::bptree::{BPTREE_INDEX_FILE_NAME, BPTREE_SHRINK_FILE_NAME},
+};
+
+/// Hard limit of `Options::max_key_size`. Key length is stored as a varint of at most 5 bytes
+/// in the log record header, and a prefix of at most 22 bytes is added to the key: a 2 byte
+/// marker followed by the varint seq_no and version of the write.
+pub const KEY_SIZE_HARD_LIMIT: usize = u32::MAX as usize - 22;
+
+/// Hard limit of `Options::max_value_size`. Value length is stored as a varint of at most 5 bytes
+/// in the log record header.
+pub const VALUE_SIZE_HARD_LIMIT: usize = u32::MAX as usize;
+
+/// Values shorter than this are never deduplicated by `Options::dedup_values`, a reference to
+/// them would take about as much room as the value
+pub const DEDUP_MIN_VALUE_SIZE: usize = 64;
+
+/// size of the buffer between `Engine::export_to` and its writer
+pub const EXPORT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// number of index entries `Engine::export_to` takes from the index at once
+pub const EXPORT_INDEX_CHUNK_LEN: usize = 1024;
+
+/// bounds of the index chunks `Engine::get_many_ordered` joins its keys with, a chunk grows
+/// from the smallest while the keys use it up
+pub const GET_MANY_MIN_CHUNK_LEN: usize = 16;
+pub const GET_MANY_MAX_CHUNK_LEN: usize = 1024;
+
+#[cfg(feature = "std")]
+#[derive(Clone)]
 pub struct Options {
   // database directory
   pub dir_path: PathBuf,
 
+  // create missing database directory and its parents on open, error out if false
+  pub create_dirs: bool,
+
+  // directory of the B+Tree index files, e.g. on a faster device than the data files. None
+  // keeps them in `dir_path`. Created like `dir_path` and locked along with it
+  pub index_dir: Option<PathBuf>,
+
   //data file size
   pub data_file_size: u64,
 
@@ -20,8 +77,107 @@ pub struct Options {
   // use mmap or not
   pub mmap_at_startup: bool,
 
+  // keep old data files memory mapped after startup, `get_mmap` reads their values without copying
+  pub mmap_old_files: bool,
+
   // merge threshold
   pub file_merge_threshold: f32,
+
+  // read-ahead buffer size for sequential scans, 0 means one read per record
+  pub scan_buffer_size: usize,
+
+  // callback invoked per file while loading index on open
+  pub open_progress: Option<Arc<dyn Fn(OpenProgress) + Send + Sync>>,
+
+  // max key length in bytes, must not exceed `KEY_SIZE_HARD_LIMIT`, which is the default. Writes
+  // are also held to what fits in one data file, see `effective_max_key_size`
+  pub max_key_size: usize,
+
+  // max value length in bytes, must not exceed `VALUE_SIZE_HARD_LIMIT`, which is the default.
+  // Writes are also held to what fits in one data file, see `effective_max_value_size`
+  pub max_value_size: usize,
+
+  // verify crc of values read by `get` and iterators. Turning it off saves CPU on hot read paths,
+  // but a corrupted value on disk is then returned as is instead of failing with
+  // `Errors::InvalidLogRecordCrc`. Loading index, merge and scans always verify.
+  pub verify_checksum_on_read: bool,
+
+  // write a hint file during merge so the next open loads merged keys without scanning data
+  // files. Skipping it halves merge writes at the cost of a slower open. Never written for
+  // B+Tree index, which is persisted and doesn't read it.
+  pub write_hint_on_merge: bool,
+
+  // when B+Tree index changes are committed to its file. Buffering amortizes the per write
+  // transaction cost, after a crash the index is rebuilt from data files
+  pub bptree_flush_policy: FlushPolicy,
+
+  // transform values on write and reverse it on read, e.g. `codec::Lz4Codec`. None stores
+  // values as is. Must stay the same across opens of a database
+  pub value_codec: Option<Arc<dyn ValueCodec>>,
+
+  // only merge data files created at least this long ago, recently written files are likely
+  // to be overwritten again soon. Creation times are kept in the database directory, files
+  // from a merge or from before the times were kept count as created when next opened. Files
+  // are merged oldest first, a file younger than this keeps it and all newer files out of the
+  // merge. Zero merges all files
+  pub merge_min_age: Duration,
+
+  // number of data files a merge writes at once, each on its own thread. Live records are
+  // split among them by key hash. Only used when `merge_min_age` is zero, a merge of cold
+  // files writes one data file at a time
+  pub merge_parallelism: usize,
+
+  // suffix of data file names, which are the zero padded file id followed by it. Lets data
+  // files sit next to other `.data` files. Must stay the same across opens of a database
+  pub file_suffix: String,
+
+  // max number of old data file handles kept open, the least recently used one is closed
+  // when it is hit and reopened on the next read. 0 keeps every data file open
+  pub max_open_files: usize,
+
+  // open old data files on their first read instead of along with the database. Handles
+  // opened to load the index are closed once it's loaded, later reads open the files they
+  // touch and keep them open, up to `max_open_files` if it isn't 0. Speeds up opening
+  // databases of many data files whose index comes from a hint file or B+Tree
+  pub lazy_open_files: bool,
+
+  // how open handles a corrupt log record in the data files
+  pub recovery_mode: RecoveryMode,
+
+  // what the crc32 of newly written log records covers. The scope is kept in each record's
+  // type byte, so it can change across opens and every record is verified by its own scope
+  pub crc_scope: CrcScope,
+
+  // append only log, every put and batch must write keys greater than all keys written before,
+  // otherwise it fails with `Errors::KeyNotAscending`. Writes skip looking up the overwritten
+  // key, and BTree and SkipList indexes become a sorted vector of keys and positions
+  pub append_only: bool,
+
+  // merge stores identical values of at least `DEDUP_MIN_VALUE_SIZE` bytes once, the other
+  // keys get a record referencing it. Saves space when many keys share large values, at the
+  // cost of a second read for referenced values and of comparing values during merge. Needs
+  // `merge_min_age` zero and `merge_parallelism` 1, so references never leave the merged files
+  pub dedup_values: bool,
+
+  // look keys up ignoring the case of ASCII letters, e.g. for user names. The index holds
+  // lowercased keys while records keep the keys as written, `list_keys` and iterators return
+  // them as written. Other bytes, non-ASCII letters included, compare exactly. Iterator
+  // prefixes and ranges are lowercased too and compare against the lowercased keys, so keys
+  // come back ordered by their lowercased form. Must stay the same across opens of a database
+  pub key_case_insensitive: bool,
+}
+
+/// Index loading progress reported by `Engine::open`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpenProgress {
+  /// number of files processed so far, hint file included
+  pub files_processed: usize,
+
+  /// total number of files to process
+  pub total_files: usize,
+
+  /// number of log records loaded so far
+  pub records_loaded: usize,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -36,22 +192,256 @@ pub enum IndexType {
   BPlusTree,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+  /// commit every put and delete right away
+  Always,
+
+  /// commit after every N puts and deletes, and on sync and close. N must be at least 1
+  EveryOps(usize),
+
+  /// commit only on sync and close
+  Manual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryMode {
+  /// any corrupt log record fails the open
+  Strict,
+
+  /// a corrupt log record in the last data file is taken as a torn write, the file is
+  /// truncated before it with a warning and the open goes on
+  Lenient,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrcScope {
+  /// crc covers the record type, key and value lengths, key and value
+  Full,
+
+  /// crc covers the value only, so rewriting the key, e.g. merge dropping the seq_no,
+  /// keeps the crc. Corruption of the key or of the record type and lengths goes undetected,
+  /// unless it shifts the value. Records can't be read by versions without this option
+  ValueOnly,
+}
+
+#[cfg(feature = "std")]
 impl Default for Options {
   fn default() -> Self {
     Self {
       dir_path: std::env::temp_dir().join("bitkv-rs"),
+      create_dirs: true,
+      index_dir: None,
       data_file_size: 256 * 1024 * 1024, // 256MB
       sync_writes: false,
       bytes_per_sync: 0,
       index_type: IndexType::BTree,
       mmap_at_startup: true,
+      mmap_old_files: false,
       file_merge_threshold: 0.6,
+      scan_buffer_size: 4 * 1024 * 1024, // 4MB
+      open_progress: None,
+      max_key_size: KEY_SIZE_HARD_LIMIT,
+      max_value_size: VALUE_SIZE_HARD_LIMIT,
+      verify_checksum_on_read: true,
+      write_hint_on_merge: true,
+      bptree_flush_policy: FlushPolicy::Always,
+      value_codec: None,
+      merge_min_age: Duration::ZERO,
+      merge_parallelism: 1,
+      file_suffix: DATA_FILE_NAME_SUFFIX.to_string(),
+      max_open_files: 0,
+      lazy_open_files: false,
+      recovery_mode: RecoveryMode::Strict,
+      crc_scope: CrcScope::Full,
+      append_only: false,
+      dedup_values: false,
+      key_case_insensitive: false,
     }
   }
 }
+
+#[cfg(feature = "std")]
+impl Options {
+  /// max key length a write accepts, `max_key_size` capped so that a record of the key fits
+  /// in one data file
+  pub fn effective_max_key_size(&self) -> usize {
+    self.max_key_size.min(self.record_room(0))
+  }
+
+  /// max value length a write of a `key_size` bytes key accepts, `max_value_size` capped so
+  /// that the record fits in one data file
+  pub fn effective_max_value_size(&self, key_size: usize) -> usize {
+    self.max_value_size.min(self.record_room(key_size))
+  }
+
+  // bytes left for the value of a record of a key_size bytes key in an empty data file
+  fn record_room(&self, key_size: usize) -> usize {
+    let data_file_size = usize::try_from(self.data_file_size).unwrap_or(usize::MAX);
+    data_file_size.saturating_sub(log_record_overhead() + key_size)
+  }
+
+  /// check the options are usable, `Engine::open` fails with the same error otherwise
+  pub fn validate(&self) -> Result<()> {
+    let dir_path = self.dir_path.to_str();
+    if dir_path.is_none() || dir_path.unwrap().is_empty() {
+      return Err(Errors::DirPathIsEmpty);
+    }
+    if let Some(index_dir) = &self.index_dir {
+      if index_dir
+        .to_str()
+        .map_or(true, |index_dir| index_dir.is_empty())
+      {
+        return Err(Errors::DirPathIsEmpty);
+      }
+    }
+
+    if self.max_key_size == 0
+      || self.max_key_size > KEY_SIZE_HARD_LIMIT
+      || self.max_value_size == 0
+      || self.max_value_size > VALUE_SIZE_HARD_LIMIT
+    {
+      return Err(Errors::InvalidMaxKeyValueSize);
+    }
+
+    // a data file holds at least one record of a one byte key, larger keys and values are
+    // capped to what fits by `effective_max_key_size` and `effective_max_value_size`
+    if self.data_file_size < (log_record_overhead() + 1) as u64 {
+      return Err(Errors::DataFileSizeTooSmall);
+    }
+
+    if self.bytes_per_sync as u64 > self.data_file_size {
+      return Err(Errors::InvalidBytesPerSync);
+    }
+
+    if self.file_merge_threshold < 0f32 || self.file_merge_threshold > 1f32 {
+      return Err(Errors::InvalidMergeThreshold);
+    }
+
+    if self.merge_parallelism == 0 {
+      return Err(Errors::InvalidMergeParallelism);
+    }
+
+    if self.bptree_flush_policy == FlushPolicy::EveryOps(0) {
+      return Err(Errors::InvalidFlushPolicy);
+    }
+
+    // a reference must point into the same merge output, which is renumbered when partitioned
+    // and merged only partly when cold files are merged
+    if self.dedup_values && (self.merge_parallelism > 1 || !self.merge_min_age.is_zero()) {
+      return Err(Errors::InvalidDedupValues);
+    }
+
+    // a data file suffix matching another file of the database would load it as a data file
+    let file_suffix = self.file_suffix.as_str();
+    let reserved_file_names = [
+      HINT_FILE_NAME,
+      MERGE_FINISHED_FILE_NAME,
+      MERGE_CHECKPOINT_FILE_NAME,
+      MERGE_CHECKPOINT_TMP_FILE_NAME,
+      SEQ_NO_FILE_NAME,
+      SEQ_NO_TMP_FILE_NAME,
+      META_FILE_NAME,
+      META_TMP_FILE_NAME,
+      MERGE_HISTORY_FILE_NAME,
+      MERGE_HISTORY_TMP_FILE_NAME,
+      FILE_TIMES_FILE_NAME,
+      FILE_TIMES_TMP_FILE_NAME,
+      FILE_LOCK_NAME,
+      BPTREE_INDEX_FILE_NAME,
+      BPTREE_SHRINK_FILE_NAME,
+      BPTREE_DIRTY_FILE_NAME,
+    ];
+    if file_suffix.is_empty()
+      || file_suffix.contains(std::path::is_separator)
+      || reserved_file_names
+        .iter()
+        .any(|name| name.ends_with(file_suffix))
+    {
+      return Err(Errors::InvalidFileSuffix);
+    }
+
+    // B+Tree index is written to its file in the directory, even when nothing else is
+    if self.index_type == IndexType::BPlusTree {
+      if let Ok(meta) = std::fs::metadata(self.index_dir_path()) {
+        if meta.permissions().readonly() {
+          return Err(Errors::DatabaseDirNotWritable);
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  /// directory of the B+Tree index files, `index_dir` if set, `dir_path` otherwise
+  pub fn index_dir_path(&self) -> &Path {
+    self.index_dir.as_deref().unwrap_or(&self.dir_path)
+  }
+}
+
+// bytes of a log record besides key and value: header, seq_no and version prefix of the key
+// and crc
+#[cfg(feature = "std")]
+fn log_record_overhead() -> usize {
+  max_log_record_header_size() + MAX_LOG_RECORD_KEY_PREFIX_LEN + 4
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for Options {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Options")
+      .field("dir_path", &self.dir_path)
+      .field("create_dirs", &self.create_dirs)
+      .field("index_dir", &self.index_dir)
+      .field("data_file_size", &self.data_file_size)
+      .field("sync_writes", &self.sync_writes)
+      .field("bytes_per_sync", &self.bytes_per_sync)
+      .field("index_type", &self.index_type)
+      .field("mmap_at_startup", &self.mmap_at_startup)
+      .field("mmap_old_files", &self.mmap_old_files)
+      .field("file_merge_threshold", &self.file_merge_threshold)
+      .field("scan_buffer_size", &self.scan_buffer_size)
+      .field("open_progress", &self.open_progress.is_some())
+      .field("max_key_size", &self.max_key_size)
+      .field("max_value_size", &self.max_value_size)
+      .field("verify_checksum_on_read", &self.verify_checksum_on_read)
+      .field("write_hint_on_merge", &self.write_hint_on_merge)
+      .field("bptree_flush_policy", &self.bptree_flush_policy)
+      .field("value_codec", &self.value_codec.is_some())
+      .field("merge_min_age", &self.merge_min_age)
+      .field("merge_parallelism", &self.merge_parallelism)
+      .field("file_suffix", &self.file_suffix)
+      .field("max_open_files", &self.max_open_files)
+      .field("lazy_open_files", &self.lazy_open_files)
+      .field("recovery_mode", &self.recovery_mode)
+      .field("crc_scope", &self.crc_scope)
+      .field("append_only", &self.append_only)
+      .field("dedup_values", &self.dedup_values)
+      .field("key_case_insensitive", &self.key_case_insensitive)
+      .finish()
+  }
+}
+#[derive(Clone)]
 pub struct IteratorOptions {
   pub prefix: Vec<u8>,
   pub reverse: bool,
+  // treat the byte as a key level boundary, only keys without it after the prefix are yielded,
+  // e.g. prefix `tenant|` and separator `|` yield `tenant|user` but not `tenant|user|field`
+  pub stop_at_separator: Option<u8>,
+  // only yield keys within [start_key, end_key), unbounded if None. With reverse, keys are
+  // yielded from just below end_key down to start_key
+  pub start_key: Option<Vec<u8>>,
+  pub end_key: Option<Vec<u8>>,
+  // only yield entries whose value matches, checked after the value is read
+  pub value_filter: Option<Arc<dyn Fn(&[u8]) -> bool + Send + Sync>>,
+  // which writes made after the iterator is created it sees, see `Consistency`
+  pub consistency: Consistency,
+  // number of values read ahead by a background thread while the current one is processed,
+  // 0 reads each value on `next`
+  pub prefetch: usize,
+  // also yield tombstones, only used by `Engine::scan_all_records` as the index never holds
+  // deleted keys
+  pub include_deleted: bool,
 }
 
 #[allow(clippy::derivable_impls)]
@@ -60,6 +450,74 @@ impl Default for IteratorOptions {
     Self {
       prefix: Default::default(),
       reverse: false,
+      stop_at_separator: None,
+      start_key: None,
+      end_key: None,
+      value_filter: None,
+      consistency: Consistency::Snapshot,
+      prefetch: 0,
+      include_deleted: false,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Consistency {
+  /// Entries as of the iterator's creation, later writes are never seen.
+  /// Supported by every index type, BTree and SkipList copy their entries,
+  /// B+Tree copies its committed tree and buffered changes.
+  Snapshot,
+
+  /// Walk the index as it changes, writes ahead of the iterator may be seen.
+  /// Only supported by SkipList, `Engine::iter` returns `Errors::UnsupportedIteratorMode`
+  /// for other index types.
+  Live,
+}
+
+impl IteratorOptions {
+  // whether key is within the range bounds, prefix is not checked
+  #[cfg(feature = "std")]
+  pub(crate) fn in_range(&self, key: &[u8]) -> bool {
+    if let Some(start_key) = &self.start_key {
+      if key < start_key.as_slice() {
+        return false;
+      }
+    }
+    if let Some(end_key) = &self.end_key {
+      if key >= end_key.as_slice() {
+        return false;
+      }
+    }
+    true
+  }
+
+  // bounds of the keys within [start_key, end_key) for indexes walking only that range,
+  // None if the range is empty
+  pub(crate) fn key_bounds(&self) -> Option<(Bound<&[u8]>, Bound<&[u8]>)> {
+    if let (Some(start_key), Some(end_key)) = (&self.start_key, &self.end_key) {
+      if start_key >= end_key {
+        return None;
+      }
+    }
+    let start = match &self.start_key {
+      Some(start_key) => Bound::Included(start_key.as_slice()),
+      None => Bound::Unbounded,
+    };
+    let end = match &self.end_key {
+      Some(end_key) => Bound::Excluded(end_key.as_slice()),
+      None => Bound::Unbounded,
+    };
+    Some((start, end))
+  }
+
+  // whether key, and so every key after it in iteration order, sorts past the prefix range
+  pub(crate) fn past_prefix(&self, key: &[u8]) -> bool {
+    if self.prefix.is_empty() || key.starts_with(&self.prefix) {
+      return false;
+    }
+    match self.reverse {
+      true => key < self.prefix.as_slice(),
+      false => key > self.prefix.as_slice(),
     }
   }
 }
@@ -81,6 +539,16 @@ impl Default for WriteBatchOptions {
   }
 }
 
+/// record format of `Engine::export_to`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+  /// one `{"key":"..","value":".."}` JSON object per line, keys and values base64 encoded
+  Ndjson,
+
+  /// key length, key, value length, value per record, lengths as big endian u32
+  LengthDelimited,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IOManagerType {
   // Standard IO file
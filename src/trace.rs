@@ -0,0 +1,87 @@
+// Diagnostics of the engine. With the `tracing` feature spans and events go to `tracing` along
+// with their fields, otherwise events go to `log` with the fields appended to the message and
+// spans do nothing. Field values are anything `Display`.
+
+// emit an event, e.g. `event!(DEBUG, "loaded data file", file_id = 3, records = 10)`
+#[cfg(feature = "tracing")]
+macro_rules! event {
+  ($level:ident, $msg:literal $(, $field:ident = $value:expr)* $(,)?) => {
+    tracing::event!(
+      tracing::Level::$level,
+      $($field = tracing::field::display(&$value),)*
+      $msg
+    )
+  };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+  ($level:ident, $msg:literal $(, $field:ident = $value:expr)* $(,)?) => {
+    log::log!(
+      log_level!($level),
+      concat!($msg $(, " ", stringify!($field), "={}")*)
+      $(, $value)*
+    )
+  };
+}
+
+// log level of a tracing level name
+#[cfg(not(feature = "tracing"))]
+macro_rules! log_level {
+  (TRACE) => {
+    log::Level::Trace
+  };
+  (DEBUG) => {
+    log::Level::Debug
+  };
+  (INFO) => {
+    log::Level::Info
+  };
+  (WARN) => {
+    log::Level::Warn
+  };
+  (ERROR) => {
+    log::Level::Error
+  };
+}
+
+// enter a span until the returned guard is dropped, e.g. `span!(INFO, "merge"; bytes_reclaimed)`.
+// Fields after `;` have no value yet, they are set by `record!`
+#[cfg(feature = "tracing")]
+macro_rules! span {
+  ($level:ident, $name:literal $(, $field:ident = $value:expr)* $(; $($empty:ident),+)?) => {
+    tracing::span!(
+      tracing::Level::$level,
+      $name
+      $(, $field = tracing::field::display(&$value))*
+      $($(, $empty = tracing::field::Empty)+)?
+    )
+    .entered()
+  };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+  ($level:ident, $name:literal $(, $field:ident = $value:expr)* $(; $($empty:ident),+)?) => {
+    crate::trace::NoSpan
+  };
+}
+
+// set a field of a span entered by `span!`
+#[cfg(feature = "tracing")]
+macro_rules! record {
+  ($span:expr, $field:ident = $value:expr) => {
+    $span.record(stringify!($field), tracing::field::display(&$value))
+  };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! record {
+  ($span:expr, $field:ident = $value:expr) => {
+    let _ = &$span;
+  };
+}
+
+// span guard without the `tracing` feature
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
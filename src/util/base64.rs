@@ -0,0 +1,35 @@
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// encode bytes as standard padded base64, appending to out
+pub fn encode_into(bytes: &[u8], out: &mut Vec<u8>) {
+  for chunk in bytes.chunks(3) {
+    let b = [
+      chunk[0],
+      chunk.get(1).copied().unwrap_or(0),
+      chunk.get(2).copied().unwrap_or(0),
+    ];
+    let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+    for i in 0..4 {
+      if i <= chunk.len() {
+        out.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize]);
+      } else {
+        out.push(b'=');
+      }
+    }
+  }
+}
+
+#[test]
+fn test_encode_into() {
+  let encode = |bytes: &[u8]| {
+    let mut out = Vec::new();
+    encode_into(bytes, &mut out);
+    String::from_utf8(out).unwrap()
+  };
+  assert_eq!("", encode(b""));
+  assert_eq!("Zg==", encode(b"f"));
+  assert_eq!("Zm8=", encode(b"fo"));
+  assert_eq!("Zm9v", encode(b"foo"));
+  assert_eq!("Zm9vYmFy", encode(b"foobar"));
+  assert_eq!("/+8A", encode(&[0xff, 0xef, 0x00]));
+}
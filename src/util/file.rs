@@ -3,20 +3,14 @@ use std::{
   path::{Path, PathBuf},
 };
 
-// calculate available disk space
-pub fn available_disk_space() -> u64 {
-  match fs2::available_space(PathBuf::from("/")) {
-    Ok(size) => size,
-    _ => 0,
-  }
+// calculate available disk space of the filesystem containing dir_path
+pub fn available_disk_space<P: AsRef<Path>>(dir_path: P) -> u64 {
+  fs2::available_space(dir_path).unwrap_or_default()
 }
 
 // calculate the total size of directory in disk
 pub fn dir_disk_size<P: AsRef<Path>>(dir_path: P) -> u64 {
-  match fs_extra::dir::get_size(dir_path) {
-    Ok(size) => size,
-    _ => 0,
-  }
+  fs_extra::dir::get_size(dir_path).unwrap_or_default()
 }
 
 pub fn copy_dir<P: AsRef<Path>>(src: P, dst: P, exclude: &[&str]) -> io::Result<()> {
@@ -45,6 +39,26 @@ pub fn copy_dir<P: AsRef<Path>>(src: P, dst: P, exclude: &[&str]) -> io::Result<
 
 #[test]
 fn test_available_disk_space() {
-  let size = available_disk_space();
+  let dir_path = std::env::temp_dir().join("bitkv-rs-available-space");
+  fs::create_dir_all(&dir_path).unwrap();
+
+  let size = available_disk_space(&dir_path);
   assert!(size > 0);
+
+  // the directory lives on the temp dir's filesystem, so free space is reported for that mount,
+  // allow some slack for other processes writing in between
+  let tmp_size = available_disk_space(std::env::temp_dir());
+  assert!(size.abs_diff(tmp_size) < 64 * 1024 * 1024);
+  assert_eq!(
+    fs2::total_space(&dir_path).unwrap(),
+    fs2::total_space(std::env::temp_dir()).unwrap()
+  );
+
+  // missing directories report no space
+  assert_eq!(
+    0,
+    available_disk_space(PathBuf::from("/not-exist-bitkv-rs-dir"))
+  );
+
+  fs::remove_dir_all(dir_path).unwrap();
 }
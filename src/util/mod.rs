@@ -1,3 +1,4 @@
+pub mod base64;
 pub mod file;
 
 pub mod rand_kv;
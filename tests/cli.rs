@@ -0,0 +1,100 @@
+use std::{fs, path::PathBuf};
+
+use assert_cmd::Command;
+
+fn bitkv_cli(dir_path: &PathBuf) -> Command {
+  let mut cmd = Command::cargo_bin("bitkv-cli").unwrap();
+  cmd.arg("--dir").arg(dir_path);
+  cmd
+}
+
+#[test]
+fn test_cli_put_get() {
+  let dir_path = PathBuf::from("/tmp/bitkv-rs-cli-put-get");
+
+  bitkv_cli(&dir_path)
+    .args(["put", "name", "bitkv"])
+    .assert()
+    .success();
+  bitkv_cli(&dir_path)
+    .args(["put", "other", "value"])
+    .assert()
+    .success();
+
+  bitkv_cli(&dir_path)
+    .args(["get", "name"])
+    .assert()
+    .success()
+    .stdout("bitkv\n");
+  bitkv_cli(&dir_path)
+    .args(["scan", "--prefix", "na"])
+    .assert()
+    .success()
+    .stdout("name\tbitkv\n");
+
+  // missing key fails with the engine error
+  bitkv_cli(&dir_path)
+    .args(["delete", "name"])
+    .assert()
+    .success();
+  bitkv_cli(&dir_path)
+    .args(["get", "name"])
+    .assert()
+    .failure()
+    .stderr("key is not found in database\n");
+
+  fs::remove_dir_all(dir_path).unwrap();
+}
+
+#[test]
+fn test_cli_options() {
+  let dir_path = PathBuf::from("/tmp/bitkv-rs-cli-options");
+
+  // reads take the options the database was created with, and never create one
+  bitkv_cli(&dir_path)
+    .args(["get", "name"])
+    .assert()
+    .failure()
+    .stderr("no database found in the directory\n");
+  assert!(!dir_path.exists());
+  bitkv_cli(&dir_path)
+    .args(["--index-type", "bptree", "--file-suffix", ".log"])
+    .args(["put", "name", "bitkv"])
+    .assert()
+    .success();
+  bitkv_cli(&dir_path)
+    .args(["get", "name"])
+    .args(["--index-type", "bptree", "--file-suffix", ".log"])
+    .assert()
+    .success()
+    .stdout("bitkv\n");
+  bitkv_cli(&dir_path)
+    .args(["--index-type", "hash", "get", "name"])
+    .assert()
+    .failure();
+
+  fs::remove_dir_all(dir_path).unwrap();
+}
+
+#[test]
+fn test_cli_scan_escapes() {
+  let dir_path = PathBuf::from("/tmp/bitkv-rs-cli-scan-escapes");
+
+  bitkv_cli(&dir_path)
+    .args(["put", "a\tb", "line 1\nline 2"])
+    .assert()
+    .success();
+  bitkv_cli(&dir_path)
+    .args(["put", "c\\d", "e\r"])
+    .assert()
+    .success();
+
+  // each record stays on its own line with a single tab
+  bitkv_cli(&dir_path)
+    .args(["scan"])
+    .assert()
+    .success()
+    .stdout("a\\tb\tline 1\\nline 2\nc\\\\d\te\\r\n");
+
+  fs::remove_dir_all(dir_path).unwrap();
+}